@@ -0,0 +1,69 @@
+/*
+ * C API of the Sunshine HDR helper, exported by shh.dll (cargo build --release --lib).
+ *
+ * Displays are picked with the same selectors as the commands: "primary", an index, a device
+ * name like "DISPLAY2", the monitor name or its display ID. NULL means the primary display.
+ * Strings are UTF-8.
+ *
+ * Functions that change something return the helper's exit codes (sunshine_helper --print-exit-codes),
+ * 0 on success, and keep the error message for shh_last_error on the calling thread.
+ */
+#ifndef SHH_H
+#define SHH_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+enum {
+    SHH_SUCCESS = 0,
+    SHH_FAILURE = 1,
+    SHH_DISPLAY_NOT_FOUND = 2,
+    SHH_MODE_UNSUPPORTED = 3,
+    SHH_WIN32_ERROR = 4,
+    SHH_NEEDS_ELEVATION = 5,
+    SHH_UNSUPPORTED = 6,
+    SHH_INVALID_INPUT = 7,
+};
+
+typedef struct ShhDisplay {
+    uint32_t index;
+    char device_name[32];   /* DISPLAY1, without the \\.\ prefix */
+    char friendly_name[64]; /* the monitor's name, empty if unknown */
+    uint32_t width;
+    uint32_t height;
+    uint32_t refresh_rate;
+    bool is_primary;
+    bool hdr_supported;
+    bool hdr_enabled;
+    int32_t sdr_level;      /* 0-100 like the Settings slider, -1 when it can't be read */
+} ShhDisplay;
+
+/* Fills up to `capacity` entries and returns how many active displays there are, so a first
+ * call with (NULL, 0) sizes the array. 0 with a message for shh_last_error if it failed. */
+uint32_t shh_enumerate_displays(ShhDisplay *displays, uint32_t capacity);
+
+int32_t shh_set_hdr(const char *display, bool enabled);
+
+/* 0-100 like the Settings slider, 80 to 480 nits */
+int32_t shh_set_sdr_level(const char *display, uint32_t level);
+int32_t shh_set_sdr_nits(const char *display, uint32_t nits);
+
+/* The file name of a profile associated with the display, e.g. "HDR TV.icc" */
+int32_t shh_set_icc_profile(const char *display, const char *profile);
+
+int32_t shh_set_display_mode(const char *display, uint32_t width, uint32_t height, uint32_t refresh_rate);
+
+/* Copies the calling thread's last error message into `buffer` (cut to fit, NUL terminated) and
+ * returns its full length in bytes, 0 when the last call succeeded. */
+size_t shh_last_error(char *buffer, size_t size);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,390 @@
+use std::ffi::{c_void, CString};
+use std::mem::size_of;
+use windows::Win32::System::Com::{CoTaskMemAlloc, CoTaskMemFree};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::Foundation::{E_FAIL, HMODULE};
+use windows::core::{w, Error, Result, PCSTR};
+use tracing::{info, error};
+
+use crate::displays_info::DisplayDevice;
+use crate::gpu_vendor::{ColorFormat, PixelFormat, VrrStatus};
+
+// AMD Display Library, exported by name from atiadlxx.dll. Constants and struct layouts are
+// from the public ADL SDK headers (adl_defines.h, adl_structures.h).
+const ADL_OK: i32 = 0;
+const ADL_OK_WARNING: i32 = 1;
+const ADL_ERR_NOT_SUPPORTED: i32 = -8;
+
+const DISPLAY_CONNECTED: i32 = 0x1;
+const DISPLAY_MAPPED: i32 = 0x2;
+
+const PIXEL_FORMAT_RGB: i32 = 0x1;
+const PIXEL_FORMAT_YCRCB444: i32 = 0x2;
+const PIXEL_FORMAT_YCRCB422: i32 = 0x8;
+const PIXEL_FORMAT_RGB_LIMITED: i32 = 0x10;
+const PIXEL_FORMAT_YCRCB420: i32 = 0x20;
+
+// ADL_COLORDEPTH_666 = 1 up to ADL_COLORDEPTH_161616 = 6
+const BITS_PER_COLOR: [u32; 6] = [6, 8, 10, 12, 14, 16];
+
+const DITHER_DISABLED: i32 = 1;
+const DITHER_ENABLED: i32 = 2;
+
+const MODE_TIMING_STANDARD_CVT_RB: i32 = 0x20;
+
+type AdlMallocCallback = unsafe extern "system" fn(i32) -> *mut c_void;
+
+// ADL allocates its output arrays through this, we free them with CoTaskMemFree
+unsafe extern "system" fn adl_malloc(size: i32) -> *mut c_void {
+    CoTaskMemAlloc(size.max(0) as usize)
+}
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+#[repr(C)]
+struct AdapterInfo {
+    size: i32,
+    adapter_index: i32,
+    udid: [u8; 256],
+    bus_number: i32,
+    device_number: i32,
+    function_number: i32,
+    vendor_id: i32,
+    adapter_name: [u8; 256],
+    display_name: [u8; 256],
+    present: i32,
+    exist: i32,
+    driver_path: [u8; 256],
+    driver_path_ext: [u8; 256],
+    pnp_string: [u8; 256],
+    os_display_index: i32,
+}
+
+#[repr(C)]
+struct AdlDisplayInfo {
+    logical_index: i32,
+    physical_index: i32,
+    logical_adapter_index: i32,
+    physical_adapter_index: i32,
+    controller_index: i32,
+    display_name: [u8; 256],
+    manufacturer_name: [u8; 256],
+    display_type: i32,
+    output_type: i32,
+    connector: i32,
+    info_mask: i32,
+    info_value: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct AdlDisplayMode {
+    height: i32,
+    width: i32,
+    bits_per_pixel: i32,
+    refresh_rate: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct AdlDetailedTiming {
+    size: i32,
+    timing_flags: i16,
+    h_total: i16,
+    h_display: i16,
+    h_sync_start: i16,
+    h_sync_width: i16,
+    v_total: i16,
+    v_display: i16,
+    v_sync_start: i16,
+    v_sync_width: i16,
+    pixel_clock: i16,
+    h_overscan_right: i16,
+    h_overscan_left: i16,
+    v_overscan_bottom: i16,
+    v_overscan_top: i16,
+    overscan_8b: i16,
+    overscan_gr: i16,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct AdlDisplayModeInfo {
+    timing_standard: i32,
+    possible_standard: i32,
+    refresh_rate: i32,
+    width: i32,
+    height: i32,
+    detailed_timing: AdlDetailedTiming,
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
+
+//==============================================================================
+// Session
+//==============================================================================
+
+// An ADL context, destroyed on drop
+struct AdlSession {
+    module: HMODULE,
+    context: *mut c_void,
+}
+
+impl AdlSession {
+    fn open() -> Result<Self> {
+        let module = unsafe { LoadLibraryW(w!("atiadlxx.dll")) }
+            .map_err(|_| Error::new(E_FAIL, "ADL is not available (no AMD driver installed?)"))?;
+
+        let mut session = AdlSession { module, context: std::ptr::null_mut() };
+        let mut context = std::ptr::null_mut();
+        unsafe {
+            let create: unsafe extern "C" fn(AdlMallocCallback, i32, *mut *mut c_void) -> i32 =
+                session.function("ADL2_Main_Control_Create")?;
+            session.check(create(adl_malloc, 1, &mut context), "ADL2_Main_Control_Create")?;
+        }
+        session.context = context;
+
+        info!("Loaded ADL");
+        Ok(session)
+    }
+
+    // F must be the unsafe extern "C" fn type matching the function's ADL signature
+    unsafe fn function<F: Copy>(&self, name: &str) -> Result<F> {
+        let name_c = CString::new(name).map_err(|_| Error::new(E_FAIL, "Invalid ADL function name"))?;
+        match GetProcAddress(self.module, PCSTR::from_raw(name_c.as_ptr() as *const u8)) {
+            Some(pointer) => Ok(std::mem::transmute_copy(&pointer)),
+            None => Err(Error::new(E_FAIL, format!("{} is not available in this AMD driver", name))),
+        }
+    }
+
+    fn check(&self, status: i32, what: &str) -> Result<()> {
+        match status {
+            ADL_OK | ADL_OK_WARNING => Ok(()),
+            ADL_ERR_NOT_SUPPORTED => Err(Error::new(E_FAIL, format!("{} is not supported by this display or driver", what))),
+            _ => {
+                error!("{} failed: {}", what, status);
+                Err(Error::new(E_FAIL, format!("{} failed with ADL error {}", what, status)))
+            }
+        }
+    }
+
+    // ADL addresses displays by (adapter index, display index). The adapter entry whose display name
+    // is \\.\DISPLAYn owns the display, which is the mapped display on that logical adapter.
+    fn find_display(&self, display: &DisplayDevice) -> Result<(i32, i32)> {
+        unsafe {
+            let number_of_adapters: unsafe extern "C" fn(*mut c_void, *mut i32) -> i32 =
+                self.function("ADL2_Adapter_NumberOfAdapters_Get")?;
+            let mut count = 0;
+            self.check(number_of_adapters(self.context, &mut count), "ADL2_Adapter_NumberOfAdapters_Get")?;
+
+            let mut adapters: Vec<AdapterInfo> = (0..count.max(0)).map(|_| std::mem::zeroed()).collect();
+            let adapter_info: unsafe extern "C" fn(*mut c_void, *mut AdapterInfo, i32) -> i32 =
+                self.function("ADL2_Adapter_AdapterInfo_Get")?;
+            self.check(
+                adapter_info(self.context, adapters.as_mut_ptr(), (adapters.len() * size_of::<AdapterInfo>()) as i32),
+                "ADL2_Adapter_AdapterInfo_Get",
+            )?;
+
+            let Some(adapter) = adapters.iter().find(|a| c_string(&a.display_name).eq_ignore_ascii_case(&display.device_name)) else {
+                return Err(Error::new(E_FAIL, format!("ADL does not know {}", display.device_name)));
+            };
+
+            let display_info: unsafe extern "C" fn(*mut c_void, i32, *mut i32, *mut *mut AdlDisplayInfo, i32) -> i32 =
+                self.function("ADL2_Display_DisplayInfo_Get")?;
+            let mut display_count = 0;
+            let mut infos: *mut AdlDisplayInfo = std::ptr::null_mut();
+            self.check(display_info(self.context, adapter.adapter_index, &mut display_count, &mut infos, 0), "ADL2_Display_DisplayInfo_Get")?;
+
+            let found = (0..display_count.max(0) as usize)
+                .map(|i| &*infos.add(i))
+                .find(|info| {
+                    info.logical_adapter_index == adapter.adapter_index
+                        && info.info_value & (DISPLAY_CONNECTED | DISPLAY_MAPPED) == (DISPLAY_CONNECTED | DISPLAY_MAPPED)
+                })
+                .map(|info| info.logical_index);
+            CoTaskMemFree(Some(infos as *const c_void));
+
+            match found {
+                Some(display_index) => Ok((adapter.adapter_index, display_index)),
+                None => Err(Error::new(E_FAIL, format!("ADL has no mapped display for {}", display.device_name))),
+            }
+        }
+    }
+
+    fn get_display_int(&self, display: &DisplayDevice, name: &str) -> Result<i32> {
+        let (adapter_index, display_index) = self.find_display(display)?;
+        let mut value = 0;
+        unsafe {
+            let get: unsafe extern "C" fn(*mut c_void, i32, i32, *mut i32) -> i32 = self.function(name)?;
+            self.check(get(self.context, adapter_index, display_index, &mut value), name)?;
+        }
+        Ok(value)
+    }
+
+    fn set_display_int(&self, display: &DisplayDevice, name: &str, value: i32) -> Result<()> {
+        let (adapter_index, display_index) = self.find_display(display)?;
+        unsafe {
+            let set: unsafe extern "C" fn(*mut c_void, i32, i32, i32) -> i32 = self.function(name)?;
+            self.check(set(self.context, adapter_index, display_index, value), name)
+        }
+    }
+}
+
+impl Drop for AdlSession {
+    fn drop(&mut self) {
+        if self.context.is_null() {
+            return;
+        }
+        unsafe {
+            if let Ok(destroy) = self.function::<unsafe extern "C" fn(*mut c_void) -> i32>("ADL2_Main_Control_Destroy") {
+                destroy(self.context);
+            }
+        }
+    }
+}
+
+//==============================================================================
+// VRR (FreeSync)
+//==============================================================================
+
+pub fn get_vrr(device: &DisplayDevice) -> Result<VrrStatus> {
+    let session = AdlSession::open()?;
+    let (adapter_index, display_index) = session.find_display(device)?;
+
+    let mut current = 0;
+    let mut default = 0;
+    let mut min_refresh = 0;
+    let mut max_refresh = 0;
+    let status = unsafe {
+        let get_state: unsafe extern "C" fn(*mut c_void, i32, i32, *mut i32, *mut i32, *mut i32, *mut i32) -> i32 =
+            session.function("ADL2_Display_FreeSyncState_Get")?;
+        get_state(session.context, adapter_index, display_index, &mut current, &mut default, &mut min_refresh, &mut max_refresh)
+    };
+
+    if status == ADL_ERR_NOT_SUPPORTED {
+        return Ok(VrrStatus { supported: false, enabled: false, active: false });
+    }
+    session.check(status, "ADL2_Display_FreeSyncState_Get")?;
+
+    info!("FreeSync range of {}: {:.1}-{:.1} Hz", device.device_name, min_refresh as f64 / 1e6, max_refresh as f64 / 1e6);
+    Ok(VrrStatus {
+        supported: max_refresh > min_refresh,
+        enabled: current != 0,
+        active: false, // ADL doesn't say whether VRR is engaged right now
+    })
+}
+
+pub fn set_vrr(device: &DisplayDevice, enabled: bool) -> Result<()> {
+    let session = AdlSession::open()?;
+    let (adapter_index, display_index) = session.find_display(device)?;
+    info!("Turning FreeSync {} for {}", if enabled { "on" } else { "off" }, device.device_name);
+
+    unsafe {
+        let set_state: unsafe extern "C" fn(*mut c_void, i32, i32, i32, i32) -> i32 = session.function("ADL2_Display_FreeSyncState_Set")?;
+        session.check(set_state(session.context, adapter_index, display_index, enabled as i32, 0), "ADL2_Display_FreeSyncState_Set")
+    }
+}
+
+//==============================================================================
+// Output color format
+//==============================================================================
+
+pub fn get_color_format(display: &DisplayDevice) -> Result<ColorFormat> {
+    let session = AdlSession::open()?;
+    let pixel_format = match session.get_display_int(display, "ADL2_Display_PixelFormat_Get")? {
+        PIXEL_FORMAT_RGB | PIXEL_FORMAT_RGB_LIMITED => PixelFormat::Rgb,
+        PIXEL_FORMAT_YCRCB444 => PixelFormat::Ycc444,
+        PIXEL_FORMAT_YCRCB422 => PixelFormat::Ycc422,
+        PIXEL_FORMAT_YCRCB420 => PixelFormat::Ycc420,
+        other => return Err(Error::new(E_FAIL, format!("Unknown AMD pixel format {:#x}", other))),
+    };
+
+    let color_depth = session.get_display_int(display, "ADL2_Display_ColorDepth_Get")?;
+    let bits_per_color = (color_depth as usize)
+        .checked_sub(1)
+        .and_then(|index| BITS_PER_COLOR.get(index))
+        .copied()
+        .unwrap_or(0);
+
+    Ok(ColorFormat { pixel_format, bits_per_color })
+}
+
+pub fn set_color_format(device: &DisplayDevice, pixel_format: Option<PixelFormat>, bits_per_color: Option<u32>) -> Result<()> {
+    let session = AdlSession::open()?;
+
+    if let Some(pixel_format) = pixel_format {
+        let value = match pixel_format {
+            PixelFormat::Rgb => PIXEL_FORMAT_RGB,
+            PixelFormat::Ycc444 => PIXEL_FORMAT_YCRCB444,
+            PixelFormat::Ycc422 => PIXEL_FORMAT_YCRCB422,
+            PixelFormat::Ycc420 => PIXEL_FORMAT_YCRCB420,
+        };
+        info!("Setting pixel format of {} to {:?}", device.device_name, pixel_format);
+        session.set_display_int(device, "ADL2_Display_PixelFormat_Set", value)?;
+    }
+
+    if let Some(bits_per_color) = bits_per_color {
+        let Some(index) = BITS_PER_COLOR.iter().position(|&bits| bits == bits_per_color) else {
+            return Err(Error::new(E_FAIL, format!("{} bits per color is not one of {:?}", bits_per_color, BITS_PER_COLOR)));
+        };
+        info!("Setting color depth of {} to {} bpc", device.device_name, bits_per_color);
+        session.set_display_int(device, "ADL2_Display_ColorDepth_Set", index as i32 + 1)?;
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Dithering
+//==============================================================================
+
+pub fn get_dithering(display: &DisplayDevice) -> Result<bool> {
+    let session = AdlSession::open()?;
+    Ok(session.get_display_int(display, "ADL2_Display_DitherState_Get")? == DITHER_ENABLED)
+}
+
+pub fn set_dithering(device: &DisplayDevice, enabled: bool) -> Result<()> {
+    let session = AdlSession::open()?;
+    info!("Turning dithering {} for {}", if enabled { "on" } else { "off" }, device.device_name);
+    session.set_display_int(device, "ADL2_Display_DitherState_Set", if enabled { DITHER_ENABLED } else { DITHER_DISABLED })
+}
+
+//==============================================================================
+// Custom resolutions
+//==============================================================================
+
+// Let the driver compute a CVT-RB timing for the mode, then add it as a timing override
+pub fn create_custom_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32) -> Result<()> {
+    let session = AdlSession::open()?;
+    let (adapter_index, display_index) = session.find_display(device)?;
+
+    let mut mode = AdlDisplayMode {
+        width: width as i32,
+        height: height as i32,
+        bits_per_pixel: 32,
+        refresh_rate: refresh_rate as i32,
+    };
+    let mut mode_info = AdlDisplayModeInfo {
+        timing_standard: MODE_TIMING_STANDARD_CVT_RB,
+        ..Default::default()
+    };
+    mode_info.detailed_timing.size = size_of::<AdlDetailedTiming>() as i32;
+
+    unsafe {
+        let get_timing: unsafe extern "C" fn(*mut c_void, i32, i32, *mut AdlDisplayMode, *mut AdlDisplayModeInfo) -> i32 =
+            session.function("ADL2_Display_ModeTimingOverride_Get")?;
+        session.check(get_timing(session.context, adapter_index, display_index, &mut mode, &mut mode_info), "ADL2_Display_ModeTimingOverride_Get")?;
+
+        info!("Adding custom mode {}x{} @{}Hz on {} (pixel clock {} kHz)",
+            width, height, refresh_rate, device.device_name, mode_info.detailed_timing.pixel_clock as i32 * 10);
+
+        let set_timing: unsafe extern "C" fn(*mut c_void, i32, i32, *mut AdlDisplayModeInfo, i32) -> i32 =
+            session.function("ADL2_Display_ModeTimingOverride_Set")?;
+        session.check(set_timing(session.context, adapter_index, display_index, &mut mode_info, 1), "ADL2_Display_ModeTimingOverride_Set")
+    }
+}
@@ -0,0 +1,695 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Once;
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, info_span, error, instrument};
+
+use crate::change_display_mode;
+use crate::change_icc_profile;
+use crate::display_config;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code;
+use crate::hdr;
+use crate::interrupt;
+use crate::retry::{self, RetryPolicy};
+use crate::set_sdr_level;
+use crate::state;
+
+// A spec file describes how each display should end up, e.g.
+// { "displays": [{ "display": "DISPLAY1", "primary": true, "mode": { "width": 1920, "height": 1080, "refresh_rate": 60 },
+//                  "hdr": true, "sdr_nits": 240, "icc_profile": "HDR Steam Deck.icc" },
+//                { "display": "LG TV SSCR2", "enabled": false }] }
+// Settings left out are not touched. Files ending in .yaml or .yml are read as YAML instead, e.g.
+//   displays:
+//     - display: primary
+//       hdr: true   # for the Steam Deck
+//       sdr_nits: 240
+// and files ending in .toml as TOML, with a [[displays]] table per display.
+
+const UNDO_FILE: &str = "undo.json";
+
+//==============================================================================
+// Spec file
+//==============================================================================
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Spec {
+    pub displays: Vec<DisplaySpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DisplaySpec {
+    pub display: String, // selector, as for every other command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    // Only `true` does anything, another display's spec decides which one is primary otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ModeSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hdr: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sdr_nits: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icc_profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ModeSpec {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+}
+
+// WIDTHxHEIGHT@HZ, as verify --mode takes it
+impl FromStr for ModeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mode = s.split_once('x').and_then(|(width, rest)| {
+            let (height, refresh_rate) = rest.split_once('@')?;
+            Some(ModeSpec { width: width.trim().parse().ok()?, height: height.trim().parse().ok()?, refresh_rate: refresh_rate.trim().parse().ok()? })
+        });
+        mode.ok_or_else(|| format!("'{}' isn't a mode like 3840x2160@120", s))
+    }
+}
+
+pub fn load_spec_file(path: &Path) -> Result<Spec> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to read {}: {}", path.display(), e)))?;
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    parse_spec(&contents, &extension).map_err(|e| exit_code::invalid_input(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+// JSON unless the file's extension says otherwise
+fn parse_spec(contents: &str, extension: &str) -> std::result::Result<Spec, String> {
+    match extension {
+        "yaml" | "yml" => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        "toml" => toml::from_str(contents).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(contents).map_err(|e| e.to_string()),
+    }
+}
+
+// The active displays as they are now, for `status --format json|yaml`. Applying it later puts them back.
+pub fn current_spec() -> Result<Spec> {
+    let mut displays = Vec::new();
+    let active = displays_info::enumerate_displays();
+    // Saved states name displays by their stable ID, so they still apply after a reboot has
+    // renumbered them. Identical monitors without serial numbers share one and keep the name.
+    let ids: Vec<Option<String>> = active.iter().map(DisplayDevice::stable_id).collect();
+
+    for (display, id) in active.iter().zip(&ids) {
+        let (width, height) = display.current_resolution;
+        let hdr = hdr::get_advanced_color_info(display).ok().filter(|info| info.supported).map(|info| info.enabled);
+        // The SDR white level only matters while HDR is on
+        let sdr_nits = if hdr == Some(true) {
+            Some(set_sdr_level::level_to_nits(set_sdr_level::get_display_sdr_white(display)?))
+        } else {
+            None
+        };
+
+        displays.push(DisplaySpec {
+            display: id.clone()
+                .filter(|id| ids.iter().filter(|other| other.as_ref() == Some(id)).count() == 1)
+                .unwrap_or_else(|| display.device_name.trim_start_matches("\\\\.\\").to_string()),
+            enabled: Some(true),
+            primary: display.is_primary.then_some(true),
+            mode: Some(ModeSpec { width, height, refresh_rate: display.current_refresh_rate }),
+            hdr,
+            sdr_nits,
+            icc_profile: change_icc_profile::get_display_default_icc_profile(display),
+        });
+    }
+
+    Ok(Spec { displays })
+}
+
+//==============================================================================
+// Differences
+//==============================================================================
+
+// One setting that doesn't match the spec
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Setting {
+    Enabled(bool),
+    Primary,
+    Mode(ModeSpec),
+    Hdr(bool),
+    SdrNits(u32),
+    IccProfile(String),
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Setting::Enabled(true) => write!(f, "enabled"),
+            Setting::Enabled(false) => write!(f, "disabled"),
+            Setting::Primary => write!(f, "primary"),
+            Setting::Mode(mode) => write!(f, "mode {}x{} @{}Hz", mode.width, mode.height, mode.refresh_rate),
+            Setting::Hdr(enabled) => write!(f, "HDR {}", if *enabled { "on" } else { "off" }),
+            Setting::SdrNits(nits) => write!(f, "SDR white {} nits", nits),
+            Setting::IccProfile(name) => write!(f, "ICC profile '{}'", name),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Difference {
+    pub display: String, // selector from the spec
+    pub desired: Setting,
+    pub current: String,
+    // What puts things back if a later change fails, when the old value is known
+    #[serde(skip)]
+    pub undo: Option<(String, Setting)>,
+}
+
+fn difference(display: &str, desired: Setting, current: impl Into<String>) -> Difference {
+    Difference { display: display.to_string(), desired, current: current.into(), undo: None }
+}
+
+impl Difference {
+    fn with_undo(mut self, display: &str, setting: Setting) -> Self {
+        self.undo = Some((display.to_string(), setting));
+        self
+    }
+}
+
+// Settings for a display that is going to be enabled can't be read yet, so they all count as different
+fn pending_differences(entry: &DisplaySpec) -> Vec<Difference> {
+    let unknown = "unknown (display disabled)";
+    let mut differences = Vec::new();
+
+    if entry.primary == Some(true) {
+        differences.push(difference(&entry.display, Setting::Primary, unknown));
+    }
+    if let Some(mode) = entry.mode {
+        differences.push(difference(&entry.display, Setting::Mode(mode), unknown));
+    }
+    if let Some(hdr) = entry.hdr {
+        differences.push(difference(&entry.display, Setting::Hdr(hdr), unknown));
+    }
+    if let Some(nits) = entry.sdr_nits {
+        differences.push(difference(&entry.display, Setting::SdrNits(nits), unknown));
+    }
+    if let Some(profile) = &entry.icc_profile {
+        differences.push(difference(&entry.display, Setting::IccProfile(profile.clone()), unknown));
+    }
+
+    differences
+}
+
+fn display_differences(entry: &DisplaySpec, display: &DisplayDevice) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+
+    if entry.primary == Some(true) && !display.is_primary {
+        let mut change = difference(&entry.display, Setting::Primary, "not primary");
+        if let Some(primary) = displays_info::find_display("primary") {
+            change = change.with_undo(&primary.device_name, Setting::Primary);
+        }
+        differences.push(change);
+    }
+
+    if let Some(mode) = entry.mode {
+        let (width, height) = display.current_resolution;
+        let current = ModeSpec { width, height, refresh_rate: display.current_refresh_rate };
+        if current != mode {
+            differences.push(difference(&entry.display, Setting::Mode(mode), format!("{}x{} @{}Hz", width, height, current.refresh_rate))
+                .with_undo(&entry.display, Setting::Mode(current)));
+        }
+    }
+
+    if let Some(hdr) = entry.hdr {
+        let enabled = hdr::get_advanced_color_info(display)?.enabled;
+        if enabled != hdr {
+            differences.push(difference(&entry.display, Setting::Hdr(hdr), if enabled { "on" } else { "off" })
+                .with_undo(&entry.display, Setting::Hdr(enabled)));
+        }
+    }
+
+    if let Some(nits) = entry.sdr_nits {
+        let level = set_sdr_level::get_display_sdr_white(display)?;
+        if level != set_sdr_level::nits_to_level(nits) {
+            let current_nits = set_sdr_level::level_to_nits(level);
+            differences.push(difference(&entry.display, Setting::SdrNits(nits), format!("{} nits", current_nits))
+                .with_undo(&entry.display, Setting::SdrNits(current_nits)));
+        }
+    }
+
+    if let Some(profile) = &entry.icc_profile {
+        let current = change_icc_profile::get_display_default_icc_profile(display);
+        if !current.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(profile)) {
+            let mut change = difference(&entry.display, Setting::IccProfile(profile.clone()), current.clone().unwrap_or_else(|| "none".to_string()));
+            // Windows has no "no default profile" to go back to
+            if let Some(current) = current {
+                change = change.with_undo(&entry.display, Setting::IccProfile(current));
+            }
+            differences.push(change);
+        }
+    }
+
+    Ok(differences)
+}
+
+// Everything that would have to change for the system to match the spec, in the order to apply it
+pub fn differences(spec: &Spec) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+
+    for entry in &spec.displays {
+        let display = displays_info::find_display(&entry.display);
+
+        match (display, entry.enabled) {
+            (Some(_), Some(false)) => differences.push(difference(&entry.display, Setting::Enabled(false), "enabled")
+                .with_undo(&entry.display, Setting::Enabled(true))),
+            (None, Some(false)) => {}
+            (Some(display), _) => differences.extend(display_differences(entry, &display)?),
+            (None, Some(true)) => {
+                differences.push(difference(&entry.display, Setting::Enabled(true), "disabled")
+                    .with_undo(&entry.display, Setting::Enabled(false)));
+                differences.extend(pending_differences(entry));
+            }
+            (None, None) => return Err(exit_code::display_not_found(format!("No active display matches '{}'", entry.display))),
+        }
+    }
+
+    // Stable, so each display's settings keep their spec order within a kind
+    differences.sort_by_key(|difference| rank(&difference.desired));
+    Ok(differences)
+}
+
+// Displays need to be enabled before anything else can be set on them, and the mode decides
+// which HDR and SDR settings stick
+fn rank(setting: &Setting) -> u8 {
+    match setting {
+        Setting::Enabled(_) => 0,
+        Setting::Primary => 1,
+        Setting::Mode(_) => 2,
+        Setting::Hdr(_) => 3,
+        Setting::SdrNits(_) => 4,
+        Setting::IccProfile(_) => 5,
+    }
+}
+
+//==============================================================================
+// Applying
+//==============================================================================
+
+fn find_active_display(selector: &str) -> Result<DisplayDevice> {
+    displays_info::find_display(selector)
+        .ok_or_else(|| exit_code::display_not_found(format!("No active display matches '{}'", selector)))
+}
+
+// Displays are looked up again for every change, enabling one or switching modes can renumber them
+fn apply_setting(selector: &str, setting: &Setting) -> Result<()> {
+    match setting {
+        Setting::Enabled(true) => display_config::enable_display(selector).map(|_| ()),
+        Setting::Enabled(false) => display_config::disable_display(selector).map(|_| ()),
+        Setting::Primary => display_config::set_primary_display(selector).map(|_| ()),
+        Setting::Mode(mode) => {
+            let display = find_active_display(selector)?;
+            if !change_display_mode::is_supported_mode(&display, mode.width, mode.height, mode.refresh_rate, None) {
+                return Err(exit_code::mode_unsupported(format!("{} does not support {}", display.device_name, setting)));
+            }
+            if change_display_mode::change_display_mode(&display, mode.width, mode.height, mode.refresh_rate, None, false) {
+                Ok(())
+            } else {
+                Err(Error::new(E_FAIL, format!("Failed to set {} on {}", setting, display.device_name)))
+            }
+        }
+        Setting::Hdr(enabled) => hdr::set_hdr(&find_active_display(selector)?, *enabled),
+        Setting::SdrNits(nits) => set_sdr_level::set_display_sdr_white(&find_active_display(selector)?, set_sdr_level::nits_to_level(*nits)),
+        Setting::IccProfile(profile) => change_icc_profile::set_display_icc_profile(&find_active_display(selector)?, profile),
+    }
+}
+
+// Put back what was already applied, newest first. Keeps going past failures to restore as much as possible.
+fn roll_back(applied: &[&Difference], retry_policy: RetryPolicy) -> usize {
+    let mut restored = 0;
+
+    for difference in applied.iter().rev() {
+        let Some((selector, setting)) = &difference.undo else {
+            error!("Can't roll back {} on '{}', its previous value is unknown", difference.desired, difference.display);
+            continue;
+        };
+
+        let what = format!("rollback to {} on '{}'", setting, selector);
+        let _span = info_span!("rollback", display = %selector, setting = %setting).entered();
+        match retry::with_retries(retry_policy, &what, || apply_setting(selector, setting)) {
+            Ok(()) => restored += 1,
+            Err(e) => error!("Failed {}: {}", what, e.message()),
+        }
+    }
+
+    restored
+}
+
+// All or nothing: if a change still fails after retrying, the ones before it are rolled back
+pub fn apply_differences(differences: &[Difference], retry_policy: RetryPolicy, rollback: bool) -> Result<()> {
+    apply_in_order(differences, retry_policy, rollback, true)
+}
+
+// HDR, SDR white level and ICC profile changes only touch their own display. Enabling, the
+// primary display and modes move the others around.
+fn is_per_display(setting: &Setting) -> bool {
+    matches!(setting, Setting::Hdr(_) | Setting::SdrNits(_) | Setting::IccProfile(_))
+}
+
+#[instrument(name = "apply", skip_all, fields(display = %difference.display, setting = %difference.desired))]
+fn apply_one(difference: &Difference, retry_policy: RetryPolicy, interruptible: bool) -> Result<()> {
+    let what = format!("{} on '{}'", difference.desired, difference.display);
+
+    // Ctrl+C stops the batch here, between changes, and rolls back like a failure
+    if interruptible && interrupt::is_interrupted() {
+        return Err(interrupt::interrupted(format!("Interrupted before applying {}", what)));
+    }
+    info!("Applying {} (currently {})", what, difference.current);
+    retry::with_retries(retry_policy, &what, || apply_setting(&difference.display, &difference.desired))
+        .map_err(|e| Error::new(e.code(), format!("Failed to apply {}: {}", what, e.message())))?;
+    // These make Windows re-enumerate, the next change should see where it ends up
+    if matches!(difference.desired, Setting::Enabled(_) | Setting::Primary | Setting::Mode(_) | Setting::Hdr(_)) {
+        displays_info::settle();
+    }
+    Ok(())
+}
+
+// The per-display changes at the end of a batch, grouped by the display they resolve to, so two
+// selectors for the same display don't end up on different threads
+fn group_by_display(differences: &[Difference]) -> Vec<Vec<&Difference>> {
+    let mut groups: Vec<(String, Vec<&Difference>)> = Vec::new();
+    for difference in differences {
+        let key = displays_info::find_display(&difference.display).map_or_else(|| difference.display.clone(), |display| display.device_name);
+        match groups.iter_mut().find(|(display, _)| *display == key) {
+            Some((_, group)) => group.push(difference),
+            None => groups.push((key, vec![difference])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+// Restoring after Ctrl+C has to run to the end, so it isn't interruptible itself. Layout changes
+// go one at a time, then each display's own changes run on a thread per display, in order on
+// that display. A failure on one display rolls back the others too.
+fn apply_in_order(differences: &[Difference], retry_policy: RetryPolicy, rollback: bool, interruptible: bool) -> Result<()> {
+    let _in_progress = interrupt::in_progress();
+    let mut applied = Vec::new();
+
+    let split = differences.iter().rposition(|difference| !is_per_display(&difference.desired)).map_or(0, |index| index + 1);
+    let (layout, per_display) = differences.split_at(split);
+    let mut result = Ok(());
+    for difference in layout {
+        result = apply_one(difference, retry_policy, interruptible);
+        if result.is_err() {
+            break;
+        }
+        applied.push(difference);
+    }
+
+    if result.is_ok() && !per_display.is_empty() {
+        let outcomes = displays_info::for_each_in_parallel(&group_by_display(per_display), |group| {
+            let mut done = Vec::new();
+            for difference in group {
+                if let Err(e) = apply_one(difference, retry_policy, interruptible) {
+                    return (done, Err(e));
+                }
+                done.push(*difference);
+            }
+            (done, Ok(()))
+        });
+        for (done, outcome) in outcomes {
+            applied.extend(done);
+            result = result.and(outcome);
+        }
+    }
+
+    if let Err(e) = result {
+        let mut message = e.message();
+        if rollback && !applied.is_empty() {
+            let restored = roll_back(&applied, retry_policy);
+            message.push_str(&format!(" (rolled back {} of {} earlier change(s))", restored, applied.len()));
+        }
+        // Keep the original code, it decides the exit code
+        return Err(Error::new(e.code(), message));
+    }
+
+    Ok(())
+}
+
+// apply_differences with an undo snapshot around it, for `apply`
+pub fn apply_with_undo(differences: &[Difference], retry_policy: RetryPolicy, rollback: bool) -> Result<()> {
+    if differences.is_empty() {
+        return Ok(());
+    }
+
+    let enabling: Vec<&str> = differences.iter()
+        .filter(|difference| difference.desired == Setting::Enabled(true))
+        .map(|difference| difference.display.as_str())
+        .collect();
+    let snapshot = UndoSnapshot::save(&enabling, retry_policy);
+
+    match apply_differences(differences, retry_policy, rollback) {
+        Ok(()) => {
+            if let Some(snapshot) = snapshot {
+                snapshot.finish();
+            }
+            Ok(())
+        }
+        // Whatever the rollback missed, the snapshot puts back
+        Err(e) if interrupt::is_interrupted() => match snapshot.map(UndoSnapshot::restore) {
+            Some(Err(restore_error)) => Err(Error::new(e.code(), format!("{}, and restoring failed: {}", e.message(), restore_error.message()))),
+            _ => Err(e),
+        },
+        Err(e) if snapshot.is_some() => Err(Error::new(e.code(), format!("{} (the state from before is saved in {})", e.message(), undo_file_path().display()))),
+        Err(e) => Err(e),
+    }
+}
+
+//==============================================================================
+// Undo snapshot
+//==============================================================================
+
+// The displays as they were before a batch of changes, saved as a spec before anything changes.
+// If the helper panics partway, the panic hook applies it again. That runs before the stack
+// unwinds, so it works with panic = "abort" too, and dropping the snapshot while unwinding only
+// applies it when another hook has replaced this one. If the process dies outright, the file is
+// still there to `apply` by hand.
+pub struct UndoSnapshot {
+    spec: Spec,
+    retry_policy: RetryPolicy,
+    finished: bool,
+}
+
+thread_local! {
+    // The snapshot of the batch running on this thread, for the panic hook
+    static ACTIVE_SNAPSHOT: RefCell<Option<(Spec, RetryPolicy)>> = const { RefCell::new(None) };
+}
+
+// Installed once, with the first snapshot. The previous hook, which prints the panic message,
+// runs first.
+fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            // Taken, so the unwinding drop doesn't restore a second time
+            let active = ACTIVE_SNAPSHOT.try_with(|active| active.borrow_mut().take()).ok().flatten();
+            if let Some((spec, retry_policy)) = active {
+                error!("Crashed partway through a batch");
+                let _ = restore_snapshot(&spec, retry_policy);
+            }
+        }));
+    });
+}
+
+fn restore_snapshot(spec: &Spec, retry_policy: RetryPolicy) -> Result<()> {
+    info!("Restoring the displays from {}", undo_file_path().display());
+    let result = differences(spec).and_then(|differences| apply_in_order(&differences, retry_policy, false, false));
+    match &result {
+        Ok(()) => state::remove_state_file(UNDO_FILE),
+        Err(e) => error!("Failed to restore the displays, apply {} to try again: {}", undo_file_path().display(), e.message()),
+    }
+    result
+}
+
+pub fn undo_file_path() -> PathBuf {
+    state::state_dir().join(UNDO_FILE)
+}
+
+impl UndoSnapshot {
+    // Displays the batch is going to enable aren't in the current state, they are saved as disabled
+    pub fn save(enabling: &[&str], retry_policy: RetryPolicy) -> Option<Self> {
+        let mut spec = match current_spec() {
+            Ok(spec) => spec,
+            Err(e) => {
+                error!("Failed to snapshot the displays before changing them, there is no undo file: {}", e.message());
+                return None;
+            }
+        };
+        spec.displays.extend(enabling.iter().map(|display| DisplaySpec {
+            display: display.to_string(),
+            enabled: Some(false),
+            primary: None,
+            mode: None,
+            hdr: None,
+            sdr_nits: None,
+            icc_profile: None,
+        }));
+
+        if let Err(e) = state::save_state_file(UNDO_FILE, &spec) {
+            error!("Failed to save {}: {}", undo_file_path().display(), e);
+        }
+        install_panic_hook();
+        ACTIVE_SNAPSHOT.with(|active| *active.borrow_mut() = Some((spec.clone(), retry_policy)));
+        Some(UndoSnapshot { spec, retry_policy, finished: false })
+    }
+
+    // The batch went through, so there is nothing to undo
+    pub fn finish(mut self) {
+        self.finished = true;
+        state::remove_state_file(UNDO_FILE);
+    }
+
+    // Puts back whatever differs from the snapshot. The file is kept if that fails.
+    pub fn restore(mut self) -> Result<()> {
+        self.finished = true;
+        self.apply()
+    }
+
+    fn apply(&self) -> Result<()> {
+        restore_snapshot(&self.spec, self.retry_policy)
+    }
+}
+
+impl Drop for UndoSnapshot {
+    fn drop(&mut self) {
+        let active = ACTIVE_SNAPSHOT.with(|active| active.borrow_mut().take());
+        // A batch that failed normally has already rolled back what it could, or was asked not
+        // to. After a panic the hook has already restored the displays, unless it was replaced.
+        if self.finished || !std::thread::panicking() || active.is_none() {
+            return;
+        }
+
+        error!("Crashed partway through a batch");
+        let _ = self.apply();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::exit_code::ExitCode;
+
+    #[test]
+    fn yaml_specs_read_like_json() {
+        let spec = parse_spec("# Steam Deck\ndisplays:\n  - display: primary\n    mode: {width: 1280, height: 800, refresh_rate: 90}\n    hdr: true  # streams in HDR\n    icc_profile: 'HDR: Steam Deck.icc'\n  - display: \"LG TV SSCR2\"\n    enabled: false\n", "yaml").unwrap();
+        assert_eq!(spec.displays.len(), 2);
+        assert_eq!(spec.displays[0].mode, Some(ModeSpec { width: 1280, height: 800, refresh_rate: 90 }));
+        assert_eq!(spec.displays[0].hdr, Some(true));
+        assert_eq!(spec.displays[0].icc_profile.as_deref(), Some("HDR: Steam Deck.icc"));
+        assert_eq!((spec.displays[1].display.as_str(), spec.displays[1].enabled), ("LG TV SSCR2", Some(false)));
+
+        let error = parse_spec("displays:\n  - display: primary\n    hdr: maybe\n", "yml").unwrap_err();
+        assert!(error.contains("line 3"), "{}", error);
+    }
+
+    #[test]
+    fn toml_specs_read_like_json() {
+        let spec = parse_spec("# Steam Deck\n[[displays]]\ndisplay = \"primary\"\nmode = { width = 1280, height = 800, refresh_rate = 90 }\nhdr = true\n\n[[displays]]\ndisplay = 'LG TV SSCR2'\nenabled = false\n", "toml").unwrap();
+        assert_eq!(spec.displays.len(), 2);
+        assert_eq!(spec.displays[0].mode, Some(ModeSpec { width: 1280, height: 800, refresh_rate: 90 }));
+        assert_eq!(spec.displays[0].hdr, Some(true));
+        assert_eq!((spec.displays[1].display.as_str(), spec.displays[1].enabled), ("LG TV SSCR2", Some(false)));
+        assert!(parse_spec("[[displays]]\ndisplay = \"primary\"\nhdr = \"on\"\n", "toml").unwrap_err().contains("line 3"));
+    }
+
+    #[test]
+    fn verify_lists_each_mismatch() {
+        MockDisplayApi::install(vec![MockDisplay::hdr_tv()]);
+
+        let expect = |mode: &str, hdr, level| Spec { displays: vec![DisplaySpec {
+            display: "primary".to_string(), enabled: None, primary: None, mode: Some(mode.parse().unwrap()),
+            hdr: Some(hdr), sdr_nits: Some(set_sdr_level::level_to_nits(level)), icc_profile: None,
+        }] };
+        // The mock starts at 240 nits, level 40
+        assert!(differences(&expect("3840x2160@120", true, 40)).unwrap().is_empty());
+
+        let mismatches = differences(&expect("3840x2160@60", false, 65)).unwrap();
+        assert_eq!(mismatches.iter().map(|mismatch| mismatch.desired.to_string()).collect::<Vec<_>>(), ["mode 3840x2160 @60Hz", "HDR off", "SDR white 340 nits"]);
+        assert_eq!((mismatches[1].current.as_str(), mismatches[2].current.as_str()), ("on", "240 nits"));
+        assert!("3840x2160".parse::<ModeSpec>().is_err());
+    }
+
+    #[test]
+    fn a_panic_mid_batch_restores_the_snapshot() {
+        state::use_test_state_dir(std::env::temp_dir().join("sunshine-helper-test-panic-mid-batch"));
+        let mut tv = MockDisplay::hdr_tv();
+        tv.hdr_enabled = false;
+        tv.modes = vec![(3840, 2160, 120), (1920, 1080, 60)];
+        let api = MockDisplayApi::install(vec![tv]);
+
+        let result = std::panic::catch_unwind(|| {
+            let _snapshot = UndoSnapshot::save(&[], RetryPolicy::from_args(Some(0), Some(0), 0));
+            assert!(undo_file_path().exists());
+            let tv = displays_info::find_display("primary").unwrap();
+            hdr::set_hdr(&tv, true).unwrap();
+            change_display_mode::change_display_mode(&tv, 1920, 1080, 60, None, false);
+            panic!("crash after changing the mode");
+        });
+
+        assert!(result.is_err());
+        let tv = api.display("\\\\.\\DISPLAY1");
+        assert_eq!(tv.mode, (3840, 2160, 120));
+        assert!(!tv.hdr_enabled);
+    }
+
+    #[test]
+    fn the_panic_hook_restores_without_unwinding() {
+        state::use_test_state_dir(std::env::temp_dir().join("sunshine-helper-test-panic-hook"));
+        let mut tv = MockDisplay::hdr_tv();
+        tv.hdr_enabled = false;
+        let api = MockDisplayApi::install(vec![tv]);
+
+        // A forgotten snapshot is never dropped, as with panic = "abort"
+        let result = std::panic::catch_unwind(|| {
+            std::mem::forget(UndoSnapshot::save(&[], RetryPolicy::from_args(Some(0), Some(0), 0)));
+            hdr::set_hdr(&displays_info::find_display("primary").unwrap(), true).unwrap();
+            panic!("crash after turning HDR on");
+        });
+
+        assert!(result.is_err());
+        assert!(!api.display("\\\\.\\DISPLAY1").hdr_enabled);
+        assert!(!undo_file_path().exists());
+    }
+
+    #[test]
+    fn a_failure_on_one_display_rolls_back_the_others() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.hdr_enabled = false;
+        let mut monitor = MockDisplay::desk_monitor();
+        monitor.hdr_supported = true;
+        let projector = MockDisplay::new(3, "Epson", (1920, 1080, 60));
+        let api = MockDisplayApi::install(vec![tv, monitor, projector]);
+
+        let entry = |display: &str| DisplaySpec { display: display.to_string(), enabled: None, primary: None, mode: None, hdr: Some(true), sdr_nits: Some(320), icc_profile: None };
+        let policy = RetryPolicy::from_args(Some(0), Some(0), 0);
+        let both = differences(&Spec { displays: vec![entry("DISPLAY1"), entry("DISPLAY2")] }).unwrap();
+        apply_differences(&both, policy, true).unwrap();
+        for name in ["\\\\.\\DISPLAY1", "\\\\.\\DISPLAY2"] {
+            assert!(api.display(name).hdr_enabled);
+            assert_eq!(api.display(name).sdr_white_level, 4000);
+        }
+
+        // The projector has no HDR, so the TV's changes are undone as well
+        let spec = Spec { displays: vec![DisplaySpec { hdr: Some(false), sdr_nits: Some(240), ..entry("primary") }, entry("DISPLAY3")] };
+        let error = apply_differences(&differences(&spec).unwrap(), policy, true).unwrap_err();
+        assert_eq!(ExitCode::from_error(&error), ExitCode::Unsupported);
+        assert!(api.display("\\\\.\\DISPLAY1").hdr_enabled);
+        assert_eq!(api.display("\\\\.\\DISPLAY1").sdr_white_level, 4000);
+    }
+}
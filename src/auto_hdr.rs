@@ -0,0 +1,62 @@
+use windows::Win32::System::Registry::HKEY_CURRENT_USER;
+use windows::core::Result;
+use tracing::{info, instrument};
+
+use crate::registry;
+
+// Auto HDR shares its key with the per-app GPU preferences. The global switch lives in
+// DirectXUserGlobalSettings, per-executable overrides in a value named after the exe path.
+// Both hold "Name=Value;" lists that we have to edit without losing the other entries.
+const PREFERENCES_KEY: &str = "Software\\Microsoft\\DirectX\\UserGpuPreferences";
+const GLOBAL_VALUE: &str = "DirectXUserGlobalSettings";
+const AUTO_HDR_SETTING: &str = "AutoHDREnable";
+
+fn parse_settings(data: &str) -> Vec<(String, String)> {
+    data.split(';')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn format_settings(settings: &[(String, String)]) -> String {
+    settings.iter().map(|(name, value)| format!("{}={};", name, value)).collect()
+}
+
+// Newer builds store a bit field (e.g. 2097 / 2096) where only the lowest bit is the switch
+fn is_enabled_value(value: &str) -> bool {
+    value.parse::<u32>().map(|v| v & 1 == 1).unwrap_or(false)
+}
+
+fn toggled_value(current: Option<&str>, enabled: bool) -> String {
+    let bits = current.and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let bits = if enabled { bits | 1 } else { bits & !1 };
+    bits.to_string()
+}
+
+fn value_name(exe: Option<&str>) -> &str {
+    exe.unwrap_or(GLOBAL_VALUE)
+}
+
+// None means Auto HDR was never set, so the global default (off) or, for an exe, the global setting applies
+pub fn get_auto_hdr(exe: Option<&str>) -> Result<Option<bool>> {
+    let data = registry::read_string(HKEY_CURRENT_USER, PREFERENCES_KEY, value_name(exe))?.unwrap_or_default();
+    Ok(parse_settings(&data)
+        .iter()
+        .find(|(name, _)| name == AUTO_HDR_SETTING)
+        .map(|(_, value)| is_enabled_value(value)))
+}
+
+#[instrument(skip_all, fields(exe = %exe.unwrap_or("global"), enabled = enabled))]
+pub fn set_auto_hdr(exe: Option<&str>, enabled: bool) -> Result<()> {
+    let name = value_name(exe);
+    let data = registry::read_string(HKEY_CURRENT_USER, PREFERENCES_KEY, name)?.unwrap_or_default();
+    let mut settings = parse_settings(&data);
+
+    match settings.iter_mut().find(|(setting, _)| setting == AUTO_HDR_SETTING) {
+        Some((_, value)) => *value = toggled_value(Some(value), enabled),
+        None => settings.push((AUTO_HDR_SETTING.to_string(), toggled_value(None, enabled))),
+    }
+
+    info!("Setting Auto HDR {} for {}", if enabled { "on" } else { "off" }, exe.unwrap_or("all games"));
+    registry::write_string(HKEY_CURRENT_USER, PREFERENCES_KEY, name, &format_settings(&settings))
+}
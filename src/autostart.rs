@@ -0,0 +1,193 @@
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use windows::Win32::Foundation::E_FAIL;
+use windows::Win32::System::Registry::HKEY_CURRENT_USER;
+use windows::Win32::System::Threading::CREATE_NO_WINDOW;
+use windows::core::{Error, Result};
+use tracing::info;
+
+use crate::apply;
+use crate::displays_info;
+use crate::elevation::{self, quote_argument};
+use crate::exit_code;
+use crate::portable;
+use crate::registry;
+
+// `autostart install` puts the displays in the desired state at every logon, so a streaming host
+// that rebooted comes back ready without anyone at the console. It runs `apply <spec>` or starts
+// the daemon, from a scheduled task by default or from the current user's Run key. The task takes
+// an elevated prompt to create, but waits a few seconds after logon for the displays to come up
+// and runs with the user's full rights. The Run key needs neither and starts straight away.
+
+pub const TASK_NAME: &str = "sunshine-helper autostart";
+const RUN_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+const RUN_VALUE: &str = "sunshine-helper";
+// schtasks refuses a longer /TR
+const MAX_TASK_COMMAND: usize = 261;
+
+pub enum Target {
+    Apply(PathBuf),
+    Daemon,
+}
+
+pub struct Installed {
+    pub method: &'static str,
+    pub command: String,
+    pub detail: Option<String>,
+}
+
+// The spec is checked now, not at the next logon
+pub fn command_line(target: &Target) -> Result<String> {
+    let exe = std::env::current_exe().map_err(|e| Error::new(E_FAIL, format!("Failed to find the helper's executable: {}", e)))?;
+    let mut command = quote_argument(&exe.to_string_lossy());
+    if portable::requested() {
+        command.push_str(" --portable");
+    }
+    if let Some(adapter) = displays_info::adapter() {
+        command.push_str(&format!(" --adapter {}", quote_argument(&adapter.to_string())));
+    }
+    match target {
+        Target::Apply(spec) => {
+            apply::load_spec_file(spec)?;
+            let spec = std::path::absolute(spec).unwrap_or_else(|_| spec.clone());
+            command.push_str(&format!(" --log --settle apply {}", quote_argument(&spec.to_string_lossy())));
+        }
+        Target::Daemon => command.push_str(" --log daemon --detach"),
+    }
+    Ok(command)
+}
+
+//==============================================================================
+// Installing
+//==============================================================================
+
+// Replaces the Run key entry, if there is one, so the command doesn't run twice at logon
+pub fn install_task(command: &str, delay_secs: u32) -> Result<()> {
+    elevation::require_elevation("Creating the scheduled task")?;
+    if command.len() > MAX_TASK_COMMAND {
+        return Err(exit_code::invalid_input(format!("The command is {} characters long, more than a scheduled task takes ({}). Move the spec or the helper to a shorter path, or use --run-key.",
+            command.len(), MAX_TASK_COMMAND)));
+    }
+    let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(name)) => format!("{}\\{}", domain, name),
+        (_, Ok(name)) => name,
+        _ => return Err(Error::new(E_FAIL, "Failed to find the current user's name")),
+    };
+    // Interactive only, the displays belong to the user's session
+    schtasks(&["/Create", "/F", "/TN", TASK_NAME, "/SC", "ONLOGON", "/RU", &user, "/IT", "/RL", "HIGHEST",
+        "/DELAY", &task_delay(delay_secs), "/TR", command])?;
+    info!("Created scheduled task '{}' running {}", TASK_NAME, command);
+    registry::delete_value(HKEY_CURRENT_USER, RUN_KEY, RUN_VALUE)
+}
+
+pub fn install_run_key(command: &str) -> Result<()> {
+    registry::write_string(HKEY_CURRENT_USER, RUN_KEY, RUN_VALUE, command)?;
+    info!("Added {} to the Run key", command);
+    // Removing the task needs an elevated prompt too, without one a leftover task stays
+    if task_installed() {
+        schtasks(&["/Delete", "/F", "/TN", TASK_NAME])?;
+    }
+    Ok(())
+}
+
+// False if neither was installed
+pub fn remove() -> Result<bool> {
+    let mut removed = false;
+    if task_installed() {
+        schtasks(&["/Delete", "/F", "/TN", TASK_NAME])?;
+        removed = true;
+    }
+    if registry::read_string(HKEY_CURRENT_USER, RUN_KEY, RUN_VALUE)?.is_some() {
+        registry::delete_value(HKEY_CURRENT_USER, RUN_KEY, RUN_VALUE)?;
+        removed = true;
+    }
+    Ok(removed)
+}
+
+pub fn installed() -> Result<Vec<Installed>> {
+    let mut installed = Vec::new();
+    // Verbose CSV without a header: the task's status is the fourth column, its last result the seventh and its command the ninth
+    if let Ok(output) = schtasks(&["/Query", "/TN", TASK_NAME, "/FO", "CSV", "/NH", "/V"]) {
+        if let Some(fields) = output.lines().find(|line| !line.trim().is_empty()).map(csv_fields).filter(|fields| fields.len() > 8) {
+            installed.push(Installed {
+                method: "scheduled task",
+                command: fields[8].clone(),
+                detail: Some(format!("{}, last result {}", fields[3], fields[6])),
+            });
+        }
+    }
+    if let Some(command) = registry::read_string(HKEY_CURRENT_USER, RUN_KEY, RUN_VALUE)? {
+        installed.push(Installed { method: "Run key", command, detail: None });
+    }
+    Ok(installed)
+}
+
+//==============================================================================
+// schtasks
+//==============================================================================
+
+fn task_installed() -> bool {
+    schtasks(&["/Query", "/TN", TASK_NAME]).is_ok()
+}
+
+// Its own message on failure, like "Access is denied."
+fn schtasks(args: &[&str]) -> Result<String> {
+    let output = Command::new(Path::new("schtasks.exe"))
+        .args(args)
+        .creation_flags(CREATE_NO_WINDOW.0)
+        .output()
+        .map_err(|e| Error::new(E_FAIL, format!("Failed to run schtasks: {}", e)))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if output.status.success() {
+        return Ok(stdout);
+    }
+    let message = String::from_utf8_lossy(&output.stderr).trim().trim_start_matches("ERROR:").trim().to_string();
+    if message.contains("Access is denied") {
+        return Err(exit_code::needs_elevation(format!("schtasks: {}", message)));
+    }
+    Err(Error::new(E_FAIL, format!("schtasks: {}", message)))
+}
+
+// mmmm:ss, as /DELAY takes it
+fn task_delay(secs: u32) -> String {
+    format!("{:04}:{:02}", secs / 60, secs % 60)
+}
+
+// One line of schtasks' CSV, where every field is quoted and quotes inside are doubled
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schtasks_formats_are_read_and_written() {
+        assert_eq!(task_delay(15), "0000:15");
+        assert_eq!(task_delay(75), "0001:15");
+
+        let line = r#""HOST","\sunshine-helper autostart","N/A","Ready","Interactive only","10/17/2026 9:02:11 AM","0","user","""C:\Tools\sunshine_helper.exe"" --log --settle apply C:\specs\tv.json""#;
+        let fields = csv_fields(line);
+        assert_eq!(fields.len(), 9);
+        assert_eq!((fields[3].as_str(), fields[6].as_str()), ("Ready", "0"));
+        assert_eq!(fields[8], r#""C:\Tools\sunshine_helper.exe" --log --settle apply C:\specs\tv.json"#);
+    }
+}
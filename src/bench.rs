@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, error, instrument};
+
+use crate::change_display_mode;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code;
+use crate::hdr;
+use crate::set_sdr_level;
+
+// `test bench` times the calls prep commands are made of on this machine, to budget Sunshine's
+// prep-cmd timeout. Everything runs on the primary display and ends in the state it started in:
+// the mode and SDR white level are set to their current values, and HDR is toggled in pairs.
+// Times are wall clock and include the settle wait after a mode change.
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+pub struct Measurement {
+    pub operation: &'static str,
+    pub samples: Vec<Duration>,
+    pub skipped: Option<String>, // why there are no samples
+}
+
+impl Measurement {
+    fn new(operation: &'static str) -> Self {
+        Measurement { operation, samples: Vec::new(), skipped: None }
+    }
+
+    fn skipped(operation: &'static str, reason: &str) -> Self {
+        info!("Skipping {}: {}", operation, reason);
+        Measurement { operation, samples: Vec::new(), skipped: Some(reason.to_string()) }
+    }
+
+    fn time<T>(&mut self, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = operation();
+        self.samples.push(start.elapsed());
+        result
+    }
+
+    // Nearest rank, so every reported time is one that was actually measured
+    pub fn percentile(&self, percent: u32) -> Option<Duration> {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let rank = (percent as usize * sorted.len()).div_ceil(100).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+}
+
+//==============================================================================
+// Benchmark
+//==============================================================================
+
+#[instrument(name = "bench")]
+pub fn run(runs: u32) -> Result<Vec<Measurement>> {
+    let primary = displays_info::find_display("primary")
+        .ok_or_else(|| exit_code::display_not_found("No primary display found"))?;
+
+    let mut enumerate = Measurement::new("Enumerate displays");
+    let mut mode = Measurement::new("Mode switch");
+    for _ in 0..runs {
+        // Without the cache, which would make every run after the first free
+        enumerate.time(|| {
+            displays_info::invalidate_topology();
+            Ok(displays_info::enumerate_displays())
+        })?;
+        mode.time(|| reapply_mode(&primary))?;
+    }
+
+    let color_info = hdr::get_advanced_color_info(&primary)?;
+    let sdr = if color_info.enabled {
+        let level = set_sdr_level::get_display_sdr_white(&primary)?;
+        let mut sdr = Measurement::new("SDR white level");
+        for _ in 0..runs {
+            sdr.time(|| set_sdr_level::set_display_sdr_white(&primary, level))?;
+        }
+        sdr
+    } else {
+        Measurement::skipped("SDR white level", "HDR is off")
+    };
+
+    let hdr = if !color_info.supported {
+        Measurement::skipped("HDR toggle", "HDR is not supported")
+    } else if color_info.force_disabled {
+        Measurement::skipped("HDR toggle", "HDR is blocked")
+    } else {
+        toggle_hdr(&primary, color_info.enabled, runs)?
+    };
+
+    Ok(vec![enumerate, mode, hdr, sdr])
+}
+
+// The current mode again, without the supported mode check (a current custom mode may not be listed)
+fn reapply_mode(display: &DisplayDevice) -> Result<()> {
+    let (width, height) = display.current_resolution;
+    if change_display_mode::change_display_mode(display, width, height, display.current_refresh_rate, None, true) {
+        Ok(())
+    } else {
+        Err(Error::new(E_FAIL, format!("Failed to re-apply {}x{} @{}Hz on {}", width, height, display.current_refresh_rate, display.device_name)))
+    }
+}
+
+// Both directions count, they usually take about as long. HDR is put back if a toggle fails.
+fn toggle_hdr(device: &DisplayDevice, enabled: bool, runs: u32) -> Result<Measurement> {
+    let mut toggle = Measurement::new("HDR toggle");
+    for _ in 0..runs {
+        for state in [!enabled, enabled] {
+            if let Err(e) = toggle.time(|| hdr::set_hdr(device, state)) {
+                if state != enabled {
+                    if let Err(restore_error) = hdr::set_hdr(device, enabled) {
+                        error!("Failed to turn HDR back {} for {}: {}", if enabled { "on" } else { "off" }, device.device_name, restore_error.message());
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(toggle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let mut measurement = Measurement::new("test");
+        measurement.samples = [5, 1, 4, 2, 3, 10, 6, 9, 7, 8].iter().map(|&ms| Duration::from_millis(ms)).collect();
+        assert_eq!(measurement.percentile(50), Some(Duration::from_millis(5)));
+        assert_eq!(measurement.percentile(95), Some(Duration::from_millis(10)));
+        assert_eq!(measurement.percentile(0), Some(Duration::from_millis(1)));
+        assert_eq!(measurement.max(), Some(Duration::from_millis(10)));
+        assert_eq!(Measurement::new("empty").percentile(50), None);
+    }
+
+    #[test]
+    fn every_operation_runs_and_hdr_ends_as_it_started() {
+        let api = MockDisplayApi::install(vec![MockDisplay::hdr_tv()]);
+
+        let measurements = run(3).unwrap();
+        let counts: Vec<(&str, usize)> = measurements.iter().map(|m| (m.operation, m.samples.len())).collect();
+        assert_eq!(counts, [("Enumerate displays", 3), ("Mode switch", 3), ("HDR toggle", 6), ("SDR white level", 3)]);
+        assert!(api.display("\\\\.\\DISPLAY1").hdr_enabled);
+    }
+
+    #[test]
+    fn hdr_and_sdr_are_skipped_without_hdr() {
+        let mut monitor = MockDisplay::new(1, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        MockDisplayApi::install(vec![monitor]);
+
+        let measurements = run(2).unwrap();
+        assert_eq!(measurements[2].skipped.as_deref(), Some("HDR is not supported"));
+        assert_eq!(measurements[3].skipped.as_deref(), Some("HDR is off"));
+    }
+}
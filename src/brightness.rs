@@ -0,0 +1,45 @@
+use windows::core::Result;
+use tracing::{info, instrument};
+
+use crate::displays_info::DisplayDevice;
+use crate::{ddc_ci, wmi_brightness};
+
+pub struct Brightness {
+    pub minimum: u32,
+    pub current: u32,
+    pub maximum: u32,
+}
+
+impl Brightness {
+    // Monitors report their own range, usually but not always 0-100
+    pub fn percent(&self) -> u32 {
+        if self.maximum <= self.minimum {
+            return 0;
+        }
+        ((self.current.saturating_sub(self.minimum)) as f64 * 100.0 / (self.maximum - self.minimum) as f64).round() as u32
+    }
+}
+
+//==============================================================================
+// Backend selection
+//==============================================================================
+
+// Internal panels go through WMI, everything else through DDC/CI
+pub fn get_display_brightness(device: &DisplayDevice) -> Result<Brightness> {
+    if device.is_internal() {
+        info!("{} is an internal panel, reading brightness through WMI", device.device_name);
+        wmi_brightness::get_brightness(device)
+    } else {
+        ddc_ci::get_brightness(device)
+    }
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, percent = percent))]
+pub fn set_display_brightness(device: &DisplayDevice, percent: u32) -> Result<()> {
+    if device.is_internal() {
+        info!("{} is an internal panel, setting brightness through WMI", device.device_name);
+        wmi_brightness::set_brightness(device, percent)
+    } else {
+        ddc_ci::set_brightness(device, percent)
+    }
+}
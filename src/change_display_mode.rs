@@ -1,8 +1,6 @@
-use log::{info, error};
+use tracing::{debug, info, warn, error, instrument};
 use windows::Win32::Graphics::Gdi::{
     DEVMODEW,
-    ChangeDisplaySettingsExW,
-    CDS_UPDATEREGISTRY,
     DISP_CHANGE_SUCCESSFUL,
     DISP_CHANGE_BADMODE,
     DISP_CHANGE_FAILED,
@@ -10,29 +8,211 @@ use windows::Win32::Graphics::Gdi::{
     DM_PELSWIDTH,
     DM_PELSHEIGHT,
     DM_DISPLAYFREQUENCY,
+    DM_DISPLAYFLAGS,
+    DM_DISPLAYORIENTATION,
+    DEVMODE_DISPLAY_ORIENTATION,
+    DMDO_DEFAULT,
+    DMDO_90,
+    DMDO_180,
+    DMDO_270,
 };
-use windows::core::PCWSTR;
-use std::{thread, time::Duration};
-use std::ffi::{OsStr};
-use std::os::windows::ffi::{OsStrExt};
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
-use crate::displays_info::{self};
+use crate::display_api;
+use crate::displays_info::{self, DisplayDevice, DisplayMode, FixedOutput};
+use crate::timings;
 
-pub fn change_primary_display_mode(width: u32, height: u32, refresh_rate: u32, unsafe_mode: bool) -> bool {
-    info!("Attempting to change primary display mode to {}x{} @{}Hz (unsafe: {})",
-        width, height, refresh_rate, unsafe_mode);
+// Desktop rotation in degrees clockwise, as accepted by --rotate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Landscape,
+    Portrait,
+    LandscapeFlipped,
+    PortraitFlipped,
+}
 
-    let Some((primary, supported_modes)) = displays_info::get_primary_display_info() else {
+impl Rotation {
+    fn orientation(&self) -> DEVMODE_DISPLAY_ORIENTATION {
+        match self {
+            Rotation::Landscape => DMDO_DEFAULT,
+            Rotation::Portrait => DMDO_90,
+            Rotation::LandscapeFlipped => DMDO_180,
+            Rotation::PortraitFlipped => DMDO_270,
+        }
+    }
+
+    // The rotation of a DMDO_* value, as DisplayDevice::orientation holds it
+    pub fn from_orientation(orientation: u32) -> Option<Self> {
+        match DEVMODE_DISPLAY_ORIENTATION(orientation) {
+            DMDO_DEFAULT => Some(Rotation::Landscape),
+            DMDO_90 => Some(Rotation::Portrait),
+            DMDO_180 => Some(Rotation::LandscapeFlipped),
+            DMDO_270 => Some(Rotation::PortraitFlipped),
+            _ => None,
+        }
+    }
+
+    fn is_portrait(&self) -> bool {
+        matches!(self, Rotation::Portrait | Rotation::PortraitFlipped)
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Rotation::Landscape),
+            "90" => Ok(Rotation::Portrait),
+            "180" => Ok(Rotation::LandscapeFlipped),
+            "270" => Ok(Rotation::PortraitFlipped),
+            _ => Err("Rotation must be 0, 90, 180 or 270".to_string()),
+        }
+    }
+}
+
+// Width and height are the desktop size after rotation, e.g. 1600x2560 for a portrait tablet
+pub fn change_primary_display_mode(width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>, unsafe_mode: bool) -> bool {
+    info!("Attempting to change primary display mode to {}x{} @{}Hz (rotation: {:?}, unsafe: {})",
+        width, height, refresh_rate, rotation, unsafe_mode);
+
+    let Some(primary) = displays_info::find_display("primary") else {
         error!("Failed to get primary display information");
         return false;
     };
 
-    // Early return if mode validation is required and the mode isn't supported
-    if !unsafe_mode && !supported_modes.iter().any(|mode|
-        mode.width == width &&
-            mode.height == height &&
+    change_display_mode(&primary, width, height, refresh_rate, rotation, unsafe_mode)
+}
+
+// Whether the display already runs this mode, so a repeated prep command doesn't blank the screen for nothing
+pub fn is_current_mode(display: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>) -> bool {
+    display.current_resolution == (width, height)
+        && display.current_refresh_rate == refresh_rate
+        && rotation.is_none_or(|rotation| display.orientation == rotation.orientation().0)
+}
+
+// The display's modes at this size and refresh rate, progressive first, interlaced or scaled
+// variants included. Width and height are after rotation, as for change_display_mode.
+fn matching_modes(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>) -> Vec<DisplayMode> {
+    let supported_modes = device.get_supported_modes();
+    info!("Found {} supported modes for {}", supported_modes.len(), device.device_name);
+
+    // Modes are reported in the display's current orientation. Compare in landscape terms when rotating.
+    let currently_portrait = device.orientation == DMDO_90.0 || device.orientation == DMDO_270.0;
+    let (check_width, check_height) = match rotation {
+        Some(rotation) if rotation.is_portrait() != currently_portrait => (height, width),
+        _ => (width, height),
+    };
+
+    supported_modes.into_iter().filter(|mode|
+        mode.width == check_width &&
+            mode.height == check_height &&
             mode.refresh_rate == refresh_rate
-    ) {
+    ).collect()
+}
+
+// Whether the display reports this mode, progressive or interlaced
+pub fn is_supported_mode(display: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>) -> bool {
+    !matching_modes(display, width, height, refresh_rate, rotation).is_empty()
+}
+
+// The refresh rates the display offers at a size in its current orientation, lowest first, for
+// telling set-refresh and set-resolution users what they could ask for instead
+pub fn refresh_rates_at(display: &DisplayDevice, width: u32, height: u32) -> Vec<u32> {
+    let mut rates: Vec<u32> = display.get_supported_modes().into_iter()
+        .filter(|mode| (mode.width, mode.height) == (width, height))
+        .map(|mode| mode.refresh_rate)
+        .collect();
+    rates.sort_unstable();
+    rates.dedup();
+    rates
+}
+
+// Whether the display reports a progressive variant of this mode, for cpdm --progressive
+pub fn is_progressive_mode(display: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>) -> bool {
+    matching_modes(display, width, height, refresh_rate, rotation).iter().any(|mode| !mode.interlaced)
+}
+
+// A Moonlight client's resolution for cpdm --fit, as <width>x<height> with an optional @<hz>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FitTarget {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: Option<u32>,
+}
+
+impl FromStr for FitTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' isn't a resolution like 1280x800 or 1280x800@90", s);
+        let (resolution, refresh_rate) = match s.split_once('@') {
+            Some((resolution, refresh_rate)) => (resolution, Some(refresh_rate.trim().parse().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let (width, height) = resolution.split_once('x').ok_or_else(invalid)?;
+        let (width, height): (u32, u32) = (width.trim().parse().map_err(|_| invalid())?, height.trim().parse().map_err(|_| invalid())?);
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(FitTarget { width, height, refresh_rate })
+    }
+}
+
+// Why --fit picked its mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitReason {
+    Exact,
+    SameAspect,
+    // The share of the host picture the client shows without bars, below 1
+    Letterbox(f64),
+}
+
+// The mode that best fits a client: its exact resolution, else the highest resolution with the
+// same aspect ratio, else the one that leaves the least of the picture to letterboxing or
+// pillarboxing (the larger on a tie). At that resolution the client's refresh rate, else the
+// closest one. Only progressive, unscaled modes are considered unless there are none.
+pub fn fit_mode(modes: &[DisplayMode], target: FitTarget) -> Option<((u32, u32, u32), FitReason)> {
+    let plain = |mode: &&DisplayMode| !mode.interlaced && mode.fixed_output == FixedOutput::Default;
+    let mut candidates: Vec<&DisplayMode> = modes.iter().filter(plain).collect();
+    if candidates.is_empty() {
+        candidates = modes.iter().collect();
+    }
+
+    let aspect = (target.width, target.height);
+    let target_ratio = target.width as f64 / target.height as f64;
+    let coverage = |mode: &DisplayMode| {
+        let ratio = mode.width as f64 / mode.height as f64;
+        (ratio / target_ratio).min(target_ratio / ratio)
+    };
+    let area = |mode: &DisplayMode| mode.width as u64 * mode.height as u64;
+
+    let (chosen, reason) = if let Some(mode) = candidates.iter().find(|mode| (mode.width, mode.height) == aspect) {
+        (*mode, FitReason::Exact)
+    } else if let Some(mode) = candidates.iter().filter(|mode| displays_info::has_aspect(mode.width, mode.height, aspect)).max_by_key(|mode| area(mode)) {
+        (*mode, FitReason::SameAspect)
+    } else {
+        let mode = candidates.iter().max_by(|a, b| coverage(a).total_cmp(&coverage(b)).then(area(a).cmp(&area(b))))?;
+        (*mode, FitReason::Letterbox(coverage(mode)))
+    };
+
+    let refresh_rates = candidates.iter().filter(|mode| (mode.width, mode.height) == (chosen.width, chosen.height)).map(|mode| mode.refresh_rate);
+    let refresh_rate = match target.refresh_rate {
+        // The higher of two equally close rates
+        Some(wanted) => refresh_rates.min_by_key(|&rate| (rate.abs_diff(wanted), std::cmp::Reverse(rate)))?,
+        None => refresh_rates.max()?,
+    };
+    Some(((chosen.width, chosen.height, refresh_rate), reason))
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, width = width, height = height, refresh_rate = refresh_rate, rotation = ?rotation))]
+pub fn change_display_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>, unsafe_mode: bool) -> bool {
+    // Early return if mode validation is required and the mode isn't supported
+    if !unsafe_mode && !is_supported_mode(device, width, height, refresh_rate, rotation) {
         error!("Requested mode {}x{} @{}Hz is not reported by the display as supported.",
             width, height, refresh_rate);
         return false;
@@ -46,40 +226,231 @@ pub fn change_primary_display_mode(width: u32, height: u32, refresh_rate: u32, u
     dev_mode.dmDisplayFrequency = refresh_rate;
     dev_mode.dmFields = DM_PELSWIDTH | DM_PELSHEIGHT | DM_DISPLAYFREQUENCY;
 
-    // Convert device name to wide string and keep it in scope
-    let device_name: Vec<u16> = OsStr::new(&primary.device_name)
-        .encode_wide()
-        .chain(Some(0))
-        .collect();
-    let pcwstr = PCWSTR::from_raw(device_name.as_ptr());
-
-    // Attempt to change the display settings
-    unsafe {
-        let result = ChangeDisplaySettingsExW(
-            pcwstr,
-            Some(&dev_mode),
-            None,
-            CDS_UPDATEREGISTRY,
-            None,
-        );
-
-        match result {
-            DISP_CHANGE_SUCCESSFUL => {
-                thread::sleep(Duration::from_millis(3000));
-                info!("Successfully changed display mode to {}x{} @{}Hz",
-                    width, height, refresh_rate);
-                true
-            }
-            error_code => {
-                let error_msg = match error_code {
-                    DISP_CHANGE_BADMODE => "The graphics mode is not supported",
-                    DISP_CHANGE_FAILED => "The display driver failed the specified graphics mode",
-                    DISP_CHANGE_RESTART => "The computer must be restarted for the graphics mode to work",
-                    _ => "Unknown error occurred"
-                };
-                error!("Failed to change display mode: {}. Error code: {}", error_msg, error_code.0);
-                false
+    if let Some(rotation) = rotation {
+        dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation = rotation.orientation();
+        dev_mode.dmFields |= DM_DISPLAYORIENTATION;
+    }
+
+    // Without display flags the driver may pick an interlaced variant of the same size and
+    // refresh rate, so progressive is asked for whenever there is one. Custom modes are progressive.
+    if unsafe_mode || is_progressive_mode(device, width, height, refresh_rate, rotation) {
+        dev_mode.Anonymous2.dmDisplayFlags = 0;
+        dev_mode.dmFields |= DM_DISPLAYFLAGS;
+    }
+
+    if !apply_display_settings(&device.device_name, &dev_mode) {
+        return false;
+    }
+    // A dry run changed nothing, so there's nothing to check
+    if !display_api::is_dry_run() {
+        timings::time("verification", &device.device_name, || verify_display_mode(device, width, height, refresh_rate, rotation));
+    }
+    true
+}
+
+// Reads the mode back after a change. Drivers have reported success and kept the old mode, which
+// is worth a warning, but not a failure the change gets retried for.
+fn verify_display_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32, rotation: Option<Rotation>) {
+    let current = displays_info::enumerate_displays().into_iter().find(|current| current.device_name == device.device_name);
+    match current {
+        Some(current) if is_current_mode(&current, width, height, refresh_rate, rotation) => {
+            debug!("{} runs {}x{} @{}Hz", device.device_name, width, height, refresh_rate);
+        }
+        Some(current) => warn!("{} reported success but runs {}x{} @{}Hz instead of {}x{} @{}Hz", device.device_name,
+            current.current_resolution.0, current.current_resolution.1, current.current_refresh_rate, width, height, refresh_rate),
+        None => warn!("{} is gone after changing its mode", device.device_name),
+    }
+}
+
+// Apply a DEVMODE to a display by device name, waiting for the display to settle on success
+pub fn apply_display_settings(device_name: &str, dev_mode: &DEVMODEW) -> bool {
+    debug!("ChangeDisplaySettingsExW({}) with {}x{} @{}Hz, fields {:#x}",
+        device_name, dev_mode.dmPelsWidth, dev_mode.dmPelsHeight, dev_mode.dmDisplayFrequency, dev_mode.dmFields.0);
+
+    let result = display_api::api().change_display_settings(device_name, dev_mode);
+    displays_info::invalidate_topology();
+    match result {
+        DISP_CHANGE_SUCCESSFUL => {
+            info!("Successfully changed display mode of {} to {}x{} @{}Hz",
+                device_name, dev_mode.dmPelsWidth, dev_mode.dmPelsHeight, dev_mode.dmDisplayFrequency);
+            true
+        }
+        error_code => {
+            let error_msg = match error_code {
+                DISP_CHANGE_BADMODE => "The graphics mode is not supported",
+                DISP_CHANGE_FAILED => "The display driver failed the specified graphics mode",
+                DISP_CHANGE_RESTART => "The computer must be restarted for the graphics mode to work",
+                _ => "Unknown error occurred"
+            };
+            error!("Failed to change display mode: {}. Error code: {}", error_msg, error_code.0);
+            false
+        }
+    }
+}
+
+// A forced mode (cpdm --force) the display can't show leaves it blank, with no way to see the
+// console to undo it. Like Windows' "Keep these display settings?", it's undone after a while
+// unless Enter is pressed on the console. Without a console nobody can confirm, so it's undone too.
+pub fn keep_or_revert(previous: &DisplayDevice, seconds: u32) -> bool {
+    let rotation = Rotation::from_orientation(previous.orientation);
+    let (width, height) = previous.current_resolution;
+    if !std::io::stdin().is_terminal() {
+        warn!("No console to confirm the forced mode on, going back to {}x{} @{}Hz in {} s", width, height, previous.current_refresh_rate, seconds);
+    } else {
+        eprintln!("Press Enter within {} seconds to keep this mode, otherwise {} goes back to {}x{} @{}Hz", seconds, previous.device_name, width, height, previous.current_refresh_rate);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    if std::io::stdin().is_terminal() {
+        // Left blocked on stdin if nobody answers, the process ends soon after anyway
+        thread::spawn(move || {
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line).is_ok_and(|read| read > 0) {
+                let _ = sender.send(());
             }
+        });
+    }
+    if receiver.recv_timeout(Duration::from_secs(seconds.into())).is_ok() {
+        info!("Keeping the forced mode on {}", previous.device_name);
+        return true;
+    }
+
+    warn!("The forced mode wasn't confirmed, going back to {}x{} @{}Hz on {}", width, height, previous.current_refresh_rate, previous.device_name);
+    // The old mode was running a moment ago, whether or not the display reports it
+    let current = displays_info::enumerate_displays().into_iter().find(|display| display.device_name == previous.device_name);
+    if !current.is_some_and(|current| change_display_mode(&current, width, height, previous.current_refresh_rate, rotation, true)) {
+        error!("Failed to put {} back to {}x{} @{}Hz", previous.device_name, width, height, previous.current_refresh_rate);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn tablet() -> MockDisplay {
+        let mut display = MockDisplay::new(1, "Tablet", (2560, 1600, 60));
+        display.primary = true;
+        display.modes = vec![(2560, 1600, 60), (2560, 1600, 120), (1920, 1200, 60)];
+        display
+    }
+
+    fn primary() -> DisplayDevice {
+        displays_info::find_display("primary").unwrap()
+    }
+
+    #[test]
+    fn supported_mode_needs_matching_refresh_rate() {
+        MockDisplayApi::install(vec![tablet()]);
+        assert!(is_supported_mode(&primary(), 2560, 1600, 120, None));
+        assert!(!is_supported_mode(&primary(), 2560, 1600, 90, None));
+        assert!(!is_supported_mode(&primary(), 1600, 2560, 60, None));
+    }
+
+    #[test]
+    fn portrait_modes_are_checked_against_landscape_modes() {
+        MockDisplayApi::install(vec![tablet()]);
+        assert!(is_supported_mode(&primary(), 1600, 2560, 60, Some(Rotation::Portrait)));
+        assert!(is_supported_mode(&primary(), 1200, 1920, 60, Some(Rotation::PortraitFlipped)));
+        assert!(is_supported_mode(&primary(), 2560, 1600, 60, Some(Rotation::LandscapeFlipped)));
+    }
+
+    #[test]
+    fn orientation_gives_the_rotation_back() {
+        for rotation in [Rotation::Landscape, Rotation::Portrait, Rotation::LandscapeFlipped, Rotation::PortraitFlipped] {
+            assert_eq!(Rotation::from_orientation(rotation.orientation().0), Some(rotation));
         }
+        assert_eq!(Rotation::from_orientation(4), None);
+    }
+
+    #[test]
+    fn refresh_rates_are_listed_per_size() {
+        MockDisplayApi::install(vec![tablet()]);
+        assert_eq!(refresh_rates_at(&primary(), 2560, 1600), [60, 120]);
+        assert_eq!(refresh_rates_at(&primary(), 1920, 1200), [60]);
+        assert!(refresh_rates_at(&primary(), 1280, 800).is_empty());
+    }
+
+    #[test]
+    fn current_mode_includes_rotation_only_when_given() {
+        MockDisplayApi::install(vec![tablet()]);
+        let display = primary();
+        assert!(is_current_mode(&display, 2560, 1600, 60, None));
+        assert!(is_current_mode(&display, 2560, 1600, 60, Some(Rotation::Landscape)));
+        assert!(!is_current_mode(&display, 2560, 1600, 60, Some(Rotation::Portrait)));
+        assert!(!is_current_mode(&display, 2560, 1600, 120, None));
+    }
+
+    #[test]
+    fn fit_prefers_exact_then_aspect_then_least_letterboxing() {
+        let mode = |width, height, refresh_rate| DisplayMode { width, height, refresh_rate, interlaced: false, fixed_output: FixedOutput::Default };
+        let modes = [mode(3840, 2160, 60), mode(3840, 2160, 120), mode(2560, 1600, 60), mode(1920, 1200, 60), mode(1280, 800, 60), mode(1280, 800, 90)];
+        let fit = |target: &str| fit_mode(&modes, target.parse().unwrap());
+
+        assert_eq!(fit("1280x800"), Some(((1280, 800, 90), FitReason::Exact)));
+        assert_eq!(fit("1280x800@60"), Some(((1280, 800, 60), FitReason::Exact)));
+        assert_eq!(fit("1440x900@144"), Some(((2560, 1600, 60), FitReason::SameAspect)));
+        assert_eq!(fit("2560x1440@100"), Some(((3840, 2160, 120), FitReason::SameAspect)));
+        let Some(((width, height, _), FitReason::Letterbox(coverage))) = fit("2400x1080") else {
+            panic!("a 20:9 phone has no matching mode");
+        };
+        assert_eq!((width, height), (3840, 2160));
+        assert!((coverage - 0.8).abs() < 0.001);
+
+        assert!("1280x800@".parse::<FitTarget>().is_err());
+        assert!("1280:800".parse::<FitTarget>().is_err());
+        assert!(fit_mode(&[], "1280x800".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn progressive_is_asked_for_when_offered() {
+        let mut tv = MockDisplay::new(1, "Old TV", (1280, 720, 60));
+        tv.primary = true;
+        tv.modes = vec![(1280, 720, 60), (1920, 1080, 60)];
+        tv.interlaced_modes = vec![(1920, 1080, 60), (1920, 1080, 50)];
+        let api = MockDisplayApi::install(vec![tv]);
+
+        assert!(is_progressive_mode(&primary(), 1920, 1080, 60, None));
+        assert!(is_supported_mode(&primary(), 1920, 1080, 50, None));
+        assert!(!is_progressive_mode(&primary(), 1920, 1080, 50, None));
+
+        assert!(change_display_mode(&primary(), 1920, 1080, 60, None, false));
+        assert!(change_display_mode(&primary(), 1920, 1080, 50, None, false));
+        assert_eq!(api.calls(), [
+            "change_display_settings \\\\.\\DISPLAY1 1920x1080@60",
+            "change_display_settings \\\\.\\DISPLAY1 1920x1080@50 interlaced",
+        ]);
+    }
+
+    #[test]
+    fn unsupported_mode_is_not_attempted() {
+        let api = MockDisplayApi::install(vec![tablet()]);
+        assert!(!change_display_mode(&primary(), 3840, 2160, 60, None, false));
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn unsafe_mode_skips_the_check() {
+        let api = MockDisplayApi::install(vec![tablet()]);
+        // The driver still refuses it, but the change is attempted
+        assert!(!change_display_mode(&primary(), 3840, 2160, 60, None, true));
+        assert_eq!(api.calls(), ["change_display_settings \\\\.\\DISPLAY1 3840x2160@60"]);
+    }
+
+    #[test]
+    fn mode_change_with_rotation() {
+        let api = MockDisplayApi::install(vec![tablet()]);
+        assert!(change_display_mode(&primary(), 1600, 2560, 60, Some(Rotation::Portrait), false));
+        let display = api.display("\\\\.\\DISPLAY1");
+        assert_eq!(display.mode, (1600, 2560, 60));
+        assert_eq!(display.orientation, DMDO_90.0);
+    }
+
+    #[test]
+    fn rotation_parsing() {
+        assert_eq!(Rotation::from_str("90"), Ok(Rotation::Portrait));
+        assert_eq!(Rotation::from_str("270"), Ok(Rotation::PortraitFlipped));
+        assert!(Rotation::from_str("45").is_err());
     }
-}
\ No newline at end of file
+}
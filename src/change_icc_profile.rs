@@ -1,122 +1,277 @@
-use windows::Win32::Graphics::Gdi::{CreateDCW, DeleteDC};
-use windows::Win32::Foundation::LPARAM;
-use windows::core::{PCWSTR, Result};
-use log::{info, error};
-use std::path::PathBuf;
-
-use crate::displays_info::{DisplayDevice, get_primary_display_info};
-
-use windows::Win32::UI::ColorSystem::{
-    ColorProfileSetDisplayDefaultAssociation,
-    EnumICMProfilesW,
-    WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
-    CPT_ICC,
-    CPST_RGB_WORKING_SPACE
-};
+use windows::core::Result;
+use tracing::{info, warn, error, instrument};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::display_api;
+use crate::displays_info::{self, enumerate_displays, find_display, DisplayDevice};
+use crate::exit_code;
+use crate::icc_file;
+use crate::state;
+
+const ICC_RESTORE_FILE: &str = "icc_restore.json";
 
 pub struct IccProfile {
     pub name: String,
     pub path: PathBuf,
 }
 
-fn get_display_icc_profiles(display: &DisplayDevice) -> Vec<IccProfile> {
-    info!("Retrieving ICC profiles for display: {} ({})", display.device_name, display.device_string);
-    let mut profiles = Vec::new();
-
-    unsafe {
-        let dc = CreateDCW(
-            PCWSTR::from_raw(display.device_name.encode_utf16().chain(std::iter::once(0)).collect::<Vec<u16>>().as_ptr()),
-            PCWSTR::null(),
-            PCWSTR::null(),
-            None,
-        );
-
-        if dc.is_invalid() {
-            error!("Failed to create DC for display {}", display.device_name);
-            return profiles;
-        }
-
-        extern "system" fn enum_profiles_callback(profile_name: PCWSTR, param: LPARAM) -> i32 {
-            unsafe {
-                let profiles = &mut *(param.0 as *mut Vec<IccProfile>);
-                let path_str = profile_name.to_string().unwrap_or_default();
-                let path = PathBuf::from(&path_str);
-                let name = path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown")
-                    .to_string();
-
-                info!("Found ICC profile - Name: '{}', Path: '{}'", name, path.display());
-                profiles.push(IccProfile { name, path });
-                1 // Return 1 for success
-            }
-        }
+impl IccProfile {
+    // Written by the Windows HDR Calibration app, see set_display_hdr_calibration_profile
+    pub fn is_hdr_calibration(&self) -> bool {
+        icc_file::is_hdr_calibration_file(&self.path)
+    }
+}
 
-        let result = EnumICMProfilesW(
-            dc,
-            Some(enum_profiles_callback),
-            LPARAM(&mut profiles as *mut _ as isize),
-        );
+pub fn get_display_icc_profiles(device: &DisplayDevice) -> Vec<IccProfile> {
+    info!("Retrieving ICC profiles for display: {} ({})", device.device_name, device.device_string);
+    let mut profiles = Vec::new();
 
-        let _ = DeleteDC(dc);
+    for path in display_api::api().icc_profiles(&device.device_name) {
+        let name = path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
 
-        if result == -1 {
-            info!("No ICC profiles found for display {}", display.device_name);
-        } else if result == 0 {
-            error!("Enumeration of ICC profiles was interrupted");
-        }
+        info!("Found ICC profile - Name: '{}', Path: '{}'", name, path.display());
+        profiles.push(IccProfile { name, path });
     }
 
-    info!("Retrieved {} ICC profile(s) for display {}", profiles.len(), display.device_name);
+    if profiles.is_empty() {
+        info!("No ICC profiles found for display {}", device.device_name);
+    }
+    info!("Retrieved {} ICC profile(s) for display {}", profiles.len(), device.device_name);
     profiles
 }
 
-// Set a display's default ICC profile
-
+// A name that isn't associated with the display is either a typo or a profile that needs
+// associating first, and a file that isn't a valid profile would only fail inside the color
+// system. Both get a specific error before anything is associated.
+fn profile_not_associated(display: &DisplayDevice, profile_name: &str) -> windows::core::Error {
+    // The mock's profiles aren't real files
+    if display_api::is_mock() || !icc_file::color_directory().join(profile_name).exists() {
+        return exit_code::invalid_input(format!("There's no ICC profile named '{}' for {}, check the name with `test licc`", profile_name, display.device_name));
+    }
+    exit_code::invalid_input(format!("'{}' is in the color directory but isn't associated with {}, add it under Color Management first", profile_name, display.device_name))
+}
 
+fn check_profile_file(path: &Path) -> Result<()> {
+    if display_api::is_mock() {
+        return Ok(());
+    }
+    icc_file::check_profile_file(path).map_err(exit_code::invalid_input)
+}
 
-fn set_display_icc_profile(display: &DisplayDevice, profile_name: &str) -> Result<()> {
-    info!("Attempting to set ICC profile '{}' for display: {}", profile_name, display.device_name);
+// Set a display's default ICC profile
+#[instrument(skip_all, fields(display = %device.device_name, profile = %profile_name))]
+pub fn set_display_icc_profile(device: &DisplayDevice, profile_name: &str) -> Result<()> {
+    info!("Attempting to set ICC profile '{}' for display: {}", profile_name, device.device_name);
 
     // Get available profiles and validate the requested profile
-    let available_profiles = get_display_icc_profiles(display);
+    let available_profiles = get_display_icc_profiles(device);
     let profile = available_profiles.iter().find(|p| p.name == profile_name);
 
     let profile = match profile {
         Some(p) => p,
         None => {
             error!("Profile '{}' not found in available profiles for display", profile_name);
-            return Err(windows::core::Error::from_win32());
+            return Err(profile_not_associated(device, profile_name));
         }
     };
+    check_profile_file(&profile.path)?;
+
+    if profile.is_hdr_calibration() {
+        warn!("'{}' is an HDR Calibration profile, it only applies as the HDR profile (set-hdr-calibration)", profile_name);
+    }
+
+    match display_api::api().set_default_icc_profile(device.adapter_id, device.source_id, &profile.path) {
+        Ok(()) => {
+            info!("Successfully set ICC profile '{}' for display", profile_name);
+            Ok(())
+        },
+        Err(e) => {
+            error!("Failed to set ICC profile: {}", e);
+            Err(e)
+        }
+    }
+}
+
+
+
+// Adds a profile to the display's list like the Color Management dialog's Add button, leaving
+// the default alone unless `make_default`. Takes a name in the color directory or a path.
+// False when the profile was associated already.
+#[instrument(skip_all, fields(display = %device.device_name, profile = %name_or_path, make_default = make_default))]
+pub fn associate_display_icc_profile(device: &DisplayDevice, name_or_path: &str, make_default: bool) -> Result<bool> {
+    let path = if display_api::is_mock() { PathBuf::from(name_or_path) } else { icc_file::resolve_profile_path(name_or_path) };
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or(name_or_path).to_string();
+
+    let existing = get_display_icc_profiles(device).into_iter().find(|p| p.name.eq_ignore_ascii_case(&name));
+    let associated = existing.is_none();
+    let name = match existing {
+        Some(profile) => profile.name,
+        None => {
+            if !display_api::is_mock() && !path.exists() {
+                return Err(exit_code::invalid_input(format!("There's no ICC profile '{}' in {}", name_or_path, icc_file::color_directory().display())));
+            }
+            check_profile_file(&path)?;
+            info!("Associating '{}' with {}", path.display(), device.device_name);
+            display_api::api().associate_icc_profile(device.adapter_id, device.source_id, &path)?;
+            name
+        }
+    };
+
+    if make_default && !get_display_default_icc_profile(device).is_some_and(|current| current.eq_ignore_ascii_case(&name)) {
+        set_display_icc_profile(device, &name)?;
+    }
+    Ok(associated)
+}
+
+// File name of the display's current default ICC profile, if it has one
+pub fn get_display_default_icc_profile(display: &DisplayDevice) -> Option<String> {
+    display_api::api().default_icc_profile(display.adapter_id, display.source_id)
+}
+
+// Hard coding some ICC profile strings. Don't use these! I mean, you can if you really want.
+#[derive(Debug)]
+pub enum PresetString {
+    LgOled,
+    SteamDeck,
+    TestProfile,
+}
+
+impl PresetString {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresetString::LgOled => "HDR LG OLED.icc", // Update these to your own ICC profile names if you want to save some typing.
+            PresetString::SteamDeck => "HDR Steam Deck.icc",
+            PresetString::TestProfile => "HDR bad test.icc",
+        }
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Self::LgOled),
+            1 => Some(Self::SteamDeck),
+            2 => Some(Self::TestProfile),
+            _ => None,
+        }
+    }
+}
+
+//==============================================================================
+// HDR Calibration profiles
+//==============================================================================
+
+// Profiles from the Windows HDR Calibration app (an MHC2 tag) only do anything as the display's
+// advanced color profile, the one Windows applies while HDR is on. Made the default the way
+// `sicc` does it they're an SDR profile Windows ignores in HDR.
+
+pub fn get_display_default_hdr_icc_profile(display: &DisplayDevice) -> Option<String> {
+    display_api::api().default_hdr_icc_profile(display.adapter_id, display.source_id)
+}
+
+// Without a name, the display's newest calibration, as the app writes a new profile each run.
+// Returns the profile's name, and false when it was the advanced color profile already.
+#[instrument(skip_all, fields(display = %device.device_name, profile = %profile_name.unwrap_or("newest")))]
+pub fn set_display_hdr_calibration_profile(device: &DisplayDevice, profile_name: Option<&str>) -> Result<(String, bool)> {
+    let profiles = get_display_icc_profiles(device);
 
-    // Convert path to wide string for Windows API
-    let profile_path = profile.path.to_string_lossy();
-    let profile_path_wide: Vec<u16> = profile_path.encode_utf16().chain(std::iter::once(0)).collect();
-
-    unsafe {
-        match ColorProfileSetDisplayDefaultAssociation(
-            WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
-            PCWSTR::from_raw(profile_path_wide.as_ptr()),
-            CPT_ICC,
-            CPST_RGB_WORKING_SPACE,
-            display.adapter_id,
-            display.source_id,
-        ) {
-            Ok(()) => {
-                info!("Successfully set ICC profile '{}' for display", profile_name);
-                Ok(())
-            },
-            Err(e) => {
-                error!("Failed to set ICC profile: {}", e);
-                Err(e)
+    let profile = match profile_name {
+        Some(name) => {
+            let profile = profiles.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| profile_not_associated(device, name))?;
+            if !profile.is_hdr_calibration() {
+                return Err(exit_code::invalid_input(format!("'{}' isn't an HDR Calibration profile (it has no MHC2 tag), set it with set-icc-profile", profile.name)));
             }
+            profile
         }
+        None => profiles.iter()
+            .filter(|p| p.is_hdr_calibration())
+            .max_by_key(|p| std::fs::metadata(&p.path).and_then(|metadata| metadata.modified()).ok())
+            .ok_or_else(|| exit_code::not_supported(format!("No HDR Calibration profile is associated with {}, run the Windows HDR Calibration app on it first", device.device_name)))?,
+    };
+
+    if get_display_default_hdr_icc_profile(device).is_some_and(|current| current.eq_ignore_ascii_case(&profile.name)) {
+        return Ok((profile.name.clone(), false));
     }
+    check_profile_file(&profile.path)?;
+    info!("Making '{}' the advanced color profile of {}", profile.name, device.device_name);
+    display_api::api().set_default_hdr_icc_profile(device.adapter_id, device.source_id, &profile.path)?;
+    Ok((profile.name.clone(), true))
+}
+
+//==============================================================================
+// Restoring the previous default
+//==============================================================================
+
+// `sicc` remembers the default it replaced so `icc restore` can put it back without the undo
+// command naming the desktop profile. Like `topology`, the first change is kept until it's
+// restored, so running `sicc` twice in a stream still restores the desktop's profile.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SavedIccProfile {
+    display: String, // DisplayDevice::stable_id, or the device name without one
+    profile: String,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+struct IccRestore {
+    displays: Vec<SavedIccProfile>,
+}
 
+fn restore_key(display: &DisplayDevice) -> String {
+    display.stable_id().unwrap_or_else(|| display.device_name.clone())
+}
 
+// False when the display already has an earlier profile saved
+fn remember(saved: &mut Vec<SavedIccProfile>, display: String, profile: String) -> bool {
+    if saved.iter().any(|entry| entry.display.eq_ignore_ascii_case(&display)) {
+        return false;
+    }
+    saved.push(SavedIccProfile { display, profile });
+    true
+}
+
+// Saving is best effort, failing to save shouldn't stop the profile change
+fn remember_default_icc_profile(device: &DisplayDevice) {
+    let Some(current) = get_display_default_icc_profile(device) else {
+        info!("{} has no default ICC profile to remember", device.device_name);
+        return;
+    };
+    let mut restore = state::load_state_file::<IccRestore>(ICC_RESTORE_FILE).unwrap_or_default();
+    if !remember(&mut restore.displays, restore_key(device), current.clone()) {
+        info!("Keeping the saved ICC profile of {} until it is restored", device.device_name);
+        return;
+    }
+    info!("Remembering '{}' as the ICC profile to restore on {}", current, device.device_name);
+    if let Err(e) = state::save_state_file(ICC_RESTORE_FILE, &restore) {
+        error!("Failed to save the ICC profile to restore: {}", e);
+    }
+}
+
+// Puts back the profile saved by `sicc`. The restored profile, or None when nothing was saved.
+#[instrument(skip_all, fields(display = %device.device_name))]
+pub fn restore_display_icc_profile(device: &DisplayDevice) -> Result<Option<String>> {
+    let mut restore = state::load_state_file::<IccRestore>(ICC_RESTORE_FILE).unwrap_or_default();
+    let Some(index) = restore.displays.iter().position(|entry| displays_info::display_matches_selector(device, &entry.display)) else {
+        return Ok(None);
+    };
+
+    let saved = restore.displays[index].profile.clone();
+    if !get_display_default_icc_profile(device).is_some_and(|current| current.eq_ignore_ascii_case(&saved)) {
+        info!("Restoring ICC profile '{}' on {}", saved, device.device_name);
+        set_display_icc_profile(device, &saved)?;
+    }
+
+    restore.displays.remove(index);
+    if restore.displays.is_empty() {
+        state::remove_state_file(ICC_RESTORE_FILE);
+    } else if let Err(e) = state::save_state_file(ICC_RESTORE_FILE, &restore) {
+        error!("Failed to update the saved ICC profiles: {}", e);
+    }
+    Ok(Some(saved))
+}
 
 //==============================================================================
 // Helper functions for CLI commands
@@ -124,7 +279,7 @@ fn set_display_icc_profile(display: &DisplayDevice, profile_name: &str) -> Resul
 
 // Primary display only right now (makes most sense for game streaming).
 pub fn list_icc_profiles() -> Vec<(String, PathBuf)> {
-    if let Some((primary_display, _)) = get_primary_display_info() {
+    if let Some(primary_display) = find_display("primary") {
         get_display_icc_profiles(&primary_display)
             .into_iter()
             .map(|p| (p.name, p.path))
@@ -134,17 +289,116 @@ pub fn list_icc_profiles() -> Vec<(String, PathBuf)> {
     }
 }
 
+// A profile installed in the color directory or associated with any display, and the device
+// names of the displays it's associated with
+pub struct InstalledProfile {
+    pub name: String,
+    pub path: PathBuf,
+    pub displays: Vec<String>,
+    pub default_for: Vec<String>,
+}
+
+fn is_profile_file(path: &std::path::Path) -> bool {
+    path.is_file() && path.extension().and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("icc") || ext.eq_ignore_ascii_case("icm"))
+}
+
+// Everything in the color directory, whether a display uses it or not, so the name `sicc` wants
+// can be found. The mock's profiles aren't files, only its associations are listed.
+pub fn list_all_icc_profiles() -> Vec<InstalledProfile> {
+    let mut profiles: Vec<InstalledProfile> = Vec::new();
+    if !display_api::is_mock() {
+        let directory = icc_file::color_directory();
+        match std::fs::read_dir(&directory) {
+            Ok(entries) => {
+                for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| is_profile_file(path)) {
+                    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("unknown").to_string();
+                    profiles.push(InstalledProfile { name, path, displays: Vec::new(), default_for: Vec::new() });
+                }
+            }
+            Err(e) => error!("Failed to list the color directory {}: {}", directory.display(), e),
+        }
+    }
+
+    for display in enumerate_displays() {
+        let default_profile = get_display_default_icc_profile(&display);
+        for associated in get_display_icc_profiles(&display) {
+            let index = match profiles.iter().position(|p| p.name.eq_ignore_ascii_case(&associated.name)) {
+                Some(index) => index,
+                None => {
+                    profiles.push(InstalledProfile { name: associated.name.clone(), path: associated.path, displays: Vec::new(), default_for: Vec::new() });
+                    profiles.len() - 1
+                }
+            };
+            profiles[index].displays.push(display.device_name.clone());
+            if default_profile.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(&associated.name)) {
+                profiles[index].default_for.push(display.device_name.clone());
+            }
+        }
+    }
+
+    profiles.sort_by_key(|profile| profile.name.to_lowercase());
+    profiles
+}
+
 pub fn change_primary_display_icc_profile(profile_name: &str) -> Result<()> {
-    match get_primary_display_info() {
-        Some((primary_display, _)) => {
+    match find_display("primary") {
+        Some(primary_display) => {
             info!("Setting ICC profile '{}' for primary display", profile_name);
+            remember_default_icc_profile(&primary_display);
             set_display_icc_profile(&primary_display, profile_name)
         }
         None => {
-            let error = windows::core::Error::from_win32();
+            let error = exit_code::display_not_found("No primary display found");
             error!("Error setting primary display default ICC color profile: {}", error);
             Err(error)
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::exit_code::ExitCode;
+
+    #[test]
+    fn unknown_profile_is_invalid_input() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.primary = true;
+        tv.icc_profiles = vec!["HDR Steam Deck.icc".to_string()];
+        let api = MockDisplayApi::install(vec![tv]);
+
+        let error = change_primary_display_icc_profile("HDR Steam Dek.icc").unwrap_err();
+        assert_eq!(ExitCode::from_error(&error), ExitCode::InvalidInput);
+        assert!(error.message().contains("'HDR Steam Dek.icc'"));
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn the_first_profile_is_kept_until_restored() {
+        let mut saved = Vec::new();
+        assert!(remember(&mut saved, "GSM-C0A8-SSCR2".to_string(), "Desktop.icc".to_string()));
+        assert!(!remember(&mut saved, "gsm-c0a8-sscr2".to_string(), "HDR Steam Deck.icc".to_string()));
+        assert!(remember(&mut saved, "\\\\.\\DISPLAY2".to_string(), "sRGB.icm".to_string()));
+        assert_eq!(saved.iter().map(|entry| entry.profile.as_str()).collect::<Vec<_>>(), ["Desktop.icc", "sRGB.icm"]);
+    }
+
+    #[test]
+    fn all_profiles_list_every_display() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.icc_profiles = vec!["sRGB.icm".to_string(), "HDR TV.icc".to_string()];
+        tv.icc_profile = Some("HDR TV.icc".to_string());
+        let mut monitor = MockDisplay::new(2, "DELL U2720Q", (3840, 2160, 60));
+        monitor.icc_profiles = vec!["srgb.icm".to_string(), "Dell.icm".to_string()];
+        MockDisplayApi::install(vec![tv, monitor]);
+
+        let profiles = list_all_icc_profiles();
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Dell.icm", "HDR TV.icc", "sRGB.icm"]);
+        assert_eq!(profiles[1].default_for, ["\\\\.\\DISPLAY1"]);
+        assert_eq!(profiles[2].displays, ["\\\\.\\DISPLAY1", "\\\\.\\DISPLAY2"]);
+        assert!(profiles[2].default_for.is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, Command};
+
+use crate::change_icc_profile;
+use crate::config;
+use crate::displays_info::enumerate_displays;
+use crate::state;
+
+// Shell completion works in two halves. The script registered with the shell passes the words
+// typed so far to the hidden __complete command, which walks the clap command tree and adds
+// values only known at runtime: display selectors, ICC profile names, saved states and the
+// config's client and app presets.
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Shell {
+    Powershell,
+    Bash,
+}
+
+pub fn completion_script(shell: Shell) -> String {
+    let exe = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| "sunshine_helper.exe".to_string());
+
+    match shell {
+        Shell::Powershell => format!(
+r#"# Add to your $PROFILE: sunshine_helper.exe completions powershell | Out-String | Invoke-Expression
+Register-ArgumentCompleter -Native -CommandName 'sunshine_helper', 'sunshine_helper.exe' -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | Where-Object {{ $_.Extent.EndOffset -le $cursorPosition }} | ForEach-Object {{ $_.ToString().Trim('"', "'") }})
+    if ($wordToComplete -eq '') {{ $words += '' }}
+    & '{exe}' __complete -- @words | ForEach-Object {{
+        $text = if ($_ -match '\s') {{ "'$_'" }} else {{ $_ }}
+        [System.Management.Automation.CompletionResult]::new($text, $_, 'ParameterValue', $_)
+    }}
+}}
+"#),
+        Shell::Bash => format!(
+r#"# Add to your .bashrc: eval "$(sunshine_helper.exe completions bash)"
+_sunshine_helper() {{
+    local IFS=$'\n'
+    COMPREPLY=($('{exe}' __complete -- "${{COMP_WORDS[@]:1:COMP_CWORD}}"))
+}}
+complete -F _sunshine_helper sunshine_helper sunshine_helper.exe
+"#),
+    }
+}
+
+//==============================================================================
+// Candidates
+//==============================================================================
+
+fn display_selectors() -> Vec<String> {
+    let mut selectors = vec!["primary".to_string()];
+    for display in enumerate_displays() {
+        selectors.push(display.device_index.to_string());
+        selectors.push(display.device_name.trim_start_matches("\\\\.\\").to_string());
+        if let Some(target) = display.get_target_device_name() {
+            if !target.friendly_name.is_empty() {
+                selectors.push(target.friendly_name);
+            }
+        }
+    }
+    selectors
+}
+
+fn icc_profile_names() -> Vec<String> {
+    let mut names: Vec<String> = change_icc_profile::list_icc_profiles().into_iter().map(|(name, _)| name).collect();
+    // The numbered presets are accepted too
+    names.extend((0..).map_while(|index| change_icc_profile::PresetString::from_index(index).map(|_| index.to_string())));
+    names
+}
+
+fn state_names() -> Vec<String> {
+    state::list_named_states().into_iter().map(|(name, _)| name).collect()
+}
+
+// The [clients.<name>] or [apps.<name>] sections of the config given with --config, or the default one
+fn preset_names(config_path: Option<&Path>, kind: &str) -> Vec<String> {
+    let path = config_path.map(Path::to_path_buf).unwrap_or_else(config::config_file_path);
+    let Ok(config) = config::load(&path) else {
+        return Vec::new();
+    };
+    let presets = if kind == "client" { config.clients } else { config.apps };
+    presets.into_keys().collect()
+}
+
+fn arg_values(command: &Command, arg: &Arg, config_path: Option<&Path>) -> Vec<String> {
+    let mut values: Vec<String> = arg.get_possible_values().iter().map(|value| value.get_name().to_string()).collect();
+
+    match (command.get_name(), arg.get_id().as_str()) {
+        (_, "selector" | "display") => values.extend(display_selectors()),
+        ("restore-state" | "delete-state", "name") => values.extend(state_names()),
+        ("sunshine-prep", "client") => values.extend(preset_names(config_path, "client")),
+        ("sunshine-prep", "app") | ("generate-config", "apps") => values.extend(preset_names(config_path, "app")),
+        // Calibration profiles are a display's own, not every installed ICC profile
+        ("set-hdr-calibration", _) => {}
+        (_, "profile_name" | "profile" | "icc") => values.extend(icc_profile_names()),
+        _ => {}
+    }
+    values
+}
+
+fn find_option<'a>(command: &'a Command, word: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| {
+        word.strip_prefix("--").is_some_and(|long| arg.get_long() == Some(long))
+            || (word.len() == 2 && word.strip_prefix('-').and_then(|short| short.chars().next()) == arg.get_short())
+    })
+}
+
+// Candidates for the last word, given everything typed before it
+pub fn complete(root: &Command, words: &[String]) -> Vec<String> {
+    let (partial, before) = match words.split_last() {
+        Some((partial, before)) => (partial.as_str(), before),
+        None => ("", words),
+    };
+
+    let mut command = root;
+    let mut positionals = 0;
+    let mut pending_option: Option<&Arg> = None;
+    let mut config_path: Option<PathBuf> = None;
+
+    for word in before {
+        if let Some(option) = pending_option.take() {
+            if option.get_id() == "config" {
+                config_path = Some(PathBuf::from(word));
+            }
+            continue;
+        }
+        if let Some(subcommand) = command.find_subcommand(word) {
+            command = subcommand;
+            positionals = 0;
+        } else if word.starts_with('-') {
+            pending_option = find_option(command, word).filter(|arg| arg.get_action().takes_values());
+        } else {
+            positionals += 1;
+        }
+    }
+
+    let candidates: Vec<String> = if let Some(arg) = pending_option {
+        arg_values(command, arg, config_path.as_deref())
+    } else if partial.starts_with('-') {
+        command.get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .filter_map(|arg| arg.get_long().map(|long| format!("--{}", long)))
+            .collect()
+    } else {
+        let mut candidates: Vec<String> = command.get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect();
+        if let Some(arg) = command.get_positionals().nth(positionals) {
+            candidates.extend(arg_values(command, arg, config_path.as_deref()));
+        }
+        candidates
+    };
+
+    let partial = partial.to_lowercase();
+    // Two monitors of one model share a name
+    let mut seen = HashSet::new();
+    candidates.into_iter()
+        .filter(|c| c.to_lowercase().starts_with(&partial))
+        .filter(|c| seen.insert(c.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn cli() -> Command {
+        Command::new("sunshine_helper")
+            .subcommand(Command::new("get-brightness").arg(Arg::new("selector")))
+            .subcommand(Command::new("restore-state").arg(Arg::new("name")))
+            .subcommand(Command::new("sunshine-prep")
+                .arg(Arg::new("config").long("config"))
+                .arg(Arg::new("client").long("client"))
+                .arg(Arg::new("app").long("app")))
+    }
+
+    fn words(line: &str) -> Vec<String> {
+        line.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn saved_states_and_presets_complete() {
+        let dir = std::env::temp_dir().join("sunshine-helper-test-completions");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        state::use_test_state_dir(dir.clone());
+        state::save_named_state("tv-hdr", &serde_json::json!({})).unwrap();
+        state::save_named_state("desk", &serde_json::json!({})).unwrap();
+        assert_eq!(complete(&cli(), &words("restore-state ")), ["desk", "tv-hdr"]);
+
+        let config = dir.join("other.toml");
+        std::fs::write(&config, "version = 1\n[clients.deck]\nhdr = true\n[apps.Desktop]\nhdr = false\n[apps.Dolphin]\nhdr = false\n").unwrap();
+        let path = config.display().to_string();
+        assert_eq!(complete(&cli(), &words(&format!("sunshine-prep --config {} --client ", path))), ["deck"]);
+        assert_eq!(complete(&cli(), &words(&format!("sunshine-prep --config {} --app d", path))), ["Desktop", "Dolphin"]);
+    }
+
+    #[test]
+    fn candidates_are_listed_once() {
+        // Two monitors of one model, the second not next to the first
+        MockDisplayApi::install(vec![
+            MockDisplay::new(1, "DELL U2723QE", (2560, 1440, 60)),
+            MockDisplay::new(2, "LG TV SSCR2", (3840, 2160, 120)),
+            MockDisplay::new(3, "DELL U2723QE", (2560, 1440, 60)),
+        ]);
+        assert_eq!(complete(&cli(), &words("get-brightness DE")), ["DELL U2723QE"]);
+    }
+}
@@ -0,0 +1,515 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::info;
+
+use crate::apply::{DisplaySpec, ModeSpec, Spec};
+use crate::change_display_mode;
+use crate::change_icc_profile;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code;
+use crate::hdr;
+use crate::state;
+use crate::sunshine::Issue;
+
+// The config file says what `sunshine-prep` sets up when a stream starts, e.g.
+//   [prep]
+//   hdr = true
+//   sdr_nits = 240
+//
+//   [clients."Steam Deck"]
+//   mode = { width = 1280, height = 800, refresh_rate = 90 }
+//   icc_profile = "HDR Steam Deck.icc"
+//
+//   [clients.phone]
+//   uuid = "8C1A5E4B-..."
+//   hdr = false
+//
+//   [apps."Cyberpunk 2077"]
+//   sdr_nits = 320
+//
+//   [rules."cyberpunk2077.exe"]
+//   state = "hdr-game"
+//
+//   [defaults]
+//   display = "DISPLAY2"
+//   log_level = "debug"
+// [prep] applies to every stream, the connecting client's section goes on top of it and the
+// streamed app's section on top of that. Sunshine passes the client in SUNSHINE_CLIENT_NAME and
+// SUNSHINE_CLIENT_UUID, and the app in SUNSHINE_APP_NAME. [rules] are for the daemon, see daemon.rs.
+// [defaults] holds default values for the command line options, named like their
+// SUNSHINE_HELPER_* environment variables without the prefix (see main.rs).
+//
+// `version = 1` at the top says which format the file is in, files without it are from before
+// there was a version. Older files are migrated in memory when loaded, and only rewritten by
+// `config upgrade`, which keeps a copy of the old one as config.toml.v<version>.bak. Newer ones
+// are refused rather than half understood.
+
+const CONFIG_FILE: &str = "config.toml";
+pub const CONFIG_VERSION: u32 = 1;
+
+// Each step takes the text of a config file from one version to the next, so comments survive.
+// The version line itself is written by migrate().
+const MIGRATIONS: [fn(&str) -> String; CONFIG_VERSION as usize] = [
+    // 1: the format is unchanged, it only got its version line
+    str::to_string,
+];
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub prep: Profile,
+    #[serde(default)]
+    pub clients: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub apps: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub rules: BTreeMap<String, Rule>,
+    #[serde(default)]
+    pub defaults: BTreeMap<String, serde_json::Value>,
+}
+
+// Settings left out are not touched, as in a spec file
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub display: Option<String>, // selector, the primary display if left out
+    pub uuid: Option<String>,    // only in [clients] sections, matched before the section name
+    pub mode: Option<ModeSpec>,
+    pub hdr: Option<bool>,
+    pub sdr_nits: Option<u32>,
+    pub icc_profile: Option<String>,
+}
+
+// [rules."cyberpunk2077.exe"] for the daemon: while the process runs, the displays are put in a
+// state saved with save-state
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub state: String,
+    #[serde(default = "restore_by_default")]
+    pub restore: bool, // put the displays back when the process exits
+}
+
+fn restore_by_default() -> bool {
+    true
+}
+
+// Who is connecting, from Sunshine's environment
+#[derive(Debug, Default)]
+pub struct Session {
+    pub client_name: Option<String>,
+    pub client_uuid: Option<String>,
+    pub app_name: Option<String>,
+}
+
+//==============================================================================
+// Loading
+//==============================================================================
+
+// config.toml in the state directory, %APPDATA%\sunshine-helper unless running portable
+pub fn config_file_path() -> PathBuf {
+    state::state_dir().join(CONFIG_FILE)
+}
+
+pub fn load(path: &Path) -> Result<Config> {
+    let (contents, version) = read_versioned(path)?;
+    let contents = if version < CONFIG_VERSION { migrate(&contents, version) } else { contents };
+    parse(&contents).map_err(|e| parse_failed(path, e))
+}
+
+// The version the file on disk is in, for `config validate`
+pub fn stored_version(path: &Path) -> Result<u32> {
+    read_versioned(path).map(|(_, version)| version)
+}
+
+fn read_versioned(path: &Path) -> Result<(String, u32)> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to read {}: {}", path.display(), e)))?;
+    let version = read_table(&contents).and_then(|table| file_version(&table)).map_err(|e| parse_failed(path, e))?;
+    Ok((contents, version))
+}
+
+fn parse_failed(path: &Path, e: String) -> Error {
+    exit_code::invalid_input(format!("Failed to parse {}: {}", path.display(), e))
+}
+
+// [defaults] of the usual config file, read before the command line is parsed. A broken file
+// only costs the defaults, so `setup` and friends still run to fix it.
+pub fn load_defaults() -> BTreeMap<String, serde_json::Value> {
+    let path = config_file_path();
+    if !path.exists() {
+        return BTreeMap::new();
+    }
+    match load(&path) {
+        Ok(config) => config.defaults,
+        Err(e) => {
+            eprintln!("warning: {}, not using its [defaults]", e.message());
+            BTreeMap::new()
+        }
+    }
+}
+
+// The version is checked and taken out first, the rest is what Config describes
+fn parse(contents: &str) -> std::result::Result<Config, String> {
+    let mut table = read_table(contents)?;
+    file_version(&table)?;
+    table.remove("version");
+    Config::deserialize(table).map_err(|e| e.to_string())
+}
+
+// The file without its meaning, enough to tell its version
+fn read_table(contents: &str) -> std::result::Result<toml::Table, String> {
+    toml::from_str(contents).map_err(|e| e.to_string())
+}
+
+//==============================================================================
+// Versions
+//==============================================================================
+
+fn file_version(table: &toml::Table) -> std::result::Result<u32, String> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(version) => version.as_integer().and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("version = {} isn't a whole number", version))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!("written by a newer version of the helper (config version {}, this one reads up to {})", version, CONFIG_VERSION));
+    }
+    Ok(version)
+}
+
+// The text of an older file in the current format, with its version line set
+fn migrate(contents: &str, from: u32) -> String {
+    let migrated = MIGRATIONS[from as usize..].iter().fold(contents.to_string(), |text, step| step(&text));
+    let version_line = format!("version = {}", CONFIG_VERSION);
+    let mut lines: Vec<String> = migrated.lines().map(str::to_string).collect();
+    let is_version = |line: &str| line.trim_start().strip_prefix("version").is_some_and(|rest| rest.trim_start().starts_with('='));
+
+    // Top-level keys go before the first [table], after any comments heading the file
+    let first_table = lines.iter().position(|line| line.trim_start().starts_with('[')).unwrap_or(lines.len());
+    if let Some(index) = lines[..first_table].iter().position(|line| is_version(line)) {
+        lines[index] = version_line;
+    } else {
+        let first = lines.iter().position(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#')).unwrap_or(lines.len());
+        if first == first_table && first < lines.len() {
+            lines.insert(first, String::new());
+        }
+        lines.insert(first, version_line);
+    }
+    lines.join("\n") + "\n"
+}
+
+// `config upgrade`: rewrites an older file in the current format, keeping the old one next to it.
+// The backup's path, None when the file was already current.
+pub fn upgrade(path: &Path) -> Result<Option<PathBuf>> {
+    let (contents, version) = read_versioned(path)?;
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+    let migrated = migrate(&contents, version);
+    // A file that wouldn't load after all is left as it is
+    parse(&migrated).map_err(|e| parse_failed(path, e))?;
+    let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+    let written = std::fs::write(&backup, &contents).and_then(|()| std::fs::write(path, &migrated));
+    written.map_err(|e| Error::new(E_FAIL, format!("Failed to write {}: {}", path.display(), e)))?;
+    info!("Upgraded {} from config version {} to {}", path.display(), version, CONFIG_VERSION);
+    Ok(Some(backup))
+}
+
+//==============================================================================
+// Profiles
+//==============================================================================
+
+impl Session {
+    // Empty values count as unset, Sunshine sets them empty for clients it can't name
+    pub fn from_env() -> Session {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.trim().is_empty());
+        Session {
+            client_name: var("SUNSHINE_CLIENT_NAME"),
+            client_uuid: var("SUNSHINE_CLIENT_UUID"),
+            app_name: var("SUNSHINE_APP_NAME"),
+        }
+    }
+}
+
+impl Profile {
+    fn is_empty(&self) -> bool {
+        Profile { display: None, uuid: None, ..self.clone() } == Profile::default()
+    }
+
+    // A spec for the one display the profile is about, None if it doesn't set anything
+    fn into_spec(self) -> Option<Spec> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(Spec {
+            displays: vec![DisplaySpec {
+                display: self.display.unwrap_or_else(|| "primary".to_string()),
+                enabled: None,
+                primary: None,
+                mode: self.mode,
+                hdr: self.hdr,
+                sdr_nits: self.sdr_nits,
+                icc_profile: self.icc_profile,
+            }],
+        })
+    }
+
+    // Settings from `other` win
+    fn overlay(&mut self, other: &Profile) {
+        self.display = other.display.clone().or(self.display.take());
+        self.mode = other.mode.or(self.mode);
+        self.hdr = other.hdr.or(self.hdr);
+        self.sdr_nits = other.sdr_nits.or(self.sdr_nits);
+        self.icc_profile = other.icc_profile.clone().or(self.icc_profile.take());
+    }
+}
+
+impl Config {
+    // The [clients] section for this client: its uuid first, then its name (ignoring case)
+    pub fn client(&self, session: &Session) -> Option<(&str, &Profile)> {
+        let by_uuid = session.client_uuid.as_deref().and_then(|uuid| {
+            self.clients.iter().find(|(name, profile)| {
+                profile.uuid.as_deref().is_some_and(|expected| expected.eq_ignore_ascii_case(uuid)) || name.eq_ignore_ascii_case(uuid)
+            })
+        });
+        let by_name = || session.client_name.as_deref().and_then(|client| {
+            self.clients.iter().find(|(name, _)| name.eq_ignore_ascii_case(client))
+        });
+        by_uuid.or_else(by_name).map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    // The [apps] section for the streamed app, by name ignoring case
+    pub fn app(&self, session: &Session) -> Option<(&str, &Profile)> {
+        let app = session.app_name.as_deref()?;
+        self.apps.iter().find(|(name, _)| name.eq_ignore_ascii_case(app)).map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    // What to apply for this session, None if nothing applies
+    pub fn prep_spec(&self, session: &Session) -> Option<Spec> {
+        let mut profile = self.prep.clone();
+        match self.client(session) {
+            Some((name, client)) => {
+                info!("Using the [clients.\"{}\"] profile", name);
+                profile.overlay(client);
+            }
+            None => info!("No [clients] profile for {:?} ({:?})", session.client_name, session.client_uuid),
+        }
+        match self.app(session) {
+            Some((name, app)) => {
+                info!("Using the [apps.\"{}\"] preset", name);
+                profile.overlay(app);
+            }
+            None => info!("No [apps] preset for {:?}", session.app_name),
+        }
+
+        profile.into_spec()
+    }
+
+    // Every profile as it would be applied, each section on top of [prep], for `sunshine validate`
+    pub fn profiles(&self) -> Vec<(String, Spec)> {
+        let sections = std::iter::once(("[prep]".to_string(), None))
+            .chain(self.clients.iter().map(|(name, profile)| (format!("[clients.\"{}\"]", name), Some(profile))))
+            .chain(self.apps.iter().map(|(name, profile)| (format!("[apps.\"{}\"]", name), Some(profile))));
+        sections
+            .filter_map(|(label, section)| {
+                let mut profile = self.prep.clone();
+                if let Some(section) = section {
+                    profile.overlay(section);
+                }
+                profile.into_spec().map(|spec| (label, spec))
+            })
+            .collect()
+    }
+}
+
+//==============================================================================
+// Validation
+//==============================================================================
+
+impl Config {
+    // Each profile as sunshine-prep would apply it, against the connected displays
+    pub fn check_profiles(&self, displays: &[DisplayDevice]) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let names = displays.iter().map(|display| display.device_name.trim_start_matches("\\\\.\\").to_string()).collect::<Vec<_>>().join(", ");
+        for (label, spec) in self.profiles() {
+            for display_spec in &spec.displays {
+                if let Some(nits) = display_spec.sdr_nits.filter(|nits| !(80..=480).contains(nits)) {
+                    issues.push(Issue::error(format!("{}: sdr_nits {} is out of range (80-480)", label, nits)));
+                }
+                let Some(target) = displays.iter().find(|display| displays_info::display_matches_selector(display, &display_spec.display)) else {
+                    issues.push(Issue::error(format!("{}: display {} matches no active display (active: {})", label, display_spec.display, names)));
+                    continue;
+                };
+                if let Some(mode) = display_spec.mode {
+                    if !change_display_mode::is_supported_mode(target, mode.width, mode.height, mode.refresh_rate, None) {
+                        issues.push(Issue::error(format!("{}: {} doesn't offer {}x{} at {} Hz",
+                            label, target.device_name, mode.width, mode.height, mode.refresh_rate)));
+                    }
+                }
+                if display_spec.hdr == Some(true) && !hdr::get_advanced_color_info(target).is_ok_and(|info| info.supported) {
+                    issues.push(Issue::error(format!("{}: turns HDR on, but {} isn't HDR capable", label, target.device_name)));
+                }
+                if let Some(profile) = &display_spec.icc_profile {
+                    let associated = change_icc_profile::get_display_icc_profiles(target);
+                    if !associated.iter().any(|associated| associated.name.eq_ignore_ascii_case(profile)) {
+                        issues.push(Issue::error(format!("{}: ICC profile {} isn't associated with {}", label, profile, target.device_name)));
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    // The daemon can only switch to states that have been saved
+    pub fn check_rules(&self) -> Vec<Issue> {
+        self.rules.iter()
+            .filter(|(_, rule)| state::load_named_state::<Spec>(&rule.state).is_none())
+            .map(|(process, rule)| Issue::error(format!("[rules.\"{}\"]: there is no saved state named {}", process, rule.state)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        # Everything streams in HDR unless the client says otherwise
+        [prep]
+        hdr = true
+        sdr_nits = 240
+
+        [clients."Steam Deck"]
+        mode = { width = 1280, height = 800, refresh_rate = 90 }
+        icc_profile = 'HDR Steam Deck.icc'
+
+        [clients.phone]
+        uuid = "8C1A5E4B-0000-4000-8000-000000000001"
+        display = "DISPLAY2"
+        hdr = false
+
+        [apps."Cyberpunk 2077"]
+        sdr_nits = 320
+    "#;
+
+    fn session(name: Option<&str>, uuid: Option<&str>) -> Session {
+        Session { client_name: name.map(String::from), client_uuid: uuid.map(String::from), app_name: None }
+    }
+
+    #[test]
+    fn client_sections_go_on_top_of_prep() {
+        let config = parse(CONFIG).unwrap();
+
+        let deck = config.prep_spec(&session(Some("steam deck"), None)).unwrap();
+        let display = &deck.displays[0];
+        assert_eq!(display.display, "primary");
+        assert_eq!(display.mode, Some(ModeSpec { width: 1280, height: 800, refresh_rate: 90 }));
+        assert_eq!((display.hdr, display.sdr_nits), (Some(true), Some(240)));
+        assert_eq!(display.icc_profile.as_deref(), Some("HDR Steam Deck.icc"));
+
+        // Matched by uuid whatever the name says
+        let phone = config.prep_spec(&session(Some("Pixel"), Some("8c1a5e4b-0000-4000-8000-000000000001"))).unwrap();
+        assert_eq!(phone.displays[0].display, "DISPLAY2");
+        assert_eq!(phone.displays[0].hdr, Some(false));
+
+        let unknown = config.prep_spec(&session(Some("Living room TV"), None)).unwrap();
+        assert_eq!(unknown.displays[0].mode, None);
+        assert_eq!(unknown.displays[0].hdr, Some(true));
+    }
+
+    #[test]
+    fn app_presets_go_on_top_of_the_client() {
+        let config = parse(CONFIG).unwrap();
+        let game = Session { app_name: Some("cyberpunk 2077".to_string()), ..session(Some("Steam Deck"), None) };
+        let spec = config.prep_spec(&game).unwrap();
+        assert_eq!(spec.displays[0].sdr_nits, Some(320));
+        assert_eq!(spec.displays[0].icc_profile.as_deref(), Some("HDR Steam Deck.icc"));
+
+        let desktop = Session { app_name: Some("Desktop".to_string()), ..session(Some("Steam Deck"), None) };
+        assert_eq!(config.prep_spec(&desktop).unwrap().displays[0].sdr_nits, Some(240));
+    }
+
+    #[test]
+    fn nothing_to_apply_without_a_matching_profile() {
+        let config = parse("[clients.\"Steam Deck\"]\nhdr = true\n").unwrap();
+        assert!(config.prep_spec(&session(Some("phone"), None)).is_none());
+        assert!(config.prep_spec(&session(None, None)).is_none());
+    }
+
+    #[test]
+    fn rules_restore_by_default() {
+        let config = parse("[rules.\"cyberpunk2077.exe\"]\nstate = \"hdr-game\"\n\n[rules.'obs64.exe']\nstate = \"record\"\nrestore = false\n").unwrap();
+        assert_eq!(config.rules["cyberpunk2077.exe"], Rule { state: "hdr-game".to_string(), restore: true });
+        assert!(!config.rules["obs64.exe"].restore);
+        assert!(parse("[rules.game]\nrestore = true\n").unwrap_err().contains("state"));
+    }
+
+    #[test]
+    fn defaults_keep_their_types() {
+        let config = parse("[defaults]\ndisplay = \"LG TV\"\nlog = true\nretries = 5\n").unwrap();
+        assert_eq!(config.defaults["display"], "LG TV");
+        assert_eq!(config.defaults["log"], true);
+        assert_eq!(config.defaults["retries"], 5);
+    }
+
+    #[test]
+    fn the_version_is_not_a_setting() {
+        let config = parse("version = 1\n\n[defaults]\nadapter = \"nvidia\"\nversion = 3\n").unwrap();
+        assert_eq!(config.defaults["adapter"], "nvidia");
+        // Only the top-level version is the file's, one in [defaults] is left for the option check
+        assert_eq!(config.defaults["version"], 3);
+
+        assert!(parse("version = 2\n").unwrap_err().contains("newer version of the helper"));
+        for version in ["\"1\"", "-1", "1.5"] {
+            assert!(parse(&format!("version = {}\n", version)).unwrap_err().contains("isn't a whole number"), "version = {}", version);
+        }
+    }
+
+    #[test]
+    fn config_mistakes_are_reported() {
+        assert!(parse("[clients.deck]\nhrd = true\n").unwrap_err().contains("hrd"));
+        assert!(parse("[prep]\nhdr = yes\n").unwrap_err().contains("at line 2"));
+    }
+
+    #[test]
+    fn old_files_get_a_version_line() {
+        let old = "# My streaming setup\n\n[prep]\nhdr = true\n";
+        let migrated = migrate(old, 0);
+        assert_eq!(migrated, "# My streaming setup\n\nversion = 1\n\n[prep]\nhdr = true\n");
+        assert_eq!(file_version(&read_table(&migrated).unwrap()), Ok(CONFIG_VERSION));
+        assert!(parse(&migrated).unwrap().prep.hdr.unwrap());
+
+        assert_eq!(migrate("version = 0\nlog = 1\n", 0), "version = 1\nlog = 1\n");
+    }
+
+    #[test]
+    fn old_files_are_only_rewritten_by_upgrade() {
+        let dir = std::env::temp_dir().join("sunshine-helper-test-config-upgrade");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+        let old = "[prep]\nhdr = true\n";
+        std::fs::write(&path, old).unwrap();
+
+        assert!(load(&path).unwrap().prep.hdr.unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), old);
+        assert_eq!(stored_version(&path).unwrap(), 0);
+
+        let backup = upgrade(&path).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), old);
+        assert_eq!(stored_version(&path).unwrap(), CONFIG_VERSION);
+        assert!(upgrade(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        assert!(parse("version = 99\n[prep]\nhdr = true\n").unwrap_err().contains("newer version"));
+        assert!(parse("version = \"1\"\n").unwrap_err().contains("whole number"));
+        assert!(parse(&format!("version = {}\n", CONFIG_VERSION)).is_ok());
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::windows::process::CommandExt;
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, E_FAIL, HWND, LPARAM, LRESULT, WAIT_OBJECT_0, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::RemoteDesktop::{WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION};
+use windows::Win32::System::Threading::{CreateEventW, OpenEventW, SetEvent, WaitForSingleObject, CREATE_NO_WINDOW, DETACHED_PROCESS, EVENT_MODIFY_STATE};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW,
+    DefWindowProcW,
+    DispatchMessageW,
+    GetMessageW,
+    RegisterClassW,
+    HMENU,
+    HWND_MESSAGE,
+    MSG,
+    WINDOW_EX_STYLE,
+    WINDOW_STYLE,
+    WM_WTSSESSION_CHANGE,
+    WNDCLASSW,
+    WTS_CONSOLE_CONNECT,
+    WTS_CONSOLE_DISCONNECT,
+    WTS_SESSION_LOCK,
+    WTS_SESSION_UNLOCK,
+};
+use windows::core::{w, Error, Result, PCWSTR};
+use tracing::{debug, info, warn, error};
+
+use crate::apply::{self, DisplaySpec, Spec};
+use crate::config::Rule;
+use crate::displays_info;
+use crate::event_log::DriverResetWatch;
+use crate::exit_code;
+use crate::keep_awake;
+use crate::portable;
+use crate::processes;
+use crate::retry::RetryPolicy;
+use crate::state;
+use crate::sunshine;
+
+// Sunshine only runs its undo commands when a stream ends normally. If Sunshine crashes or is
+// killed mid-stream, the displays stay the way the prep commands left them. The daemon watches
+// for that: while the state saved at the start of a stream is still there (the undo command
+// deletes it), it restores that state once Sunshine is gone, or optionally once Sunshine's log
+// says the client disconnected and didn't come back.
+//
+// It also runs the [rules] of the config file, for local play as much as streaming: while one of
+// the listed processes runs the displays are put in a saved state, and put back when it exits.
+// One rule is active at a time, the first listed process found wins.
+//
+// Some systems reset the SDR white level after the lock screen or fast user switching, so the
+// daemon also remembers each display's SDR level and ICC profile as the session goes away and
+// puts back what changed when it returns. A GPU driver reset (TDR) mid-stream turns HDR off and
+// the SDR level back to the default, so when one shows up in the System log the daemon applies
+// the active rule's state, or else the stream's spec from sunshine-prep, again. With --keep-awake
+// it also keeps the host and its displays from sleeping for as long as a stream is in progress.
+
+// Same pattern as keep-awake: the named event is both the "already running" check and the stop signal
+const STOP_EVENT_NAME: windows::core::PCWSTR = w!("Local\\SunshineHelperDaemon");
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// After a driver reset, how long the displays have to stay put before the preset goes back on
+const RESET_SETTLE: Duration = Duration::from_secs(2);
+const RESET_SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const SUNSHINE_PROCESS: &str = "sunshine.exe";
+const SUNSHINE_LOG: &str = "sunshine.log";
+// Saved when a rule's process starts and restored when it exits
+const RULE_STATE: &str = "rule-before";
+
+pub struct DaemonOptions {
+    pub disconnect_grace: Option<Duration>,
+    pub sunshine_log: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub rules: BTreeMap<String, Rule>,
+    pub keep_awake: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientEvent {
+    Connected,
+    Disconnected,
+}
+
+// %ProgramFiles%\Sunshine\config\sunshine.log
+pub fn default_sunshine_log() -> PathBuf {
+    sunshine::default_config_dir().join(SUNSHINE_LOG)
+}
+
+//==============================================================================
+// Watching
+//==============================================================================
+
+// Blocks until `daemon --stop` is run
+pub fn run(options: DaemonOptions, retry: RetryPolicy) -> Result<()> {
+    let stop_event = unsafe { CreateEventW(None, true, false, STOP_EVENT_NAME)? };
+    if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+        let _ = unsafe { CloseHandle(stop_event) };
+        return Err(Error::new(E_FAIL, "The daemon is already running"));
+    }
+    info!("Daemon started, watching for Sunshine streams that end without running their undo commands");
+    for (exe, rule) in &options.rules {
+        info!("Rule: while {} runs, use state '{}'", exe, rule.state);
+        if state::load_named_state::<Spec>(&rule.state).is_none() {
+            warn!("The rule for {} uses state '{}', which isn't saved (see save-state)", exe, rule.state);
+        }
+    }
+
+    let mut log = options.disconnect_grace.map(|_| LogTail::new(options.sunshine_log.clone().unwrap_or_else(default_sunshine_log)));
+    let mut disconnected_since: Option<Instant> = None;
+    // A rule left active by an earlier daemon is picked up again below if its process still runs
+    let mut active_rule: Option<String> = None;
+    let mut resume_rule = state::load_named_state::<Spec>(RULE_STATE).is_some();
+    let session_events = watch_session();
+    let mut before_away: Option<Spec> = None;
+    let mut awake = false;
+    let mut driver_resets = match DriverResetWatch::open() {
+        Ok(watch) => Some(watch),
+        Err(e) => {
+            warn!("Not watching for GPU driver resets, the System event log can't be read: {}", e.message());
+            None
+        }
+    };
+
+    while unsafe { WaitForSingleObject(stop_event, POLL_INTERVAL.as_millis() as u32) } != WAIT_OBJECT_0 {
+        // Other processes, Sunshine's prep commands among them, change the displays between polls
+        displays_info::invalidate_topology();
+
+        // Session lock and reconnect
+        while let Ok(event) = session_events.try_recv() {
+            match event {
+                SessionEvent::Away(settings) => before_away = settings.or(before_away),
+                SessionEvent::Back => {
+                    if let Some(settings) = before_away.take() {
+                        reapply_settings(&settings, retry);
+                    }
+                }
+            }
+        }
+
+        if let Some(event) = log.as_mut().and_then(LogTail::last_client_event) {
+            info!("Sunshine log: client {:?}", event);
+            disconnected_since = (event == ClientEvent::Disconnected).then(Instant::now);
+        }
+
+        let names = match processes::running_process_names() {
+            Ok(names) => names,
+            Err(e) => {
+                error!("Failed to list processes: {}", e.message());
+                continue;
+            }
+        };
+
+        // Driver resets
+        match driver_resets.as_mut().map(DriverResetWatch::new_resets) {
+            Some(Ok(0)) | None => {}
+            Some(Ok(count)) => {
+                warn!("The GPU driver was reset ({} time(s) since the last check)", count);
+                let rule = active_rule.as_deref().and_then(|exe| options.rules.get(exe));
+                recover_from_driver_reset(rule, retry);
+            }
+            Some(Err(e)) => error!("Failed to read the System event log: {}", e.message()),
+        }
+
+        // Rules
+        let running_rule = options.rules.iter().find(|(exe, _)| processes::is_running(&names, exe));
+        if std::mem::take(&mut resume_rule) {
+            active_rule = Some(running_rule.map_or(String::new(), |(exe, _)| exe.clone()));
+        }
+        match (&active_rule, running_rule) {
+            (Some(exe), _) if !processes::is_running(&names, exe) => {
+                info!("{} exited, restoring the displays", if exe.is_empty() { "The rule's process" } else { exe });
+                restore_state(RULE_STATE, "from before the rule", retry);
+                active_rule = None;
+            }
+            (None, Some((exe, rule))) => {
+                info!("{} started, applying state '{}'", exe, rule.state);
+                start_rule(rule, retry);
+                active_rule = Some(exe.clone());
+            }
+            _ => {}
+        }
+
+        // A stream is in progress from its prep commands saving the state until its undo commands delete it
+        let streaming = state::load_named_state::<Spec>(sunshine::PRE_STREAM_STATE).is_some();
+        if options.keep_awake && streaming != awake {
+            if streaming {
+                info!("A stream started, keeping the system and displays awake");
+                keep_awake::hold();
+            } else {
+                info!("The stream ended, letting the system and displays sleep again");
+                keep_awake::release();
+            }
+            awake = streaming;
+        }
+
+        // Streams that ended without their undo commands
+        if !streaming {
+            continue;
+        }
+        let reason = if !processes::is_running(&names, SUNSHINE_PROCESS) {
+            Some("Sunshine isn't running")
+        } else {
+            match (disconnected_since, options.disconnect_grace) {
+                (Some(since), Some(grace)) if since.elapsed() >= grace => Some("the client disconnected and didn't come back"),
+                _ => None,
+            }
+        };
+        if let Some(reason) = reason {
+            warn!("A stream ended without its undo commands ({}), restoring the displays", reason);
+            restore_state(sunshine::PRE_STREAM_STATE, "from before the stream", retry);
+            disconnected_since = None;
+        }
+    }
+
+    if awake {
+        keep_awake::release();
+    }
+    let _ = unsafe { CloseHandle(stop_event) };
+    info!("Daemon stopped");
+    Ok(())
+}
+
+fn apply_state(name: &str, retry: RetryPolicy) -> Result<()> {
+    let spec = state::load_named_state::<Spec>(name)
+        .ok_or_else(|| exit_code::invalid_input(format!("No saved state named '{}'", name)))?;
+    apply::differences(&spec).and_then(|differences| apply::apply_with_undo(&differences, retry, true))
+}
+
+// Saves the current displays first when the rule restores them afterwards
+fn start_rule(rule: &Rule, retry: RetryPolicy) {
+    if rule.restore {
+        let saved = apply::current_spec().and_then(|spec| {
+            state::save_named_state(RULE_STATE, &spec).map_err(|e| Error::new(E_FAIL, e.to_string()))
+        });
+        if let Err(e) = saved {
+            error!("Failed to save the displays before applying state '{}', not applying it: {}", rule.state, e.message());
+            return;
+        }
+    }
+    if let Err(e) = apply_state(&rule.state, retry) {
+        error!("Failed to apply state '{}': {}", rule.state, e.message());
+    }
+}
+
+// Deleted even if restoring fails, so a display that refuses the old mode isn't retried every poll
+fn restore_state(name: &str, what: &str, retry: RetryPolicy) {
+    if state::load_named_state::<Spec>(name).is_none() {
+        return;
+    }
+    match apply_state(name, retry) {
+        Ok(()) => info!("Restored the displays {}", what),
+        Err(e) => error!("Failed to restore the displays {}: {}", what, e.message()),
+    }
+    if let Err(e) = state::delete_named_state(name) {
+        error!("Failed to delete the state saved {}: {}", what, e);
+    }
+}
+
+//==============================================================================
+// Session lock and reconnect
+//==============================================================================
+
+enum SessionEvent {
+    // The lock screen or another user took over the console, with the settings from just before
+    Away(Option<Spec>),
+    // Unlocked, or back on the console
+    Back,
+}
+
+// The window procedure has no other way to reach the daemon's loop
+static SESSION_EVENTS: Mutex<Option<mpsc::Sender<SessionEvent>>> = Mutex::new(None);
+
+// Session notifications need a window, a message-only one on its own thread does
+fn watch_session() -> mpsc::Receiver<SessionEvent> {
+    let (sender, receiver) = mpsc::channel();
+    if let Ok(mut events) = SESSION_EVENTS.lock() {
+        *events = Some(sender);
+    }
+    std::thread::spawn(|| {
+        if let Err(e) = session_message_loop() {
+            warn!("Not watching for session unlocks, SDR levels Windows resets won't be put back: {}", e.message());
+        }
+    });
+    receiver
+}
+
+fn session_message_loop() -> Result<()> {
+    unsafe {
+        let instance = GetModuleHandleW(PCWSTR::null())?;
+        let class_name = w!("SunshineHelperDaemonSession");
+        let window_class = WNDCLASSW {
+            lpfnWndProc: Some(session_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        if RegisterClassW(&window_class) == 0 {
+            return Err(Error::from_win32());
+        }
+        let hwnd = CreateWindowExW(WINDOW_EX_STYLE::default(), class_name, PCWSTR::null(), WINDOW_STYLE::default(), 0, 0, 0, 0,
+            HWND_MESSAGE, HMENU::default(), instance, None)?;
+        WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION)?;
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+            DispatchMessageW(&msg);
+        }
+    }
+    Ok(())
+}
+
+extern "system" fn session_window_proc(hwnd: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if message != WM_WTSSESSION_CHANGE {
+        return unsafe { DefWindowProcW(hwnd, message, wparam, lparam) };
+    }
+    // Read the settings here rather than at the daemon's next poll, by then Windows may have reset them
+    let event = match wparam.0 as u32 {
+        WTS_SESSION_LOCK | WTS_CONSOLE_DISCONNECT => {
+            debug!("Session locked or disconnected from the console, remembering the SDR levels and ICC profiles");
+            SessionEvent::Away(remember_settings())
+        }
+        WTS_SESSION_UNLOCK | WTS_CONSOLE_CONNECT => SessionEvent::Back,
+        _ => return LRESULT(0),
+    };
+    if let Some(sender) = SESSION_EVENTS.lock().ok().and_then(|events| events.clone()) {
+        let _ = sender.send(event);
+    }
+    LRESULT(0)
+}
+
+// The session thread keeps its own topology cache, which would still hold the displays from the first lock
+fn remember_settings() -> Option<Spec> {
+    displays_info::invalidate_topology();
+    match apply::current_spec() {
+        Ok(spec) => Some(sdr_and_icc(spec)),
+        Err(e) => {
+            error!("Failed to read the displays' SDR levels and ICC profiles: {}", e.message());
+            None
+        }
+    }
+}
+
+// Only the settings Windows resets, the rest is left as it is on return
+fn sdr_and_icc(spec: Spec) -> Spec {
+    let displays = spec.displays.into_iter()
+        .filter(|display| display.sdr_nits.is_some() || display.icc_profile.is_some())
+        .map(|display| DisplaySpec { enabled: None, primary: None, mode: None, hdr: None, ..display })
+        .collect();
+    Spec { displays }
+}
+
+// Displays that went away in the meantime are skipped
+fn reapply_settings(settings: &Spec, retry: RetryPolicy) {
+    displays_info::invalidate_topology();
+    let present = Spec {
+        displays: settings.displays.iter().filter(|display| displays_info::find_display(&display.display).is_some()).cloned().collect(),
+    };
+    let result = apply::differences(&present).and_then(|differences| {
+        for difference in &differences {
+            info!("Back from the lock screen, {} on '{}' was reset to {}, putting it back", difference.desired, difference.display, difference.current);
+        }
+        apply::apply_differences(&differences, retry, true)
+    });
+    if let Err(e) = result {
+        error!("Failed to put back the SDR levels and ICC profiles after the lock screen: {}", e.message());
+    }
+}
+
+//==============================================================================
+// Driver resets
+//==============================================================================
+
+// The spec sunshine-prep applied, if it's for the stream in progress. It's saved after the state
+// from before the stream, one left over from an earlier stream was saved before it.
+fn stream_preset() -> Option<Spec> {
+    let before = state::named_state_sequence(sunshine::PRE_STREAM_STATE)?;
+    let prep = state::named_state_sequence(sunshine::STREAM_PREP_STATE)?;
+    if prep < before {
+        return None;
+    }
+    state::load_named_state(sunshine::STREAM_PREP_STATE)
+}
+
+// Waits for the driver to bring the displays back, then applies whatever should be in effect
+fn recover_from_driver_reset(rule: Option<&Rule>, retry: RetryPolicy) {
+    let (what, spec) = match rule {
+        Some(rule) => (format!("state '{}'", rule.state), state::load_named_state::<Spec>(&rule.state)),
+        None => ("the stream's spec".to_string(), stream_preset()),
+    };
+    let Some(spec) = spec else {
+        info!("No rule or stream is active, leaving the displays as the driver reset left them");
+        return;
+    };
+
+    if !displays_info::wait_until_stable(displays_info::topology_fingerprint, RESET_SETTLE, RESET_SETTLE_TIMEOUT) {
+        warn!("The displays were still changing {} s after the driver reset, applying {} anyway", RESET_SETTLE_TIMEOUT.as_secs(), what);
+    }
+    displays_info::invalidate_topology();
+    let result = apply::differences(&spec).and_then(|differences| {
+        for difference in &differences {
+            info!("After the driver reset, {} on '{}' is {}, putting it back", difference.desired, difference.display, difference.current);
+        }
+        apply::apply_differences(&differences, retry, true)
+    });
+    match result {
+        Ok(()) => info!("Applied {} again after the driver reset", what),
+        Err(e) => error!("Failed to apply {} again after the driver reset: {}", what, e.message()),
+    }
+}
+
+//==============================================================================
+// Sunshine's log
+//==============================================================================
+
+// Reads what Sunshine appended since the last poll. Sunshine starts a new log each time it starts,
+// so a file shorter than before is read from the beginning again.
+struct LogTail {
+    path: PathBuf,
+    position: Option<u64>,
+}
+
+impl LogTail {
+    fn new(path: PathBuf) -> LogTail {
+        LogTail { path, position: None }
+    }
+
+    fn last_client_event(&mut self) -> Option<ClientEvent> {
+        let mut file = File::open(&self.path).ok()?;
+        let length = file.metadata().ok()?.len();
+        // Only what comes after the daemon started counts
+        let start = match self.position {
+            None => {
+                self.position = Some(length);
+                return None;
+            }
+            Some(position) if position > length => 0,
+            Some(position) => position,
+        };
+        file.seek(SeekFrom::Start(start)).ok()?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended).ok()?;
+        self.position = Some(start + appended.len() as u64);
+        last_client_event(&String::from_utf8_lossy(&appended))
+    }
+}
+
+// Sunshine logs "CLIENT CONNECTED" and "CLIENT DISCONNECTED" as a client comes and goes
+pub fn last_client_event(text: &str) -> Option<ClientEvent> {
+    text.lines().rev().find_map(|line| {
+        if line.contains("CLIENT DISCONNECTED") {
+            Some(ClientEvent::Disconnected)
+        } else if line.contains("CLIENT CONNECTED") {
+            Some(ClientEvent::Connected)
+        } else {
+            None
+        }
+    })
+}
+
+//==============================================================================
+// Background instance
+//==============================================================================
+
+// Returns false when no daemon was running
+pub fn stop_daemon() -> Result<bool> {
+    let Ok(stop_event) = (unsafe { OpenEventW(EVENT_MODIFY_STATE, false, STOP_EVENT_NAME) }) else {
+        return Ok(false);
+    };
+
+    let result = unsafe { SetEvent(stop_event) };
+    let _ = unsafe { CloseHandle(stop_event) };
+    result.map(|()| true)
+}
+
+// The background copy logs to the log file, there is no console to write to
+pub fn spawn_daemon(options: &DaemonOptions) -> std::io::Result<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    if portable::requested() {
+        command.arg("--portable");
+    }
+    if let Some(adapter) = displays_info::adapter() {
+        command.arg("--adapter").arg(adapter.to_string());
+    }
+    command.args(["--log", "daemon"]);
+    if let Some(grace) = options.disconnect_grace {
+        command.args(["--disconnect-grace", &grace.as_secs().to_string()]);
+    }
+    if let Some(path) = &options.sunshine_log {
+        command.arg("--sunshine-log").arg(path);
+    }
+    if let Some(path) = &options.config {
+        command.arg("--config").arg(path);
+    }
+    if options.keep_awake {
+        command.arg("--keep-awake");
+    }
+
+    command.creation_flags((DETACHED_PROCESS | CREATE_NO_WINDOW).0).spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::set_sdr_level;
+
+    #[test]
+    fn the_last_client_event_wins() {
+        let log = "[2024-11-02 20:14:03]: Info: CLIENT CONNECTED\n\
+            [2024-11-02 21:40:11]: Info: CLIENT DISCONNECTED\n\
+            [2024-11-02 21:40:12]: Info: Executing Undo cmd\n";
+        assert_eq!(last_client_event(log), Some(ClientEvent::Disconnected));
+        assert_eq!(last_client_event(&format!("{}[2024-11-02 21:41:00]: Info: CLIENT CONNECTED\n", log)), Some(ClientEvent::Connected));
+        assert_eq!(last_client_event("[2024-11-02 21:40:12]: Info: Executing Undo cmd\n"), None);
+    }
+
+    #[test]
+    fn a_reset_sdr_level_is_put_back() {
+        let api = MockDisplayApi::install(vec![MockDisplay::hdr_tv(), MockDisplay::desk_monitor()]);
+
+        // Only HDR displays have an SDR level, and the monitor has no profile either
+        let settings = remember_settings().unwrap();
+        assert_eq!(settings.displays.len(), 1);
+        assert_eq!((settings.displays[0].mode, settings.displays[0].hdr, settings.displays[0].sdr_nits), (None, None, Some(240)));
+
+        let tv = displays_info::find_display("LG TV SSCR2").unwrap();
+        set_sdr_level::set_display_sdr_white(&tv, 20).unwrap();
+        reapply_settings(&settings, RetryPolicy::from_args(Some(0), Some(0), 0));
+        assert_eq!(api.calls().last().unwrap(), "set_sdr_white_level DISPLAY1 40 (240 nits)");
+        assert_eq!(set_sdr_level::get_display_sdr_white(&tv).unwrap(), 40);
+    }
+
+    #[test]
+    fn only_the_current_streams_spec_is_applied_again() {
+        state::use_test_state_dir(std::env::temp_dir().join("sunshine-helper-test-stream-preset"));
+        let _ = state::delete_named_state(sunshine::PRE_STREAM_STATE);
+        let spec = Spec { displays: Vec::new() };
+
+        state::save_named_state(sunshine::STREAM_PREP_STATE, &spec).unwrap();
+        assert!(stream_preset().is_none());
+
+        // sunshine-prep runs after save-state, so its spec is the newer one
+        state::save_named_state(sunshine::PRE_STREAM_STATE, &spec).unwrap();
+        assert!(stream_preset().is_none());
+        state::save_named_state(sunshine::STREAM_PREP_STATE, &spec).unwrap();
+        assert!(stream_preset().is_some());
+    }
+}
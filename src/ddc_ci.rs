@@ -0,0 +1,311 @@
+use windows::Win32::Devices::Display::{
+    CapabilitiesRequestAndCapabilitiesReply,
+    DestroyPhysicalMonitors,
+    GetCapabilitiesStringLength,
+    GetMonitorBrightness,
+    GetNumberOfPhysicalMonitorsFromHMONITOR,
+    GetPhysicalMonitorsFromHMONITOR,
+    GetVCPFeatureAndVCPFeatureReply,
+    SetMonitorBrightness,
+    SetVCPFeature,
+    PHYSICAL_MONITOR,
+};
+use windows::Win32::Graphics::Gdi::{MonitorFromPoint, MONITOR_DEFAULTTONULL};
+use windows::Win32::Foundation::{E_FAIL, POINT};
+use windows::core::{Error, Result};
+use tracing::{info, error, instrument};
+
+use crate::brightness::Brightness;
+use crate::displays_info::{wide_to_string, DisplayDevice};
+
+//==============================================================================
+// Physical monitor handles
+//==============================================================================
+
+// Handles for the physical monitors behind a display, released on drop
+struct PhysicalMonitors {
+    monitors: Vec<PHYSICAL_MONITOR>,
+}
+
+impl Drop for PhysicalMonitors {
+    fn drop(&mut self) {
+        let _ = unsafe { DestroyPhysicalMonitors(&self.monitors) };
+    }
+}
+
+impl PhysicalMonitors {
+    // A display normally has one physical monitor. Clones of a source share an HMONITOR though,
+    // so use the first one, like Windows' own brightness slider does.
+    fn first(&self) -> &PHYSICAL_MONITOR {
+        &self.monitors[0]
+    }
+}
+
+fn open_physical_monitors(device: &DisplayDevice) -> Result<PhysicalMonitors> {
+    let point = POINT { x: device.position.0, y: device.position.1 };
+    let hmonitor = unsafe { MonitorFromPoint(point, MONITOR_DEFAULTTONULL) };
+    if hmonitor.is_invalid() {
+        return Err(Error::new(E_FAIL, format!("No monitor found at the position of {}", device.device_name)));
+    }
+
+    let mut count: u32 = 0;
+    unsafe { GetNumberOfPhysicalMonitorsFromHMONITOR(hmonitor, &mut count)? };
+    if count == 0 {
+        return Err(Error::new(E_FAIL, format!("{} has no physical monitors", device.device_name)));
+    }
+
+    let mut monitors = vec![PHYSICAL_MONITOR::default(); count as usize];
+    unsafe { GetPhysicalMonitorsFromHMONITOR(hmonitor, &mut monitors)? };
+
+    for monitor in &monitors {
+        // PHYSICAL_MONITOR is packed, so copy the description out before borrowing it
+        let description = monitor.szPhysicalMonitorDescription;
+        info!("Physical monitor for {}: {}", device.device_name, wide_to_string(&description));
+    }
+
+    Ok(PhysicalMonitors { monitors })
+}
+
+//==============================================================================
+// Brightness
+//==============================================================================
+
+pub fn get_brightness(device: &DisplayDevice) -> Result<Brightness> {
+    let monitors = open_physical_monitors(device)?;
+    let mut brightness = Brightness { minimum: 0, current: 0, maximum: 0 };
+
+    let success = unsafe {
+        GetMonitorBrightness(
+            monitors.first().hPhysicalMonitor,
+            &mut brightness.minimum,
+            &mut brightness.current,
+            &mut brightness.maximum,
+        )
+    };
+
+    if success == 0 {
+        error!("GetMonitorBrightness failed for {}", device.device_name);
+        return Err(Error::new(E_FAIL, format!("{} did not answer the DDC/CI brightness query", device.device_name)));
+    }
+
+    Ok(brightness)
+}
+
+pub fn set_brightness(device: &DisplayDevice, percent: u32) -> Result<()> {
+    let brightness = get_brightness(device)?;
+    let value = brightness.minimum + (brightness.maximum.saturating_sub(brightness.minimum) as f64 * percent.min(100) as f64 / 100.0).round() as u32;
+
+    info!("Setting brightness of {} from {} to {} (range {}-{})",
+        device.device_name, brightness.current, value, brightness.minimum, brightness.maximum);
+
+    let monitors = open_physical_monitors(device)?;
+    let success = unsafe { SetMonitorBrightness(monitors.first().hPhysicalMonitor, value) };
+    if success == 0 {
+        error!("SetMonitorBrightness failed for {}", device.device_name);
+        return Err(Error::new(E_FAIL, format!("{} rejected the DDC/CI brightness change", device.device_name)));
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Raw VCP features
+//==============================================================================
+
+// Names for the MCCS codes people actually reach for in prep scripts
+pub fn vcp_code_name(code: u8) -> Option<&'static str> {
+    match code {
+        0x04 => Some("Restore factory defaults"),
+        0x10 => Some("Brightness"),
+        0x12 => Some("Contrast"),
+        0x14 => Some("Select color preset"),
+        0x16 => Some("Red gain"),
+        0x18 => Some("Green gain"),
+        0x1A => Some("Blue gain"),
+        0x60 => Some("Input source"),
+        0x62 => Some("Audio volume"),
+        0x8D => Some("Audio mute"),
+        0xAA => Some("Screen orientation"),
+        0xD6 => Some("Power mode"),
+        0xDC => Some("Display mode"),
+        _ => None,
+    }
+}
+
+// VCP codes are always written in hex, with or without the 0x prefix
+pub fn parse_vcp_code(text: &str) -> std::result::Result<u8, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex VCP code (00-FF)", text))
+}
+
+// Values are decimal unless prefixed with 0x, since input source tables are usually listed in hex
+pub fn parse_vcp_value(text: &str) -> std::result::Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse::<u32>(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a valid VCP value", text))
+}
+
+pub struct VcpValue {
+    pub current: u32,
+    pub maximum: u32,
+}
+
+pub fn get_vcp(device: &DisplayDevice, code: u8) -> Result<VcpValue> {
+    let monitors = open_physical_monitors(device)?;
+    let mut value = VcpValue { current: 0, maximum: 0 };
+
+    let success = unsafe {
+        GetVCPFeatureAndVCPFeatureReply(
+            monitors.first().hPhysicalMonitor,
+            code,
+            None,
+            &mut value.current,
+            Some(&mut value.maximum),
+        )
+    };
+
+    if success == 0 {
+        error!("GetVCPFeatureAndVCPFeatureReply failed for {} code {:02X}", device.device_name, code);
+        return Err(Error::new(E_FAIL, format!("{} did not answer VCP code {:02X}", device.device_name, code)));
+    }
+
+    Ok(value)
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, code = %format!("{:02X}", code), value = value))]
+pub fn set_vcp(device: &DisplayDevice, code: u8, value: u32) -> Result<()> {
+    info!("Setting VCP code {:02X} of {} to {}", code, device.device_name, value);
+
+    let monitors = open_physical_monitors(device)?;
+    let success = unsafe { SetVCPFeature(monitors.first().hPhysicalMonitor, code, value) };
+    if success == 0 {
+        error!("SetVCPFeature failed for {} code {:02X}", device.device_name, code);
+        return Err(Error::new(E_FAIL, format!("{} rejected VCP code {:02X} = {}", device.device_name, code, value)));
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Capabilities string
+//==============================================================================
+
+pub struct VcpCapability {
+    pub code: u8,
+    // Allowed values for non-continuous features, empty when any value in range is accepted
+    pub values: Vec<u8>,
+}
+
+pub struct Capabilities {
+    pub raw: String,
+    pub model: Option<String>,
+    pub mccs_version: Option<String>,
+    pub vcp: Vec<VcpCapability>,
+}
+
+// The string is a tree of "name(value)" groups, e.g.
+// (prot(monitor)type(lcd)model(U2720Q)vcp(02 10 12 60(0F 11 1B) D6(01 04))mccs_ver(2.1))
+fn split_groups(text: &str) -> Vec<(&str, &str)> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut name_start = 0;
+    let mut value_start = 0;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => {
+                if depth == 0 {
+                    value_start = index + 1;
+                }
+                depth += 1;
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push((text[name_start..value_start - 1].trim(), &text[value_start..index]));
+                    name_start = index + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    groups
+}
+
+fn parse_vcp_list(list: &str) -> Vec<VcpCapability> {
+    let mut capabilities = Vec::new();
+    let mut rest = list.trim();
+
+    while !rest.is_empty() {
+        let code_end = rest.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(rest.len());
+        let Ok(code) = u8::from_str_radix(&rest[..code_end], 16) else {
+            break;
+        };
+        rest = rest[code_end..].trim_start();
+
+        let mut values = Vec::new();
+        if rest.starts_with('(') {
+            let value_end = rest.find(')').unwrap_or(rest.len());
+            values = rest[1..value_end]
+                .split_whitespace()
+                .filter_map(|v| u8::from_str_radix(v, 16).ok())
+                .collect();
+            rest = rest.get(value_end + 1..).unwrap_or("").trim_start();
+        }
+
+        capabilities.push(VcpCapability { code, values });
+    }
+
+    capabilities
+}
+
+pub fn parse_capabilities(raw: &str) -> Capabilities {
+    let trimmed = raw.trim();
+    // The whole string is usually wrapped in one more pair of parentheses
+    let inner = match split_groups(trimmed).as_slice() {
+        [("", inner)] => *inner,
+        _ => trimmed,
+    };
+
+    let mut capabilities = Capabilities {
+        raw: raw.to_string(),
+        model: None,
+        mccs_version: None,
+        vcp: Vec::new(),
+    };
+
+    for (name, value) in split_groups(inner) {
+        match name.to_ascii_lowercase().as_str() {
+            "model" => capabilities.model = Some(value.to_string()),
+            "mccs_ver" => capabilities.mccs_version = Some(value.to_string()),
+            "vcp" => capabilities.vcp = parse_vcp_list(value),
+            _ => {}
+        }
+    }
+
+    capabilities
+}
+
+pub fn get_capabilities(device: &DisplayDevice) -> Result<Capabilities> {
+    let monitors = open_physical_monitors(device)?;
+    let handle = monitors.first().hPhysicalMonitor;
+
+    // Both calls go over the slow DDC/CI bus, the reply can take a second or two
+    let mut length: u32 = 0;
+    if unsafe { GetCapabilitiesStringLength(handle, &mut length) } == 0 || length == 0 {
+        error!("GetCapabilitiesStringLength failed for {}", device.device_name);
+        return Err(Error::new(E_FAIL, format!("{} did not report a DDC/CI capabilities string", device.device_name)));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    if unsafe { CapabilitiesRequestAndCapabilitiesReply(handle, &mut buffer) } == 0 {
+        error!("CapabilitiesRequestAndCapabilitiesReply failed for {}", device.device_name);
+        return Err(Error::new(E_FAIL, format!("{} did not return its DDC/CI capabilities string", device.device_name)));
+    }
+
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Ok(parse_capabilities(&String::from_utf8_lossy(&buffer[..end])))
+}
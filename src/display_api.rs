@@ -0,0 +1,1563 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use windows::Win32::Devices::Display::{
+    DisplayConfigGetDeviceInfo,
+    DisplayConfigSetDeviceInfo,
+    GetDisplayConfigBufferSizes,
+    QueryDisplayConfig,
+    SetDisplayConfig,
+    DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+    DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+    DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    DISPLAYCONFIG_MODE_INFO,
+    DISPLAYCONFIG_PATH_INFO,
+    DISPLAYCONFIG_SOURCE_DEVICE_NAME,
+    DISPLAYCONFIG_TARGET_DEVICE_NAME,
+    QUERY_DISPLAY_CONFIG_FLAGS,
+    SET_DISPLAY_CONFIG_FLAGS,
+};
+use windows::Win32::Foundation::{LocalFree, ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS, HLOCAL, LPARAM, LUID};
+use windows::Win32::Graphics::Gdi::{
+    ChangeDisplaySettingsExW,
+    CreateDCW,
+    DeleteDC,
+    EnumDisplayDevicesW,
+    EnumDisplaySettingsW,
+    CDS_UPDATEREGISTRY,
+    DEVMODEW,
+    DISPLAY_DEVICEW,
+    DISP_CHANGE,
+    DISP_CHANGE_SUCCESSFUL,
+    DM_INTERLACED,
+    ENUM_CURRENT_SETTINGS,
+    ENUM_DISPLAY_SETTINGS_MODE,
+};
+use windows::Win32::UI::ColorSystem::{
+    ColorProfileAddDisplayAssociation,
+    ColorProfileGetDisplayDefault,
+    ColorProfileRemoveDisplayAssociation,
+    ColorProfileSetDisplayDefaultAssociation,
+    EnumICMProfilesW,
+    COLORPROFILESUBTYPE,
+    CPST_EXTENDED_DISPLAY_COLOR_MODE,
+    CPST_RGB_WORKING_SPACE,
+    CPT_ICC,
+    WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+};
+use windows::core::{PCWSTR, Result};
+use tracing::{debug, error};
+
+use crate::displays_info::{self, wide_to_string, DisplayMode, FixedOutput, TargetDeviceName};
+use crate::timings;
+
+// The Win32 display calls everything else is built on. Commands go through api(), so the logic
+// around these calls (selectors, mode matching, layout changes) can run against a mock, in tests
+// or with --mock <fixture.json>.
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+// One EnumDisplayDevices entry, an adapter output that may or may not have a display attached
+pub struct DisplayDeviceEntry {
+    pub device_index: u32,
+    pub device_name: String,
+    pub device_string: String,
+    pub state_flags: u32,
+    pub device_id: String,
+}
+
+// What EnumDisplaySettings reports for ENUM_CURRENT_SETTINGS
+pub struct CurrentSettings {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+    pub position: (i32, i32),
+    pub orientation: u32,
+}
+
+//==============================================================================
+// DisplayApi
+//==============================================================================
+
+pub trait DisplayApi: Sync {
+    // EnumDisplayDevicesW over every adapter output
+    fn display_devices(&self) -> Vec<DisplayDeviceEntry>;
+
+    // EnumDisplaySettingsW with ENUM_CURRENT_SETTINGS
+    fn current_settings(&self, device_name: &str) -> Option<CurrentSettings>;
+
+    // EnumDisplaySettingsW over every mode index, without duplicates. Interlaced and scaled
+    // variants of a mode are kept apart.
+    fn display_modes(&self, device_name: &str) -> Vec<DisplayMode>;
+
+    // ChangeDisplaySettingsExW with CDS_UPDATEREGISTRY, waiting for the display to settle on success
+    fn change_display_settings(&self, device_name: &str, dev_mode: &DEVMODEW) -> DISP_CHANGE;
+
+    // GetDisplayConfigBufferSizes + QueryDisplayConfig
+    fn query_display_config(&self, flags: QUERY_DISPLAY_CONFIG_FLAGS) -> Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)>;
+
+    // SetDisplayConfig, returning its Win32 code
+    fn set_display_config(&self, paths: Option<&[DISPLAYCONFIG_PATH_INFO]>, modes: Option<&[DISPLAYCONFIG_MODE_INFO]>, flags: SET_DISPLAY_CONFIG_FLAGS) -> i32;
+
+    // DisplayConfigGetDeviceInfo for a source's GDI device name (\\.\DISPLAY1)
+    fn source_gdi_name(&self, adapter_id: LUID, source_id: u32) -> Option<String>;
+
+    // DisplayConfigGetDeviceInfo for a target's monitor name and device path
+    fn target_device_name(&self, adapter_id: LUID, target_id: u32) -> Option<TargetDeviceName>;
+
+    // DisplayConfigGetDeviceInfo, returning its Win32 code. The header must start a request of header.size bytes.
+    fn get_device_info(&self, header: &mut DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32;
+
+    // DisplayConfigSetDeviceInfo, returning its Win32 code. The header must start a request of header.size bytes.
+    fn set_device_info(&self, header: &DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32;
+
+    // EnumICMProfilesW on the display's DC, the paths of the ICC profiles associated with it
+    fn icc_profiles(&self, device_name: &str) -> Vec<PathBuf>;
+
+    // ColorProfileGetDisplayDefault for the current user, the file name of the default profile
+    fn default_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String>;
+
+    // ColorProfileSetDisplayDefaultAssociation for the current user
+    fn set_default_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()>;
+
+    // ColorProfileGetDisplayDefault for the current user's advanced color profile, the one
+    // Windows uses while HDR is on
+    fn default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String>;
+
+    // ColorProfileAddDisplayAssociation for the current user, as the default advanced color profile
+    fn set_default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()>;
+
+    // ColorProfileAddDisplayAssociation for the current user, adding the profile to the display's
+    // list without making it the default
+    fn associate_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()>;
+
+    // ColorProfileRemoveDisplayAssociation for the current user, from the advanced color list too
+    fn remove_icc_association(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()>;
+
+    // Whether changes are only printed, for --dry-run
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+}
+
+static WIN32: Win32DisplayApi = Win32DisplayApi;
+static MOCK: OnceLock<&'static dyn DisplayApi> = OnceLock::new();
+static DRY_RUN: OnceLock<&'static dyn DisplayApi> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static TEST_API: std::cell::Cell<Option<&'static dyn DisplayApi>> = const { std::cell::Cell::new(None) };
+}
+
+pub fn api() -> &'static dyn DisplayApi {
+    #[cfg(test)]
+    if let Some(api) = TEST_API.with(|api| api.get()) {
+        return api;
+    }
+    if let Some(api) = DRY_RUN.get() {
+        return *api;
+    }
+    match MOCK.get() {
+        Some(api) => *api,
+        None => &WIN32,
+    }
+}
+
+// Points api() at a mock for the rest of the process, for --mock
+pub fn use_mock(api: &'static dyn DisplayApi) {
+    let _ = MOCK.set(api);
+}
+
+// Points api() at a dry run of the current API for the rest of the process, for --dry-run. Goes
+// after use_mock, so a fixture can be dry run too.
+pub fn use_dry_run() {
+    let dry_run: &'static dry_run::DryRunDisplayApi = Box::leak(Box::new(dry_run::DryRunDisplayApi::new(api())));
+    let _ = DRY_RUN.set(dry_run);
+}
+
+pub fn is_dry_run() -> bool {
+    api().is_dry_run()
+}
+
+// Whether api() is a mock, the --mock fixture or a test's
+pub fn is_mock() -> bool {
+    #[cfg(test)]
+    if TEST_API.with(|api| api.get()).is_some() {
+        return true;
+    }
+    MOCK.get().is_some()
+}
+
+// The current test's mock, for threads the test starts
+#[cfg(test)]
+pub fn test_api() -> Option<&'static dyn DisplayApi> {
+    TEST_API.with(|api| api.get())
+}
+
+// Points api() at a mock for the rest of the current test. Tests run on their own threads.
+#[cfg(test)]
+pub fn use_test_api(api: &'static dyn DisplayApi) {
+    TEST_API.with(|current| current.set(Some(api)));
+    crate::displays_info::invalidate_topology();
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+//==============================================================================
+// Win32 implementation
+//==============================================================================
+
+pub struct Win32DisplayApi;
+
+// GetDisplayConfigBufferSizes + QueryDisplayConfig, timed as one step
+fn query_display_config(flags: QUERY_DISPLAY_CONFIG_FLAGS) -> Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)> {
+    let mut path_count: u32 = 0;
+    let mut mode_count: u32 = 0;
+
+    // The topology can change between the two calls, so retry if the buffers turn out too small
+    loop {
+        let result = unsafe { GetDisplayConfigBufferSizes(flags, &mut path_count, &mut mode_count) };
+        if result != ERROR_SUCCESS {
+            error!(win32_code = result.0, "GetDisplayConfigBufferSizes failed with code: {:?}", result);
+            return Err(result.into());
+        }
+
+        let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+        let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+
+        let result = unsafe {
+            QueryDisplayConfig(
+                flags,
+                &mut path_count,
+                paths.as_mut_ptr(),
+                &mut mode_count,
+                modes.as_mut_ptr(),
+                None,
+            )
+        };
+
+        if result == ERROR_INSUFFICIENT_BUFFER {
+            continue;
+        }
+
+        if result != ERROR_SUCCESS {
+            error!(win32_code = result.0, "QueryDisplayConfig failed with code: {:?}", result);
+            return Err(result.into());
+        }
+
+        debug!("QueryDisplayConfig({:?}) returned {} path(s) and {} mode(s)", flags, path_count, mode_count);
+        paths.truncate(path_count as usize);
+        modes.truncate(mode_count as usize);
+        return Ok((paths, modes));
+    }
+}
+
+impl DisplayApi for Win32DisplayApi {
+    fn display_devices(&self) -> Vec<DisplayDeviceEntry> {
+        let mut devices = Vec::new();
+        let mut device_index: u32 = 0;
+
+        loop {
+            let mut display_device = DISPLAY_DEVICEW {
+                cb: size_of::<DISPLAY_DEVICEW>() as u32,
+                ..Default::default()
+            };
+            if !unsafe { EnumDisplayDevicesW(PCWSTR::null(), device_index, &mut display_device, 0) }.as_bool() {
+                break;
+            }
+
+            devices.push(DisplayDeviceEntry {
+                device_index,
+                device_name: wide_to_string(&display_device.DeviceName),
+                device_string: wide_to_string(&display_device.DeviceString),
+                state_flags: display_device.StateFlags,
+                device_id: wide_to_string(&display_device.DeviceID),
+            });
+            device_index += 1;
+        }
+
+        devices
+    }
+
+    fn current_settings(&self, device_name: &str) -> Option<CurrentSettings> {
+        let device_name = to_wide(device_name);
+        let mut dev_mode = DEVMODEW {
+            dmSize: size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+        if !unsafe { EnumDisplaySettingsW(PCWSTR::from_raw(device_name.as_ptr()), ENUM_CURRENT_SETTINGS, &mut dev_mode) }.as_bool() {
+            return None;
+        }
+
+        let (position, orientation) = unsafe {
+            let display_fields = dev_mode.Anonymous1.Anonymous2;
+            ((display_fields.dmPosition.x, display_fields.dmPosition.y), display_fields.dmDisplayOrientation.0)
+        };
+        Some(CurrentSettings {
+            width: dev_mode.dmPelsWidth,
+            height: dev_mode.dmPelsHeight,
+            refresh_rate: dev_mode.dmDisplayFrequency,
+            position,
+            orientation,
+        })
+    }
+
+    fn display_modes(&self, device_name: &str) -> Vec<DisplayMode> {
+        let device_name = to_wide(device_name);
+        let mut modes = HashSet::new();
+        let mut mode_num: u32 = 0;
+
+        loop {
+            let mut dev_mode = DEVMODEW {
+                dmSize: size_of::<DEVMODEW>() as u16,
+                ..Default::default()
+            };
+            if !unsafe { EnumDisplaySettingsW(PCWSTR::from_raw(device_name.as_ptr()), ENUM_DISPLAY_SETTINGS_MODE(mode_num), &mut dev_mode) }.as_bool() {
+                break;
+            }
+
+            let (display_flags, fixed_output) = unsafe {
+                (dev_mode.Anonymous2.dmDisplayFlags, dev_mode.Anonymous1.Anonymous2.dmDisplayFixedOutput.0)
+            };
+            modes.insert(DisplayMode {
+                width: dev_mode.dmPelsWidth,
+                height: dev_mode.dmPelsHeight,
+                refresh_rate: dev_mode.dmDisplayFrequency,
+                interlaced: display_flags & DM_INTERLACED.0 != 0,
+                fixed_output: FixedOutput::from_devmode(fixed_output),
+            });
+            mode_num += 1;
+        }
+
+        modes.into_iter().collect()
+    }
+
+    fn change_display_settings(&self, device_name: &str, dev_mode: &DEVMODEW) -> DISP_CHANGE {
+        let device_name_wide = to_wide(device_name);
+        let result = timings::time("mode_switch", device_name, || unsafe {
+            ChangeDisplaySettingsExW(
+                PCWSTR::from_raw(device_name_wide.as_ptr()),
+                Some(dev_mode),
+                None,
+                CDS_UPDATEREGISTRY,
+                None,
+            )
+        });
+
+        if result == DISP_CHANGE_SUCCESSFUL {
+            displays_info::settle_after_mode_switch(device_name);
+        }
+        result
+    }
+
+    fn query_display_config(&self, flags: QUERY_DISPLAY_CONFIG_FLAGS) -> Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)> {
+        timings::time("query_display_config", format!("{:?}", flags), || query_display_config(flags))
+    }
+
+    fn set_display_config(&self, paths: Option<&[DISPLAYCONFIG_PATH_INFO]>, modes: Option<&[DISPLAYCONFIG_MODE_INFO]>, flags: SET_DISPLAY_CONFIG_FLAGS) -> i32 {
+        timings::time("set_display_config", format!("{:?}", flags), || unsafe { SetDisplayConfig(paths, modes, flags) })
+    }
+
+    fn source_gdi_name(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+        let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME::default();
+        source_name.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME;
+        source_name.header.size = size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32;
+        source_name.header.adapterId = adapter_id;
+        source_name.header.id = source_id;
+
+        let result = unsafe { DisplayConfigGetDeviceInfo(&mut source_name.header) };
+        if result != ERROR_SUCCESS.0 as i32 {
+            error!(win32_code = result, "DisplayConfigGetDeviceInfo (source name) failed for source {} with code: {}", source_id, result);
+            return None;
+        }
+        Some(wide_to_string(&source_name.viewGdiDeviceName))
+    }
+
+    fn target_device_name(&self, adapter_id: LUID, target_id: u32) -> Option<TargetDeviceName> {
+        let mut target_name = DISPLAYCONFIG_TARGET_DEVICE_NAME::default();
+        target_name.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME;
+        target_name.header.size = size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32;
+        target_name.header.adapterId = adapter_id;
+        target_name.header.id = target_id;
+
+        let result = unsafe { DisplayConfigGetDeviceInfo(&mut target_name.header) };
+        if result != ERROR_SUCCESS.0 as i32 {
+            error!(win32_code = result, "DisplayConfigGetDeviceInfo (target name) failed for target {} with code: {}", target_id, result);
+            return None;
+        }
+
+        Some(TargetDeviceName {
+            friendly_name: wide_to_string(&target_name.monitorFriendlyDeviceName),
+            device_path: wide_to_string(&target_name.monitorDevicePath),
+        })
+    }
+
+    fn get_device_info(&self, header: &mut DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+        unsafe { DisplayConfigGetDeviceInfo(header) }
+    }
+
+    fn set_device_info(&self, header: &DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+        timings::time("set_device_info", format!("{:?}", header.r#type), || unsafe { DisplayConfigSetDeviceInfo(header) })
+    }
+
+    fn icc_profiles(&self, device_name: &str) -> Vec<PathBuf> {
+        let device_name_wide = to_wide(device_name);
+        let mut profiles: Vec<PathBuf> = Vec::new();
+
+        unsafe {
+            let dc = CreateDCW(PCWSTR::from_raw(device_name_wide.as_ptr()), PCWSTR::null(), PCWSTR::null(), None);
+            if dc.is_invalid() {
+                error!("Failed to create DC for display {}", device_name);
+                return profiles;
+            }
+
+            extern "system" fn enum_profiles_callback(profile_name: PCWSTR, param: LPARAM) -> i32 {
+                unsafe {
+                    let profiles = &mut *(param.0 as *mut Vec<PathBuf>);
+                    profiles.push(PathBuf::from(profile_name.to_string().unwrap_or_default()));
+                }
+                1 // Return 1 to keep enumerating
+            }
+
+            let result = EnumICMProfilesW(dc, Some(enum_profiles_callback), LPARAM(&mut profiles as *mut _ as isize));
+            let _ = DeleteDC(dc);
+
+            if result == 0 {
+                error!("Enumeration of ICC profiles was interrupted");
+            }
+        }
+
+        profiles
+    }
+
+    fn default_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+        display_default_profile(adapter_id, source_id, CPST_RGB_WORKING_SPACE)
+    }
+
+    fn set_default_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+        let profile_path_wide = to_wide(&profile_path.to_string_lossy());
+        unsafe {
+            ColorProfileSetDisplayDefaultAssociation(
+                WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+                PCWSTR::from_raw(profile_path_wide.as_ptr()),
+                CPT_ICC,
+                CPST_RGB_WORKING_SPACE,
+                adapter_id,
+                source_id,
+            )
+        }
+    }
+
+    fn default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+        display_default_profile(adapter_id, source_id, CPST_EXTENDED_DISPLAY_COLOR_MODE)
+    }
+
+    fn set_default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+        let profile_path_wide = to_wide(&profile_path.to_string_lossy());
+        unsafe {
+            ColorProfileAddDisplayAssociation(
+                WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+                PCWSTR::from_raw(profile_path_wide.as_ptr()),
+                adapter_id,
+                source_id,
+                true,
+                true,
+            )
+        }
+    }
+
+    fn associate_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+        let profile_path_wide = to_wide(&profile_path.to_string_lossy());
+        unsafe {
+            ColorProfileAddDisplayAssociation(
+                WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+                PCWSTR::from_raw(profile_path_wide.as_ptr()),
+                adapter_id,
+                source_id,
+                false,
+                false,
+            )
+        }
+    }
+
+    fn remove_icc_association(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+        let profile_path_wide = to_wide(&profile_path.to_string_lossy());
+        unsafe {
+            ColorProfileRemoveDisplayAssociation(
+                WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+                PCWSTR::from_raw(profile_path_wide.as_ptr()),
+                adapter_id,
+                source_id,
+                true,
+            )
+        }
+    }
+}
+
+// The file name of a display's default profile of one subtype
+fn display_default_profile(adapter_id: LUID, source_id: u32, subtype: COLORPROFILESUBTYPE) -> Option<String> {
+    unsafe {
+        let profile = ColorProfileGetDisplayDefault(
+            WCS_PROFILE_MANAGEMENT_SCOPE_CURRENT_USER,
+            adapter_id,
+            source_id,
+            CPT_ICC,
+            subtype,
+        ).ok()?;
+
+        let name = profile.to_string().ok();
+        let _ = LocalFree(HLOCAL(profile.0 as *mut core::ffi::c_void));
+        name.filter(|name| !name.is_empty())
+    }
+}
+
+//==============================================================================
+// Dry run, for --dry-run
+//==============================================================================
+
+pub mod dry_run {
+    use std::mem::size_of;
+    use std::path::{Path, PathBuf};
+    use windows::Win32::Devices::Display::{
+        DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+        DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+        DISPLAYCONFIG_DEVICE_INFO_HEADER,
+        DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE,
+        DISPLAYCONFIG_DEVICE_INFO_TYPE,
+        DISPLAYCONFIG_MODE_INFO,
+        DISPLAYCONFIG_PATH_INFO,
+        QDC_ONLY_ACTIVE_PATHS,
+        QUERY_DISPLAY_CONFIG_FLAGS,
+        SDC_APPLY,
+        SDC_SAVE_TO_DATABASE,
+        SDC_VALIDATE,
+        SET_DISPLAY_CONFIG_FLAGS,
+    };
+    use windows::Win32::Foundation::{ERROR_SUCCESS, LUID};
+    use windows::Win32::Graphics::Gdi::{
+        DEVMODEW,
+        DISPLAYCONFIG_PATH_ACTIVE,
+        DISP_CHANGE,
+        DISP_CHANGE_SUCCESSFUL,
+        DM_DISPLAYFREQUENCY,
+        DM_DISPLAYORIENTATION,
+        DM_PELSHEIGHT,
+        DM_PELSWIDTH,
+        DM_POSITION,
+    };
+    use windows::core::Result;
+
+    use super::{CurrentSettings, DisplayApi, DisplayDeviceEntry};
+    use crate::displays_info::{DisplayMode, TargetDeviceName};
+    use crate::dpi_scaling::{DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE, DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE, DPI_SCALE_STEPS};
+    use crate::hdr::ADVANCED_COLOR_ENABLED;
+    use crate::set_sdr_level::{self, DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL};
+
+    // Reads go to the real API (or the --mock fixture), every change is printed with the value it
+    // would replace and reported as done. SetDisplayConfig is still validated, with SDC_VALIDATE.
+    pub struct DryRunDisplayApi {
+        inner: &'static dyn DisplayApi,
+    }
+
+    impl DryRunDisplayApi {
+        pub fn new(inner: &'static dyn DisplayApi) -> Self {
+            DryRunDisplayApi { inner }
+        }
+
+        fn report(&self, call: String) {
+            eprintln!("[dry run] {}", call);
+        }
+
+        fn source_name(&self, adapter_id: LUID, source_id: u32) -> String {
+            self.inner.source_gdi_name(adapter_id, source_id).unwrap_or_else(|| format!("source {}", source_id))
+        }
+
+        fn target_name(&self, adapter_id: LUID, target_id: u32) -> String {
+            self.inner.target_device_name(adapter_id, target_id).map(|target| target.friendly_name)
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| format!("target {}", target_id))
+        }
+
+        // The current value of a DisplayConfig setting, asked for with a get request of the same shape
+        fn current<T: Copy + Default>(&self, request_type: DISPLAYCONFIG_DEVICE_INFO_TYPE, adapter_id: LUID, id: u32) -> Option<T> {
+            #[repr(C)]
+            struct Request<T> {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+                payload: T,
+            }
+            let mut request = Request {
+                header: DISPLAYCONFIG_DEVICE_INFO_HEADER { r#type: request_type, size: size_of::<Request<T>>() as u32, adapterId: adapter_id, id },
+                payload: T::default(),
+            };
+            (self.inner.get_device_info(&mut request.header) == ERROR_SUCCESS.0 as i32).then_some(request.payload)
+        }
+
+        // The active displays of a path set, each where its source mode puts it
+        fn layout(&self, paths: &[DISPLAYCONFIG_PATH_INFO], modes: &[DISPLAYCONFIG_MODE_INFO]) -> String {
+            let active: Vec<String> = paths.iter().filter(|path| path.flags & DISPLAYCONFIG_PATH_ACTIVE != 0).map(|path| {
+                let name = self.source_name(path.sourceInfo.adapterId, path.sourceInfo.id);
+                let index = unsafe { path.sourceInfo.Anonymous.modeInfoIdx };
+                match modes.get(index as usize) {
+                    Some(mode) => {
+                        let position = unsafe { mode.Anonymous.sourceMode.position };
+                        format!("{} at ({}, {})", name, position.x, position.y)
+                    }
+                    None => format!("{} where Windows puts it", name),
+                }
+            }).collect();
+            if active.is_empty() { "no displays".to_string() } else { active.join(", ") }
+        }
+    }
+
+    fn describe_settings(width: u32, height: u32, refresh_rate: u32) -> String {
+        format!("{}x{} @{}Hz", width, height, refresh_rate)
+    }
+
+    fn scale_percent(recommended: i32, step: i32) -> String {
+        usize::try_from(recommended + step).ok().and_then(|index| DPI_SCALE_STEPS.get(index))
+            .map_or_else(|| format!("step {:+}", step), |percent| format!("{}%", percent))
+    }
+
+    impl DisplayApi for DryRunDisplayApi {
+        fn display_devices(&self) -> Vec<DisplayDeviceEntry> {
+            self.inner.display_devices()
+        }
+
+        fn current_settings(&self, device_name: &str) -> Option<CurrentSettings> {
+            self.inner.current_settings(device_name)
+        }
+
+        fn display_modes(&self, device_name: &str) -> Vec<DisplayMode> {
+            self.inner.display_modes(device_name)
+        }
+
+        fn change_display_settings(&self, device_name: &str, dev_mode: &DEVMODEW) -> DISP_CHANGE {
+            let current = self.inner.current_settings(device_name);
+            let old = current.as_ref().map_or_else(|| "unknown".to_string(), |current| describe_settings(current.width, current.height, current.refresh_rate));
+            let pick = |field, new: u32, old: Option<u32>| if dev_mode.dmFields.0 & field != 0 { new } else { old.unwrap_or(new) };
+            let new = describe_settings(
+                pick(DM_PELSWIDTH.0, dev_mode.dmPelsWidth, current.as_ref().map(|current| current.width)),
+                pick(DM_PELSHEIGHT.0, dev_mode.dmPelsHeight, current.as_ref().map(|current| current.height)),
+                pick(DM_DISPLAYFREQUENCY.0, dev_mode.dmDisplayFrequency, current.as_ref().map(|current| current.refresh_rate)),
+            );
+            let mut extra = String::new();
+            if dev_mode.dmFields.0 & DM_POSITION.0 != 0 {
+                let position = unsafe { dev_mode.Anonymous1.Anonymous2.dmPosition };
+                extra.push_str(&format!(", position ({}, {})", position.x, position.y));
+            }
+            if dev_mode.dmFields.0 & DM_DISPLAYORIENTATION.0 != 0 {
+                let orientation = unsafe { dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation };
+                extra.push_str(&format!(", rotated {} degrees", orientation.0 * 90));
+            }
+            self.report(format!("ChangeDisplaySettingsExW({}, fields {:#x}, CDS_UPDATEREGISTRY): {} -> {}{}", device_name, dev_mode.dmFields.0, old, new, extra));
+            DISP_CHANGE_SUCCESSFUL
+        }
+
+        fn query_display_config(&self, flags: QUERY_DISPLAY_CONFIG_FLAGS) -> Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)> {
+            self.inner.query_display_config(flags)
+        }
+
+        // Windows checks the configuration without applying it, so a dry run fails where the real one would
+        fn set_display_config(&self, paths: Option<&[DISPLAYCONFIG_PATH_INFO]>, modes: Option<&[DISPLAYCONFIG_MODE_INFO]>, flags: SET_DISPLAY_CONFIG_FLAGS) -> i32 {
+            let old = self.inner.query_display_config(QDC_ONLY_ACTIVE_PATHS)
+                .map_or_else(|_| "unknown".to_string(), |(paths, modes)| self.layout(&paths, &modes));
+            let new = match (paths, modes) {
+                (Some(paths), Some(modes)) => self.layout(paths, modes),
+                _ => "the topology Windows picks".to_string(),
+            };
+            let result = self.inner.set_display_config(paths, modes, (flags & !(SDC_APPLY | SDC_SAVE_TO_DATABASE)) | SDC_VALIDATE);
+            self.report(format!("SetDisplayConfig(flags {:#x}): {} -> {}{}", flags.0, old, new,
+                if result == ERROR_SUCCESS.0 as i32 { String::new() } else { format!(" (rejected with code {})", result) }));
+            result
+        }
+
+        fn source_gdi_name(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.inner.source_gdi_name(adapter_id, source_id)
+        }
+
+        fn target_device_name(&self, adapter_id: LUID, target_id: u32) -> Option<TargetDeviceName> {
+            self.inner.target_device_name(adapter_id, target_id)
+        }
+
+        fn get_device_info(&self, header: &mut DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+            self.inner.get_device_info(header)
+        }
+
+        fn set_device_info(&self, header: &DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+            let (adapter_id, id) = (header.adapterId, header.id);
+            // The value follows the header, every set request this helper makes has one
+            let value = unsafe { *(header as *const DISPLAYCONFIG_DEVICE_INFO_HEADER).add(1).cast::<u32>() };
+            let change = match header.r#type {
+                DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE => {
+                    let old = self.current::<[u32; 3]>(DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO, adapter_id, id)
+                        .map_or("unknown", |info| if info[0] & ADVANCED_COLOR_ENABLED != 0 { "on" } else { "off" });
+                    format!("SET_ADVANCED_COLOR_STATE, {}): HDR {} -> {}", self.target_name(adapter_id, id), old, if value & 1 != 0 { "on" } else { "off" })
+                }
+                DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL => {
+                    let old = self.current::<u32>(DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL, adapter_id, id)
+                        .map_or_else(|| "unknown".to_string(), |raw| format!("{} nits (raw {})", set_sdr_level::raw_to_nits(raw), raw));
+                    format!("SET_SDR_WHITE_LEVEL, {}): {} -> {} nits (raw {})", self.target_name(adapter_id, id), old, set_sdr_level::raw_to_nits(value), value)
+                }
+                DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE => {
+                    // Steps are relative to the recommended scale, which the lowest step gives away
+                    let steps = self.current::<[i32; 3]>(DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE, adapter_id, id);
+                    let recommended = steps.map_or(0, |steps| -steps[0]);
+                    let old = steps.map_or_else(|| "unknown".to_string(), |steps| scale_percent(recommended, steps[1]));
+                    format!("SET_DPI_SCALE, {}): {} -> {}", self.source_name(adapter_id, id), old, scale_percent(recommended, value as i32))
+                }
+                request_type => format!("type {}, adapter {:08X}:{:08X} id {}): value {:#x}", request_type.0, adapter_id.HighPart, adapter_id.LowPart, id, value),
+            };
+            self.report(format!("DisplayConfigSetDeviceInfo({}", change));
+            ERROR_SUCCESS.0 as i32
+        }
+
+        fn icc_profiles(&self, device_name: &str) -> Vec<PathBuf> {
+            self.inner.icc_profiles(device_name)
+        }
+
+        fn default_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.inner.default_icc_profile(adapter_id, source_id)
+        }
+
+        fn set_default_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let old = self.inner.default_icc_profile(adapter_id, source_id).unwrap_or_else(|| "none".to_string());
+            self.report(format!("ColorProfileSetDisplayDefaultAssociation({}, {}): {} -> {}",
+                self.source_name(adapter_id, source_id), profile_path.display(), old, file_name(profile_path)));
+            Ok(())
+        }
+
+        fn default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.inner.default_hdr_icc_profile(adapter_id, source_id)
+        }
+
+        fn set_default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let old = self.inner.default_hdr_icc_profile(adapter_id, source_id).unwrap_or_else(|| "none".to_string());
+            self.report(format!("ColorProfileAddDisplayAssociation({}, {}, advanced color default): {} -> {}",
+                self.source_name(adapter_id, source_id), profile_path.display(), old, file_name(profile_path)));
+            Ok(())
+        }
+
+        fn associate_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            self.report(format!("ColorProfileAddDisplayAssociation({}, {}): associate {}",
+                self.source_name(adapter_id, source_id), profile_path.display(), file_name(profile_path)));
+            Ok(())
+        }
+
+        fn remove_icc_association(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            self.report(format!("ColorProfileRemoveDisplayAssociation({}, {}): remove {}",
+                self.source_name(adapter_id, source_id), profile_path.display(), file_name(profile_path)));
+            Ok(())
+        }
+
+        fn is_dry_run(&self) -> bool {
+            true
+        }
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+    }
+}
+
+//==============================================================================
+// Mock implementation, for tests and --mock
+//==============================================================================
+
+pub mod mock {
+    use std::mem::size_of;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use serde::Deserialize;
+    use windows::Win32::Devices::Display::{
+        DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+        DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+        DISPLAYCONFIG_DEVICE_INFO_HEADER,
+        DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE,
+        DISPLAYCONFIG_MODE_INFO,
+        DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE,
+        DISPLAYCONFIG_MODE_INFO_TYPE_TARGET,
+        DISPLAYCONFIG_PATH_INFO,
+        QDC_ALL_PATHS,
+        QUERY_DISPLAY_CONFIG_FLAGS,
+        SDC_VALIDATE,
+        SET_DISPLAY_CONFIG_FLAGS,
+    };
+    use windows::Win32::Foundation::{BOOL, ERROR_FILE_NOT_FOUND, ERROR_INVALID_PARAMETER, ERROR_NOT_SUPPORTED, LUID, POINTL, WIN32_ERROR};
+    use windows::Win32::Graphics::Gdi::{
+        DEVMODEW,
+        DISPLAYCONFIG_PATH_ACTIVE,
+        DISPLAYCONFIG_PATH_MODE_IDX_INVALID,
+        DISPLAY_DEVICE_ATTACHED_TO_DESKTOP,
+        DISPLAY_DEVICE_PRIMARY_DEVICE,
+        DISP_CHANGE,
+        DISP_CHANGE_BADMODE,
+        DISP_CHANGE_SUCCESSFUL,
+        DM_DISPLAYFLAGS,
+        DM_DISPLAYORIENTATION,
+        DM_INTERLACED,
+        DM_POSITION,
+    };
+    use windows::core::Result;
+
+    use super::{CurrentSettings, DisplayApi, DisplayDeviceEntry};
+    use crate::displays_info::{DisplayMode, FixedOutput, TargetDeviceName};
+    use crate::dpi_scaling::{DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE, DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE, DPI_SCALE_STEPS};
+    use crate::hdr::{ADVANCED_COLOR_ENABLED, ADVANCED_COLOR_SUPPORTED};
+    use crate::set_sdr_level::{self, DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL};
+
+    pub const ADAPTER_ID: LUID = LUID { LowPart: 1, HighPart: 0 };
+    const DEFAULT_ADAPTER: &str = "Mock Display Adapter";
+
+    const COLOR_DIRECTORY: &str = "C:\\Windows\\System32\\spool\\drivers\\color";
+
+    // One adapter output and the monitor on it. Source ids deliberately differ from the
+    // EnumDisplayDevices index, as they do on real multi-GPU systems, and like there they are
+    // only unique together with the adapter.
+    #[derive(Clone, Debug)]
+    pub struct MockDisplay {
+        pub device_name: String,
+        pub description: String,
+        pub adapter_id: LUID,
+        pub monitor_name: String,
+        pub monitor_path: String,
+        pub source_id: u32,
+        pub target_id: u32,
+        pub active: bool,
+        pub primary: bool,
+        pub mode: (u32, u32, u32),
+        pub position: (i32, i32),
+        pub orientation: u32,
+        pub modes: Vec<(u32, u32, u32)>,
+        pub interlaced_modes: Vec<(u32, u32, u32)>,
+        pub hdr_supported: bool,
+        pub hdr_enabled: bool,
+        pub sdr_white_level: u32, // 1000ths of 80 nits, as DisplayConfig reports it
+        pub scaling: u32,
+        pub recommended_scaling: u32,
+        pub icc_profiles: Vec<String>,
+        pub icc_profile: Option<String>,
+        pub hdr_icc_profile: Option<String>,
+    }
+
+    impl MockDisplay {
+        pub fn new(number: u32, monitor_name: &str, mode: (u32, u32, u32)) -> Self {
+            MockDisplay {
+                device_name: format!("\\\\.\\DISPLAY{}", number),
+                description: DEFAULT_ADAPTER.to_string(),
+                adapter_id: ADAPTER_ID,
+                monitor_name: monitor_name.to_string(),
+                monitor_path: format!("\\\\?\\DISPLAY#MCK{:04}#1&0&UID{}#{{e6f07b5f-ee97-4a90-b076-33f57bf4eaa7}}", number, number),
+                source_id: 10 + number,
+                target_id: 100 + number,
+                active: true,
+                primary: false,
+                mode,
+                position: (0, 0),
+                orientation: 0,
+                modes: vec![mode],
+                interlaced_modes: Vec::new(),
+                hdr_supported: false,
+                hdr_enabled: false,
+                sdr_white_level: 3000, // 240 nits, the Windows default
+                scaling: 100,
+                recommended_scaling: 100,
+                icc_profiles: Vec::new(),
+                icc_profile: None,
+                hdr_icc_profile: None,
+            }
+        }
+
+        // The setups most tests start from; tests change the fields they care about
+
+        // An HDR TV as the primary DISPLAY1, with HDR on
+        #[cfg(test)]
+        pub fn hdr_tv() -> Self {
+            let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+            tv.primary = true;
+            tv.hdr_supported = true;
+            tv.hdr_enabled = true;
+            tv
+        }
+
+        // An SDR desk monitor as DISPLAY2, to the right of a 4K DISPLAY1
+        #[cfg(test)]
+        pub fn desk_monitor() -> Self {
+            let mut monitor = MockDisplay::new(2, "DELL U2723QE", (2560, 1440, 60));
+            monitor.position = (3840, 0);
+            monitor
+        }
+
+        fn short_name(&self) -> &str {
+            self.device_name.trim_start_matches("\\\\.\\")
+        }
+    }
+
+    // A --mock fixture lists the displays in EnumDisplayDevices order, so the first one is
+    // DISPLAY1. Only monitor and mode are required:
+    //   { "displays": [{ "monitor": "LG TV SSCR2", "mode": "3840x2160@120", "primary": true }] }
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Fixture {
+        displays: Vec<FixtureDisplay>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct FixtureDisplay {
+        monitor: String,
+        adapter: Option<String>,
+        #[serde(default = "enabled")]
+        active: bool,
+        #[serde(default)]
+        primary: bool,
+        mode: String,
+        #[serde(default)]
+        modes: Vec<String>,
+        #[serde(default)]
+        interlaced_modes: Vec<String>,
+        #[serde(default)]
+        position: (i32, i32),
+        #[serde(default)]
+        hdr_supported: bool,
+        #[serde(default)]
+        hdr: bool,
+        sdr_nits: Option<u32>,
+        scaling: Option<u32>,
+        recommended_scaling: Option<u32>,
+        #[serde(default)]
+        icc_profiles: Vec<String>,
+        icc_profile: Option<String>,
+    }
+
+    fn enabled() -> bool {
+        true
+    }
+
+    fn parse_mode(text: &str) -> std::result::Result<(u32, u32, u32), String> {
+        let mode = text.split_once('x').and_then(|(width, rest)| {
+            let (height, refresh_rate) = rest.split_once('@')?;
+            Some((width.trim().parse().ok()?, height.trim().parse().ok()?, refresh_rate.trim().parse().ok()?))
+        });
+        mode.ok_or_else(|| format!("mode '{}' isn't WIDTHxHEIGHT@HZ", text))
+    }
+
+    impl FixtureDisplay {
+        fn into_mock(self, number: u32) -> std::result::Result<MockDisplay, String> {
+            let mut display = MockDisplay::new(number, &self.monitor, parse_mode(&self.mode)?);
+            if !self.modes.is_empty() {
+                display.modes = self.modes.iter().map(|mode| parse_mode(mode)).collect::<std::result::Result<_, _>>()?;
+                if !display.modes.contains(&display.mode) {
+                    return Err(format!("its mode {} isn't in its modes", self.mode));
+                }
+            }
+            display.interlaced_modes = self.interlaced_modes.iter().map(|mode| parse_mode(mode)).collect::<std::result::Result<_, _>>()?;
+            if let Some(adapter) = self.adapter {
+                display.description = adapter;
+            }
+            display.active = self.active;
+            display.primary = self.primary;
+            display.position = self.position;
+
+            if self.hdr && !self.hdr_supported {
+                return Err("hdr is on but hdr_supported isn't".to_string());
+            }
+            display.hdr_supported = self.hdr_supported;
+            display.hdr_enabled = self.hdr;
+            if let Some(nits) = self.sdr_nits {
+                display.sdr_white_level = 1000 + set_sdr_level::nits_to_level(nits) * 50;
+            }
+
+            display.scaling = self.scaling.unwrap_or(100);
+            display.recommended_scaling = self.recommended_scaling.unwrap_or(display.scaling);
+            for scaling in [display.scaling, display.recommended_scaling] {
+                if !DPI_SCALE_STEPS.contains(&scaling) {
+                    return Err(format!("{}% is not a Windows scaling step", scaling));
+                }
+            }
+
+            if let Some(profile) = &self.icc_profile {
+                if !self.icc_profiles.contains(profile) {
+                    return Err(format!("icc_profile '{}' isn't in its icc_profiles", profile));
+                }
+            }
+            display.icc_profiles = self.icc_profiles;
+            display.icc_profile = self.icc_profile;
+            Ok(display)
+        }
+    }
+
+    pub fn parse_fixture(contents: &str) -> std::result::Result<Vec<MockDisplay>, String> {
+        let fixture: Fixture = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        let mut displays = fixture.displays.into_iter().enumerate()
+            .map(|(index, display)| display.into_mock(index as u32 + 1).map_err(|e| format!("display {}: {}", index + 1, e)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Every adapter name gets its own LUID
+        let mut adapters = vec![DEFAULT_ADAPTER.to_string()];
+        for display in &mut displays {
+            let index = adapters.iter().position(|name| *name == display.description).unwrap_or_else(|| {
+                adapters.push(display.description.clone());
+                adapters.len() - 1
+            });
+            display.adapter_id = LUID { LowPart: ADAPTER_ID.LowPart + index as u32, ..ADAPTER_ID };
+        }
+
+        let primaries: Vec<&MockDisplay> = displays.iter().filter(|d| d.active && d.primary).collect();
+        match primaries.as_slice() {
+            [primary] if primary.position == (0, 0) => Ok(displays),
+            [_] => Err("the primary display must be at position [0, 0]".to_string()),
+            _ => Err("exactly one active display must be primary".to_string()),
+        }
+    }
+
+    pub struct MockDisplayApi {
+        pub displays: Mutex<Vec<MockDisplay>>,
+        pub calls: Mutex<Vec<String>>,
+        echo: bool, // print each change as it's made, for --mock
+    }
+
+    impl MockDisplayApi {
+        // Leaks the mock and points api() at it for the current test
+        #[cfg(test)]
+        pub fn install(displays: Vec<MockDisplay>) -> &'static MockDisplayApi {
+            let api: &'static MockDisplayApi = Box::leak(Box::new(MockDisplayApi { displays: Mutex::new(displays), calls: Mutex::new(Vec::new()), echo: false }));
+            super::use_test_api(api);
+            api
+        }
+
+        pub fn load(path: &Path) -> std::result::Result<MockDisplayApi, String> {
+            let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let displays = parse_fixture(&contents)?;
+            Ok(MockDisplayApi { displays: Mutex::new(displays), calls: Mutex::new(Vec::new()), echo: true })
+        }
+
+        #[cfg(test)]
+        pub fn display(&self, device_name: &str) -> MockDisplay {
+            self.displays.lock().unwrap().iter().find(|d| d.device_name == device_name).cloned().unwrap()
+        }
+
+        #[cfg(test)]
+        pub fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn record(&self, call: String) {
+            if self.echo {
+                eprintln!("[mock] {}", call);
+            }
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    // The fields of a DisplayConfig request after its header, if header.size leaves room for them
+    fn payload<T>(header: *const DISPLAYCONFIG_DEVICE_INFO_HEADER) -> Option<*mut T> {
+        let size = unsafe { (*header).size } as usize;
+        (size >= size_of::<DISPLAYCONFIG_DEVICE_INFO_HEADER>() + size_of::<T>()).then(|| unsafe { header.add(1) } as *mut T)
+    }
+
+    fn scale_index(percent: u32) -> i32 {
+        DPI_SCALE_STEPS.iter().position(|&step| step == percent).unwrap_or(0) as i32
+    }
+
+    // Answers the requests hdr, set_sdr_level and dpi_scaling make
+    fn read_device_info(displays: &[MockDisplay], header: *mut DISPLAYCONFIG_DEVICE_INFO_HEADER) -> std::result::Result<(), WIN32_ERROR> {
+        let (request_type, adapter_id, id) = unsafe { ((*header).r#type, (*header).adapterId, (*header).id) };
+        let by_target = displays.iter().find(|d| d.adapter_id == adapter_id && d.target_id == id);
+        let by_source = displays.iter().find(|d| d.adapter_id == adapter_id && d.source_id == id);
+
+        match request_type {
+            DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO => {
+                let (display, payload) = by_target.zip(payload::<[u32; 3]>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                let mut value = 0;
+                if display.hdr_supported {
+                    value |= ADVANCED_COLOR_SUPPORTED;
+                }
+                if display.hdr_enabled {
+                    value |= ADVANCED_COLOR_ENABLED;
+                }
+                // value, colorEncoding (RGB), bitsPerColorChannel
+                unsafe { *payload = [value, 0, if display.hdr_enabled { 10 } else { 8 }] };
+            }
+            DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL => {
+                let (display, payload) = by_target.zip(payload::<u32>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                unsafe { *payload = display.sdr_white_level };
+            }
+            DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE => {
+                let (display, payload) = by_source.zip(payload::<[i32; 3]>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                let recommended = scale_index(display.recommended_scaling);
+                let steps = [-recommended, scale_index(display.scaling) - recommended, DPI_SCALE_STEPS.len() as i32 - 1 - recommended];
+                unsafe { *payload = steps };
+            }
+            _ => return Err(ERROR_NOT_SUPPORTED),
+        }
+        Ok(())
+    }
+
+    // Applies a set request, returning the call to record
+    fn write_device_info(displays: &mut [MockDisplay], header: &DISPLAYCONFIG_DEVICE_INFO_HEADER) -> std::result::Result<String, WIN32_ERROR> {
+        let (adapter_id, id) = (header.adapterId, header.id);
+        let by_target = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.target_id == id);
+
+        match header.r#type {
+            DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE => {
+                let (display, payload) = by_target.zip(payload::<u32>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                let enabled = unsafe { *payload } & 1 != 0;
+                if enabled && !display.hdr_supported {
+                    return Err(ERROR_NOT_SUPPORTED);
+                }
+                display.hdr_enabled = enabled;
+                Ok(format!("set_hdr {} {}", display.short_name(), if enabled { "on" } else { "off" }))
+            }
+            DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL => {
+                let (display, payload) = by_target.zip(payload::<u32>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                display.sdr_white_level = unsafe { *payload };
+                let raw = display.sdr_white_level;
+                if (1000..=6000).contains(&raw) && raw % 50 == 0 {
+                    let level = (raw - 1000) / 50;
+                    Ok(format!("set_sdr_white_level {} {} ({} nits)", display.short_name(), level, set_sdr_level::level_to_nits(level)))
+                } else {
+                    Ok(format!("set_sdr_white_level {} raw {} ({} nits)", display.short_name(), raw, set_sdr_level::raw_to_nits(raw)))
+                }
+            }
+            DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE => {
+                let display = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.source_id == id);
+                let (display, payload) = display.zip(payload::<i32>(header)).ok_or(ERROR_INVALID_PARAMETER)?;
+                let index = scale_index(display.recommended_scaling) + unsafe { *payload };
+                let scaling = usize::try_from(index).ok().and_then(|index| DPI_SCALE_STEPS.get(index)).ok_or(ERROR_INVALID_PARAMETER)?;
+                display.scaling = *scaling;
+                Ok(format!("set_dpi_scale {} {}%", display.short_name(), display.scaling))
+            }
+            _ => Err(ERROR_NOT_SUPPORTED),
+        }
+    }
+
+    impl DisplayApi for MockDisplayApi {
+        fn display_devices(&self) -> Vec<DisplayDeviceEntry> {
+            self.displays.lock().unwrap().iter().enumerate().map(|(index, display)| {
+                let mut state_flags = 0;
+                if display.active {
+                    state_flags |= DISPLAY_DEVICE_ATTACHED_TO_DESKTOP;
+                }
+                if display.primary {
+                    state_flags |= DISPLAY_DEVICE_PRIMARY_DEVICE;
+                }
+                DisplayDeviceEntry {
+                    device_index: index as u32,
+                    device_name: display.device_name.clone(),
+                    device_string: display.description.clone(),
+                    state_flags,
+                    device_id: "PCI\\VEN_1234&DEV_5678".to_string(),
+                }
+            }).collect()
+        }
+
+        fn current_settings(&self, device_name: &str) -> Option<CurrentSettings> {
+            let displays = self.displays.lock().unwrap();
+            let display = displays.iter().find(|d| d.device_name == device_name && d.active)?;
+            Some(CurrentSettings {
+                width: display.mode.0,
+                height: display.mode.1,
+                refresh_rate: display.mode.2,
+                position: display.position,
+                orientation: display.orientation,
+            })
+        }
+
+        fn display_modes(&self, device_name: &str) -> Vec<DisplayMode> {
+            let displays = self.displays.lock().unwrap();
+            displays.iter().find(|d| d.device_name == device_name).map(|display| {
+                let progressive = display.modes.iter().map(|&mode| (mode, false));
+                let interlaced = display.interlaced_modes.iter().map(|&mode| (mode, true));
+                progressive.chain(interlaced).map(|((width, height, refresh_rate), interlaced)| {
+                    DisplayMode { width, height, refresh_rate, interlaced, fixed_output: FixedOutput::Default }
+                }).collect()
+            }).unwrap_or_default()
+        }
+
+        fn change_display_settings(&self, device_name: &str, dev_mode: &DEVMODEW) -> DISP_CHANGE {
+            let mode = (dev_mode.dmPelsWidth, dev_mode.dmPelsHeight, dev_mode.dmDisplayFrequency);
+            let mut displays = self.displays.lock().unwrap();
+            let display = displays.iter_mut().find(|d| d.device_name == device_name);
+
+            // Modes are listed in landscape, a portrait request is the same mode turned around.
+            // Like some drivers, the interlaced variant wins unless progressive is asked for.
+            let listed = |modes: &[(u32, u32, u32)]| modes.contains(&mode) || modes.contains(&(mode.1, mode.0, mode.2));
+            let progressive = dev_mode.dmFields.contains(DM_DISPLAYFLAGS) && unsafe { dev_mode.Anonymous2.dmDisplayFlags } & DM_INTERLACED.0 == 0;
+            let interlaced = !progressive && display.as_ref().is_some_and(|display| listed(&display.interlaced_modes));
+            self.record(format!("change_display_settings {} {}x{}@{}{}", device_name, mode.0, mode.1, mode.2, if interlaced { " interlaced" } else { "" }));
+
+            let Some(display) = display else {
+                return DISP_CHANGE_BADMODE;
+            };
+            if !interlaced && !listed(&display.modes) {
+                return DISP_CHANGE_BADMODE;
+            }
+
+            display.mode = mode;
+            unsafe {
+                if dev_mode.dmFields.contains(DM_POSITION) {
+                    let position = dev_mode.Anonymous1.Anonymous2.dmPosition;
+                    display.position = (position.x, position.y);
+                }
+                if dev_mode.dmFields.contains(DM_DISPLAYORIENTATION) {
+                    display.orientation = dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation.0;
+                }
+            }
+            DISP_CHANGE_SUCCESSFUL
+        }
+
+        // One path per display with a source and a target mode each. Inactive displays only
+        // show up with QDC_ALL_PATHS, as a path without modes.
+        fn query_display_config(&self, flags: QUERY_DISPLAY_CONFIG_FLAGS) -> Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)> {
+            let mut paths = Vec::new();
+            let mut modes = Vec::new();
+
+            for display in self.displays.lock().unwrap().iter() {
+                if !display.active && flags != QDC_ALL_PATHS {
+                    continue;
+                }
+
+                let mut path = DISPLAYCONFIG_PATH_INFO::default();
+                path.sourceInfo.adapterId = display.adapter_id;
+                path.sourceInfo.id = display.source_id;
+                path.targetInfo.adapterId = display.adapter_id;
+                path.targetInfo.id = display.target_id;
+                path.targetInfo.targetAvailable = BOOL(1);
+
+                if display.active {
+                    path.flags = DISPLAYCONFIG_PATH_ACTIVE;
+                    path.sourceInfo.Anonymous.modeInfoIdx = modes.len() as u32;
+                    path.targetInfo.Anonymous.modeInfoIdx = modes.len() as u32 + 1;
+
+                    let mut source_mode = DISPLAYCONFIG_MODE_INFO {
+                        infoType: DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE,
+                        id: display.source_id,
+                        adapterId: display.adapter_id,
+                        ..Default::default()
+                    };
+                    source_mode.Anonymous.sourceMode.width = display.mode.0;
+                    source_mode.Anonymous.sourceMode.height = display.mode.1;
+                    source_mode.Anonymous.sourceMode.position = POINTL { x: display.position.0, y: display.position.1 };
+                    modes.push(source_mode);
+
+                    modes.push(DISPLAYCONFIG_MODE_INFO {
+                        infoType: DISPLAYCONFIG_MODE_INFO_TYPE_TARGET,
+                        id: display.target_id,
+                        adapterId: display.adapter_id,
+                        ..Default::default()
+                    });
+                } else {
+                    path.sourceInfo.Anonymous.modeInfoIdx = DISPLAYCONFIG_PATH_MODE_IDX_INVALID;
+                    path.targetInfo.Anonymous.modeInfoIdx = DISPLAYCONFIG_PATH_MODE_IDX_INVALID;
+                }
+                paths.push(path);
+            }
+
+            Ok((paths, modes))
+        }
+
+        // Takes the supplied paths as the new set of active displays, each at its source mode's
+        // position. Windows keeps the primary display at the origin.
+        fn set_display_config(&self, paths: Option<&[DISPLAYCONFIG_PATH_INFO]>, modes: Option<&[DISPLAYCONFIG_MODE_INFO]>, flags: SET_DISPLAY_CONFIG_FLAGS) -> i32 {
+            let (Some(paths), Some(modes)) = (paths, modes) else {
+                self.record(format!("set_display_config flags {:#x}", flags.0));
+                return 0;
+            };
+            // Validating changes nothing, and every layout the helper builds is a valid one here
+            if flags.contains(SDC_VALIDATE) {
+                return 0;
+            }
+
+            let mut displays = self.displays.lock().unwrap();
+            for display in displays.iter_mut() {
+                let path = paths.iter().find(|p| p.targetInfo.adapterId == display.adapter_id && p.targetInfo.id == display.target_id && p.flags & DISPLAYCONFIG_PATH_ACTIVE != 0);
+                display.active = path.is_some();
+                if let Some(path) = path {
+                    let index = unsafe { path.sourceInfo.Anonymous.modeInfoIdx };
+                    if let Some(mode) = modes.get(index as usize) {
+                        let position = unsafe { mode.Anonymous.sourceMode.position };
+                        display.position = (position.x, position.y);
+                    }
+                }
+                display.primary = display.active && display.position == (0, 0);
+            }
+
+            let layout: Vec<String> = displays.iter().map(|display| match (display.active, display.primary) {
+                (false, _) => format!("{} off", display.short_name()),
+                (true, primary) => format!("{} at ({}, {}){}", display.short_name(), display.position.0, display.position.1,
+                    if primary { " primary" } else { "" }),
+            }).collect();
+            drop(displays);
+
+            self.record(format!("set_display_config {} path(s) flags {:#x}: {}", paths.len(), flags.0, layout.join(", ")));
+            0
+        }
+
+        fn source_gdi_name(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.displays.lock().unwrap().iter().find(|d| d.adapter_id == adapter_id && d.source_id == source_id).map(|d| d.device_name.clone())
+        }
+
+        fn target_device_name(&self, adapter_id: LUID, target_id: u32) -> Option<TargetDeviceName> {
+            self.displays.lock().unwrap().iter().find(|d| d.adapter_id == adapter_id && d.target_id == target_id).map(|d| TargetDeviceName {
+                friendly_name: d.monitor_name.clone(),
+                device_path: d.monitor_path.clone(),
+            })
+        }
+
+        fn get_device_info(&self, header: &mut DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+            match read_device_info(&self.displays.lock().unwrap(), header) {
+                Ok(()) => 0,
+                Err(code) => code.0 as i32,
+            }
+        }
+
+        fn set_device_info(&self, header: &DISPLAYCONFIG_DEVICE_INFO_HEADER) -> i32 {
+            let result = write_device_info(&mut self.displays.lock().unwrap(), header);
+            match result {
+                Ok(call) => {
+                    self.record(call);
+                    0
+                }
+                Err(code) => code.0 as i32,
+            }
+        }
+
+        fn icc_profiles(&self, device_name: &str) -> Vec<PathBuf> {
+            let displays = self.displays.lock().unwrap();
+            displays.iter().find(|d| d.device_name == device_name).map(|display| {
+                display.icc_profiles.iter().map(|name| Path::new(COLOR_DIRECTORY).join(name)).collect()
+            }).unwrap_or_default()
+        }
+
+        fn default_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.displays.lock().unwrap().iter().find(|d| d.adapter_id == adapter_id && d.source_id == source_id).and_then(|d| d.icc_profile.clone())
+        }
+
+        fn set_default_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let name = profile_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut displays = self.displays.lock().unwrap();
+            let Some(display) = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.source_id == source_id) else {
+                return Err(ERROR_INVALID_PARAMETER.into());
+            };
+            if !display.icc_profiles.contains(&name) {
+                return Err(ERROR_FILE_NOT_FOUND.into());
+            }
+
+            display.icc_profile = Some(name.clone());
+            let call = format!("set_icc_profile {} {}", display.short_name(), name);
+            drop(displays);
+            self.record(call);
+            Ok(())
+        }
+
+        fn default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32) -> Option<String> {
+            self.displays.lock().unwrap().iter().find(|d| d.adapter_id == adapter_id && d.source_id == source_id).and_then(|d| d.hdr_icc_profile.clone())
+        }
+
+        fn set_default_hdr_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let name = profile_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut displays = self.displays.lock().unwrap();
+            let Some(display) = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.source_id == source_id) else {
+                return Err(ERROR_INVALID_PARAMETER.into());
+            };
+            if !display.icc_profiles.contains(&name) {
+                return Err(ERROR_FILE_NOT_FOUND.into());
+            }
+
+            display.hdr_icc_profile = Some(name.clone());
+            let call = format!("set_hdr_icc_profile {} {}", display.short_name(), name);
+            drop(displays);
+            self.record(call);
+            Ok(())
+        }
+
+        // Any name goes, the mock's profiles are names without files
+        fn associate_icc_profile(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let name = profile_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut displays = self.displays.lock().unwrap();
+            let Some(display) = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.source_id == source_id) else {
+                return Err(ERROR_INVALID_PARAMETER.into());
+            };
+            if !display.icc_profiles.contains(&name) {
+                display.icc_profiles.push(name.clone());
+            }
+            let call = format!("associate_icc_profile {} {}", display.short_name(), name);
+            drop(displays);
+            self.record(call);
+            Ok(())
+        }
+
+        fn remove_icc_association(&self, adapter_id: LUID, source_id: u32, profile_path: &Path) -> Result<()> {
+            let name = profile_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let mut displays = self.displays.lock().unwrap();
+            let Some(display) = displays.iter_mut().find(|d| d.adapter_id == adapter_id && d.source_id == source_id) else {
+                return Err(ERROR_INVALID_PARAMETER.into());
+            };
+            if !display.icc_profiles.contains(&name) {
+                return Err(ERROR_FILE_NOT_FOUND.into());
+            }
+
+            display.icc_profiles.retain(|profile| *profile != name);
+            for default in [&mut display.icc_profile, &mut display.hdr_icc_profile] {
+                if default.as_ref() == Some(&name) {
+                    *default = None;
+                }
+            }
+            let call = format!("remove_icc_profile {} {}", display.short_name(), name);
+            drop(displays);
+            self.record(call);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{parse_fixture, MockDisplayApi};
+    use crate::displays_info::{enumerate_displays, find_display};
+    use crate::{change_icc_profile, dpi_scaling, hdr, set_sdr_level};
+
+    const FIXTURE: &str = r#"{
+        "displays": [
+            {
+                "monitor": "LG TV SSCR2",
+                "adapter": "NVIDIA GeForce RTX 4080",
+                "mode": "3840x2160@120",
+                "modes": ["3840x2160@120", "3840x2160@60", "2560x1440@120"],
+                "position": [-3840, 0],
+                "hdr_supported": true,
+                "hdr": true,
+                "sdr_nits": 280,
+                "scaling": 150,
+                "recommended_scaling": 300,
+                "icc_profiles": ["lg_oled.icm", "srgb.icm"],
+                "icc_profile": "lg_oled.icm"
+            },
+            { "monitor": "DELL U2723QE", "mode": "2560x1440@60", "primary": true },
+            { "monitor": "Unplugged", "mode": "1920x1080@60", "active": false }
+        ]
+    }"#;
+
+    #[test]
+    fn fixture_describes_the_displays() {
+        MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        let displays = enumerate_displays();
+        assert_eq!(displays.len(), 2);
+
+        let tv = &displays[0];
+        assert_eq!(tv.device_string, "NVIDIA GeForce RTX 4080");
+        assert_eq!((tv.current_resolution, tv.current_refresh_rate, tv.position), ((3840, 2160), 120, (-3840, 0)));
+        assert_eq!(tv.get_supported_modes().len(), 3);
+        assert!(displays[1].is_primary);
+
+        let color_info = hdr::get_advanced_color_info(tv).unwrap();
+        assert!(color_info.supported && color_info.enabled);
+        assert_eq!(set_sdr_level::get_display_sdr_white(tv).unwrap(), 50);
+        let scaling = dpi_scaling::get_display_scaling(tv).unwrap();
+        assert_eq!((scaling.current, scaling.recommended, scaling.minimum), (150, 300, 100));
+        assert_eq!(change_icc_profile::get_display_default_icc_profile(tv).as_deref(), Some("lg_oled.icm"));
+
+        let monitor = &displays[1];
+        assert!(!hdr::get_advanced_color_info(monitor).unwrap().supported);
+        assert_eq!(change_icc_profile::get_display_default_icc_profile(monitor), None);
+    }
+
+    #[test]
+    fn changes_are_recorded_and_read_back() {
+        let api = MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        let tv = find_display("LG TV SSCR2").unwrap();
+
+        hdr::set_hdr(&tv, false).unwrap();
+        set_sdr_level::set_display_sdr_white(&tv, 40).unwrap();
+        dpi_scaling::set_display_scaling(&tv, 200).unwrap();
+        change_icc_profile::set_display_icc_profile(&tv, "srgb.icm").unwrap();
+        assert_eq!(api.calls(), [
+            "set_hdr DISPLAY1 off",
+            "set_sdr_white_level DISPLAY1 40 (240 nits)",
+            "set_dpi_scale DISPLAY1 200%",
+            "set_icc_profile DISPLAY1 srgb.icm",
+        ]);
+
+        assert!(!hdr::get_advanced_color_info(&tv).unwrap().enabled);
+        assert_eq!(set_sdr_level::get_display_sdr_white(&tv).unwrap(), 40);
+        assert_eq!(dpi_scaling::get_display_scaling(&tv).unwrap().current, 200);
+        assert_eq!(change_icc_profile::get_display_default_icc_profile(&tv).as_deref(), Some("srgb.icm"));
+    }
+
+    #[test]
+    fn plain_profiles_arent_hdr_calibrations() {
+        let api = MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        let tv = find_display("LG TV SSCR2").unwrap();
+
+        // The fixture's profiles are names without files, so none has an MHC2 tag
+        let error = change_icc_profile::set_display_hdr_calibration_profile(&tv, Some("srgb.icm")).unwrap_err();
+        assert!(error.message().contains("isn't an HDR Calibration profile"));
+        assert!(change_icc_profile::set_display_hdr_calibration_profile(&tv, None).is_err());
+        assert!(change_icc_profile::set_display_hdr_calibration_profile(&tv, Some("missing.icm")).is_err());
+        assert!(api.calls().is_empty());
+        assert_eq!(change_icc_profile::get_display_default_hdr_icc_profile(&tv), None);
+    }
+
+    #[test]
+    fn associating_adds_without_changing_the_default() {
+        let api = MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        let tv = find_display("LG TV SSCR2").unwrap();
+
+        assert!(change_icc_profile::associate_display_icc_profile(&tv, "HDR Steam Deck.icc", false).unwrap());
+        assert!(!change_icc_profile::associate_display_icc_profile(&tv, "hdr steam deck.icc", false).unwrap());
+        assert_eq!(change_icc_profile::get_display_default_icc_profile(&tv).as_deref(), Some("lg_oled.icm"));
+        assert!(change_icc_profile::get_display_icc_profiles(&tv).iter().any(|p| p.name == "HDR Steam Deck.icc"));
+
+        // Making it the default is a separate step, already associated or not
+        assert!(!change_icc_profile::associate_display_icc_profile(&tv, "HDR Steam Deck.icc", true).unwrap());
+        assert_eq!(api.calls(), [
+            "associate_icc_profile DISPLAY1 HDR Steam Deck.icc",
+            "set_icc_profile DISPLAY1 HDR Steam Deck.icc",
+        ]);
+    }
+
+    #[test]
+    fn a_dry_run_changes_nothing() {
+        let api = MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        super::use_test_api(Box::leak(Box::new(super::dry_run::DryRunDisplayApi::new(api))));
+        assert!(super::is_dry_run());
+        let tv = find_display("LG TV SSCR2").unwrap();
+
+        hdr::set_hdr(&tv, false).unwrap();
+        set_sdr_level::set_display_sdr_white(&tv, 40).unwrap();
+        dpi_scaling::set_display_scaling(&tv, 200).unwrap();
+        change_icc_profile::set_display_icc_profile(&tv, "srgb.icm").unwrap();
+        assert!(api.calls().is_empty());
+
+        assert!(hdr::get_advanced_color_info(&tv).unwrap().enabled);
+        assert_eq!(set_sdr_level::get_display_sdr_white(&tv).unwrap(), 50);
+        assert_eq!(dpi_scaling::get_display_scaling(&tv).unwrap().current, 150);
+        assert_eq!(change_icc_profile::get_display_default_icc_profile(&tv).as_deref(), Some("lg_oled.icm"));
+    }
+
+    #[test]
+    fn hdr_needs_hdr_support() {
+        let api = MockDisplayApi::install(parse_fixture(FIXTURE).unwrap());
+        let monitor = find_display("primary").unwrap();
+        assert!(hdr::set_hdr(&monitor, true).is_err());
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn bad_fixtures_are_rejected() {
+        let error = |json: &str| parse_fixture(json).unwrap_err();
+
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "3840x2160" , "primary": true }] }"#).contains("WIDTHxHEIGHT@HZ"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60", "primary": true, "hdmi": 2 }] }"#).contains("unknown field"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60", "primary": true, "hdr": true }] }"#).contains("hdr_supported"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60", "primary": true, "scaling": 110 }] }"#).contains("scaling step"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60" }] }"#).contains("primary"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60", "primary": true, "position": [10, 0] }] }"#).contains("[0, 0]"));
+        assert!(error(r#"{ "displays": [{ "monitor": "TV", "mode": "1920x1080@60", "primary": true, "icc_profile": "a.icm" }] }"#).contains("icc_profiles"));
+    }
+}
@@ -0,0 +1,670 @@
+use windows::Win32::Devices::Display::{
+    DISPLAYCONFIG_MODE_INFO,
+    DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE,
+    DISPLAYCONFIG_PATH_INFO,
+    QDC_ALL_PATHS,
+    QDC_ONLY_ACTIVE_PATHS,
+    SDC_ALLOW_CHANGES,
+    SDC_APPLY,
+    SDC_SAVE_TO_DATABASE,
+    SDC_TOPOLOGY_CLONE,
+    SDC_TOPOLOGY_EXTEND,
+    SDC_TOPOLOGY_EXTERNAL,
+    SDC_TOPOLOGY_INTERNAL,
+    SDC_USE_SUPPLIED_DISPLAY_CONFIG,
+    SET_DISPLAY_CONFIG_FLAGS,
+};
+use windows::Win32::Foundation::{ERROR_SUCCESS, E_FAIL, LUID, WIN32_ERROR};
+use windows::Win32::Graphics::Gdi::{
+    DEVMODEW,
+    DISPLAYCONFIG_PATH_ACTIVE,
+    DISPLAYCONFIG_PATH_MODE_IDX_INVALID,
+    DM_DISPLAYFREQUENCY,
+    DM_PELSHEIGHT,
+    DM_PELSWIDTH,
+    DM_POSITION,
+};
+use windows::Win32::Foundation::POINTL;
+use windows::core::{Error, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, error, instrument};
+use std::{thread, time::Duration};
+
+use crate::change_display_mode::apply_display_settings;
+use crate::display_api;
+use crate::displays_info::{self, query_display_config, DisplayDevice};
+use crate::exit_code;
+use crate::state::{self, DisabledDisplay};
+
+const TOPOLOGY_RESTORE_FILE: &str = "topology_restore.json";
+
+// Raw copy of the active paths and modes. The structs are plain data, so they're stored as hex
+// in a JSON state file. Adapter LUIDs only last until reboot, so this is for same-session restores.
+#[derive(Serialize, Deserialize)]
+pub struct DisplayConfigSnapshot {
+    pub paths: String,
+    pub modes: String,
+}
+
+impl DisplayConfigSnapshot {
+    pub fn capture() -> Result<Self> {
+        let (paths, modes) = query_display_config(QDC_ONLY_ACTIVE_PATHS)?;
+        Ok(DisplayConfigSnapshot {
+            paths: to_hex(&paths),
+            modes: to_hex(&modes),
+        })
+    }
+
+    pub fn apply(&self) -> Result<()> {
+        let (Some(paths), Some(modes)) = (from_hex::<DISPLAYCONFIG_PATH_INFO>(&self.paths), from_hex::<DISPLAYCONFIG_MODE_INFO>(&self.modes)) else {
+            return Err(Error::new(E_FAIL, "Saved display config is corrupt"));
+        };
+        apply_display_config(&paths, &modes)
+    }
+}
+
+fn to_hex<T: Copy>(items: &[T]) -> String {
+    let bytes = unsafe { std::slice::from_raw_parts(items.as_ptr() as *const u8, std::mem::size_of_val(items)) };
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<T: Copy + Default>(hex: &str) -> Option<Vec<T>> {
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+
+    let item_size = size_of::<T>();
+    if !bytes.len().is_multiple_of(item_size) {
+        return None;
+    }
+
+    let mut items = vec![T::default(); bytes.len() / item_size];
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), items.as_mut_ptr() as *mut u8, bytes.len());
+    }
+    Some(items)
+}
+
+//==============================================================================
+// SetDisplayConfig wrappers
+//==============================================================================
+
+#[instrument(skip_all, fields(paths = paths.len(), modes = modes.len()))]
+pub fn apply_display_config(paths: &[DISPLAYCONFIG_PATH_INFO], modes: &[DISPLAYCONFIG_MODE_INFO]) -> Result<()> {
+    info!("Applying display config with {} path(s) and {} mode(s)", paths.len(), modes.len());
+    for path in paths {
+        debug!("  path: adapter {:08X}:{:08X} source {} -> target {}, flags {:#x}",
+            path.sourceInfo.adapterId.HighPart, path.sourceInfo.adapterId.LowPart, path.sourceInfo.id, path.targetInfo.id, path.flags);
+    }
+
+    let result = display_api::api().set_display_config(
+        Some(paths),
+        Some(modes),
+        SDC_APPLY | SDC_USE_SUPPLIED_DISPLAY_CONFIG | SDC_ALLOW_CHANGES | SDC_SAVE_TO_DATABASE,
+    );
+    displays_info::invalidate_topology();
+
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "SetDisplayConfig failed with code: {}", result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(flags = %format!("{:#x}", topology.0)))]
+fn apply_topology(topology: SET_DISPLAY_CONFIG_FLAGS) -> Result<()> {
+    let result = display_api::api().set_display_config(None, None, SDC_APPLY | topology);
+    displays_info::invalidate_topology();
+
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "SetDisplayConfig (topology {:?}) failed with code: {}", topology, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    Ok(())
+}
+
+fn path_target_device_path(path: &DISPLAYCONFIG_PATH_INFO) -> Option<String> {
+    display_api::api().target_device_name(path.targetInfo.adapterId, path.targetInfo.id)
+        .map(|target_name| target_name.device_path)
+}
+
+fn is_display_path(path: &DISPLAYCONFIG_PATH_INFO, display: &DisplayDevice) -> bool {
+    path.sourceInfo.adapterId == display.adapter_id
+        && path.sourceInfo.id == display.source_id
+        && path.targetInfo.id == display.target_id
+}
+
+fn source_mode_index(paths: &[DISPLAYCONFIG_PATH_INFO], mode_count: usize, display: &DisplayDevice) -> Result<usize> {
+    paths.iter()
+        .find(|p| is_display_path(p, display))
+        .map(|p| unsafe { p.sourceInfo.Anonymous.modeInfoIdx } as usize)
+        .filter(|&index| index < mode_count)
+        .ok_or_else(|| Error::new(E_FAIL, format!("No active source mode found for {}", display.device_name)))
+}
+
+fn find_active_display(selector: &str) -> Result<DisplayDevice> {
+    displays_info::find_display(selector)
+        .ok_or_else(|| exit_code::display_not_found(format!("No active display matches '{}'", selector)))
+}
+
+//==============================================================================
+// Primary display
+//==============================================================================
+
+// The primary display is the one at the desktop origin, so shift every source so the target sits at (0, 0)
+#[instrument(skip_all, fields(display = %selector))]
+pub fn set_primary_display(selector: &str) -> Result<DisplayDevice> {
+    let device = find_active_display(selector)?;
+    if device.is_primary {
+        info!("{} is already the primary display", device.device_name);
+        return Ok(device);
+    }
+
+    let (paths, mut modes) = query_display_config(QDC_ONLY_ACTIVE_PATHS)?;
+    let index = source_mode_index(&paths, modes.len(), &device)?;
+    let offset = unsafe { modes[index].Anonymous.sourceMode.position };
+
+    info!("Making {} primary by shifting desktop origins by ({}, {})", device.device_name, -offset.x, -offset.y);
+
+    for mode in modes.iter_mut().filter(|m| m.infoType == DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE) {
+        unsafe {
+            let position = &mut mode.Anonymous.sourceMode.position;
+            position.x -= offset.x;
+            position.y -= offset.y;
+        }
+    }
+
+    apply_display_config(&paths, &modes)?;
+    Ok(device)
+}
+
+//==============================================================================
+// Display positions
+//==============================================================================
+
+// One entry of a layout file, e.g. [{ "display": "DISPLAY2", "x": 3840, "y": 0 }]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DisplayPosition {
+    pub display: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+pub fn load_layout_file(path: &std::path::Path) -> Result<Vec<DisplayPosition>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to read {}: {}", path.display(), e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+// Move displays around the virtual desktop in a single SetDisplayConfig call. Positions are
+// relative to the primary display, which Windows keeps at (0, 0), so moving the primary
+// display moves everything else the opposite way instead.
+#[instrument(skip_all, fields(displays = positions.len()))]
+pub fn set_display_positions(positions: &[DisplayPosition]) -> Result<()> {
+    let displays = displays_info::enumerate_displays();
+    let (paths, mut modes) = query_display_config(QDC_ONLY_ACTIVE_PATHS)?;
+
+    for position in positions {
+        let Some(device) = displays.iter().find(|d| displays_info::display_matches_selector(d, &position.display)) else {
+            return Err(exit_code::display_not_found(format!("No active display matches '{}'", position.display)));
+        };
+
+        let index = source_mode_index(&paths, modes.len(), device)?;
+        info!("Moving {} to ({}, {})", device.device_name, position.x, position.y);
+        modes[index].Anonymous.sourceMode.position = POINTL { x: position.x, y: position.y };
+    }
+
+    if let Some(primary) = displays.iter().find(|d| d.is_primary) {
+        let primary_position = unsafe { modes[source_mode_index(&paths, modes.len(), primary)?].Anonymous.sourceMode.position };
+        if primary_position.x != 0 || primary_position.y != 0 {
+            for mode in modes.iter_mut().filter(|m| m.infoType == DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE) {
+                unsafe {
+                    let position = &mut mode.Anonymous.sourceMode.position;
+                    position.x -= primary_position.x;
+                    position.y -= primary_position.y;
+                }
+            }
+        }
+    }
+
+    apply_display_config(&paths, &modes)
+}
+
+//==============================================================================
+// Topology (Win+P)
+//==============================================================================
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Topology {
+    Extend,
+    Clone,
+    Internal,
+    External,
+    Restore,
+}
+
+// Switch topology like Win+P does. The first switch saves the current config so `restore` can
+// undo it; later switches keep that original snapshot until it has been restored.
+pub fn set_topology(topology: Topology) -> Result<()> {
+    let flags = match topology {
+        Topology::Extend => SDC_TOPOLOGY_EXTEND,
+        Topology::Clone => SDC_TOPOLOGY_CLONE,
+        Topology::Internal => SDC_TOPOLOGY_INTERNAL,
+        Topology::External => SDC_TOPOLOGY_EXTERNAL,
+        Topology::Restore => {
+            let Some(snapshot) = state::load_state_file::<DisplayConfigSnapshot>(TOPOLOGY_RESTORE_FILE) else {
+                return Err(Error::new(E_FAIL, "No saved topology to restore"));
+            };
+            info!("Restoring saved topology");
+            snapshot.apply()?;
+            state::remove_state_file(TOPOLOGY_RESTORE_FILE);
+            return Ok(());
+        }
+    };
+
+    if state::load_state_file::<DisplayConfigSnapshot>(TOPOLOGY_RESTORE_FILE).is_some() {
+        info!("Keeping existing saved topology until it is restored");
+    } else {
+        let snapshot = DisplayConfigSnapshot::capture()?;
+        if let Err(e) = state::save_state_file(TOPOLOGY_RESTORE_FILE, &snapshot) {
+            return Err(Error::new(E_FAIL, format!("Failed to save current topology, not switching: {}", e)));
+        }
+    }
+
+    info!("Switching topology to {:?}", topology);
+    apply_topology(flags)
+}
+
+//==============================================================================
+// Disable / enable displays
+//==============================================================================
+
+// Remove a display from the desktop, remembering enough about it to bring it back later
+#[instrument(skip_all, fields(display = %selector))]
+pub fn disable_display(selector: &str) -> Result<DisabledDisplay> {
+    let displays = displays_info::enumerate_displays();
+    let Some(device) = displays.iter().find(|d| displays_info::display_matches_selector(d, selector)) else {
+        return Err(exit_code::display_not_found(format!("No active display matches '{}'", selector)));
+    };
+
+    if displays.len() < 2 {
+        return Err(Error::new(E_FAIL, "Refusing to disable the only active display"));
+    }
+
+    let (paths, modes) = query_display_config(QDC_ONLY_ACTIVE_PATHS)?;
+    if !paths.iter().any(|p| is_display_path(p, device)) {
+        return Err(Error::new(E_FAIL, format!("No active display path found for {}", device.device_name)));
+    }
+
+    let target_name = device.get_target_device_name();
+    let record = DisabledDisplay {
+        device_name: device.device_name.clone(),
+        device_index: device.device_index,
+        friendly_name: target_name.as_ref().map(|t| t.friendly_name.clone()).unwrap_or_default(),
+        monitor_device_path: target_name.map(|t| t.device_path).unwrap_or_default(),
+        adapter_id_low: device.adapter_id.LowPart,
+        adapter_id_high: device.adapter_id.HighPart,
+        target_id: device.target_id,
+        width: device.current_resolution.0,
+        height: device.current_resolution.1,
+        refresh_rate: device.current_refresh_rate,
+        position: device.position,
+        was_primary: device.is_primary,
+        display_id: device.stable_id(),
+    };
+
+    // Save before touching anything, so the display can always be found again
+    let mut disabled = state::load_disabled_displays();
+    disabled.retain(|d| !(d.target_id == record.target_id && d.adapter_id_low == record.adapter_id_low && d.adapter_id_high == record.adapter_id_high));
+    disabled.push(record.clone());
+    if let Err(e) = state::save_disabled_displays(&disabled) {
+        return Err(Error::new(E_FAIL, format!("Failed to save display state, not disabling: {}", e)));
+    }
+
+    if device.is_primary {
+        info!("Disabling the primary display, Windows will choose a new primary");
+    }
+
+    let remaining: Vec<DISPLAYCONFIG_PATH_INFO> = paths.into_iter()
+        .filter(|p| !is_display_path(p, device))
+        .collect();
+
+    info!("Disabling display {} ({})", device.device_name, record.friendly_name);
+    apply_display_config(&remaining, &modes)?;
+    Ok(record)
+}
+
+// Re-attach a display previously removed with disable_display
+#[instrument(skip_all, fields(display = %selector))]
+pub fn enable_display(selector: &str) -> Result<DisabledDisplay> {
+    let mut disabled = state::load_disabled_displays();
+    let Some(record_index) = disabled.iter().position(|d| d.matches_selector(selector)) else {
+        return Err(exit_code::display_not_found(format!("No display disabled by this tool matches '{}'", selector)));
+    };
+    let record = disabled[record_index].clone();
+    let adapter_id = LUID { LowPart: record.adapter_id_low, HighPart: record.adapter_id_high };
+
+    let (paths, modes) = query_display_config(QDC_ALL_PATHS)?;
+    let is_active = |p: &DISPLAYCONFIG_PATH_INFO| p.flags & DISPLAYCONFIG_PATH_ACTIVE != 0;
+
+    // Adapter LUIDs change across reboots, so fall back to the monitor's device path
+    let is_record_target = |p: &DISPLAYCONFIG_PATH_INFO| {
+        (p.targetInfo.adapterId == adapter_id && p.targetInfo.id == record.target_id)
+            || (!record.monitor_device_path.is_empty()
+                && path_target_device_path(p).as_deref() == Some(record.monitor_device_path.as_str()))
+    };
+
+    let mut active: Vec<DISPLAYCONFIG_PATH_INFO> = paths.iter().copied().filter(|p| is_active(p)).collect();
+
+    if active.iter().any(is_record_target) {
+        info!("Display {} is already enabled", record.device_name);
+    } else {
+        // Pick a path to the target whose source isn't already driving another display
+        let candidate = paths.iter().find(|p| {
+            !is_active(p)
+                && p.targetInfo.targetAvailable.as_bool()
+                && is_record_target(p)
+                && !active.iter().any(|a| a.sourceInfo.adapterId == p.sourceInfo.adapterId && a.sourceInfo.id == p.sourceInfo.id)
+        });
+
+        let Some(candidate) = candidate else {
+            return Err(Error::new(E_FAIL, format!("No available display path found for {}, is it still connected?", record.device_name)));
+        };
+
+        let mut path = *candidate;
+        path.flags |= DISPLAYCONFIG_PATH_ACTIVE;
+        path.sourceInfo.Anonymous.modeInfoIdx = DISPLAYCONFIG_PATH_MODE_IDX_INVALID;
+        path.targetInfo.Anonymous.modeInfoIdx = DISPLAYCONFIG_PATH_MODE_IDX_INVALID;
+        active.push(path);
+
+        info!("Enabling display {} ({})", record.device_name, record.friendly_name);
+        apply_display_config(&active, &modes)?;
+
+        // Windows picks a mode and position for the new path, put back what the display had before
+        thread::sleep(Duration::from_millis(1000));
+        restore_display_mode(&record);
+    }
+
+    disabled.remove(record_index);
+    if let Err(e) = state::save_disabled_displays(&disabled) {
+        error!("Failed to update disabled display records: {}", e);
+    }
+
+    Ok(record)
+}
+
+fn restore_display_mode(record: &DisabledDisplay) {
+    let Some(device) = displays_info::enumerate_displays().into_iter().find(|d| {
+        d.target_id == record.target_id
+            && d.get_target_device_name().map(|t| t.device_path) == Some(record.monitor_device_path.clone())
+    }) else {
+        error!("Could not find {} after enabling it, leaving the mode chosen by Windows", record.device_name);
+        return;
+    };
+
+    let mut dev_mode = DEVMODEW {
+        dmSize: size_of::<DEVMODEW>() as u16,
+        dmPelsWidth: record.width,
+        dmPelsHeight: record.height,
+        dmDisplayFrequency: record.refresh_rate,
+        dmFields: DM_PELSWIDTH | DM_PELSHEIGHT | DM_DISPLAYFREQUENCY | DM_POSITION,
+        ..Default::default()
+    };
+    dev_mode.Anonymous1.Anonymous2.dmPosition = POINTL { x: record.position.0, y: record.position.1 };
+
+    if !apply_display_settings(&device.device_name, &dev_mode) {
+        error!("Failed to restore {}x{} @{}Hz at ({}, {}) on {}",
+            record.width, record.height, record.refresh_rate, record.position.0, record.position.1, device.device_name);
+    }
+}
+
+//==============================================================================
+// Exported config
+//==============================================================================
+
+// Every path QueryDisplayConfig knows, inactive ones included, with the modes they use. Unlike a
+// state snapshot this survives a reboot: adapter LUIDs change then, so each target's monitor is
+// kept by device path and found again on import.
+#[derive(Serialize, Deserialize)]
+pub struct ExportedConfig {
+    pub targets: Vec<ExportedTarget>,
+    pub paths: String,
+    pub modes: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportedTarget {
+    pub adapter_id_low: u32,
+    pub adapter_id_high: i32,
+    pub target_id: u32,
+    pub monitor: String,
+    pub device_path: String,
+}
+
+impl ExportedTarget {
+    fn adapter_id(&self) -> LUID {
+        LUID { LowPart: self.adapter_id_low, HighPart: self.adapter_id_high }
+    }
+}
+
+pub fn export_config(path: &std::path::Path) -> Result<ExportedConfig> {
+    let (paths, modes) = query_display_config(QDC_ALL_PATHS)?;
+    let mut targets: Vec<ExportedTarget> = Vec::new();
+    // The same target shows up once for every source that could drive it
+    for path in paths.iter().filter(|p| p.targetInfo.targetAvailable.as_bool()) {
+        let (adapter_id, target_id) = (path.targetInfo.adapterId, path.targetInfo.id);
+        if targets.iter().any(|t| t.adapter_id() == adapter_id && t.target_id == target_id) {
+            continue;
+        }
+        let Some(name) = display_api::api().target_device_name(adapter_id, target_id) else {
+            continue;
+        };
+        targets.push(ExportedTarget {
+            adapter_id_low: adapter_id.LowPart,
+            adapter_id_high: adapter_id.HighPart,
+            target_id,
+            monitor: name.friendly_name,
+            device_path: name.device_path,
+        });
+    }
+
+    let config = ExportedConfig { targets, paths: to_hex(&paths), modes: to_hex(&modes) };
+    let contents = serde_json::to_string_pretty(&config).map_err(|e| Error::new(E_FAIL, e.to_string()))?;
+    std::fs::write(path, contents).map_err(|e| Error::new(E_FAIL, format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(config)
+}
+
+pub fn load_exported_config(path: &std::path::Path) -> Result<ExportedConfig> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to read {}: {}", path.display(), e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| exit_code::invalid_input(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+// Moves the exported paths and modes over to the adapter LUIDs and target IDs the monitors have
+// now. Inactive paths to monitors that are gone are dropped, active ones are an error.
+#[instrument(skip_all, fields(targets = config.targets.len()))]
+pub fn import_config(config: &ExportedConfig) -> Result<()> {
+    let (Some(mut paths), Some(mut modes)) = (from_hex::<DISPLAYCONFIG_PATH_INFO>(&config.paths), from_hex::<DISPLAYCONFIG_MODE_INFO>(&config.modes)) else {
+        return Err(exit_code::invalid_input("The exported display config is corrupt"));
+    };
+
+    let (current, _) = query_display_config(QDC_ALL_PATHS)?;
+    let mut moved_targets: Vec<((LUID, u32), (LUID, u32))> = Vec::new();
+    for target in &config.targets {
+        if let Some(now) = current.iter().find(|p| path_target_device_path(p).as_deref() == Some(target.device_path.as_str())) {
+            moved_targets.push(((target.adapter_id(), target.target_id), (now.targetInfo.adapterId, now.targetInfo.id)));
+        }
+    }
+    let moved_adapter = |adapter_id: LUID| moved_targets.iter().find(|(old, _)| old.0 == adapter_id).map(|(_, new)| new.0);
+    let moved_target = |adapter_id: LUID, target_id: u32| moved_targets.iter().find(|(old, _)| *old == (adapter_id, target_id)).map(|(_, new)| *new);
+
+    let mut missing = Vec::new();
+    paths.retain_mut(|path| {
+        let active = path.flags & DISPLAYCONFIG_PATH_ACTIVE != 0;
+        match (moved_target(path.targetInfo.adapterId, path.targetInfo.id), moved_adapter(path.sourceInfo.adapterId)) {
+            (Some((adapter_id, target_id)), Some(source_adapter_id)) => {
+                path.targetInfo.adapterId = adapter_id;
+                path.targetInfo.id = target_id;
+                path.sourceInfo.adapterId = source_adapter_id;
+                true
+            }
+            _ => {
+                if active {
+                    missing.push(config.targets.iter()
+                        .find(|t| t.adapter_id() == path.targetInfo.adapterId && t.target_id == path.targetInfo.id)
+                        .map_or_else(|| format!("target {}", path.targetInfo.id), |t| t.monitor.clone()));
+                }
+                false
+            }
+        }
+    });
+    if !missing.is_empty() {
+        return Err(exit_code::display_not_found(format!("Not connected, but active in the export: {}", missing.join(", "))));
+    }
+
+    for mode in &mut modes {
+        let old_adapter_id = mode.adapterId;
+        if mode.infoType == DISPLAYCONFIG_MODE_INFO_TYPE_SOURCE {
+            if let Some(adapter_id) = moved_adapter(old_adapter_id) {
+                mode.adapterId = adapter_id;
+            }
+        } else if let Some((adapter_id, target_id)) = moved_target(old_adapter_id, mode.id) {
+            mode.adapterId = adapter_id;
+            mode.id = target_id;
+        }
+    }
+
+    apply_display_config(&paths, &modes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::exit_code::ExitCode;
+
+    // TV left of the primary monitor, a second monitor to its right
+    fn desk() -> Vec<MockDisplay> {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.position = (-3840, 0);
+        let mut monitor = MockDisplay::new(2, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        let mut side = MockDisplay::new(3, "DELL P2419H", (1920, 1080, 60));
+        side.position = (2560, 0);
+        vec![tv, monitor, side]
+    }
+
+    fn positions(api: &MockDisplayApi) -> Vec<(i32, i32)> {
+        api.displays.lock().unwrap().iter().map(|d| d.position).collect()
+    }
+
+    #[test]
+    fn set_primary_moves_the_display_to_the_origin() {
+        let api = MockDisplayApi::install(desk());
+        set_primary_display("LG TV SSCR2").unwrap();
+        assert_eq!(positions(api), [(0, 0), (3840, 0), (6400, 0)]);
+        assert!(api.display("\\\\.\\DISPLAY1").primary);
+        assert!(!api.display("\\\\.\\DISPLAY2").primary);
+    }
+
+    #[test]
+    fn set_primary_on_the_primary_changes_nothing() {
+        let api = MockDisplayApi::install(desk());
+        set_primary_display("primary").unwrap();
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn unknown_display_is_display_not_found() {
+        MockDisplayApi::install(desk());
+        let error = set_primary_display("DISPLAY9").err().expect("DISPLAY9 doesn't exist");
+        assert_eq!(ExitCode::from_error(&error), ExitCode::DisplayNotFound);
+    }
+
+    #[test]
+    fn set_positions_moves_only_listed_displays() {
+        let api = MockDisplayApi::install(desk());
+        set_display_positions(&[DisplayPosition { display: "DISPLAY3".to_string(), x: 0, y: 1440 }]).unwrap();
+        assert_eq!(positions(api), [(-3840, 0), (0, 0), (0, 1440)]);
+    }
+
+    #[test]
+    fn moving_the_primary_moves_everything_else_instead() {
+        let api = MockDisplayApi::install(desk());
+        set_display_positions(&[DisplayPosition { display: "primary".to_string(), x: 100, y: 50 }]).unwrap();
+        assert_eq!(positions(api), [(-3940, -50), (0, 0), (2460, -50)]);
+    }
+
+    #[test]
+    fn snapshot_restores_the_layout() {
+        let api = MockDisplayApi::install(desk());
+        let snapshot = DisplayConfigSnapshot::capture().unwrap();
+
+        set_primary_display("DISPLAY3").unwrap();
+        assert_eq!(positions(api), [(-6400, 0), (-2560, 0), (0, 0)]);
+
+        snapshot.apply().unwrap();
+        assert_eq!(positions(api), [(-3840, 0), (0, 0), (2560, 0)]);
+        assert!(api.display("\\\\.\\DISPLAY2").primary);
+    }
+
+    #[test]
+    fn corrupt_snapshot_is_rejected() {
+        let api = MockDisplayApi::install(desk());
+        let mut snapshot = DisplayConfigSnapshot::capture().unwrap();
+        snapshot.modes.truncate(snapshot.modes.len() - 2);
+        assert!(snapshot.apply().is_err());
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn refuses_to_disable_the_only_display() {
+        let mut monitor = MockDisplay::new(1, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        let api = MockDisplayApi::install(vec![monitor]);
+        assert!(disable_display("primary").is_err());
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn an_exported_config_comes_back_after_a_reboot() {
+        let file = std::env::temp_dir().join("sunshine-helper-test-export.json");
+        MockDisplayApi::install(desk());
+        let config = export_config(&file).unwrap();
+        assert_eq!(config.targets.len(), 3);
+
+        // New adapter LUID, the side monitor switched off and the TV made primary
+        let mut rebooted = desk();
+        for display in &mut rebooted {
+            display.adapter_id = LUID { LowPart: 0x1f2e3, HighPart: 0 };
+        }
+        rebooted[0].position = (0, 0);
+        rebooted[0].primary = true;
+        rebooted[1].position = (3840, 0);
+        rebooted[1].primary = false;
+        rebooted[2].active = false;
+        let api = MockDisplayApi::install(rebooted);
+
+        import_config(&load_exported_config(&file).unwrap()).unwrap();
+        assert_eq!(positions(api), [(-3840, 0), (0, 0), (2560, 0)]);
+        assert!(api.display("\\\\.\\DISPLAY2").primary);
+        assert!(api.display("\\\\.\\DISPLAY3").active);
+    }
+
+    #[test]
+    fn importing_with_an_active_monitor_gone_changes_nothing() {
+        MockDisplayApi::install(desk());
+        let config = export_config(&std::env::temp_dir().join("sunshine-helper-test-export-gone.json")).unwrap();
+
+        let api = MockDisplayApi::install(desk().into_iter().take(2).collect());
+        let error = import_config(&config).expect_err("the side monitor is gone");
+        assert_eq!(ExitCode::from_error(&error), ExitCode::DisplayNotFound);
+        assert!(error.message().contains("DELL P2419H"));
+        assert!(api.calls().is_empty());
+    }
+}
@@ -1,28 +1,43 @@
-use windows::{
-    Win32::{
-        Graphics::Gdi::{
-            EnumDisplayDevicesW,
-            EnumDisplaySettingsW,
-            DEVMODEW,
-            ENUM_CURRENT_SETTINGS,
-            ENUM_DISPLAY_SETTINGS_MODE,
-            DISPLAY_DEVICEW,
-            DISPLAY_DEVICE_PRIMARY_DEVICE,
-        },
-        Foundation::{LUID, ERROR_SUCCESS},
-        Devices::Display::{
-            GetDisplayConfigBufferSizes,
-            QueryDisplayConfig,
-            DISPLAYCONFIG_MODE_INFO,
-            DISPLAYCONFIG_PATH_INFO,
-            QDC_ONLY_ACTIVE_PATHS,
-        },
+use windows::Win32::{
+    Graphics::Gdi::{DISPLAYCONFIG_PATH_ACTIVE, DISPLAY_DEVICE_PRIMARY_DEVICE},
+    Foundation::LUID,
+    Devices::Display::{
+        DISPLAYCONFIG_MODE_INFO,
+        DISPLAYCONFIG_PATH_INFO,
+        DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EXTERNAL,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_USB_TUNNEL,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DVI,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_HD15,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_HDMI,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_VIRTUAL,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_WIRED,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_LVDS,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_MIRACAST,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED,
+        DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EXTERNAL,
+        QDC_ALL_PATHS,
+        QDC_ONLY_ACTIVE_PATHS,
+        QUERY_DISPLAY_CONFIG_FLAGS,
     },
-    core::{PCWSTR, PWSTR}
 };
-use log::{info, error};
-use std::collections::HashSet;
+use tracing::{debug, info, warn, error};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::display_api;
+use crate::edid;
+use crate::hdr;
+use crate::timings;
+use crate::vdd::VddDriver;
 
+#[derive(Clone)]
 pub struct DisplayDevice {
     pub device_index: u32,
     pub device_name: String,
@@ -31,59 +46,261 @@ pub struct DisplayDevice {
     pub is_primary: bool,
     pub current_resolution: (u32, u32),
     pub current_refresh_rate: u32,
+    pub position: (i32, i32),
+    pub orientation: u32, // DMDO_* value, 1 and 3 mean the desktop is rotated to portrait
     pub adapter_id: LUID,
     pub source_id: u32,
+    pub target_id: u32,
+    pub output_technology: DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY,
+    pub adapter_device_id: String, // the adapter's PnP hardware ID, e.g. PCI\VEN_10DE&DEV_2684...
+    pub adapter_index: usize, // adapters are numbered in the order their first display enumerates
+}
+
+// Monitor details reported by the display config API for a display's target
+pub struct TargetDeviceName {
+    pub friendly_name: String,
+    pub device_path: String,
+}
+
+impl TargetDeviceName {
+    // Monitor device paths look like \\?\DISPLAY#GSM5B08#5&2a1b3c4d&0&UID4353#{e6f07b5f-...},
+    // the PnP instance ID behind it is DISPLAY\GSM5B08\5&2a1b3c4d&0&UID4353
+    pub fn device_instance_id(&self) -> Option<String> {
+        let trimmed = self.device_path.trim_start_matches("\\\\?\\");
+        let instance = match trimmed.rfind("#{") {
+            Some(index) => &trimmed[..index],
+            None => trimmed,
+        };
+
+        if instance.is_empty() {
+            return None;
+        }
+
+        Some(instance.replace('#', "\\"))
+    }
+
+    // The monitor's PnP hardware ID, GSM5B08 in the path above
+    pub fn hardware_id(&self) -> Option<&str> {
+        self.device_path.trim_start_matches("\\\\?\\").split('#').nth(1).filter(|id| !id.is_empty())
+    }
 }
 
+// Modes that differ only in being interlaced or in how they are scaled are listed separately,
+// as EnumDisplaySettings reports them
 #[derive(Hash, Eq, PartialEq, Debug)]
 pub struct DisplayMode {
     pub width: u32,
     pub height: u32,
     pub refresh_rate: u32,
+    pub interlaced: bool,
+    pub fixed_output: FixedOutput,
 }
 
-impl DisplayDevice {
-    // Get supported modes for a display
-    pub fn get_supported_modes(&self) -> Vec<DisplayMode> {
-        let mut modes = HashSet::new();
-        let mut mode_num: u32 = 0;
-
-        loop {
-            let mut dev_mode: DEVMODEW = unsafe { std::mem::zeroed() };
-            dev_mode.dmSize = size_of::<DEVMODEW>() as u16;
-
-            let success = unsafe {
-                EnumDisplaySettingsW(
-                    PCWSTR::from_raw(self.device_name.encode_utf16()
-                        .chain(std::iter::once(0))
-                        .collect::<Vec<u16>>()
-                        .as_ptr()),
-                    ENUM_DISPLAY_SETTINGS_MODE(mode_num),
-                    &mut dev_mode,
-                )
-            };
-
-            if !success.as_bool() {
-                break;
-            }
+// How a fixed-resolution panel shows a mode below its own resolution (dmDisplayFixedOutput)
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum FixedOutput {
+    #[default]
+    Default,
+    Stretch,
+    Center,
+}
+
+impl FixedOutput {
+    pub fn from_devmode(value: u32) -> FixedOutput {
+        match value {
+            1 => FixedOutput::Stretch,
+            2 => FixedOutput::Center,
+            _ => FixedOutput::Default,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FixedOutput::Default => "default",
+            FixedOutput::Stretch => "stretch",
+            FixedOutput::Center => "center",
+        }
+    }
+}
+
+// Filters for the mode lists of `modes` and `test pdm`, a mode has to pass all of them
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ModeFilter {
+    #[arg(long, value_name = "HZ", help = "Only modes at this refresh rate or higher")]
+    pub min_refresh: Option<u32>,
+    #[arg(long, value_name = "HZ", help = "Only modes at this refresh rate or lower")]
+    pub max_refresh: Option<u32>,
+    #[arg(long, value_name = "PIXELS", help = "Only modes at least this wide")]
+    pub min_width: Option<u32>,
+    #[arg(long, value_name = "PIXELS", help = "Only modes at least this tall")]
+    pub min_height: Option<u32>,
+    #[arg(long, value_name = "W:H", value_parser = parse_aspect, help = "Only modes with this aspect ratio, like 16:9 or 21:9")]
+    pub aspect: Option<(u32, u32)>,
+    #[arg(long, help = "Leave out interlaced modes")]
+    pub progressive: bool,
+    #[arg(long, help = "Only list modes if the display is HDR capable. Windows doesn't say which of its modes HDR works in.")]
+    pub hdr_capable: bool,
+}
+
+// Marketing ratios are rounded, 1366x768 is sold as 16:9 and 3440x1440 as 21:9
+const ASPECT_TOLERANCE: f64 = 0.03;
+
+fn parse_aspect(text: &str) -> Result<(u32, u32), String> {
+    let aspect = text.split_once(':').and_then(|(width, height)| Some((width.trim().parse().ok()?, height.trim().parse().ok()?)));
+    match aspect {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("'{}' isn't an aspect ratio like 16:9", text)),
+    }
+}
+
+pub fn has_aspect(width: u32, height: u32, aspect: (u32, u32)) -> bool {
+    let ratio = width as f64 / height as f64;
+    let expected = aspect.0 as f64 / aspect.1 as f64;
+    (ratio - expected).abs() <= expected * ASPECT_TOLERANCE
+}
 
-            modes.insert(DisplayMode {
-                width: dev_mode.dmPelsWidth,
-                height: dev_mode.dmPelsHeight,
-                refresh_rate: dev_mode.dmDisplayFrequency,
-            });
+impl ModeFilter {
+    pub fn matches(&self, mode: &DisplayMode) -> bool {
+        self.min_refresh.is_none_or(|min| mode.refresh_rate >= min)
+            && self.max_refresh.is_none_or(|max| mode.refresh_rate <= max)
+            && self.min_width.is_none_or(|min| mode.width >= min)
+            && self.min_height.is_none_or(|min| mode.height >= min)
+            && self.aspect.is_none_or(|aspect| has_aspect(mode.width, mode.height, aspect))
+            && !(self.progressive && mode.interlaced)
+    }
+
+    pub fn apply(&self, device: &DisplayDevice, modes: Vec<DisplayMode>) -> Vec<DisplayMode> {
+        if self.hdr_capable && !hdr::get_advanced_color_info(device).is_ok_and(|info| info.supported) {
+            info!("{} isn't HDR capable, leaving out every mode", device.device_name);
+            return Vec::new();
+        }
+        modes.into_iter().filter(|mode| self.matches(mode)).collect()
+    }
+}
+
+impl DisplayDevice {
+    // Laptop panels, which have no DDC/CI and take their brightness from the firmware instead
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self.output_technology,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL
+                | DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED
+                | DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED
+        )
+    }
 
-            mode_num += 1;
+    // Short name of the connector, as shown by `test ed --wide`
+    pub fn connection_name(&self) -> &'static str {
+        match self.output_technology {
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_HD15 => "VGA",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DVI => "DVI",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_HDMI => "HDMI",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EXTERNAL => "DisplayPort",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED => "eDP",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_USB_TUNNEL => "USB-C",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EXTERNAL | DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED => "UDI",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_LVDS => "LVDS",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL => "Internal",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_MIRACAST => "Miracast",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_WIRED => "Indirect",
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_VIRTUAL => "Virtual",
+            _ => "Other",
         }
+    }
+
+    // Displays created by a virtual display driver sit on that driver's root-enumerated adapter
+    pub fn virtual_driver(&self) -> Option<VddDriver> {
+        VddDriver::from_hardware_id(&self.adapter_device_id)
+    }
 
-        let mut result: Vec<DisplayMode> = modes.into_iter().collect();
-        result.sort_by(|a, b| {
+    // Get supported modes for a display, largest first
+    pub fn get_supported_modes(&self) -> Vec<DisplayMode> {
+        let mut modes = display_api::api().display_modes(&self.device_name);
+        modes.sort_by(|a, b| {
             b.width.cmp(&a.width)
                 .then(b.height.cmp(&a.height))
                 .then(b.refresh_rate.cmp(&a.refresh_rate))
+                .then(a.interlaced.cmp(&b.interlaced))
         });
-        result
+        modes
+    }
+
+    // The current settings don't say whether they are interlaced, a progressive mode is assumed
+    pub fn is_current_mode(&self, mode: &DisplayMode) -> bool {
+        (mode.width, mode.height) == self.current_resolution
+            && mode.refresh_rate == self.current_refresh_rate && !mode.interlaced
+            && mode.fixed_output == FixedOutput::Default
+    }
+
+    // Get the monitor name and device path for the display's target
+    pub fn get_target_device_name(&self) -> Option<TargetDeviceName> {
+        display_api::api().target_device_name(self.adapter_id, self.target_id)
+    }
+
+    // An ID that stays the same across reboots, unlike the index and \\.\DISPLAYn name: the
+    // EDID's manufacturer-product-serial, or the monitor's device instance ID without an EDID
+    pub fn stable_id(&self) -> Option<String> {
+        edid::get_display_edid_info(self)
+            .map(|edid| edid.stable_id())
+            .or_else(|| self.get_target_device_name()?.device_instance_id())
+    }
+}
+
+// EDID IDs look like GSM-5B08-123456, checked before reading every display's EDID
+fn looks_like_edid_id(selector: &str) -> bool {
+    let mut parts = selector.splitn(3, '-');
+    let manufacturer = parts.next().unwrap_or_default();
+    let product = parts.next().unwrap_or_default();
+    manufacturer.len() == 3 && manufacturer.chars().all(|c| c.is_ascii_alphabetic())
+        && product.len() == 4 && product.chars().all(|c| c.is_ascii_hexdigit())
+        && parts.next().is_some()
+}
+
+pub fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+// Does a display match a selector? Selectors can be "primary", a device index, a device
+// name (\\.\DISPLAY2 or just DISPLAY2), the monitor's friendly name or its stable ID (the
+// EDID ID or device instance ID), all case-insensitive. Only the last two survive a reboot.
+pub fn display_matches_selector(display: &DisplayDevice, selector: &str) -> bool {
+    let selector = selector.trim();
+
+    if selector.eq_ignore_ascii_case("primary") {
+        return display.is_primary;
+    }
+
+    if let Ok(index) = selector.parse::<u32>() {
+        return display.device_index == index;
+    }
+
+    let short_name = display.device_name.trim_start_matches("\\\\.\\");
+    if display.device_name.eq_ignore_ascii_case(selector) || short_name.eq_ignore_ascii_case(selector) {
+        return true;
+    }
+
+    if let Some(target) = display.get_target_device_name() {
+        if target.friendly_name.eq_ignore_ascii_case(selector)
+            || target.device_instance_id().is_some_and(|id| id.eq_ignore_ascii_case(selector)) {
+            return true;
+        }
+    }
+
+    looks_like_edid_id(selector)
+        && edid::get_display_edid_info(display).is_some_and(|edid| edid.stable_id().eq_ignore_ascii_case(selector))
+}
+
+// Find an active display by selector (see display_matches_selector)
+pub fn find_display(selector: &str) -> Option<DisplayDevice> {
+    let display = enumerate_displays()
+        .into_iter()
+        .find(|d| display_matches_selector(d, selector));
+
+    if display.is_none() {
+        error!("No active display matches selector '{}'", selector);
     }
+    display
 }
 
 // Get primary display info with supported modes
@@ -103,151 +320,442 @@ pub fn get_primary_display_info() -> Option<(DisplayDevice, Vec<DisplayMode>)> {
     }
 }
 
-pub fn enumerate_displays() -> Vec<DisplayDevice> {
-    info!("Initiating displays enumeration");
-    // First get display configuration information
-    let mut num_paths: u32 = 0;
-    let mut num_modes: u32 = 0;
+//==============================================================================
+// Adapters
+//==============================================================================
 
-    info!("Getting display configuration buffer sizes");
-    let result = unsafe {
-        GetDisplayConfigBufferSizes(
-            QDC_ONLY_ACTIVE_PATHS,
-            &mut num_paths,
-            &mut num_modes,
-        )
-    };
+// With hybrid graphics the same monitor can show up on the integrated and the discrete GPU, so
+// --adapter narrows enumeration, and with it every command, to one adapter. It's picked by its
+// index or LUID as `test ed` shows them, or by part of its name like "nvidia".
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Luid(LUID),
+    Name(String),
+}
 
-    if result != ERROR_SUCCESS {
-        error!("GetDisplayConfigBufferSizes failed with code: {:?}", result);
-        return Vec::new();
+static ADAPTER: OnceLock<AdapterSelector> = OnceLock::new();
+
+// LUIDs print as their high and low parts in hex, like 00000000:0000D2F1
+pub fn luid_text(luid: LUID) -> String {
+    format!("{:08X}:{:08X}", luid.HighPart as u32, luid.LowPart)
+}
+
+impl FromStr for AdapterSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(index) = s.parse() {
+            return Ok(AdapterSelector::Index(index));
+        }
+        let hex = |part: &str| u32::from_str_radix(part, 16).ok();
+        let luid = match s.split_once(':') {
+            Some((high, low)) => hex(high).zip(hex(low)),
+            None => s.strip_prefix("0x").and_then(|value| u64::from_str_radix(value, 16).ok()).map(|value| ((value >> 32) as u32, value as u32)),
+        };
+        match luid {
+            Some((high, low)) => Ok(AdapterSelector::Luid(LUID { LowPart: low, HighPart: high as i32 })),
+            None if s.is_empty() => Err("The adapter can't be empty".to_string()),
+            None => Ok(AdapterSelector::Name(s.to_string())),
+        }
     }
+}
 
-    let mut paths: Vec<DISPLAYCONFIG_PATH_INFO> = vec![Default::default(); num_paths as usize];
-    let mut modes: Vec<DISPLAYCONFIG_MODE_INFO> = vec![Default::default(); num_modes as usize];
+// As given, so a process the helper starts gets the same adapter
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdapterSelector::Index(index) => write!(f, "{}", index),
+            AdapterSelector::Luid(luid) => write!(f, "{}", luid_text(*luid)),
+            AdapterSelector::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
 
-    let result = unsafe {
-        QueryDisplayConfig(
-            QDC_ONLY_ACTIVE_PATHS,
-            &mut num_paths,
-            paths.as_mut_ptr(),
-            &mut num_modes,
-            modes.as_mut_ptr(),
-            None,
-        )
+impl AdapterSelector {
+    pub fn matches(&self, display: &DisplayDevice) -> bool {
+        self.matches_adapter(display.adapter_id, &display.device_string, Some(display.adapter_index))
+    }
+
+    // Adapters without an active display have no index
+    fn matches_adapter(&self, adapter_id: LUID, name: &str, index: Option<usize>) -> bool {
+        match self {
+            AdapterSelector::Index(wanted) => index == Some(*wanted),
+            AdapterSelector::Luid(luid) => adapter_id == *luid,
+            AdapterSelector::Name(wanted) => name.to_lowercase().contains(&wanted.to_lowercase()),
+        }
+    }
+}
+
+// For --adapter, before anything enumerates
+pub fn use_adapter(adapter: AdapterSelector) {
+    let _ = ADAPTER.set(adapter);
+}
+
+pub fn adapter() -> Option<&'static AdapterSelector> {
+    ADAPTER.get()
+}
+
+//==============================================================================
+// Inactive outputs
+//==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputStatus {
+    Inactive,     // a monitor is connected but not part of the desktop
+    Disconnected, // a GPU output with nothing on it
+}
+
+impl OutputStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputStatus::Inactive => "inactive",
+            OutputStatus::Disconnected => "disconnected",
+        }
+    }
+}
+
+// What `test ed --all` lists besides the active displays: monitors switched off in Windows (by
+// disable-display, Win+P or a virtual display driver's display nobody turned on) and outputs
+// without a display. An inactive monitor has no GDI name until it's enabled, an output without
+// a display has no monitor.
+pub struct InactiveOutput {
+    pub status: OutputStatus,
+    pub device_name: String,
+    pub monitor: String,
+    pub adapter: String,
+    pub adapter_id: LUID,
+    pub adapter_device_id: String,
+}
+
+impl InactiveOutput {
+    pub fn virtual_driver(&self) -> Option<VddDriver> {
+        VddDriver::from_hardware_id(&self.adapter_device_id)
+    }
+}
+
+pub fn enumerate_inactive_outputs() -> Vec<InactiveOutput> {
+    let api = display_api::api();
+    let devices = api.display_devices();
+    let paths = api.query_display_config(QDC_ALL_PATHS).map(|(paths, _)| paths).unwrap_or_else(|e| {
+        error!("Failed to query inactive display paths: {}", e);
+        Vec::new()
+    });
+
+    // QDC_ALL_PATHS pairs every target with every source that could drive it, which also gives
+    // each GDI output's adapter
+    let mut sources: Vec<(String, LUID)> = Vec::new();
+    for path in &paths {
+        if let Some(gdi_name) = api.source_gdi_name(path.sourceInfo.adapterId, path.sourceInfo.id) {
+            if !sources.iter().any(|(name, _)| name.eq_ignore_ascii_case(&gdi_name)) {
+                sources.push((gdi_name, path.sourceInfo.adapterId));
+            }
+        }
+    }
+    let device_on = |adapter_id: LUID| sources.iter()
+        .filter(|(_, luid)| *luid == adapter_id)
+        .find_map(|(gdi_name, _)| devices.iter().find(|device| device.device_name.eq_ignore_ascii_case(gdi_name)));
+
+    // An available target has a monitor on it. It's listed once, not once per source.
+    let mut outputs: Vec<InactiveOutput> = Vec::new();
+    let mut seen: Vec<(LUID, u32)> = Vec::new();
+    for path in &paths {
+        let target = (path.targetInfo.adapterId, path.targetInfo.id);
+        let active = paths.iter().any(|other| other.flags & DISPLAYCONFIG_PATH_ACTIVE != 0 && (other.targetInfo.adapterId, other.targetInfo.id) == target);
+        if !path.targetInfo.targetAvailable.as_bool() || active || seen.contains(&target) {
+            continue;
+        }
+        seen.push(target);
+
+        let device = device_on(target.0);
+        outputs.push(InactiveOutput {
+            status: OutputStatus::Inactive,
+            device_name: String::new(),
+            monitor: api.target_device_name(target.0, target.1).map(|name| name.friendly_name).unwrap_or_default(),
+            adapter: device.map(|device| device.device_string.clone()).unwrap_or_default(),
+            adapter_id: target.0,
+            adapter_device_id: device.map(|device| device.device_id.clone()).unwrap_or_default(),
+        });
+    }
+
+    for device in devices.iter().filter(|device| device.state_flags == 0) {
+        outputs.push(InactiveOutput {
+            status: OutputStatus::Disconnected,
+            device_name: device.device_name.clone(),
+            monitor: String::new(),
+            adapter: device.device_string.clone(),
+            adapter_id: sources.iter().find(|(name, _)| name.eq_ignore_ascii_case(&device.device_name)).map_or(LUID::default(), |(_, luid)| *luid),
+            adapter_device_id: device.device_id.clone(),
+        });
+    }
+
+    let Some(adapter) = ADAPTER.get() else {
+        return outputs;
     };
+    let active = topology().map(|topology| topology.displays.clone()).unwrap_or_default();
+    outputs.into_iter().filter(|output| {
+        let index = active.iter().find(|display| display.adapter_id == output.adapter_id).map(|display| display.adapter_index);
+        adapter.matches_adapter(output.adapter_id, &output.adapter, index)
+    }).collect()
+}
 
-    if result != ERROR_SUCCESS {
-        error!("QueryDisplayConfig failed with code: {:?}", result);
-        return Vec::new();
+//==============================================================================
+// Display enumeration
+//==============================================================================
+
+// The active paths and displays, queried once and shared by everything that runs until the
+// next change. A prep script line that checks the mode, SDR level and ICC profile would
+// otherwise query and enumerate three times. Whatever changes modes or paths calls
+// invalidate_topology(). Kept per thread, which for the CLI is per process, so each test
+// gets its own mock's topology.
+#[derive(Clone)]
+pub struct DisplayTopology {
+    pub paths: Vec<DISPLAYCONFIG_PATH_INFO>,
+    pub modes: Vec<DISPLAYCONFIG_MODE_INFO>,
+    pub displays: Vec<DisplayDevice>,
+}
+
+thread_local! {
+    static TOPOLOGY: RefCell<Option<Rc<DisplayTopology>>> = const { RefCell::new(None) };
+}
+
+pub fn topology() -> windows::core::Result<Rc<DisplayTopology>> {
+    if let Some(topology) = TOPOLOGY.with(|cached| cached.borrow().clone()) {
+        debug!("Using the cached display topology");
+        return Ok(topology);
+    }
+
+    let topology = Rc::new(timings::time("enumeration", "", query_topology)?);
+    TOPOLOGY.with(|cached| *cached.borrow_mut() = Some(topology.clone()));
+    Ok(topology)
+}
+
+pub fn invalidate_topology() {
+    TOPOLOGY.with(|cached| *cached.borrow_mut() = None);
+}
+
+// Every module that needs paths, sources or targets goes through here. Active paths come
+// from the cached topology.
+pub fn query_display_config(flags: QUERY_DISPLAY_CONFIG_FLAGS) -> windows::core::Result<(Vec<DISPLAYCONFIG_PATH_INFO>, Vec<DISPLAYCONFIG_MODE_INFO>)> {
+    if flags == QDC_ONLY_ACTIVE_PATHS {
+        let topology = topology()?;
+        return Ok((topology.paths.clone(), topology.modes.clone()));
     }
+    display_api::api().query_display_config(flags)
+}
+
+// The active displays, on the --adapter adapter only when one was picked
+pub fn enumerate_displays() -> Vec<DisplayDevice> {
+    let displays = topology().map(|topology| topology.displays.clone()).unwrap_or_default();
+    match ADAPTER.get() {
+        Some(adapter) => displays.into_iter().filter(|display| adapter.matches(display)).collect(),
+        None => displays,
+    }
+}
+
+fn query_topology() -> windows::core::Result<DisplayTopology> {
+    info!("Initiating displays enumeration");
+    let api = display_api::api();
+    let (paths, modes) = api.query_display_config(QDC_ONLY_ACTIVE_PATHS)?;
 
-    // Store the paths info for later matching
-    let path_info: Vec<(u32, LUID)> = paths[..num_paths as usize]
+    // Store the paths info for later matching, keyed by the GDI device name of the path's source,
+    // which is what ties a path to EnumDisplayDevices
+    let path_info: Vec<(String, u32, LUID, u32, DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY)> = paths
         .iter()
-        .map(|path| {
-            info!("Path source ID: {}, Adapter ID: {:?}", path.sourceInfo.id, path.sourceInfo.adapterId);
-            (path.sourceInfo.id, path.sourceInfo.adapterId)
+        .filter_map(|path| {
+            let gdi_name = api.source_gdi_name(path.sourceInfo.adapterId, path.sourceInfo.id)?;
+            info!("Path {}: source ID: {}, Adapter ID: {:?}, Target ID: {}", gdi_name, path.sourceInfo.id, path.sourceInfo.adapterId, path.targetInfo.id);
+            Some((gdi_name, path.sourceInfo.id, path.sourceInfo.adapterId, path.targetInfo.id, path.targetInfo.outputTechnology))
         })
         .collect();
 
-    // Now enumerate displays using EnumDisplayDevicesW
     let mut displays: Vec<DisplayDevice> = Vec::new();
-    let mut device_index: u32 = 0;
+    let mut adapters: Vec<LUID> = Vec::new();
+    for device in api.display_devices() {
+        // If state is 0, no display is attached
+        if device.state_flags == 0 {
+            info!("Port {} exists but no display attached", device.device_name);
+            continue;
+        }
 
-    loop {
-        let mut display_device: DISPLAY_DEVICEW = unsafe { std::mem::zeroed() };
-        display_device.cb = size_of::<DISPLAY_DEVICEW>() as u32;
-
-        let success = unsafe {
-            EnumDisplayDevicesW(
-                PWSTR::null(),
-                device_index,
-                &mut display_device,
-                0,
-            )
+        let Some(settings) = api.current_settings(&device.device_name) else {
+            error!("Failed to get settings for display: {}", device.device_name);
+            continue;
         };
 
-        if !success.as_bool() {
-            break;
-        }
+        // A cloned source has one path per target, the first one stands for the display. Source
+        // IDs start over on every adapter, only the GDI name ties a path to this device.
+        let (adapter_id, source_id, target_id, output_technology) = match path_info.iter()
+            .find(|(gdi_name, _, _, _, _)| gdi_name.eq_ignore_ascii_case(&device.device_name))
+        {
+            Some(&(_, id, luid, target, technology)) => (luid, id, target, technology),
+            None => {
+                error!("No active path leads to {}, its adapter and target are unknown", device.device_name);
+                (LUID { LowPart: 0, HighPart: 0 }, 0, 0, DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY::default())
+            }
+        };
+        let adapter_index = match adapters.iter().position(|&luid| luid == adapter_id) {
+            Some(index) => index,
+            None => {
+                adapters.push(adapter_id);
+                adapters.len() - 1
+            }
+        };
 
-        let device_name = String::from_utf16_lossy(
-            &display_device.DeviceName[..].iter()
-                .take_while(|&&c| c != 0)
-                .map(|&c| c)
-                .collect::<Vec<u16>>()
-        );
-        let device_string = String::from_utf16_lossy(
-            &display_device.DeviceString[..].iter()
-                .take_while(|&&c| c != 0)
-                .map(|&c| c)
-                .collect::<Vec<u16>>()
+        let found = DisplayDevice {
+            device_index: device.device_index,
+            device_name: device.device_name,
+            device_string: device.device_string,
+            state_flags: device.state_flags,
+            is_primary: (device.state_flags & DISPLAY_DEVICE_PRIMARY_DEVICE) != 0,
+            current_resolution: (settings.width, settings.height),
+            current_refresh_rate: settings.refresh_rate,
+            position: settings.position,
+            orientation: settings.orientation,
+            adapter_id,
+            source_id,
+            target_id,
+            output_technology,
+            adapter_device_id: device.device_id,
+            adapter_index,
+        };
+
+        info!("Found display: {} ({}) - {}x{} @{}Hz{} [device_index: {}, source_id: {}, adapter: {}]",
+            found.device_name,
+            found.device_string,
+            found.current_resolution.0,
+            found.current_resolution.1,
+            found.current_refresh_rate,
+            if found.is_primary { " [Primary]" } else { "" },
+            found.device_index,
+            found.source_id,
+            luid_text(found.adapter_id)
         );
 
-        let state_flags = display_device.StateFlags;
+        displays.push(found);
+    }
 
-        // If state is 0, no display is attached
-        if state_flags == 0 {
-            info!("Port {} exists but no display attached", device_name);
-            device_index += 1;
-            continue;
-        }
+    Ok(DisplayTopology { paths, modes, displays })
+}
 
-        // Get current display settings
-        let mut dev_mode: DEVMODEW = unsafe { std::mem::zeroed() };
-        dev_mode.dmSize = size_of::<DEVMODEW>() as u16;
+//==============================================================================
+// Parallel work
+//==============================================================================
 
-        let settings_success = unsafe {
-            EnumDisplaySettingsW(
-                PCWSTR::from_raw(display_device.DeviceName.as_ptr()),
-                ENUM_CURRENT_SETTINGS,
-                &mut dev_mode,
-            )
-        };
+// Runs the operation for each item on its own thread, for Win32 calls on different displays that
+// don't wait on each other. The threads start from the caller's topology instead of enumerating
+// again. What they change isn't visible to the caller's cached topology, so it's dropped
+// afterwards. Results come back in the items' order.
+pub fn for_each_in_parallel<I: Sync, T: Send>(items: &[I], operation: impl Fn(&I) -> T + Sync) -> Vec<T> {
+    if items.len() < 2 {
+        return items.iter().map(operation).collect();
+    }
+
+    let shared = topology().ok().map(|topology| DisplayTopology::clone(&topology));
+    #[cfg(test)]
+    let test_api = display_api::test_api();
+    let results = std::thread::scope(|scope| {
+        let threads: Vec<_> = items.iter().map(|item| {
+            let shared = shared.clone();
+            let operation = &operation;
+            scope.spawn(move || {
+                #[cfg(test)]
+                if let Some(api) = test_api {
+                    display_api::use_test_api(api);
+                }
+                if let Some(topology) = shared {
+                    TOPOLOGY.with(|cached| *cached.borrow_mut() = Some(Rc::new(topology)));
+                }
+                operation(item)
+            })
+        }).collect();
+        threads.into_iter().map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))).collect()
+    });
+    invalidate_topology();
+    results
+}
+
+//==============================================================================
+// Settling
+//==============================================================================
 
-        if settings_success.as_bool() {
-            let is_primary = (state_flags & DISPLAY_DEVICE_PRIMARY_DEVICE) != 0;
-
-            // Find matching path info. WARNING: we assume that the source ID matches the device index. Not sure if this is always true.
-            let (adapter_id, source_id) = path_info.iter()
-                .find(|(id, _)| *id == device_index)
-                .map(|(id, luid)| (*luid, *id))
-                .unwrap_or((LUID { LowPart: 0, HighPart: 0 }, 0));
-
-            let display = DisplayDevice {
-                device_index,
-                device_name: device_name.clone(),
-                device_string: device_string.clone(),
-                state_flags,
-                is_primary,
-                current_resolution: (dev_mode.dmPelsWidth, dev_mode.dmPelsHeight),
-                current_refresh_rate: dev_mode.dmDisplayFrequency,
-                adapter_id,
-                source_id,
-            };
-
-            info!("Found display: {} ({}) - {}x{} @{}Hz{} [device_index: {}, source_id: {}, adapter: {:?}]",
-                display.device_name,
-                display.device_string,
-                display.current_resolution.0,
-                display.current_resolution.1,
-                display.current_refresh_rate,
-                if display.is_primary { " [Primary]" } else { "" },
-                display.device_index,
-                display.source_id,
-                display.adapter_id
-            );
-
-            displays.push(display);
-        } else {
-            error!("Failed to get settings for display: {}", device_name);
+// Enabling a virtual display or toggling HDR makes Windows re-enumerate several times over a
+// second or two. With --settle, commands that change displays wait until the display
+// configuration has stayed the same for a while, so the next step (or the next prep command)
+// doesn't look at a topology that's still being rebuilt.
+
+const SETTLE_POLL: Duration = Duration::from_millis(50);
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(10);
+// Without --settle, long enough for most displays to resync after a mode switch
+const MODE_SWITCH_WAIT: Duration = Duration::from_secs(3);
+
+static SETTLE: OnceLock<Duration> = OnceLock::new();
+
+// For --settle, how long the topology has to stay the same
+pub fn use_settle(stable_for: Duration) {
+    let _ = SETTLE.set(stable_for);
+}
+
+// Every path and mode, available or not, so a display still appearing counts as a change
+pub fn topology_fingerprint() -> Option<u64> {
+    use std::hash::{DefaultHasher, Hasher};
+    let (paths, modes) = display_api::api().query_display_config(QDC_ALL_PATHS).ok()?;
+    let mut hasher = DefaultHasher::new();
+    // Both are plain C structs, as QueryDisplayConfig filled them in
+    unsafe {
+        hasher.write(std::slice::from_raw_parts(paths.as_ptr().cast::<u8>(), std::mem::size_of_val(paths.as_slice())));
+        hasher.write(std::slice::from_raw_parts(modes.as_ptr().cast::<u8>(), std::mem::size_of_val(modes.as_slice())));
+    }
+    Some(hasher.finish())
+}
+
+// Polls until the fingerprint has stayed the same for stable_for. A failed query counts as a
+// change, Windows fails them mid re-enumeration. False if it didn't settle before the timeout.
+pub fn wait_until_stable(mut fingerprint: impl FnMut() -> Option<u64>, stable_for: Duration, timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut last = fingerprint();
+    let mut unchanged_since = Instant::now();
+    loop {
+        if last.is_some() && unchanged_since.elapsed() >= stable_for {
+            return true;
+        }
+        if start.elapsed() >= timeout {
+            return false;
         }
+        std::thread::sleep(SETTLE_POLL.min(stable_for));
+        let current = fingerprint();
+        if current.is_none() || current != last {
+            last = current;
+            unchanged_since = Instant::now();
+        }
+    }
+}
 
-        device_index += 1;
+// Waits for the topology to settle after a change, if --settle asked for it. Nothing changed in a dry run.
+pub fn settle() {
+    let Some(&stable_for) = SETTLE.get() else {
+        return;
+    };
+    if display_api::is_dry_run() {
+        return;
+    }
+    let settled = timings::time("settle", format!("{} ms", stable_for.as_millis()), || wait_until_stable(topology_fingerprint, stable_for, SETTLE_TIMEOUT));
+    if settled {
+        debug!("Display topology stable for {} ms", stable_for.as_millis());
+    } else {
+        warn!("Display topology still changing after {} s, going on anyway", SETTLE_TIMEOUT.as_secs());
     }
+    invalidate_topology();
+}
 
-    displays
+// After ChangeDisplaySettingsEx. With --settle it only waits as long as the topology keeps changing.
+pub fn settle_after_mode_switch(device_name: &str) {
+    if SETTLE.get().is_some() {
+        settle();
+    } else {
+        timings::time("settle_wait", device_name, || std::thread::sleep(MODE_SWITCH_WAIT));
+    }
 }
 
 
@@ -344,3 +852,199 @@ pub fn test_query_display_config() -> Result<(), windows::core::Error> {
 pub fn test_query_display_config() -> Result<(), windows::core::Error> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn three_displays() -> Vec<MockDisplay> {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.position = (-3840, 0);
+        let mut monitor = MockDisplay::new(2, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        let mut unplugged = MockDisplay::new(3, "", (1920, 1080, 60));
+        unplugged.active = false;
+        vec![tv, monitor, unplugged]
+    }
+
+    #[test]
+    fn settling_waits_out_re_enumeration() {
+        // Changes twice, fails a query, then holds still
+        let mut fingerprints = [Some(1), Some(2), None, Some(3)].into_iter();
+        let start = Instant::now();
+        assert!(wait_until_stable(|| fingerprints.next().unwrap_or(Some(3)), Duration::from_millis(100), Duration::from_secs(5)));
+        assert!(start.elapsed() >= Duration::from_millis(250));
+
+        let mut counter = 0;
+        assert!(!wait_until_stable(|| { counter += 1; Some(counter) }, Duration::from_millis(100), Duration::from_millis(300)));
+
+        MockDisplayApi::install(three_displays());
+        assert_eq!(topology_fingerprint(), topology_fingerprint());
+    }
+
+    #[test]
+    fn enumerate_skips_outputs_without_a_display() {
+        MockDisplayApi::install(three_displays());
+        let displays = enumerate_displays();
+        let names: Vec<&str> = displays.iter().map(|d| d.device_name.as_str()).collect();
+        assert_eq!(names, ["\\\\.\\DISPLAY1", "\\\\.\\DISPLAY2"]);
+    }
+
+    #[test]
+    fn inactive_outputs_are_listed_apart() {
+        let mut displays = three_displays();
+        displays[2].monitor_name = "Virtual Display".to_string();
+        MockDisplayApi::install(displays);
+
+        let outputs: Vec<(OutputStatus, String, String)> = enumerate_inactive_outputs().into_iter()
+            .map(|output| (output.status, output.device_name, output.monitor))
+            .collect();
+        // The switched off monitor, and the GDI output it left behind
+        assert_eq!(outputs, [
+            (OutputStatus::Inactive, String::new(), "Virtual Display".to_string()),
+            (OutputStatus::Disconnected, "\\\\.\\DISPLAY3".to_string(), String::new()),
+        ]);
+        assert_eq!(enumerate_displays().len(), 2);
+    }
+
+    #[test]
+    fn enumerate_matches_paths_by_source_name_not_index() {
+        MockDisplayApi::install(three_displays());
+        let displays = enumerate_displays();
+        // Source ids are 11 and 12 while the device indexes are 0 and 1
+        assert_eq!((displays[0].source_id, displays[0].target_id), (11, 101));
+        assert_eq!((displays[1].source_id, displays[1].target_id), (12, 102));
+        assert_eq!(displays[0].position, (-3840, 0));
+        assert!(displays[1].is_primary);
+    }
+
+    #[test]
+    fn hybrid_graphics_sources_are_told_apart_by_adapter() {
+        // Both GPUs number their sources and targets from 0
+        let mut laptop = MockDisplay::new(1, "Built-in panel", (2560, 1600, 165));
+        laptop.primary = true;
+        laptop.description = "Intel(R) UHD Graphics".to_string();
+        laptop.hdr_supported = true;
+        let mut tv = MockDisplay::new(2, "LG TV SSCR2", (3840, 2160, 120));
+        tv.description = "NVIDIA GeForce RTX 4070 Laptop GPU".to_string();
+        tv.adapter_id = LUID { LowPart: 0xD2F1, HighPart: 0 };
+        tv.position = (2560, 0);
+        for display in [&mut laptop, &mut tv] {
+            (display.source_id, display.target_id) = (0, 0);
+        }
+        MockDisplayApi::install(vec![laptop, tv]);
+
+        let displays = enumerate_displays();
+        let monitors: Vec<String> = displays.iter().map(|d| d.get_target_device_name().unwrap().friendly_name).collect();
+        assert_eq!(monitors, ["Built-in panel", "LG TV SSCR2"]);
+        assert_eq!((displays[0].adapter_index, displays[1].adapter_index), (0, 1));
+        assert_eq!(displays[1].position, (2560, 0));
+        // The same target ID, but only the panel is HDR capable
+        assert!(hdr::get_advanced_color_info(&displays[0]).unwrap().supported);
+        assert!(!hdr::get_advanced_color_info(&displays[1]).unwrap().supported);
+
+        let picks = |selector: &str| {
+            let adapter: AdapterSelector = selector.parse().unwrap();
+            displays.iter().filter(|d| adapter.matches(d)).map(|d| d.device_name.as_str()).collect::<Vec<_>>()
+        };
+        assert_eq!(picks("1"), ["\\\\.\\DISPLAY2"]);
+        assert_eq!(picks("nvidia"), ["\\\\.\\DISPLAY2"]);
+        assert_eq!(picks("00000000:0000d2f1"), ["\\\\.\\DISPLAY2"]);
+        assert_eq!(picks("0xD2F1"), ["\\\\.\\DISPLAY2"]);
+        assert_eq!(picks("intel"), ["\\\\.\\DISPLAY1"]);
+        assert_eq!(luid_text(displays[1].adapter_id), "00000000:0000D2F1");
+    }
+
+    #[test]
+    fn mode_filters() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.modes = vec![(3840, 2160, 120), (3840, 2160, 60), (2560, 1600, 120), (1366, 768, 60), (1280, 800, 90)];
+        MockDisplayApi::install(vec![tv]);
+        let display = find_display("primary").unwrap();
+        let listed = |filter: &ModeFilter| {
+            filter.apply(&display, display.get_supported_modes()).iter().map(|mode| (mode.width, mode.height, mode.refresh_rate)).collect::<Vec<_>>()
+        };
+
+        let sixteen_by_nine = ModeFilter { aspect: Some((16, 9)), min_refresh: Some(60), max_refresh: Some(100), ..Default::default() };
+        assert_eq!(listed(&sixteen_by_nine), [(3840, 2160, 60), (1366, 768, 60)]);
+        let sixteen_by_ten = ModeFilter { aspect: parse_aspect("16:10").ok(), min_width: Some(1920), ..Default::default() };
+        assert_eq!(listed(&sixteen_by_ten), [(2560, 1600, 120)]);
+        assert!(listed(&ModeFilter { hdr_capable: true, ..Default::default() }).is_empty());
+        assert!(parse_aspect("16x9").is_err());
+        assert!(parse_aspect("16:0").is_err());
+    }
+
+    #[test]
+    fn selectors() {
+        MockDisplayApi::install(three_displays());
+        let found = |selector: &str| find_display(selector).map(|d| d.device_name);
+
+        assert_eq!(found("primary").as_deref(), Some("\\\\.\\DISPLAY2"));
+        assert_eq!(found("0").as_deref(), Some("\\\\.\\DISPLAY1"));
+        assert_eq!(found("display2").as_deref(), Some("\\\\.\\DISPLAY2"));
+        assert_eq!(found("\\\\.\\DISPLAY1").as_deref(), Some("\\\\.\\DISPLAY1"));
+        assert_eq!(found(" lg tv sscr2 ").as_deref(), Some("\\\\.\\DISPLAY1"));
+        assert_eq!(found("DISPLAY3"), None);
+        assert_eq!(found("7"), None);
+    }
+
+    #[test]
+    fn stable_ids_select_displays() {
+        MockDisplayApi::install(three_displays());
+        // The mock has no EDIDs, so the ID is the monitor's device instance ID
+        let monitor = find_display("DELL U2723QE").unwrap();
+        assert_eq!(monitor.stable_id().as_deref(), Some("DISPLAY\\MCK0002\\1&0&UID2"));
+        assert_eq!(find_display("display\\mck0002\\1&0&uid2").map(|d| d.device_name).as_deref(), Some("\\\\.\\DISPLAY2"));
+
+        assert!(looks_like_edid_id("GSM-5B08-123456"));
+        assert!(looks_like_edid_id("DEL-A1B2-CN0-XYZ"));
+        assert!(!looks_like_edid_id("LG TV SSCR2"));
+        assert!(!looks_like_edid_id("GSM-5B08"));
+    }
+
+    #[test]
+    fn supported_modes_are_sorted_largest_first() {
+        let mut display = MockDisplay::new(1, "TV", (1920, 1080, 60));
+        display.modes = vec![(1920, 1080, 60), (3840, 2160, 60), (1920, 1080, 120), (1280, 720, 60)];
+        MockDisplayApi::install(vec![display]);
+
+        let modes = find_display("0").unwrap().get_supported_modes();
+        let modes: Vec<(u32, u32, u32)> = modes.iter().map(|m| (m.width, m.height, m.refresh_rate)).collect();
+        assert_eq!(modes, [(3840, 2160, 60), (1920, 1080, 120), (1920, 1080, 60), (1280, 720, 60)]);
+    }
+
+    #[test]
+    fn topology_is_shared_until_something_changes() {
+        let api = MockDisplayApi::install(three_displays());
+        assert_eq!(find_display("primary").unwrap().current_resolution, (2560, 1440));
+
+        // Changed behind the helper's back, so the cached topology still has the old mode
+        api.displays.lock().unwrap()[1].mode = (1920, 1080, 60);
+        assert_eq!(find_display("primary").unwrap().current_resolution, (2560, 1440));
+
+        invalidate_topology();
+        assert_eq!(find_display("primary").unwrap().current_resolution, (1920, 1080));
+    }
+
+    #[test]
+    fn mode_changes_refresh_the_topology() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.modes.push((3840, 2160, 60));
+        MockDisplayApi::install(vec![tv]);
+
+        let display = find_display("primary").unwrap();
+        assert!(crate::change_display_mode::change_display_mode(&display, 3840, 2160, 60, None, false));
+        assert_eq!(find_display("primary").unwrap().current_refresh_rate, 60);
+    }
+
+    #[test]
+    fn device_instance_id_from_monitor_path() {
+        let target = TargetDeviceName {
+            friendly_name: "DELL".to_string(),
+            device_path: "\\\\?\\DISPLAY#GSM5B08#5&2a1b3c4d&0&UID4353#{e6f07b5f-ee97-4a90-b076-33f57bf4eaa7}".to_string(),
+        };
+        assert_eq!(target.device_instance_id().as_deref(), Some("DISPLAY\\GSM5B08\\5&2a1b3c4d&0&UID4353"));
+        assert_eq!(target.hardware_id(), Some("GSM5B08"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::mem::size_of;
+use windows::Win32::Devices::Display::{
+    DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    DISPLAYCONFIG_DEVICE_INFO_TYPE,
+};
+use windows::Win32::Foundation::{ERROR_SUCCESS, WIN32_ERROR};
+use windows::core::Result;
+use tracing::{info, error, instrument};
+
+use crate::display_api;
+use crate::displays_info::DisplayDevice;
+use crate::exit_code;
+use crate::windows_version::{self, Feature};
+
+// Undocumented, but stable since Windows 10 1607. The values are relative steps from the
+// recommended scale, which is what the Settings app slider uses under the hood.
+pub const DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE: DISPLAYCONFIG_DEVICE_INFO_TYPE = DISPLAYCONFIG_DEVICE_INFO_TYPE(-3i32);
+pub const DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE: DISPLAYCONFIG_DEVICE_INFO_TYPE = DISPLAYCONFIG_DEVICE_INFO_TYPE(-4i32);
+
+// The scale steps offered by Windows, in order
+pub const DPI_SCALE_STEPS: [u32; 12] = [100, 125, 150, 175, 200, 225, 250, 300, 350, 400, 450, 500];
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DisplayconfigSourceDpiScaleGet {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    min_scale_rel: i32,
+    cur_scale_rel: i32,
+    max_scale_rel: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DisplayconfigSourceDpiScaleSet {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    scale_rel: i32,
+}
+
+pub struct DpiScaling {
+    pub current: u32,
+    pub recommended: u32,
+    pub minimum: u32,
+    pub maximum: u32,
+    recommended_index: i32,
+}
+
+fn scale_at(index: i32) -> u32 {
+    DPI_SCALE_STEPS[index.clamp(0, DPI_SCALE_STEPS.len() as i32 - 1) as usize]
+}
+
+//==============================================================================
+// Get / set scaling
+//==============================================================================
+
+pub fn get_display_scaling(device: &DisplayDevice) -> Result<DpiScaling> {
+    let mut request = DisplayconfigSourceDpiScaleGet {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_DPI_SCALE,
+            size: size_of::<DisplayconfigSourceDpiScaleGet>() as u32,
+            adapterId: device.adapter_id,
+            id: device.source_id,
+        },
+        min_scale_rel: 0,
+        cur_scale_rel: 0,
+        max_scale_rel: 0,
+    };
+
+    let result = display_api::api().get_device_info(&mut request.header);
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "Failed to get DPI scaling for {}: {}", device.device_name, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    // The minimum relative step is always 100%, which tells us where "recommended" sits
+    let recommended_index = request.min_scale_rel.abs();
+    Ok(DpiScaling {
+        current: scale_at(recommended_index + request.cur_scale_rel),
+        recommended: scale_at(recommended_index),
+        minimum: scale_at(recommended_index + request.min_scale_rel),
+        maximum: scale_at(recommended_index + request.max_scale_rel),
+        recommended_index,
+    })
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, percent = percent))]
+pub fn set_display_scaling(device: &DisplayDevice, percent: u32) -> Result<()> {
+    windows_version::require(Feature::PerDisplayScaling)?;
+    let Some(target_index) = DPI_SCALE_STEPS.iter().position(|&step| step == percent) else {
+        return Err(exit_code::invalid_input(format!("{}% is not a Windows scaling step ({:?})", percent, DPI_SCALE_STEPS)));
+    };
+
+    let scaling = get_display_scaling(device)?;
+    if percent < scaling.minimum || percent > scaling.maximum {
+        return Err(exit_code::not_supported(format!("{}% is outside the range supported by {} ({}%-{}%)",
+            percent, device.device_name, scaling.minimum, scaling.maximum)));
+    }
+
+    info!("Setting DPI scaling of {} from {}% to {}%", device.device_name, scaling.current, percent);
+
+    let request = DisplayconfigSourceDpiScaleSet {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_SET_DPI_SCALE,
+            size: size_of::<DisplayconfigSourceDpiScaleSet>() as u32,
+            adapterId: device.adapter_id,
+            id: device.source_id,
+        },
+        scale_rel: target_index as i32 - scaling.recommended_index,
+    };
+
+    let result = display_api::api().set_device_info(&request.header);
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "Failed to set DPI scaling for {}: {}", device.device_name, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    Ok(())
+}
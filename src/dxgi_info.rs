@@ -0,0 +1,133 @@
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1,
+    IDXGIFactory1,
+    IDXGIOutput6,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_COLOR_SPACE_TYPE,
+    DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+    DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+    DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020,
+    DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P2020,
+};
+use windows::Win32::Foundation::LUID;
+use windows::core::Interface;
+use tracing::{info, error};
+
+use crate::displays_info::wide_to_string;
+
+// What DXGI reports for an output, including what Windows is actually sending to it right now
+pub struct OutputColorInfo {
+    pub device_name: String,
+    pub adapter_name: String,
+    pub bits_per_color: u32,
+    pub color_space: DXGI_COLOR_SPACE_TYPE,
+    pub red_primary: [f32; 2],
+    pub green_primary: [f32; 2],
+    pub blue_primary: [f32; 2],
+    pub white_point: [f32; 2],
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    pub max_full_frame_luminance: f32,
+}
+
+impl OutputColorInfo {
+    // BT.2020 primaries with the PQ (ST 2084) curve is what Windows outputs when HDR is on
+    pub fn is_hdr_active(&self) -> bool {
+        self.color_space == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020
+    }
+
+    pub fn color_space_name(&self) -> &'static str {
+        match self.color_space {
+            DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709 => "sRGB (BT.709, gamma 2.2)",
+            DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709 => "scRGB (BT.709, linear)",
+            DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020 => "HDR10 (BT.2020, PQ)",
+            DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P2020 => "BT.2020, gamma 2.2",
+            _ => "Other",
+        }
+    }
+}
+
+// Query every output on every adapter. Outputs on drivers without IDXGIOutput6 are skipped.
+pub fn query_output_color_info() -> windows::core::Result<Vec<OutputColorInfo>> {
+    info!("Querying DXGI outputs for color information");
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+    let mut outputs = Vec::new();
+    let mut adapter_index = 0;
+
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(adapter_index) } {
+        let adapter_name = unsafe { adapter.GetDesc1() }
+            .map(|desc| wide_to_string(&desc.Description))
+            .unwrap_or_default();
+
+        let mut output_index = 0;
+        while let Ok(output) = unsafe { adapter.EnumOutputs(output_index) } {
+            output_index += 1;
+
+            let output6 = match output.cast::<IDXGIOutput6>() {
+                Ok(output6) => output6,
+                Err(e) => {
+                    error!("IDXGIOutput6 not available for output {} on {}: {}", output_index - 1, adapter_name, e);
+                    continue;
+                }
+            };
+
+            let desc = match unsafe { output6.GetDesc1() } {
+                Ok(desc) => desc,
+                Err(e) => {
+                    error!("GetDesc1 failed for output {} on {}: {}", output_index - 1, adapter_name, e);
+                    continue;
+                }
+            };
+
+            let output_info = OutputColorInfo {
+                device_name: wide_to_string(&desc.DeviceName),
+                adapter_name: adapter_name.clone(),
+                bits_per_color: desc.BitsPerColor,
+                color_space: desc.ColorSpace,
+                red_primary: desc.RedPrimary,
+                green_primary: desc.GreenPrimary,
+                blue_primary: desc.BluePrimary,
+                white_point: desc.WhitePoint,
+                min_luminance: desc.MinLuminance,
+                max_luminance: desc.MaxLuminance,
+                max_full_frame_luminance: desc.MaxFullFrameLuminance,
+            };
+
+            info!("DXGI output {} on {}: {} bpc, color space {:?}, luminance {}-{} nits",
+                output_info.device_name,
+                output_info.adapter_name,
+                output_info.bits_per_color,
+                output_info.color_space,
+                output_info.min_luminance,
+                output_info.max_luminance
+            );
+
+            outputs.push(output_info);
+        }
+
+        adapter_index += 1;
+    }
+
+    Ok(outputs)
+}
+
+// PCI vendor ID of the adapter behind a display config LUID (0x10DE NVIDIA, 0x1002 AMD, 0x8086 Intel)
+pub fn adapter_vendor_id(adapter_id: LUID) -> Option<u32> {
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1() }.ok()?;
+    let mut adapter_index = 0;
+
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(adapter_index) } {
+        adapter_index += 1;
+        let Ok(desc) = (unsafe { adapter.GetDesc1() }) else {
+            continue;
+        };
+
+        if desc.AdapterLuid.LowPart == adapter_id.LowPart && desc.AdapterLuid.HighPart == adapter_id.HighPart {
+            return Some(desc.VendorId);
+        }
+    }
+
+    error!("No DXGI adapter matches LUID {:?}", adapter_id);
+    None
+}
@@ -0,0 +1,501 @@
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use tracing::{info, error};
+
+use crate::displays_info::{DisplayDevice, DisplayMode, FixedOutput};
+use crate::registry;
+
+const EDID_BLOCK_SIZE: usize = 128;
+const EDID_HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+const CTA_EXTENSION_TAG: u8 = 0x02;
+const CTA_EXTENDED_TAG: u8 = 7;
+const CTA_EXT_COLORIMETRY: u8 = 5;
+const CTA_EXT_HDR_STATIC_METADATA: u8 = 6;
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+#[derive(Debug, Default)]
+pub struct HdrStaticMetadata {
+    pub sdr: bool,
+    pub hdr10: bool, // SMPTE ST 2084 (PQ) EOTF
+    pub hlg: bool,
+    pub bt2020: bool,
+    pub max_luminance: Option<f32>,
+    pub max_frame_avg_luminance: Option<f32>,
+    pub min_luminance: Option<f32>,
+}
+
+#[derive(Debug)]
+pub struct EdidInfo {
+    pub manufacturer: String,
+    pub product_code: u16,
+    pub serial_number: u32,
+    pub serial_string: Option<String>,
+    pub monitor_name: Option<String>,
+    pub manufacture_week: u8,
+    pub manufacture_year: u16,
+    pub native_mode: Option<DisplayMode>,
+    pub hdr: Option<HdrStaticMetadata>,
+}
+
+impl EdidInfo {
+    // Identifier built from the EDID that survives reboots and port changes, unlike \\.\DISPLAYn names
+    pub fn stable_id(&self) -> String {
+        let serial = match &self.serial_string {
+            Some(serial) => serial.clone(),
+            None => self.serial_number.to_string(),
+        };
+        format!("{}-{:04X}-{}", self.manufacturer, self.product_code, serial)
+    }
+}
+
+//==============================================================================
+// Read raw EDID from the registry
+//==============================================================================
+
+pub fn read_display_edid(device: &DisplayDevice) -> Option<Vec<u8>> {
+    let target_name = device.get_target_device_name()?;
+    let Some(instance_id) = target_name.device_instance_id() else {
+        error!("Unexpected monitor device path for {}: '{}'", device.device_name, target_name.device_path);
+        return None;
+    };
+    let key = format!("SYSTEM\\CurrentControlSet\\Enum\\{}\\Device Parameters", instance_id);
+
+    info!("Reading EDID for {} from HKLM\\{}", device.device_name, key);
+    registry::read_binary(HKEY_LOCAL_MACHINE, &key, "EDID").ok()
+}
+
+//==============================================================================
+// EDID / CTA-861 parsing
+//==============================================================================
+
+pub fn parse_edid(edid: &[u8]) -> Option<EdidInfo> {
+    if edid.len() < EDID_BLOCK_SIZE || edid[..8] != EDID_HEADER {
+        error!("EDID is too short or has an invalid header ({} bytes)", edid.len());
+        return None;
+    }
+    // A block that doesn't add up was cut short or corrupted on the way, nothing in it can be trusted
+    let extension_count = edid[126] as usize;
+    if edid.len() < (1 + extension_count) * EDID_BLOCK_SIZE {
+        error!("EDID reports {} extension blocks but only {} bytes are present", extension_count, edid.len());
+        return None;
+    }
+    if let Some(index) = edid.chunks(EDID_BLOCK_SIZE).take(1 + extension_count).position(|block| !checksum_holds(block)) {
+        error!("EDID block {} has a bad checksum", index);
+        return None;
+    }
+
+    // Manufacturer ID is three 5-bit letters packed big-endian
+    let packed = u16::from_be_bytes([edid[8], edid[9]]);
+    let manufacturer: String = [10u16, 5, 0]
+        .iter()
+        .map(|shift| (b'@' + ((packed >> shift) & 0x1F) as u8) as char)
+        .collect();
+
+    let product_code = u16::from_le_bytes([edid[10], edid[11]]);
+    let serial_number = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+
+    let mut info = EdidInfo {
+        manufacturer,
+        product_code,
+        serial_number,
+        serial_string: None,
+        monitor_name: None,
+        manufacture_week: edid[16],
+        manufacture_year: 1990 + edid[17] as u16,
+        native_mode: None,
+        hdr: None,
+    };
+
+    // Four 18-byte descriptors. The first detailed timing is the preferred (native) mode.
+    for offset in [54, 72, 90, 108] {
+        let descriptor = &edid[offset..offset + 18];
+        let pixel_clock = u16::from_le_bytes([descriptor[0], descriptor[1]]);
+
+        if pixel_clock != 0 {
+            if info.native_mode.is_none() {
+                info.native_mode = Some(parse_detailed_timing(descriptor));
+            }
+            continue;
+        }
+
+        match descriptor[3] {
+            0xFF => info.serial_string = Some(descriptor_text(descriptor)),
+            0xFC => info.monitor_name = Some(descriptor_text(descriptor)),
+            _ => {}
+        }
+    }
+
+    for block in edid.chunks(EDID_BLOCK_SIZE).skip(1).take(extension_count) {
+        if block[0] == CTA_EXTENSION_TAG {
+            parse_cta_block(block, &mut info);
+        }
+    }
+
+    Some(info)
+}
+
+fn parse_detailed_timing(descriptor: &[u8]) -> DisplayMode {
+    let pixel_clock_hz = u16::from_le_bytes([descriptor[0], descriptor[1]]) as u64 * 10_000;
+    let h_active = descriptor[2] as u32 | ((descriptor[4] as u32 & 0xF0) << 4);
+    let h_blank = descriptor[3] as u32 | ((descriptor[4] as u32 & 0x0F) << 8);
+    let v_active = descriptor[5] as u32 | ((descriptor[7] as u32 & 0xF0) << 4);
+    let v_blank = descriptor[6] as u32 | ((descriptor[7] as u32 & 0x0F) << 8);
+
+    let total_pixels = (h_active + h_blank) as u64 * (v_active + v_blank) as u64;
+    let refresh_rate = if total_pixels > 0 {
+        ((pixel_clock_hz as f64) / (total_pixels as f64)).round() as u32
+    } else {
+        0
+    };
+
+    DisplayMode {
+        width: h_active,
+        height: v_active,
+        refresh_rate,
+        interlaced: descriptor[17] & 0x80 != 0,
+        fixed_output: FixedOutput::Default,
+    }
+}
+
+fn descriptor_text(descriptor: &[u8]) -> String {
+    descriptor[5..18]
+        .iter()
+        .take_while(|&&c| c != 0x0A)
+        .map(|&c| c as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn parse_cta_block(block: &[u8], info: &mut EdidInfo) {
+    // Byte 2 is the offset of the first detailed timing, data blocks live in between
+    let dtd_offset = (block[2] as usize).min(EDID_BLOCK_SIZE - 1);
+    let mut offset = 4;
+
+    while offset < dtd_offset {
+        let tag = block[offset] >> 5;
+        let length = (block[offset] & 0x1F) as usize;
+        let Some(payload) = block.get(offset + 1..offset + 1 + length) else {
+            break;
+        };
+
+        if tag == CTA_EXTENDED_TAG && !payload.is_empty() {
+            match payload[0] {
+                CTA_EXT_HDR_STATIC_METADATA => parse_hdr_static_metadata(&payload[1..], info),
+                CTA_EXT_COLORIMETRY if payload.len() > 1 => {
+                    // BT2020cYCC, BT2020YCC and BT2020RGB occupy bits 5-7
+                    info.hdr.get_or_insert_with(Default::default).bt2020 = payload[1] & 0xE0 != 0;
+                }
+                _ => {}
+            }
+        }
+
+        offset += 1 + length;
+    }
+}
+
+fn parse_hdr_static_metadata(data: &[u8], info: &mut EdidInfo) {
+    let Some(&eotfs) = data.first() else {
+        return;
+    };
+
+    let hdr = info.hdr.get_or_insert_with(Default::default);
+    hdr.sdr = eotfs & 0x01 != 0;
+    hdr.hdr10 = eotfs & 0x04 != 0;
+    hdr.hlg = eotfs & 0x08 != 0;
+
+    // Luminance code values, see CTA-861-G section 7.5.13
+    let max_code = data.get(2).copied().filter(|&c| c != 0);
+    let avg_code = data.get(3).copied().filter(|&c| c != 0);
+    let min_code = data.get(4).copied();
+
+    hdr.max_luminance = max_code.map(|c| 50.0 * 2f32.powf(c as f32 / 32.0));
+    hdr.max_frame_avg_luminance = avg_code.map(|c| 50.0 * 2f32.powf(c as f32 / 32.0));
+    hdr.min_luminance = match (hdr.max_luminance, min_code) {
+        (Some(max), Some(c)) => Some(max * (c as f32 / 255.0).powi(2) / 100.0),
+        _ => None,
+    };
+}
+
+//==============================================================================
+// Detailed timings for an EDID override
+//==============================================================================
+
+// CVT reduced blanking (v1), which digital displays and dummy plugs accept
+const CVT_RB_H_BLANK: u32 = 160;
+const CVT_RB_H_FRONT_PORCH: u32 = 48;
+const CVT_RB_H_SYNC: u32 = 32;
+const CVT_RB_V_FRONT_PORCH: u32 = 3;
+const CVT_RB_MIN_V_BACK_PORCH: u32 = 6;
+const CVT_RB_MIN_V_BLANK_US: f64 = 460.0;
+// Digital separate sync, horizontal sync positive and vertical negative
+const CVT_RB_SYNC_FLAGS: u8 = 0x1A;
+
+// In CVT the vertical sync width tells the aspect ratio
+fn cvt_v_sync(width: u32, height: u32) -> u32 {
+    [((4, 3), 4), ((16, 9), 5), ((16, 10), 6), ((5, 4), 7), ((15, 9), 7)]
+        .iter()
+        .find(|((aspect_width, aspect_height), _)| width * aspect_height == height * aspect_width)
+        .map_or(10, |&(_, sync)| sync)
+}
+
+// An 18-byte detailed timing descriptor for the mode with CVT-RB blanking
+pub fn detailed_timing(width: u32, height: u32, refresh_rate: u32) -> Result<[u8; 18], String> {
+    if width == 0 || height == 0 || refresh_rate == 0 {
+        return Err(format!("{}x{} @{}Hz isn't a mode", width, height, refresh_rate));
+    }
+    // 12 bits each for the active and blanking sizes
+    if width > 0xFFF || height > 0xFFF {
+        return Err(format!("{}x{} is too large for an EDID detailed timing (4095x4095 at most)", width, height));
+    }
+
+    let v_sync = cvt_v_sync(width, height);
+    let line_us = (1_000_000.0 / refresh_rate as f64 - CVT_RB_MIN_V_BLANK_US) / height as f64;
+    if line_us <= 0.0 {
+        return Err(format!("{} Hz is too high a refresh rate for {} lines", refresh_rate, height));
+    }
+    let v_blank = ((CVT_RB_MIN_V_BLANK_US / line_us).floor() as u32 + 1).max(CVT_RB_V_FRONT_PORCH + v_sync + CVT_RB_MIN_V_BACK_PORCH);
+    if v_blank > 0xFFF {
+        return Err(format!("{} Hz is too low a refresh rate for {} lines", refresh_rate, height));
+    }
+
+    let h_total = (width + CVT_RB_H_BLANK) as u64;
+    let v_total = (height + v_blank) as u64;
+    // In 10 kHz units, 655.35 MHz at most
+    let pixel_clock = (refresh_rate as u64 * h_total * v_total + 5_000) / 10_000;
+    let Ok(pixel_clock) = u16::try_from(pixel_clock) else {
+        return Err(format!("{}x{} @{}Hz needs a {} MHz pixel clock, an EDID detailed timing holds 655 MHz at most", width, height, refresh_rate, pixel_clock / 100));
+    };
+
+    let (h_blank, h_front, h_sync, v_front) = (CVT_RB_H_BLANK, CVT_RB_H_FRONT_PORCH, CVT_RB_H_SYNC, CVT_RB_V_FRONT_PORCH);
+    let mut descriptor = [0u8; 18];
+    descriptor[..2].copy_from_slice(&pixel_clock.to_le_bytes());
+    descriptor[2] = width as u8;
+    descriptor[3] = h_blank as u8;
+    descriptor[4] = ((width >> 8) << 4 | h_blank >> 8) as u8;
+    descriptor[5] = height as u8;
+    descriptor[6] = v_blank as u8;
+    descriptor[7] = ((height >> 8) << 4 | v_blank >> 8) as u8;
+    descriptor[8] = h_front as u8;
+    descriptor[9] = h_sync as u8;
+    descriptor[10] = ((v_front & 0x0F) << 4 | (v_sync & 0x0F)) as u8;
+    descriptor[11] = ((h_front >> 8) << 6 | (h_sync >> 8) << 4 | (v_front >> 4) << 2 | v_sync >> 4) as u8;
+    descriptor[17] = CVT_RB_SYNC_FLAGS;
+    Ok(descriptor)
+}
+
+// The bytes of each block add up to 0
+fn checksum_holds(block: &[u8]) -> bool {
+    block.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn set_checksum(block: &mut [u8]) {
+    let sum = block[..EDID_BLOCK_SIZE - 1].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    block[EDID_BLOCK_SIZE - 1] = 0u8.wrapping_sub(sum);
+}
+
+// The byte range of the first CTA-861 extension block
+fn cta_block_range(edid: &[u8]) -> Option<std::ops::Range<usize>> {
+    (1..=*edid.get(126)? as usize)
+        .map(|index| index * EDID_BLOCK_SIZE..(index + 1) * EDID_BLOCK_SIZE)
+        .take_while(|range| range.end <= edid.len())
+        .find(|range| edid[range.start] == CTA_EXTENSION_TAG)
+}
+
+// Offsets of the detailed timings in a CTA-861 block, which run from byte 2's offset to the
+// first empty one
+fn cta_timing_offsets(block: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    let start = (block[2] as usize).max(4);
+    (start..EDID_BLOCK_SIZE - 18).step_by(18).take_while(|&offset| block[offset] != 0 || block[offset + 1] != 0)
+}
+
+// Adds the descriptor to the EDID's CTA-861 extension, adding one if there is none. None when
+// the EDID already has it.
+pub fn add_detailed_timing(edid: &[u8], descriptor: &[u8; 18]) -> Result<Option<Vec<u8>>, String> {
+    if edid.len() < EDID_BLOCK_SIZE || edid[..8] != EDID_HEADER || edid.len() < EDID_BLOCK_SIZE * (1 + edid[126] as usize) {
+        return Err("The display's EDID isn't valid".to_string());
+    }
+    let mut edid = edid[..EDID_BLOCK_SIZE * (1 + edid[126] as usize)].to_vec();
+
+    let range = match cta_block_range(&edid) {
+        Some(range) => range,
+        None => {
+            // Version 3, no data blocks, detailed timings from byte 4
+            let mut block = vec![0u8; EDID_BLOCK_SIZE];
+            block[..3].copy_from_slice(&[CTA_EXTENSION_TAG, 3, 4]);
+            edid.extend_from_slice(&block);
+            edid[126] += 1;
+            set_checksum(&mut edid[..EDID_BLOCK_SIZE]);
+            edid.len() - EDID_BLOCK_SIZE..edid.len()
+        }
+    };
+
+    let block = &mut edid[range];
+    if block[2] == 0 {
+        block[2] = 4;
+    }
+    if cta_timing_offsets(block).any(|offset| block[offset..offset + 18] == descriptor[..]) {
+        return Ok(None);
+    }
+    let free = cta_timing_offsets(block).last().map_or((block[2] as usize).max(4), |offset| offset + 18);
+    if free + 18 > EDID_BLOCK_SIZE - 1 {
+        return Err("The EDID's CTA-861 block has no room left for another detailed timing".to_string());
+    }
+    block[free..free + 18].copy_from_slice(descriptor);
+    set_checksum(block);
+    Ok(Some(edid))
+}
+
+// Takes the mode's detailed timing out of the EDID's CTA-861 extension. None when it isn't there.
+pub fn remove_detailed_timing(edid: &[u8], width: u32, height: u32, refresh_rate: u32) -> Option<Vec<u8>> {
+    let range = cta_block_range(edid)?;
+    let mut edid = edid.to_vec();
+    let block = &mut edid[range];
+    let offset = cta_timing_offsets(block).find(|&offset| {
+        let mode = parse_detailed_timing(&block[offset..offset + 18]);
+        (mode.width, mode.height, mode.refresh_rate, mode.interlaced) == (width, height, refresh_rate, false)
+    })?;
+
+    // The ones after it move up, the checksum byte stays put
+    block.copy_within(offset + 18..EDID_BLOCK_SIZE - 1, offset);
+    block[EDID_BLOCK_SIZE - 1 - 18..EDID_BLOCK_SIZE - 1].fill(0);
+    set_checksum(block);
+    Some(edid)
+}
+
+//==============================================================================
+// Helper functions for CLI commands
+//==============================================================================
+
+pub fn get_display_edid_info(display: &DisplayDevice) -> Option<EdidInfo> {
+    let edid = read_display_edid(display)?;
+    parse_edid(&edid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Base block only: header, a 1920x1080 @60Hz preferred timing and a name descriptor
+    fn base_edid() -> Vec<u8> {
+        let mut edid = vec![0u8; EDID_BLOCK_SIZE];
+        edid[..8].copy_from_slice(&EDID_HEADER);
+        edid[54..72].copy_from_slice(&detailed_timing(1920, 1080, 60).unwrap());
+        edid[72..77].copy_from_slice(&[0, 0, 0, 0xFC, 0]);
+        edid[77..82].copy_from_slice(b"Plug\n");
+        set_checksum(&mut edid);
+        edid
+    }
+
+    fn checksums_hold(edid: &[u8]) -> bool {
+        edid.chunks(EDID_BLOCK_SIZE).all(checksum_holds)
+    }
+
+    // An LG TV: GSM, product 5B08, serial 123456, made in week 12 of 2023
+    fn tv_edid() -> Vec<u8> {
+        let mut edid = base_edid();
+        edid[8..18].copy_from_slice(&[0x1E, 0x6D, 0x08, 0x5B, 0x40, 0xE2, 0x01, 0x00, 12, 33]);
+        set_checksum(&mut edid);
+        edid
+    }
+
+    // A CTA-861 block with HDR static metadata (SDR, PQ and HLG, codes 96/80/64) and BT.2020 colorimetry
+    fn with_hdr_block(edid: &[u8]) -> Vec<u8> {
+        let mut edid = edid.to_vec();
+        let mut block = vec![0u8; EDID_BLOCK_SIZE];
+        block[..4].copy_from_slice(&[CTA_EXTENSION_TAG, 3, 15, 0]);
+        block[4..11].copy_from_slice(&[0xE6, CTA_EXT_HDR_STATIC_METADATA, 0x0D, 0x01, 96, 80, 64]);
+        block[11..15].copy_from_slice(&[0xE3, CTA_EXT_COLORIMETRY, 0xE0, 0x00]);
+        set_checksum(&mut block);
+        edid[126] = 1;
+        set_checksum(&mut edid);
+        edid.extend(block);
+        edid
+    }
+
+    #[test]
+    fn ids_come_from_the_base_block() {
+        let info = parse_edid(&tv_edid()).unwrap();
+        assert_eq!((info.manufacturer.as_str(), info.product_code, info.serial_number), ("GSM", 0x5B08, 123456));
+        assert_eq!((info.manufacture_week, info.manufacture_year), (12, 2023));
+        assert_eq!(info.stable_id(), "GSM-5B08-123456");
+        let native = info.native_mode.unwrap();
+        assert_eq!((native.width, native.height, native.refresh_rate), (1920, 1080, 60));
+        assert!(info.hdr.is_none());
+
+        // A serial number descriptor takes over from the numeric one
+        let mut edid = tv_edid();
+        edid[72..90].copy_from_slice(b"\0\0\0\xFF\0SN-8K2Q\n     ");
+        edid[90..108].copy_from_slice(b"\0\0\0\xFC\0LG TV SSCR2\n ");
+        set_checksum(&mut edid);
+        let info = parse_edid(&edid).unwrap();
+        assert_eq!(info.serial_string.as_deref(), Some("SN-8K2Q"));
+        assert_eq!(info.monitor_name.as_deref(), Some("LG TV SSCR2"));
+        assert_eq!(info.stable_id(), "GSM-5B08-SN-8K2Q");
+    }
+
+    #[test]
+    fn hdr_comes_from_the_cta_block() {
+        let hdr = parse_edid(&with_hdr_block(&tv_edid())).unwrap().hdr.unwrap();
+        assert!(hdr.sdr && hdr.hdr10 && hdr.hlg && hdr.bt2020);
+        // 50 * 2^(code / 32), the minimum relative to the maximum
+        assert!((hdr.max_luminance.unwrap() - 400.0).abs() < 0.01);
+        assert!((hdr.max_frame_avg_luminance.unwrap() - 282.84).abs() < 0.01);
+        assert!((hdr.min_luminance.unwrap() - 0.2520).abs() < 0.0001);
+    }
+
+    #[test]
+    fn damaged_edids_are_rejected() {
+        let edid = with_hdr_block(&tv_edid());
+        for length in [0, 8, 100, EDID_BLOCK_SIZE, EDID_BLOCK_SIZE + 40] {
+            assert!(parse_edid(&edid[..length]).is_none(), "{} bytes", length);
+        }
+        for index in [20, EDID_BLOCK_SIZE + 6] {
+            let mut corrupted = edid.clone();
+            corrupted[index] ^= 0x10;
+            assert!(parse_edid(&corrupted).is_none(), "byte {} changed", index);
+        }
+        let mut header = edid.clone();
+        header[0] = 0x01;
+        assert!(parse_edid(&header).is_none());
+
+        // A data block running past the timings still stops at the end of the block
+        let mut overrun = edid.clone();
+        overrun[EDID_BLOCK_SIZE + 2] = 0x7F;
+        overrun[EDID_BLOCK_SIZE + 120] = 0xFF;
+        set_checksum(&mut overrun[EDID_BLOCK_SIZE..]);
+        assert!(parse_edid(&overrun).unwrap().hdr.is_some());
+    }
+
+    #[test]
+    fn detailed_timings_read_back() {
+        for (width, height, refresh_rate) in [(1280, 800, 90), (2560, 1600, 120), (3840, 2160, 60), (1920, 1080, 144)] {
+            let mode = parse_detailed_timing(&detailed_timing(width, height, refresh_rate).unwrap());
+            assert_eq!((mode.width, mode.height, mode.refresh_rate, mode.interlaced), (width, height, refresh_rate, false));
+        }
+        // 5120 pixels don't fit 12 bits, and 4K at 240 Hz is past 655 MHz
+        assert!(detailed_timing(5120, 1440, 60).is_err());
+        assert!(detailed_timing(3840, 2160, 240).is_err());
+    }
+
+    #[test]
+    fn override_modes_go_in_a_cta_block() {
+        let edid = base_edid();
+        let steam_deck = detailed_timing(1280, 800, 90).unwrap();
+        let with_deck = add_detailed_timing(&edid, &steam_deck).unwrap().unwrap();
+        assert_eq!(with_deck.len(), 2 * EDID_BLOCK_SIZE);
+        assert_eq!(with_deck[126], 1);
+        assert!(checksums_hold(&with_deck));
+        assert_eq!(parse_edid(&with_deck).unwrap().monitor_name.as_deref(), Some("Plug"));
+        assert!(add_detailed_timing(&with_deck, &steam_deck).unwrap().is_none());
+
+        let both = add_detailed_timing(&with_deck, &detailed_timing(2400, 1080, 120).unwrap()).unwrap().unwrap();
+        assert_eq!(both.len(), 2 * EDID_BLOCK_SIZE);
+        let without_deck = remove_detailed_timing(&both, 1280, 800, 90).unwrap();
+        assert!(checksums_hold(&without_deck));
+        assert_eq!(parse_detailed_timing(&without_deck[EDID_BLOCK_SIZE + 4..EDID_BLOCK_SIZE + 22]).width, 2400);
+        assert!(remove_detailed_timing(&without_deck, 1280, 800, 90).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+use std::mem::size_of;
+use windows::Win32::Foundation::{CloseHandle, ERROR_CANCELLED, E_FAIL, HANDLE};
+use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+use windows::Win32::System::Threading::{GetCurrentProcess, GetExitCodeProcess, OpenProcessToken, WaitForSingleObject, INFINITE};
+use windows::Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW};
+use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+use windows::core::{Error, PCWSTR, Result};
+use tracing::{info, error};
+
+use crate::exit_code;
+
+// A few things only work from an elevated prompt: writing under HKLM (event log source, the SDR
+// white level registry fallback) and enabling or disabling a device (virtual display drivers).
+// Those check first and fail with NeedsElevation. With --elevate the helper instead starts itself
+// again through UAC, waits for it and exits with its exit code.
+
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+//==============================================================================
+// Detection
+//==============================================================================
+
+pub fn is_elevated() -> bool {
+    let mut token = HANDLE::default();
+    if let Err(e) = unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } {
+        error!("Failed to open the process token: {}", e.message());
+        return false;
+    }
+
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut size = 0u32;
+    let result = unsafe {
+        GetTokenInformation(token, TokenElevation, Some(&mut elevation as *mut _ as *mut _), size_of::<TOKEN_ELEVATION>() as u32, &mut size)
+    };
+    let _ = unsafe { CloseHandle(token) };
+
+    match result {
+        Ok(()) => elevation.TokenIsElevated != 0,
+        Err(e) => {
+            error!("Failed to query the token's elevation: {}", e.message());
+            false
+        }
+    }
+}
+
+// Fails with NeedsElevation unless running elevated. `what` completes "... needs an elevated prompt".
+pub fn require_elevation(what: &str) -> Result<()> {
+    if is_elevated() {
+        return Ok(());
+    }
+    Err(exit_code::needs_elevation(format!("{} needs an elevated prompt, run it as administrator or add --elevate", what)))
+}
+
+//==============================================================================
+// Relaunch through UAC
+//==============================================================================
+
+// Quoted the way the C runtime splits a command line, backslashes only matter before a quote
+pub fn quote_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '"']) {
+        return argument.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+// The current command line without --elevate, so the elevated copy doesn't relaunch itself
+fn relaunch_parameters(arguments: impl Iterator<Item = String>) -> String {
+    arguments
+        .filter(|argument| argument != "--elevate")
+        .map(|argument| quote_argument(&argument))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Runs the same command elevated and returns its exit code. It gets its own console window, so
+// its output isn't seen here; --log keeps a record of it.
+pub fn relaunch_elevated() -> Result<u32> {
+    let exe = std::env::current_exe().map_err(|e| Error::new(E_FAIL, e.to_string()))?;
+    let parameters = relaunch_parameters(std::env::args().skip(1));
+    info!("Relaunching elevated: {} {}", exe.display(), parameters);
+
+    let verb = to_wide("runas");
+    let file = to_wide(&exe.to_string_lossy());
+    let parameters = to_wide(&parameters);
+    let mut execute_info = SHELLEXECUTEINFOW {
+        cbSize: size_of::<SHELLEXECUTEINFOW>() as u32,
+        fMask: SEE_MASK_NOCLOSEPROCESS | SEE_MASK_NOASYNC,
+        lpVerb: PCWSTR::from_raw(verb.as_ptr()),
+        lpFile: PCWSTR::from_raw(file.as_ptr()),
+        lpParameters: PCWSTR::from_raw(parameters.as_ptr()),
+        nShow: SW_SHOWNORMAL.0,
+        ..Default::default()
+    };
+
+    if let Err(e) = unsafe { ShellExecuteExW(&mut execute_info) } {
+        if e.code() == ERROR_CANCELLED.to_hresult() {
+            return Err(exit_code::needs_elevation("The UAC prompt was declined"));
+        }
+        error!("Failed to relaunch elevated: {}", e.message());
+        return Err(e);
+    }
+
+    let process = execute_info.hProcess;
+    let mut code = 0u32;
+    let result = unsafe {
+        WaitForSingleObject(process, INFINITE);
+        GetExitCodeProcess(process, &mut code)
+    };
+    let _ = unsafe { CloseHandle(process) };
+    result.map(|()| code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_quoted_for_the_c_runtime() {
+        assert_eq!(quote_argument("cpdm"), "cpdm");
+        assert_eq!(quote_argument("HDR Steam Deck.icc"), "\"HDR Steam Deck.icc\"");
+        assert_eq!(quote_argument(""), "\"\"");
+        assert_eq!(quote_argument("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_argument("C:\\My Profiles\\"), "\"C:\\My Profiles\\\\\"");
+    }
+
+    #[test]
+    fn relaunch_drops_elevate() {
+        let arguments = ["--elevate", "-v", "event-log", "register"].map(String::from);
+        assert_eq!(relaunch_parameters(arguments.into_iter()), "-v event-log register");
+    }
+}
@@ -0,0 +1,223 @@
+use windows::Win32::Foundation::{ERROR_HANDLE_EOF, ERROR_INSUFFICIENT_BUFFER, HANDLE};
+use windows::Win32::Security::PSID;
+use windows::Win32::System::EventLog::{
+    CloseEventLog,
+    DeregisterEventSource,
+    GetNumberOfEventLogRecords,
+    GetOldestEventLogRecord,
+    OpenEventLogW,
+    ReadEventLogW,
+    RegisterEventSourceW,
+    ReportEventW,
+    EVENTLOGRECORD,
+    EVENTLOG_ERROR_TYPE,
+    EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_SEEK_READ,
+    EVENTLOG_WARNING_TYPE,
+    READ_EVENT_LOG_READ_FLAGS,
+};
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use std::mem::size_of;
+use windows::core::{PCWSTR, Result};
+use tracing::Level;
+
+use crate::elevation;
+use crate::registry;
+
+// Prep commands run unattended, so with --event-log warnings and errors also go to the
+// Application event log where they show up in Event Viewer. The source borrows the message
+// table of EventCreate.exe, whose IDs 1-1000 are all just "%1", so events read as the message
+// itself. Without `event-log register` the events are still written, but Event Viewer
+// complains that the description can't be found before showing the text.
+
+pub const EVENT_SOURCE: &str = "sunshine-helper";
+const SOURCE_KEY: &str = "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\sunshine-helper";
+const MESSAGE_FILE: &str = "%SystemRoot%\\System32\\EventCreate.exe";
+
+const ERROR_EVENT_ID: u32 = 1;
+const WARNING_EVENT_ID: u32 = 2;
+const INFORMATION_EVENT_ID: u32 = 3;
+
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+// Needs an elevated prompt, the source lives under HKLM
+pub fn register_event_source() -> Result<()> {
+    elevation::require_elevation("Registering the event source")?;
+    registry::write_expand_string(HKEY_LOCAL_MACHINE, SOURCE_KEY, "EventMessageFile", MESSAGE_FILE)?;
+    let types_supported = (EVENTLOG_ERROR_TYPE.0 | EVENTLOG_WARNING_TYPE.0 | EVENTLOG_INFORMATION_TYPE.0) as u32;
+    registry::write_dword(HKEY_LOCAL_MACHINE, SOURCE_KEY, "TypesSupported", types_supported)
+}
+
+pub fn unregister_event_source() -> Result<()> {
+    elevation::require_elevation("Unregistering the event source")?;
+    registry::delete_key(HKEY_LOCAL_MACHINE, SOURCE_KEY)
+}
+
+pub struct EventLog(HANDLE);
+
+// Event log handles can be used from any thread
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    pub fn open() -> Result<Self> {
+        let source = to_wide(EVENT_SOURCE);
+        let handle = unsafe { RegisterEventSourceW(PCWSTR::null(), PCWSTR::from_raw(source.as_ptr()))? };
+        Ok(EventLog(handle))
+    }
+
+    pub fn report(&self, level: Level, message: &str) {
+        let (event_type, event_id) = match level {
+            Level::ERROR => (EVENTLOG_ERROR_TYPE, ERROR_EVENT_ID),
+            Level::WARN => (EVENTLOG_WARNING_TYPE, WARNING_EVENT_ID),
+            _ => (EVENTLOG_INFORMATION_TYPE, INFORMATION_EVENT_ID),
+        };
+
+        let message = to_wide(message);
+        let strings = [PCWSTR::from_raw(message.as_ptr())];
+        // Nowhere to report a failure to, the logger is the one calling
+        let _ = unsafe { ReportEventW(self.0, event_type, 0, event_id, PSID::default(), 0, Some(&strings), None) };
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        let _ = unsafe { DeregisterEventSource(self.0) };
+    }
+}
+
+//==============================================================================
+// Driver resets
+//==============================================================================
+
+// When a GPU driver stops responding, Windows resets it (Timeout Detection and Recovery) and logs
+// event 4101 from the source "Display" in the System log: "Display driver nvlddmkm stopped
+// responding and has successfully recovered." The displays come back with HDR and the SDR level
+// as the driver defaults them.
+
+const DISPLAY_SOURCE: &str = "Display";
+const DRIVER_RESET_EVENT_ID: u32 = 4101;
+// EVENTLOG_SEEK_READ | EVENTLOG_FORWARDS_READ, the windows crate has no constant for the latter
+const SEEK_FORWARDS: READ_EVENT_LOG_READ_FLAGS = READ_EVENT_LOG_READ_FLAGS(EVENTLOG_SEEK_READ.0 | 4);
+
+// Reads the System log from where the last call left off, starting at the newest record
+pub struct DriverResetWatch {
+    handle: HANDLE,
+    next_record: u32,
+}
+
+// Event log handles can be used from any thread
+unsafe impl Send for DriverResetWatch {}
+
+impl DriverResetWatch {
+    pub fn open() -> Result<Self> {
+        let handle = unsafe { OpenEventLogW(PCWSTR::null(), windows::core::w!("System"))? };
+        let mut watch = DriverResetWatch { handle, next_record: 0 };
+        watch.next_record = watch.newest_record()? + 1;
+        Ok(watch)
+    }
+
+    fn newest_record(&self) -> Result<u32> {
+        let (mut oldest, mut count) = (0u32, 0u32);
+        unsafe {
+            GetOldestEventLogRecord(self.handle, &mut oldest)?;
+            GetNumberOfEventLogRecords(self.handle, &mut count)?;
+        }
+        Ok((oldest + count).saturating_sub(1))
+    }
+
+    // How many driver resets were logged since the last call
+    pub fn new_resets(&mut self) -> Result<usize> {
+        let newest = self.newest_record()?;
+        // The log was cleared, or wrapped around
+        if newest + 1 < self.next_record {
+            self.next_record = newest + 1;
+        }
+        let mut resets = 0;
+        let mut buffer = vec![0u8; 64 * 1024];
+        while self.next_record <= newest {
+            let (mut read, mut needed) = (0u32, 0u32);
+            let result = unsafe {
+                ReadEventLogW(self.handle, SEEK_FORWARDS, self.next_record,
+                    buffer.as_mut_ptr().cast(), buffer.len() as u32, &mut read, &mut needed)
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if e.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult() => {
+                    buffer.resize(needed as usize, 0);
+                    continue;
+                }
+                Err(e) if e.code() == ERROR_HANDLE_EOF.to_hresult() => break,
+                Err(e) => return Err(e),
+            }
+            let records = records(&buffer[..read as usize]);
+            let Some(&(last, _, _)) = records.last() else {
+                break;
+            };
+            resets += records.iter().filter(|(_, event_id, source)| is_driver_reset(*event_id, source)).count();
+            self.next_record = last + 1;
+        }
+        Ok(resets)
+    }
+}
+
+impl Drop for DriverResetWatch {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseEventLog(self.handle) };
+    }
+}
+
+// The event ID's low word is the one Event Viewer shows, the rest are severity and facility bits
+fn is_driver_reset(event_id: u32, source: &str) -> bool {
+    event_id & 0xFFFF == DRIVER_RESET_EVENT_ID && source.eq_ignore_ascii_case(DISPLAY_SOURCE)
+}
+
+// Record number, event ID and source of each EVENTLOGRECORD in what ReadEventLogW returned. The
+// source name is the first string after the fixed part of the record.
+fn records(buffer: &[u8]) -> Vec<(u32, u32, String)> {
+    let field = |record: &[u8], offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+    let header = size_of::<EVENTLOGRECORD>();
+    let mut records = Vec::new();
+    let mut rest = buffer;
+    while rest.len() >= header {
+        let length = field(rest, 0) as usize;
+        if length < header || length > rest.len() {
+            break;
+        }
+        let record = &rest[..length];
+        let source: Vec<u16> = record[header..].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|&c| c != 0).collect();
+        records.push((field(record, 8), field(record, 20), String::from_utf16_lossy(&source)));
+        rest = &rest[length..];
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(number: u32, event_id: u32, source: &str) -> Vec<u8> {
+        let mut bytes = vec![0u8; size_of::<EVENTLOGRECORD>()];
+        bytes[8..12].copy_from_slice(&number.to_le_bytes());
+        bytes[20..24].copy_from_slice(&event_id.to_le_bytes());
+        bytes.extend(source.encode_utf16().chain([0]).chain("HOST".encode_utf16()).chain([0]).flat_map(u16::to_le_bytes));
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        let length = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn driver_resets_are_found_among_other_events() {
+        let buffer = [record(41, 7036, "Service Control Manager"), record(42, 0x4000_1005, "Display"), record(43, 4101, "nvlddmkm")].concat();
+        let records = records(&buffer);
+        assert_eq!(records.iter().map(|(number, _, _)| *number).collect::<Vec<_>>(), [41, 42, 43]);
+        assert_eq!(records.iter().filter(|(_, event_id, source)| is_driver_reset(*event_id, source)).count(), 1);
+        assert_eq!(records[1].2, "Display");
+
+        // A cut off record is left for the next read
+        assert_eq!(super::records(&buffer[..buffer.len() - 2]).len(), 2);
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::time::Duration;
+use serde_json::{json, Value};
+
+use crate::displays_info::{self, DisplayDevice};
+use crate::hdr;
+use crate::schema;
+
+// `events` prints a JSON line for every display change it sees until it's cancelled, so a script
+// or another tool can react to displays coming and going without polling the helper itself. The
+// displays are polled rather than hooked: WM_DISPLAYCHANGE misses HDR toggles and says nothing
+// about which display changed. Every line is a versioned document of its own, and the displays
+// already there when it starts each get an attached event first.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayState {
+    pub device_name: String,
+    pub display_id: Option<String>,
+    pub primary: bool,
+    pub mode: (u32, u32, u32),
+    // None for displays without HDR
+    pub hdr: Option<bool>,
+}
+
+impl DisplayState {
+    fn from_display(display: &DisplayDevice) -> DisplayState {
+        DisplayState {
+            device_name: display.device_name.clone(),
+            display_id: display.stable_id(),
+            primary: display.is_primary,
+            mode: (display.current_resolution.0, display.current_resolution.1, display.current_refresh_rate),
+            hdr: hdr::get_advanced_color_info(display).ok().filter(|info| info.supported).map(|info| info.enabled),
+        }
+    }
+
+    // \\.\DISPLAYn names get handed to whichever display attaches next, the display ID doesn't
+    fn key(&self) -> &str {
+        self.display_id.as_deref().unwrap_or(&self.device_name)
+    }
+}
+
+pub fn snapshot() -> Vec<DisplayState> {
+    displays_info::invalidate_topology();
+    displays_info::enumerate_displays().iter().map(DisplayState::from_display).collect()
+}
+
+// Blocks until the process is stopped, or until whoever reads the output goes away
+pub fn run(interval: Duration) {
+    let mut before = Vec::new();
+    loop {
+        let after = snapshot();
+        let mut stdout = std::io::stdout().lock();
+        for event in changes(&before, &after) {
+            if writeln!(stdout, "{}", event).and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+        }
+        drop(stdout);
+        before = after;
+        std::thread::sleep(interval);
+    }
+}
+
+//==============================================================================
+// Changes
+//==============================================================================
+
+pub fn changes(before: &[DisplayState], after: &[DisplayState]) -> Vec<Value> {
+    let time = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+    let event = |kind: &str, display: &DisplayState, fields: Value| {
+        let mut event = json!({
+            "event": kind,
+            "time": time,
+            "display": display.device_name,
+            "display_id": display.display_id,
+        });
+        if let (Value::Object(event), Value::Object(fields)) = (&mut event, fields) {
+            event.extend(fields);
+        }
+        schema::versioned(event)
+    };
+
+    let mut events = Vec::new();
+    for old in before {
+        if !after.iter().any(|new| new.key() == old.key()) {
+            events.push(event("removed", old, json!({})));
+        }
+    }
+    for new in after {
+        let Some(old) = before.iter().find(|old| old.key() == new.key()) else {
+            events.push(event("attached", new, json!({ "mode": mode_value(new.mode), "hdr": new.hdr, "primary": new.primary })));
+            continue;
+        };
+        if old.mode != new.mode {
+            events.push(event("mode_changed", new, json!({ "old": mode_value(old.mode), "new": mode_value(new.mode) })));
+        }
+        if old.hdr != new.hdr {
+            events.push(event("hdr_changed", new, json!({ "old": old.hdr, "new": new.hdr })));
+        }
+    }
+
+    // After the attached events, so the new primary is already known to whoever reads them
+    let old_primary = before.iter().find(|display| display.primary);
+    if let Some(new_primary) = after.iter().find(|display| display.primary) {
+        if !before.is_empty() && old_primary.map(DisplayState::key) != Some(new_primary.key()) {
+            events.push(event("primary_changed", new_primary, json!({ "old": old_primary.map(|display| &display.device_name) })));
+        }
+    }
+    events
+}
+
+fn mode_value((width, height, refresh_rate): (u32, u32, u32)) -> Value {
+    json!({ "width": width, "height": height, "refresh_rate": refresh_rate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn kinds(events: &[Value]) -> Vec<(&str, &str)> {
+        events.iter().map(|event| (event["event"].as_str().unwrap(), event["display"].as_str().unwrap())).collect()
+    }
+
+    #[test]
+    fn each_display_change_is_one_event() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.hdr_enabled = false;
+        MockDisplayApi::install(vec![tv, MockDisplay::desk_monitor()]);
+
+        let before = snapshot();
+        assert_eq!(before.len(), 2);
+        assert_eq!((before[0].hdr, before[1].hdr), (Some(false), None));
+        assert_eq!(kinds(&changes(&[], &before)), [("attached", "\\\\.\\DISPLAY1"), ("attached", "\\\\.\\DISPLAY2")]);
+        assert!(changes(&before, &before).is_empty());
+
+        // The TV goes to 60 Hz with HDR on, and the monitor takes over as primary
+        let mut after = before.clone();
+        after[0].mode.2 = 60;
+        after[0].hdr = Some(true);
+        after[0].primary = false;
+        after[1].primary = true;
+        let events = changes(&before, &after);
+        assert_eq!(kinds(&events), [("mode_changed", "\\\\.\\DISPLAY1"), ("hdr_changed", "\\\\.\\DISPLAY1"), ("primary_changed", "\\\\.\\DISPLAY2")]);
+        assert_eq!(events[0]["old"]["refresh_rate"], 120);
+        assert_eq!(events[0]["new"]["refresh_rate"], 60);
+        assert_eq!(events[2]["old"], "\\\\.\\DISPLAY1");
+        assert_eq!(events[0]["schema_version"], schema::SCHEMA_VERSION);
+
+        // Unplugging the TV leaves its name to the next display, which is still a new one
+        let mut replaced = vec![after[1].clone(), before[0].clone()];
+        replaced[1].display_id = Some("SAM-7363-H4ZN".to_string());
+        assert_eq!(kinds(&changes(&after, &replaced)), [("removed", "\\\\.\\DISPLAY1"), ("attached", "\\\\.\\DISPLAY1")]);
+    }
+}
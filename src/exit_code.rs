@@ -0,0 +1,138 @@
+use windows::Win32::Foundation::{
+    ERROR_CANCELLED,
+    ERROR_GRAPHICS_MODE_NOT_IN_MODESET,
+    ERROR_NOT_FOUND,
+    ERROR_NOT_SUPPORTED,
+    ERROR_PRIVILEGE_NOT_HELD,
+    E_ACCESSDENIED,
+    E_FAIL,
+    E_INVALIDARG,
+    E_NOTIMPL,
+};
+use windows::core::Error;
+
+use crate::timings;
+
+// Every command exits with one of these, so a prep script can tell a mode the display
+// doesn't have apart from a transient failure worth retrying. The numbers are stable,
+// new codes only get added at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,
+    DisplayNotFound = 2,
+    ModeUnsupported = 3,
+    Win32Error = 4,
+    NeedsElevation = 5,
+    Unsupported = 6,
+    InvalidInput = 7,
+    Interrupted = 8,
+    Unchanged = 9,
+    Mismatch = 10,
+}
+
+impl ExitCode {
+    pub const ALL: [ExitCode; 11] = [
+        ExitCode::Success,
+        ExitCode::Failure,
+        ExitCode::DisplayNotFound,
+        ExitCode::ModeUnsupported,
+        ExitCode::Win32Error,
+        ExitCode::NeedsElevation,
+        ExitCode::Unsupported,
+        ExitCode::InvalidInput,
+        ExitCode::Interrupted,
+        ExitCode::Unchanged,
+        ExitCode::Mismatch,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExitCode::Success => "ok",
+            ExitCode::Failure => "failure",
+            ExitCode::DisplayNotFound => "display-not-found",
+            ExitCode::ModeUnsupported => "mode-unsupported",
+            ExitCode::Win32Error => "win32-error",
+            ExitCode::NeedsElevation => "needs-elevation",
+            ExitCode::Unsupported => "unsupported",
+            ExitCode::InvalidInput => "invalid-input",
+            ExitCode::Interrupted => "interrupted",
+            ExitCode::Unchanged => "unchanged",
+            ExitCode::Mismatch => "mismatch",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ExitCode::Success => "The command succeeded, or the requested state was already in effect",
+            ExitCode::Failure => "The command failed for a reason not covered below",
+            ExitCode::DisplayNotFound => "No active display matches the selector",
+            ExitCode::ModeUnsupported => "The display doesn't offer the requested mode",
+            ExitCode::Win32Error => "A Windows or driver call failed, possibly transiently",
+            ExitCode::NeedsElevation => "Access was denied, run the command as administrator",
+            ExitCode::Unsupported => "The display, GPU or this build doesn't support the feature",
+            ExitCode::InvalidInput => "Bad arguments, or an unreadable spec, layout or script file",
+            ExitCode::Interrupted => "Stopped by Ctrl+C, the changes made so far were undone",
+            ExitCode::Unchanged => "Nothing needed doing, from commands that tell this apart from ok (ensure-hdr)",
+            ExitCode::Mismatch => "The displays aren't in the state verify expected",
+        }
+    }
+
+    // Errors raised by this tool carry one of the HRESULTs made below, anything else came from Windows
+    pub fn from_error(error: &Error) -> Self {
+        let code = error.code();
+        if code == E_ACCESSDENIED || code == ERROR_PRIVILEGE_NOT_HELD.to_hresult() {
+            ExitCode::NeedsElevation
+        } else if code == ERROR_NOT_FOUND.to_hresult() {
+            ExitCode::DisplayNotFound
+        } else if code == ERROR_GRAPHICS_MODE_NOT_IN_MODESET {
+            ExitCode::ModeUnsupported
+        } else if code == ERROR_NOT_SUPPORTED.to_hresult() || code == E_NOTIMPL {
+            ExitCode::Unsupported
+        } else if code == E_INVALIDARG {
+            ExitCode::InvalidInput
+        } else if code == ERROR_CANCELLED.to_hresult() {
+            ExitCode::Interrupted
+        } else if code.is_err() && code != E_FAIL {
+            ExitCode::Win32Error
+        } else {
+            ExitCode::Failure
+        }
+    }
+
+    // Failed commands exit here, their --timings are reported all the same
+    pub fn exit(self) -> ! {
+        timings::report();
+        std::process::exit(self as i32)
+    }
+}
+
+pub fn print_exit_codes() {
+    for code in ExitCode::ALL {
+        println!("{:>3}  {:<18} {}", code as i32, code.name(), code.description());
+    }
+}
+
+//==============================================================================
+// Errors with a distinct exit code
+//==============================================================================
+
+pub fn display_not_found(message: impl AsRef<str>) -> Error {
+    Error::new(ERROR_NOT_FOUND.to_hresult(), message)
+}
+
+pub fn mode_unsupported(message: impl AsRef<str>) -> Error {
+    Error::new(ERROR_GRAPHICS_MODE_NOT_IN_MODESET, message)
+}
+
+pub fn not_supported(message: impl AsRef<str>) -> Error {
+    Error::new(ERROR_NOT_SUPPORTED.to_hresult(), message)
+}
+
+pub fn needs_elevation(message: impl AsRef<str>) -> Error {
+    Error::new(E_ACCESSDENIED, message)
+}
+
+pub fn invalid_input(message: impl AsRef<str>) -> Error {
+    Error::new(E_INVALIDARG, message)
+}
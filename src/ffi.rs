@@ -0,0 +1,303 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+
+use crate::change_display_mode;
+use crate::change_icc_profile;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code::{self, ExitCode};
+use crate::hdr;
+use crate::set_sdr_level;
+
+// C API for tools that would rather link the helper than start it for every change, like a
+// Sunshine plugin or a C# or AutoHotkey script. Declared in include/shh.h.
+//
+// Displays are picked with the same selectors as the commands (primary, an index, DISPLAY2, the
+// monitor name or its display ID), a null selector meaning the primary display. Strings are
+// UTF-8. Functions that change something return the command exit codes, 0 on success, and keep
+// the error message for shh_last_error on the calling thread.
+
+const DEVICE_NAME_SIZE: usize = 32;
+const FRIENDLY_NAME_SIZE: usize = 64;
+
+#[repr(C)]
+pub struct ShhDisplay {
+    pub index: u32,
+    pub device_name: [c_char; DEVICE_NAME_SIZE], // DISPLAY1, without the \\.\ prefix
+    pub friendly_name: [c_char; FRIENDLY_NAME_SIZE], // the monitor's name, empty if unknown
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+    pub is_primary: bool,
+    pub hdr_supported: bool,
+    pub hdr_enabled: bool,
+    pub sdr_level: i32, // 0-100 like the Settings slider, -1 when it can't be read
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+//==============================================================================
+// Helpers
+//==============================================================================
+
+fn set_last_error(message: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// A panic must not unwind into the caller's process. It's reported as a failure instead, with
+// the panic's message for shh_last_error, as a host may have nowhere to show the helper's log.
+fn panic_error(payload: Box<dyn Any + Send>) -> Error {
+    let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    Error::new(E_FAIL, format!("Internal error: {}", message))
+}
+
+// Runs an API call, keeping its error for shh_last_error
+fn call(operation: impl FnOnce() -> Result<()>) -> i32 {
+    let result = catch_unwind(AssertUnwindSafe(operation)).unwrap_or_else(|payload| Err(panic_error(payload)));
+    let (code, message) = match result {
+        Ok(()) => (ExitCode::Success, String::new()),
+        Err(e) => (ExitCode::from_error(&e), e.message()),
+    };
+    set_last_error(message);
+    code as i32
+}
+
+unsafe fn display(selector: *const c_char) -> Result<DisplayDevice> {
+    let selector = if selector.is_null() {
+        "primary"
+    } else {
+        CStr::from_ptr(selector).to_str().map_err(|_| exit_code::invalid_input("The display selector isn't valid UTF-8"))?
+    };
+    displays_info::find_display(selector)
+        .ok_or_else(|| exit_code::display_not_found(format!("No active display matches '{}'", selector)))
+}
+
+// Cut to fit on a character boundary, always NUL terminated
+fn copy_string(target: &mut [c_char], text: &str) {
+    let mut length = text.len().min(target.len() - 1);
+    while !text.is_char_boundary(length) {
+        length -= 1;
+    }
+    for (slot, byte) in target.iter_mut().zip(&text.as_bytes()[..length]) {
+        *slot = *byte as c_char;
+    }
+    target[length] = 0;
+}
+
+fn describe(display: &DisplayDevice) -> ShhDisplay {
+    let color = hdr::get_advanced_color_info(display).ok();
+    let mut described = ShhDisplay {
+        index: display.device_index,
+        device_name: [0; DEVICE_NAME_SIZE],
+        friendly_name: [0; FRIENDLY_NAME_SIZE],
+        width: display.current_resolution.0,
+        height: display.current_resolution.1,
+        refresh_rate: display.current_refresh_rate,
+        is_primary: display.is_primary,
+        hdr_supported: color.as_ref().is_some_and(|color| color.supported),
+        hdr_enabled: color.as_ref().is_some_and(|color| color.enabled),
+        sdr_level: set_sdr_level::get_display_sdr_white(display).map_or(-1, |level| level as i32),
+    };
+    copy_string(&mut described.device_name, display.device_name.trim_start_matches("\\\\.\\"));
+    if let Some(target) = display.get_target_device_name() {
+        copy_string(&mut described.friendly_name, &target.friendly_name);
+    }
+    described
+}
+
+//==============================================================================
+// Exported functions
+//==============================================================================
+
+// Fills up to `capacity` entries of `displays` and returns how many active displays there are,
+// so a first call with (NULL, 0) sizes the array. 0 with a message for shh_last_error if it failed.
+#[no_mangle]
+pub unsafe extern "C" fn shh_enumerate_displays(displays: *mut ShhDisplay, capacity: u32) -> u32 {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let found = displays_info::enumerate_displays();
+        if !displays.is_null() {
+            for (index, display) in found.iter().take(capacity as usize).enumerate() {
+                displays.add(index).write(describe(display));
+            }
+        }
+        found.len() as u32
+    }));
+    match result {
+        Ok(count) => {
+            set_last_error(String::new());
+            count
+        }
+        Err(payload) => {
+            set_last_error(panic_error(payload).message());
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn shh_set_hdr(selector: *const c_char, enabled: bool) -> i32 {
+    call(|| hdr::set_hdr(&display(selector)?, enabled))
+}
+
+// `level` is 0-100 like the Settings slider, 80 to 480 nits
+#[no_mangle]
+pub unsafe extern "C" fn shh_set_sdr_level(selector: *const c_char, level: u32) -> i32 {
+    call(|| set_sdr_level::set_display_sdr_white(&display(selector)?, level))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn shh_set_sdr_nits(selector: *const c_char, nits: u32) -> i32 {
+    call(|| {
+        if !(80..=480).contains(&nits) {
+            return Err(exit_code::invalid_input(format!("SDR white level {} nits is out of range (80-480)", nits)));
+        }
+        set_sdr_level::set_display_sdr_white(&display(selector)?, set_sdr_level::nits_to_level(nits))
+    })
+}
+
+// `profile` is the file name of a profile associated with the display, e.g. "HDR TV.icc"
+#[no_mangle]
+pub unsafe extern "C" fn shh_set_icc_profile(selector: *const c_char, profile: *const c_char) -> i32 {
+    call(|| {
+        if profile.is_null() {
+            return Err(exit_code::invalid_input("No ICC profile given"));
+        }
+        let profile = CStr::from_ptr(profile).to_str().map_err(|_| exit_code::invalid_input("The ICC profile name isn't valid UTF-8"))?;
+        change_icc_profile::set_display_icc_profile(&display(selector)?, profile)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn shh_set_display_mode(selector: *const c_char, width: u32, height: u32, refresh_rate: u32) -> i32 {
+    call(|| {
+        let display = display(selector)?;
+        if !change_display_mode::is_supported_mode(&display, width, height, refresh_rate, None) {
+            return Err(exit_code::mode_unsupported(format!("{} doesn't offer {}x{} @ {} Hz", display.device_name, width, height, refresh_rate)));
+        }
+        if change_display_mode::change_display_mode(&display, width, height, refresh_rate, None, false) {
+            Ok(())
+        } else {
+            Err(Error::new(E_FAIL, format!("Failed to change the mode of {}", display.device_name)))
+        }
+    })
+}
+
+// Copies the calling thread's last error message into `buffer` (cut to fit, NUL terminated) and
+// returns its full length in bytes, 0 when the last call succeeded
+#[no_mangle]
+pub unsafe extern "C" fn shh_last_error(buffer: *mut c_char, size: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let last = last.borrow();
+        if !buffer.is_null() && size > 0 {
+            copy_string(std::slice::from_raw_parts_mut(buffer, size), &last);
+        }
+        last.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn text(buffer: &[c_char]) -> &str {
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap()
+    }
+
+    fn last_error() -> String {
+        let mut buffer = [0; 256];
+        let length = unsafe { shh_last_error(buffer.as_mut_ptr(), buffer.len()) };
+        assert_eq!(text(&buffer).len(), length);
+        text(&buffer).to_string()
+    }
+
+    #[test]
+    fn strings_are_cut_on_a_character_boundary() {
+        let mut buffer = [1; 6];
+        copy_string(&mut buffer, "DISPLAY1");
+        assert_eq!(text(&buffer), "DISPL");
+
+        // "é" is two bytes and doesn't fit after "TV "
+        copy_string(&mut buffer, "TV éé");
+        assert_eq!(text(&buffer), "TV é");
+        copy_string(&mut buffer[..5], "TV éé");
+        assert_eq!(text(&buffer), "TV ");
+
+        copy_string(&mut buffer, "");
+        assert_eq!(buffer[0], 0);
+    }
+
+    #[test]
+    fn displays_are_described_without_the_device_prefix() {
+        MockDisplayApi::install(vec![MockDisplay::hdr_tv(), MockDisplay::desk_monitor()]);
+
+        // Sized with (NULL, 0) first, and only `capacity` entries are written
+        assert_eq!(unsafe { shh_enumerate_displays(std::ptr::null_mut(), 0) }, 2);
+        let mut displays: Vec<ShhDisplay> = Vec::with_capacity(2);
+        unsafe {
+            assert_eq!(shh_enumerate_displays(displays.as_mut_ptr(), 1), 2);
+            displays.set_len(1);
+        }
+
+        let tv = &displays[0];
+        assert_eq!((text(&tv.device_name), text(&tv.friendly_name)), ("DISPLAY1", "LG TV SSCR2"));
+        assert_eq!((tv.width, tv.height, tv.refresh_rate), (3840, 2160, 120));
+        assert!(tv.is_primary && tv.hdr_supported && tv.hdr_enabled);
+        assert_eq!(tv.sdr_level, 40); // the default 240 nits
+
+        let monitor = describe(&displays_info::find_display("DISPLAY2").unwrap());
+        assert_eq!(text(&monitor.device_name), "DISPLAY2");
+        assert!(!monitor.is_primary && !monitor.hdr_supported);
+    }
+
+    #[test]
+    fn a_null_selector_means_the_primary_display() {
+        let api = MockDisplayApi::install(vec![MockDisplay::hdr_tv(), MockDisplay::desk_monitor()]);
+        assert_eq!(unsafe { shh_set_hdr(std::ptr::null(), false) }, ExitCode::Success as i32);
+        assert_eq!(last_error(), "");
+        assert_eq!(api.calls(), ["set_hdr DISPLAY1 off"]);
+    }
+
+    #[test]
+    fn bad_arguments_fail_with_a_message() {
+        let api = MockDisplayApi::install(vec![MockDisplay::hdr_tv()]);
+
+        let not_utf8 = [0x44, 0xFF, 0];
+        assert_eq!(unsafe { shh_set_hdr(not_utf8.as_ptr() as *const c_char, true) }, ExitCode::InvalidInput as i32);
+        assert_eq!(last_error(), "The display selector isn't valid UTF-8");
+
+        assert_eq!(unsafe { shh_set_icc_profile(std::ptr::null(), std::ptr::null()) }, ExitCode::InvalidInput as i32);
+        assert_eq!(last_error(), "No ICC profile given");
+
+        let missing = c"DISPLAY7";
+        assert_eq!(unsafe { shh_set_sdr_level(missing.as_ptr(), 50) }, ExitCode::DisplayNotFound as i32);
+        assert_eq!(last_error(), "No active display matches 'DISPLAY7'");
+
+        assert_eq!(unsafe { shh_set_sdr_nits(std::ptr::null(), 500) }, ExitCode::InvalidInput as i32);
+        assert!(api.calls().is_empty());
+
+        // A buffer too small for the message still gets a terminated prefix and the full length
+        let mut buffer = [1; 4];
+        let length = unsafe { shh_last_error(buffer.as_mut_ptr(), buffer.len()) };
+        assert_eq!((text(&buffer), length), ("SDR", last_error().len()));
+    }
+
+    #[test]
+    fn panics_come_back_as_errors() {
+        assert_eq!(call(|| panic!("no topology")), ExitCode::Failure as i32);
+        assert_eq!(last_error(), "Internal error: no topology");
+        let display = "DISPLAY9";
+        assert_eq!(call(|| panic!("{} vanished", display)), ExitCode::Failure as i32);
+        assert_eq!(last_error(), "Internal error: DISPLAY9 vanished");
+
+        assert_eq!(call(|| Ok(())), ExitCode::Success as i32);
+        assert_eq!(last_error(), "");
+    }
+}
@@ -0,0 +1,67 @@
+use windows::Win32::Graphics::Gdi::{CreateDCW, DeleteDC};
+use windows::Win32::UI::ColorSystem::SetDeviceGammaRamp;
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, PCWSTR, Result};
+use tracing::{info, error, instrument};
+
+use crate::displays_info::DisplayDevice;
+use crate::icc_file::GammaRamp;
+
+//==============================================================================
+// Building ramps
+//==============================================================================
+
+// What set-gamma accepts
+pub const MIN_GAMMA: f64 = 0.1;
+pub const MAX_GAMMA: f64 = 10.0;
+
+// Identity ramp, which is what Windows uses when nothing has been loaded
+pub fn linear_ramp() -> GammaRamp {
+    gamma_ramp(1.0)
+}
+
+// Gamma adjustment like the GPU control panels offer: above 1.0 brightens, below darkens
+pub fn gamma_ramp(gamma: f64) -> GammaRamp {
+    let mut ramp = [[0u16; 256]; 3];
+    for channel in ramp.iter_mut() {
+        for (i, value) in channel.iter_mut().enumerate() {
+            let sample = (i as f64 / 255.0).powf(1.0 / gamma);
+            *value = (sample * 65535.0).round() as u16;
+        }
+    }
+    ramp
+}
+
+//==============================================================================
+// Apply ramps
+//==============================================================================
+
+#[instrument(skip_all, fields(display = %device.device_name))]
+pub fn set_display_gamma_ramp(device: &DisplayDevice, ramp: &GammaRamp) -> Result<()> {
+    info!("Setting gamma ramp for display: {}", device.device_name);
+
+    unsafe {
+        let dc = CreateDCW(
+            PCWSTR::from_raw(device.device_name.encode_utf16().chain(std::iter::once(0)).collect::<Vec<u16>>().as_ptr()),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            None,
+        );
+
+        if dc.is_invalid() {
+            error!("Failed to create DC for display {}", device.device_name);
+            return Err(Error::new(E_FAIL, format!("Failed to create DC for {}", device.device_name)));
+        }
+
+        let success = SetDeviceGammaRamp(dc, ramp.as_ptr() as *const _);
+        let _ = DeleteDC(dc);
+
+        if !success.as_bool() {
+            // Windows refuses ramps it considers too extreme
+            error!("SetDeviceGammaRamp failed for display {}", device.device_name);
+            return Err(Error::new(E_FAIL, format!("Windows rejected the gamma ramp for {}", device.device_name)));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use windows::core::{Error, Result};
+use tracing::instrument;
+
+use crate::displays_info::DisplayDevice;
+use crate::dxgi_info::adapter_vendor_id;
+use crate::exit_code;
+
+// Features Windows has no API for are handled by the GPU vendor's own library. Each backend
+// is behind a cargo feature and loads the driver's DLL at runtime, so a build with every
+// backend still runs on machines without that vendor's driver.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GpuVendor {
+    Nvidia,
+    Amd,
+    Intel,
+    Other(u32),
+}
+
+impl GpuVendor {
+    fn from_pci_id(vendor_id: u32) -> Self {
+        match vendor_id {
+            0x10DE => GpuVendor::Nvidia,
+            0x1002 => GpuVendor::Amd,
+            0x8086 => GpuVendor::Intel,
+            other => GpuVendor::Other(other),
+        }
+    }
+
+    // The cargo feature that enables this vendor's backend
+    fn backend_feature(&self) -> Option<&'static str> {
+        match self {
+            GpuVendor::Nvidia => Some("nvapi"),
+            GpuVendor::Amd => Some("adl"),
+            GpuVendor::Intel => Some("igcl"),
+            _ => None,
+        }
+    }
+}
+
+pub fn display_vendor(display: &DisplayDevice) -> Option<GpuVendor> {
+    adapter_vendor_id(display.adapter_id).map(GpuVendor::from_pci_id)
+}
+
+// Explain why a vendor feature can't be used: wrong GPU, or a backend that wasn't compiled in
+fn unsupported(display: &DisplayDevice, feature: &str, vendor: Option<GpuVendor>) -> Error {
+    let message = match vendor {
+        None => format!("{} is not supported: could not identify the GPU driving {}", feature, display.device_name),
+        Some(vendor) => match vendor.backend_feature() {
+            Some(backend) => format!("{} on {:?} GPUs requires a build with `--features {}`", feature, vendor, backend),
+            None => format!("{} is not supported on this GPU ({:?})", feature, vendor),
+        },
+    };
+    exit_code::not_supported(message)
+}
+
+//==============================================================================
+// VRR
+//==============================================================================
+
+pub struct VrrStatus {
+    pub supported: bool,
+    pub enabled: bool,
+    pub active: bool, // currently running at a variable refresh rate
+}
+
+pub fn get_vrr(display: &DisplayDevice) -> Result<VrrStatus> {
+    match display_vendor(display) {
+        #[cfg(feature = "nvapi")]
+        Some(GpuVendor::Nvidia) => crate::nvapi::get_vrr(display),
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::get_vrr(display),
+        vendor => Err(unsupported(display, "VRR control", vendor)),
+    }
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, enabled = enabled), err(level = "info"))]
+pub fn set_vrr(device: &DisplayDevice, enabled: bool) -> Result<()> {
+    match display_vendor(device) {
+        #[cfg(feature = "nvapi")]
+        Some(GpuVendor::Nvidia) => crate::nvapi::set_vrr(device, enabled),
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::set_vrr(device, enabled),
+        vendor => Err(unsupported(device, "VRR control", vendor)),
+    }
+}
+
+//==============================================================================
+// Custom resolutions
+//==============================================================================
+
+#[instrument(skip_all, fields(display = %device.device_name, width = width, height = height, refresh_rate = refresh_rate), err(level = "info"))]
+pub fn create_custom_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32) -> Result<()> {
+    match display_vendor(device) {
+        #[cfg(feature = "nvapi")]
+        Some(GpuVendor::Nvidia) => crate::nvapi::create_custom_mode(device, width, height, refresh_rate),
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::create_custom_mode(device, width, height, refresh_rate),
+        vendor => Err(unsupported(device, "Custom resolutions", vendor)),
+    }
+}
+
+//==============================================================================
+// Output color format
+//==============================================================================
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    Rgb,
+    Ycc444,
+    Ycc422,
+    Ycc420,
+}
+
+pub struct ColorFormat {
+    pub pixel_format: PixelFormat,
+    pub bits_per_color: u32,
+}
+
+pub fn get_color_format(display: &DisplayDevice) -> Result<ColorFormat> {
+    match display_vendor(display) {
+        #[cfg(feature = "nvapi")]
+        Some(GpuVendor::Nvidia) => crate::nvapi::get_color_format(display),
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::get_color_format(display),
+        #[cfg(feature = "igcl")]
+        Some(GpuVendor::Intel) => crate::igcl::get_color_format(display),
+        vendor => Err(unsupported(display, "Output color format control", vendor)),
+    }
+}
+
+// Either part can be left as it is
+#[instrument(skip_all, fields(display = %device.device_name, pixel_format = ?pixel_format, bits_per_color = ?bits_per_color), err(level = "info"))]
+pub fn set_color_format(device: &DisplayDevice, pixel_format: Option<PixelFormat>, bits_per_color: Option<u32>) -> Result<()> {
+    match display_vendor(device) {
+        #[cfg(feature = "nvapi")]
+        Some(GpuVendor::Nvidia) => crate::nvapi::set_color_format(device, pixel_format, bits_per_color),
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::set_color_format(device, pixel_format, bits_per_color),
+        #[cfg(feature = "igcl")]
+        Some(GpuVendor::Intel) => crate::igcl::set_color_format(device, pixel_format, bits_per_color),
+        vendor => Err(unsupported(device, "Output color format control", vendor)),
+    }
+}
+
+//==============================================================================
+// HDR
+//==============================================================================
+
+// Vendor workarounds to run before Windows turns HDR on. Nothing to do without a backend.
+#[cfg_attr(not(feature = "igcl"), allow(unused_variables))]
+pub fn prepare_hdr(display: &DisplayDevice) -> Result<()> {
+    match display_vendor(display) {
+        #[cfg(feature = "igcl")]
+        Some(GpuVendor::Intel) => crate::igcl::prepare_hdr(display),
+        _ => Ok(()),
+    }
+}
+
+//==============================================================================
+// Scaling
+//==============================================================================
+
+// GPU scaling for modes below the panel's native resolution
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ScalingMode {
+    Identity, // leave scaling to the display
+    Centered,
+    Stretched,
+    AspectRatio,
+}
+
+pub fn get_scaling(display: &DisplayDevice) -> Result<ScalingMode> {
+    match display_vendor(display) {
+        #[cfg(feature = "igcl")]
+        Some(GpuVendor::Intel) => crate::igcl::get_scaling(display),
+        vendor => Err(unsupported(display, "Scaling control", vendor)),
+    }
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, mode = ?mode), err(level = "info"))]
+pub fn set_scaling(device: &DisplayDevice, mode: ScalingMode) -> Result<()> {
+    match display_vendor(device) {
+        #[cfg(feature = "igcl")]
+        Some(GpuVendor::Intel) => crate::igcl::set_scaling(device, mode),
+        vendor => Err(unsupported(device, "Scaling control", vendor)),
+    }
+}
+
+//==============================================================================
+// Dithering
+//==============================================================================
+
+pub fn get_dithering(display: &DisplayDevice) -> Result<bool> {
+    match display_vendor(display) {
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::get_dithering(display),
+        vendor => Err(unsupported(display, "Dithering control", vendor)),
+    }
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, enabled = enabled), err(level = "info"))]
+pub fn set_dithering(device: &DisplayDevice, enabled: bool) -> Result<()> {
+    match display_vendor(device) {
+        #[cfg(feature = "adl")]
+        Some(GpuVendor::Amd) => crate::adl::set_dithering(device, enabled),
+        vendor => Err(unsupported(device, "Dithering control", vendor)),
+    }
+}
@@ -0,0 +1,466 @@
+use std::cell::RefCell;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, COLOR_BTNFACE, DEFAULT_GUI_FONT, HBRUSH};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{
+    InitCommonControlsEx,
+    BST_CHECKED,
+    ICC_BAR_CLASSES,
+    INITCOMMONCONTROLSEX,
+    TBM_SETPOS,
+    TBM_SETRANGE,
+    TBS_NOTICKS,
+    TB_ENDTRACK,
+    TRACKBAR_CLASSW,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::EnableWindow;
+use windows::Win32::UI::WindowsAndMessaging::{
+    AdjustWindowRect,
+    CreateWindowExW,
+    DefWindowProcW,
+    DispatchMessageW,
+    GetDlgItem,
+    GetMessageW,
+    IsDialogMessageW,
+    LoadCursorW,
+    PostQuitMessage,
+    RegisterClassW,
+    SendMessageW,
+    SetWindowTextW,
+    ShowWindow,
+    TranslateMessage,
+    BM_GETCHECK,
+    BM_SETCHECK,
+    BN_CLICKED,
+    BS_AUTOCHECKBOX,
+    BS_PUSHBUTTON,
+    CBN_SELCHANGE,
+    CBS_DROPDOWNLIST,
+    CB_ADDSTRING,
+    CB_GETCURSEL,
+    CB_RESETCONTENT,
+    CB_SETCURSEL,
+    CW_USEDEFAULT,
+    HMENU,
+    IDC_ARROW,
+    MSG,
+    SW_SHOW,
+    WINDOW_EX_STYLE,
+    WINDOW_STYLE,
+    WM_COMMAND,
+    WM_DESTROY,
+    WM_HSCROLL,
+    WM_SETFONT,
+    WM_USER,
+    WNDCLASSW,
+    WS_CAPTION,
+    WS_CHILD,
+    WS_MINIMIZEBOX,
+    WS_OVERLAPPED,
+    WS_SYSMENU,
+    WS_TABSTOP,
+    WS_VISIBLE,
+    WS_VSCROLL,
+};
+use windows::core::{w, Error, HSTRING, PCWSTR, Result};
+use tracing::{info, error};
+
+use crate::apply::{self, Spec};
+use crate::change_display_mode;
+use crate::change_icc_profile;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code;
+use crate::hdr;
+use crate::retry::{self, RetryPolicy};
+use crate::set_sdr_level;
+use crate::state;
+use crate::sunshine;
+
+// `gui` is a small window over the same functions the commands use: pick a display, then toggle
+// HDR, drag the SDR white level, pick one of its ICC profiles or one of its modes. Everything but
+// the mode applies as soon as it changes, the mode waits for Apply so a misclick doesn't blank
+// the screen. Only built with `--features gui`.
+
+const CLIENT_WIDTH: i32 = 460;
+const CLIENT_HEIGHT: i32 = 316;
+const LABEL_WIDTH: i32 = 90;
+const ROW_HEIGHT: i32 = 24;
+
+const ID_DISPLAY: i32 = 100;
+const ID_HDR: i32 = 101;
+const ID_SDR: i32 = 102;
+const ID_SDR_NITS: i32 = 103;
+const ID_ICC: i32 = 104;
+const ID_MODE: i32 = 105;
+const ID_APPLY_MODE: i32 = 106;
+const ID_RESTORE: i32 = 107;
+const ID_STATUS: i32 = 108;
+
+// Not in the windows crate, TBM_GETPOS is WM_USER itself
+const TBM_GETPOS: u32 = WM_USER;
+
+struct Gui {
+    window: HWND,
+    displays: Vec<DisplayDevice>,
+    selected: usize,
+    profiles: Vec<String>, // the ICC list after its "leave as is" entry
+    modes: Vec<(u32, u32, u32)>,
+    retry: RetryPolicy,
+}
+
+thread_local! {
+    static GUI: RefCell<Option<Gui>> = const { RefCell::new(None) };
+}
+
+//==============================================================================
+// Controls
+//==============================================================================
+
+// Changing a mode sends messages back to the window while a handler runs, those are left alone
+fn with_gui(handler: impl FnOnce(&mut Gui)) {
+    GUI.with(|gui| {
+        if let Ok(mut gui) = gui.try_borrow_mut() {
+            if let Some(gui) = gui.as_mut() {
+                handler(gui);
+            }
+        }
+    });
+}
+
+fn display_label(display: &DisplayDevice) -> String {
+    let name = display.device_name.trim_start_matches("\\\\.\\");
+    let label = match display.get_target_device_name() {
+        Some(target) if !target.friendly_name.is_empty() => format!("{} - {}", name, target.friendly_name),
+        _ => name.to_string(),
+    };
+    if display.is_primary { format!("{} (primary)", label) } else { label }
+}
+
+impl Gui {
+    fn item(&self, id: i32) -> HWND {
+        unsafe { GetDlgItem(self.window, id).unwrap_or_default() }
+    }
+
+    fn send(&self, id: i32, msg: u32, wparam: usize, lparam: isize) -> isize {
+        unsafe { SendMessageW(self.item(id), msg, WPARAM(wparam), LPARAM(lparam)).0 }
+    }
+
+    fn set_text(&self, id: i32, text: &str) {
+        let _ = unsafe { SetWindowTextW(self.item(id), &HSTRING::from(text)) };
+    }
+
+    fn fill_combo<'a>(&self, id: i32, items: impl IntoIterator<Item = &'a str>, selected: usize) {
+        self.send(id, CB_RESETCONTENT, 0, 0);
+        for item in items {
+            let text = HSTRING::from(item);
+            self.send(id, CB_ADDSTRING, 0, text.as_ptr() as isize);
+        }
+        self.send(id, CB_SETCURSEL, selected, 0);
+    }
+
+    fn combo_selection(&self, id: i32) -> Option<usize> {
+        usize::try_from(self.send(id, CB_GETCURSEL, 0, 0)).ok()
+    }
+
+    fn display(&self) -> &DisplayDevice {
+        &self.displays[self.selected]
+    }
+
+    fn status(&self, text: &str) {
+        info!("{}", text);
+        self.set_text(ID_STATUS, text);
+    }
+
+    fn report(&self, done: &str, result: Result<()>) {
+        match result {
+            Ok(()) => self.status(done),
+            Err(e) => {
+                error!("{}", e.message());
+                self.set_text(ID_STATUS, &format!("Failed: {}", e.message()));
+            }
+        }
+    }
+
+    // Displays are enumerated again after anything that can renumber or resize them
+    fn reload_displays(&mut self) {
+        let device_name = self.displays.get(self.selected).map(|display| display.device_name.clone());
+        self.displays = displays_info::enumerate_displays();
+        self.selected = self.displays.iter()
+            .position(|display| Some(&display.device_name) == device_name.as_ref())
+            .or_else(|| self.displays.iter().position(|display| display.is_primary))
+            .unwrap_or(0);
+        let labels: Vec<String> = self.displays.iter().map(display_label).collect();
+        self.fill_combo(ID_DISPLAY, labels.iter().map(String::as_str), self.selected);
+        self.load_display();
+    }
+
+    // Sets every control from the selected display's current state
+    fn load_display(&mut self) {
+        let Some(display) = self.displays.get(self.selected).cloned() else {
+            self.status("No active displays found");
+            return;
+        };
+
+        let color = hdr::get_advanced_color_info(&display).ok();
+        let hdr_supported = color.as_ref().is_some_and(|color| color.supported);
+        let hdr_enabled = color.as_ref().is_some_and(|color| color.enabled);
+        self.send(ID_HDR, BM_SETCHECK, if hdr_enabled { BST_CHECKED.0 as usize } else { 0 }, 0);
+        let _ = unsafe { EnableWindow(self.item(ID_HDR), hdr_supported) };
+
+        let level = set_sdr_level::get_display_sdr_white(&display).unwrap_or(set_sdr_level::nits_to_level(240));
+        self.send(ID_SDR, TBM_SETPOS, 1, level as isize);
+        self.show_sdr_nits(level);
+        let _ = unsafe { EnableWindow(self.item(ID_SDR), hdr_enabled) };
+
+        self.profiles = change_icc_profile::get_display_icc_profiles(&display).into_iter().map(|profile| profile.name).collect();
+        let default_profile = change_icc_profile::get_display_default_icc_profile(&display);
+        let selected_profile = self.profiles.iter()
+            .position(|name| Some(name) == default_profile.as_ref())
+            .map_or(0, |index| index + 1);
+        let profiles: Vec<&str> = std::iter::once("Leave as is").chain(self.profiles.iter().map(String::as_str)).collect();
+        self.fill_combo(ID_ICC, profiles, selected_profile);
+
+        self.modes = display.get_supported_modes().into_iter().map(|mode| (mode.width, mode.height, mode.refresh_rate)).collect();
+        self.modes.dedup();
+        let current = (display.current_resolution.0, display.current_resolution.1, display.current_refresh_rate);
+        let selected_mode = self.modes.iter().position(|mode| *mode == current).unwrap_or(0);
+        let labels: Vec<String> = self.modes.iter().map(|(width, height, refresh_rate)| format!("{}x{} @ {} Hz", width, height, refresh_rate)).collect();
+        self.fill_combo(ID_MODE, labels.iter().map(String::as_str), selected_mode);
+
+        let status = if hdr_supported { "" } else { "This display isn't HDR capable" };
+        self.set_text(ID_STATUS, status);
+    }
+
+    fn show_sdr_nits(&self, level: u32) {
+        self.set_text(ID_SDR_NITS, &format!("{} nits", set_sdr_level::level_to_nits(level)));
+    }
+
+    //==========================================================================
+    // Handlers
+    //==========================================================================
+
+    fn on_display_selected(&mut self) {
+        if let Some(index) = self.combo_selection(ID_DISPLAY).filter(|index| *index < self.displays.len()) {
+            self.selected = index;
+            self.load_display();
+        }
+    }
+
+    fn on_hdr_clicked(&mut self) {
+        let enabled = self.send(ID_HDR, BM_GETCHECK, 0, 0) == BST_CHECKED.0 as isize;
+        let display = self.display();
+        let result = retry::with_retries(self.retry, "HDR change", || hdr::set_hdr(display, enabled));
+        let done = format!("HDR {} on {}", if enabled { "enabled" } else { "disabled" }, display.device_name);
+        self.report(&done, result);
+        // The SDR level only means something in HDR, and a failed change leaves the old state
+        self.load_display();
+    }
+
+    fn on_sdr_moved(&mut self, released: bool) {
+        let level = self.send(ID_SDR, TBM_GETPOS, 0, 0).clamp(0, 100) as u32;
+        self.show_sdr_nits(level);
+        if !released {
+            return;
+        }
+        let display = self.display();
+        let result = retry::with_retries(self.retry, "SDR white level change", || set_sdr_level::set_display_sdr_white(display, level));
+        let done = format!("SDR content on {} at {} nits", display.device_name, set_sdr_level::level_to_nits(level));
+        self.report(&done, result);
+    }
+
+    fn on_icc_selected(&mut self) {
+        let Some(profile) = self.combo_selection(ID_ICC).and_then(|index| index.checked_sub(1)).and_then(|index| self.profiles.get(index)) else {
+            return;
+        };
+        let display = self.display();
+        let result = change_icc_profile::set_display_icc_profile(display, profile);
+        self.report(&format!("{} is now the default profile of {}", profile, display.device_name), result);
+    }
+
+    fn on_apply_mode(&mut self) {
+        let Some(&(width, height, refresh_rate)) = self.combo_selection(ID_MODE).and_then(|index| self.modes.get(index)) else {
+            return;
+        };
+        let display = self.display();
+        let result = if change_display_mode::change_display_mode(display, width, height, refresh_rate, None, false) {
+            Ok(())
+        } else {
+            Err(exit_code::mode_unsupported(format!("{} didn't take {}x{} @ {} Hz", display.device_name, width, height, refresh_rate)))
+        };
+        let done = format!("{} is at {}x{} @ {} Hz", display.device_name, width, height, refresh_rate);
+        self.report(&done, result);
+        self.reload_displays();
+    }
+
+    // What the daemon does when a stream ends without its undo commands, on demand
+    fn on_restore(&mut self) {
+        let Some(spec) = state::load_named_state::<Spec>(sunshine::PRE_STREAM_STATE) else {
+            self.status("There's no display state saved from before a stream");
+            return;
+        };
+        let result = apply::differences(&spec).and_then(|differences| apply::apply_with_undo(&differences, self.retry, true));
+        if result.is_ok() {
+            if let Err(e) = state::delete_named_state(sunshine::PRE_STREAM_STATE) {
+                error!("Failed to delete the state saved before the stream: {}", e);
+            }
+        }
+        self.reload_displays();
+        self.report("Restored the displays from before the stream", result);
+    }
+}
+
+//==============================================================================
+// Window
+//==============================================================================
+
+extern "system" fn gui_window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match msg {
+        WM_COMMAND => {
+            let id = (wparam.0 & 0xFFFF) as i32;
+            let notification = ((wparam.0 >> 16) & 0xFFFF) as u32;
+            match (id, notification) {
+                (ID_DISPLAY, CBN_SELCHANGE) => with_gui(Gui::on_display_selected),
+                (ID_HDR, BN_CLICKED) => with_gui(Gui::on_hdr_clicked),
+                (ID_ICC, CBN_SELCHANGE) => with_gui(Gui::on_icc_selected),
+                (ID_APPLY_MODE, BN_CLICKED) => with_gui(Gui::on_apply_mode),
+                (ID_RESTORE, BN_CLICKED) => with_gui(Gui::on_restore),
+                _ => {}
+            }
+            LRESULT(0)
+        }
+        // The slider is the only control that scrolls
+        WM_HSCROLL => {
+            let released = (wparam.0 & 0xFFFF) as u32 == TB_ENDTRACK;
+            with_gui(|gui| gui.on_sdr_moved(released));
+            LRESULT(0)
+        }
+        WM_DESTROY => {
+            unsafe { PostQuitMessage(0) };
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+    }
+}
+
+struct Layout {
+    window: HWND,
+    instance: windows::Win32::Foundation::HINSTANCE,
+}
+
+impl Layout {
+    unsafe fn control(&self, class: PCWSTR, text: &str, style: WINDOW_STYLE, id: i32, (x, y, width, height): (i32, i32, i32, i32)) -> Result<()> {
+        let control = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class,
+            &HSTRING::from(text),
+            WS_CHILD | WS_VISIBLE | style,
+            x,
+            y,
+            width,
+            height,
+            self.window,
+            HMENU(id as isize as *mut _),
+            self.instance,
+            None,
+        )?;
+        SendMessageW(control, WM_SETFONT, WPARAM(GetStockObject(DEFAULT_GUI_FONT).0 as usize), LPARAM(1));
+        Ok(())
+    }
+
+    unsafe fn label(&self, text: &str, row: i32) -> Result<()> {
+        self.control(w!("STATIC"), text, WINDOW_STYLE::default(), -1, (12, row + 4, LABEL_WIDTH, ROW_HEIGHT))
+    }
+
+    unsafe fn create_controls(&self) -> Result<()> {
+        let field = LABEL_WIDTH + 16;
+        let combo = WS_TABSTOP | WS_VSCROLL | WINDOW_STYLE(CBS_DROPDOWNLIST as u32);
+        let button = WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32);
+
+        self.label("Display", 12)?;
+        self.control(w!("COMBOBOX"), "", combo, ID_DISPLAY, (field, 12, CLIENT_WIDTH - field - 12, 240))?;
+        self.control(w!("BUTTON"), "HDR", WS_TABSTOP | WINDOW_STYLE(BS_AUTOCHECKBOX as u32), ID_HDR, (field, 50, 120, ROW_HEIGHT))?;
+        self.label("SDR content", 84)?;
+        self.control(TRACKBAR_CLASSW, "", WS_TABSTOP | WINDOW_STYLE(TBS_NOTICKS), ID_SDR, (field, 84, 260, 30))?;
+        self.control(w!("STATIC"), "", WINDOW_STYLE::default(), ID_SDR_NITS, (field + 270, 88, 70, ROW_HEIGHT))?;
+        self.label("ICC profile", 126)?;
+        self.control(w!("COMBOBOX"), "", combo, ID_ICC, (field, 126, CLIENT_WIDTH - field - 12, 240))?;
+        self.label("Mode", 164)?;
+        self.control(w!("COMBOBOX"), "", combo, ID_MODE, (field, 164, 250, 300))?;
+        self.control(w!("BUTTON"), "Apply", button, ID_APPLY_MODE, (field + 260, 163, 80, ROW_HEIGHT + 2))?;
+        self.control(w!("BUTTON"), "Restore the displays from before the stream", button, ID_RESTORE, (12, 212, CLIENT_WIDTH - 24, ROW_HEIGHT + 4))?;
+        self.control(w!("STATIC"), "", WINDOW_STYLE::default(), ID_STATUS, (12, 252, CLIENT_WIDTH - 24, CLIENT_HEIGHT - 264))?;
+
+        SendMessageW(GetDlgItem(self.window, ID_SDR)?, TBM_SETRANGE, WPARAM(0), LPARAM(100 << 16));
+        Ok(())
+    }
+}
+
+// Blocks until the window is closed
+pub fn run(retry: RetryPolicy) -> Result<()> {
+    let displays = displays_info::enumerate_displays();
+    if displays.is_empty() {
+        return Err(exit_code::display_not_found("No active displays found"));
+    }
+
+    unsafe {
+        let controls = INITCOMMONCONTROLSEX {
+            dwSize: size_of::<INITCOMMONCONTROLSEX>() as u32,
+            dwICC: ICC_BAR_CLASSES,
+        };
+        if !InitCommonControlsEx(&controls).as_bool() {
+            return Err(Error::from_win32());
+        }
+
+        let instance = GetModuleHandleW(PCWSTR::null())?;
+        let class_name = w!("SunshineHelperGui");
+        let window_class = WNDCLASSW {
+            lpfnWndProc: Some(gui_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            hCursor: LoadCursorW(None, IDC_ARROW)?,
+            hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as isize as *mut _),
+            ..Default::default()
+        };
+        if RegisterClassW(&window_class) == 0 {
+            let error = Error::from_win32();
+            error!("Failed to register the window class: {}", error);
+            return Err(error);
+        }
+
+        let style = WS_OVERLAPPED | WS_CAPTION | WS_SYSMENU | WS_MINIMIZEBOX;
+        let mut frame = RECT { left: 0, top: 0, right: CLIENT_WIDTH, bottom: CLIENT_HEIGHT };
+        AdjustWindowRect(&mut frame, style, false)?;
+        let window = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            w!("Sunshine HDR helper"),
+            style,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            frame.right - frame.left,
+            frame.bottom - frame.top,
+            HWND::default(),
+            HMENU::default(),
+            instance,
+            None,
+        )?;
+        Layout { window, instance: instance.into() }.create_controls()?;
+
+        let selected = displays.iter().position(|display| display.is_primary).unwrap_or(0);
+        GUI.with(|gui| *gui.borrow_mut() = Some(Gui { window, displays, selected, profiles: Vec::new(), modes: Vec::new(), retry }));
+        with_gui(Gui::reload_displays);
+        let _ = ShowWindow(window, SW_SHOW);
+
+        // IsDialogMessageW gives the controls tab navigation
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+            if !IsDialogMessageW(window, &msg).as_bool() {
+                let _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+    }
+
+    GUI.with(|gui| *gui.borrow_mut() = None);
+    Ok(())
+}
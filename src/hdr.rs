@@ -0,0 +1,125 @@
+use std::mem::size_of;
+use windows::Win32::Devices::Display::{
+    DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+    DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE,
+    DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO,
+    DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE,
+    DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE_0,
+};
+use windows::Win32::System::Registry::HKEY_CURRENT_USER;
+use windows::Win32::Foundation::{ERROR_SUCCESS, E_FAIL, WIN32_ERROR};
+use windows::core::{Error, Result};
+use tracing::{info, error, instrument};
+
+use crate::display_api;
+use crate::displays_info::DisplayDevice;
+use crate::exit_code;
+use crate::gpu_vendor;
+use crate::registry;
+use crate::windows_version::{self, Feature};
+
+// Bits of DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO.value
+pub const ADVANCED_COLOR_SUPPORTED: u32 = 0x1;
+pub const ADVANCED_COLOR_ENABLED: u32 = 0x2;
+pub const ADVANCED_COLOR_FORCE_DISABLED: u32 = 0x8;
+
+// "Play streaming HDR video" from Settings > Apps > Video playback. Windows keeps it per user,
+// it then applies to every HDR capable display.
+const VIDEO_SETTINGS_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\VideoSettings";
+const HDR_VIDEO_VALUE: &str = "EnableHDRForPlayback";
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+pub struct AdvancedColorInfo {
+    pub supported: bool,
+    pub enabled: bool,
+    pub force_disabled: bool,
+    pub bits_per_color: u32,
+}
+
+//==============================================================================
+// HDR (advanced color) state
+//==============================================================================
+
+pub fn get_advanced_color_info(device: &DisplayDevice) -> Result<AdvancedColorInfo> {
+    let mut request = DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+            size: size_of::<DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO>() as u32,
+            adapterId: device.adapter_id,
+            id: device.target_id,
+        },
+        ..Default::default()
+    };
+
+    let result = display_api::api().get_device_info(&mut request.header);
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "Failed to get advanced color info for {}: {}", device.device_name, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    let flags = unsafe { request.Anonymous.value };
+    Ok(AdvancedColorInfo {
+        supported: flags & ADVANCED_COLOR_SUPPORTED != 0,
+        enabled: flags & ADVANCED_COLOR_ENABLED != 0,
+        force_disabled: flags & ADVANCED_COLOR_FORCE_DISABLED != 0,
+        bits_per_color: request.bitsPerColorChannel,
+    })
+}
+
+#[instrument(skip_all, fields(display = %device.device_name, enabled = enabled))]
+pub fn set_hdr(device: &DisplayDevice, enabled: bool) -> Result<()> {
+    windows_version::require(Feature::AdvancedColorSet)?;
+    let color_info = get_advanced_color_info(device)?;
+    if enabled && !color_info.supported {
+        return Err(exit_code::not_supported(format!("{} does not support HDR", device.device_name)));
+    }
+    if enabled && color_info.force_disabled {
+        return Err(Error::new(E_FAIL, format!("HDR is blocked on {} by policy or the driver", device.device_name)));
+    }
+
+    if enabled {
+        if let Err(e) = gpu_vendor::prepare_hdr(device) {
+            // Windows can usually still turn HDR on, just maybe not at the best bit depth
+            error!("Driver preparation for HDR on {} failed: {}", device.device_name, e.message());
+        }
+    }
+
+    info!("Turning HDR {} for {}", if enabled { "on" } else { "off" }, device.device_name);
+
+    let request = DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_SET_ADVANCED_COLOR_STATE,
+            size: size_of::<DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE>() as u32,
+            adapterId: device.adapter_id,
+            id: device.target_id,
+        },
+        Anonymous: DISPLAYCONFIG_SET_ADVANCED_COLOR_STATE_0 { value: enabled as u32 },
+    };
+
+    let result = display_api::api().set_device_info(&request.header);
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "Failed to set advanced color state for {}: {}", device.device_name, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// HDR video streaming
+//==============================================================================
+
+pub fn is_hdr_video_streaming_enabled() -> Result<bool> {
+    let value = registry::read_dword(HKEY_CURRENT_USER, VIDEO_SETTINGS_KEY, HDR_VIDEO_VALUE)?;
+    Ok(value.unwrap_or(0) != 0)
+}
+
+#[instrument]
+pub fn set_hdr_video_streaming(enabled: bool) -> Result<()> {
+    info!("Turning HDR video streaming {}", if enabled { "on" } else { "off" });
+    registry::write_dword(HKEY_CURRENT_USER, VIDEO_SETTINGS_KEY, HDR_VIDEO_VALUE, enabled as u32)
+}
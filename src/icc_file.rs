@@ -0,0 +1,557 @@
+use windows::Win32::UI::ColorSystem::GetColorDirectoryW;
+use windows::core::{PCWSTR, PWSTR};
+use tracing::{info, error};
+use std::path::{Path, PathBuf};
+
+const ICC_HEADER_SIZE: usize = 128;
+const TAG_VCGT: &[u8; 4] = b"vcgt";
+const TAG_MHC2: &[u8; 4] = b"MHC2";
+
+//==============================================================================
+// Locating profiles
+//==============================================================================
+
+// The system color directory, normally C:\Windows\System32\spool\drivers\color
+pub fn color_directory() -> PathBuf {
+    let mut buffer = [0u16; 260];
+    let mut size = (buffer.len() * 2) as u32;
+
+    let success = unsafe { GetColorDirectoryW(PCWSTR::null(), PWSTR::from_raw(buffer.as_mut_ptr()), &mut size) };
+    if !success.as_bool() {
+        error!("GetColorDirectoryW failed, falling back to the default color directory");
+        return PathBuf::from("C:\\Windows\\System32\\spool\\drivers\\color");
+    }
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    PathBuf::from(String::from_utf16_lossy(&buffer[..len]))
+}
+
+// Accept either a path to a profile or just its file name in the color directory
+pub fn resolve_profile_path(name_or_path: &str) -> PathBuf {
+    let path = Path::new(name_or_path);
+    if path.is_absolute() || path.exists() {
+        path.to_path_buf()
+    } else {
+        color_directory().join(name_or_path)
+    }
+}
+
+//==============================================================================
+// Tag table
+//==============================================================================
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    read_u32(data, offset).map(|v| v as i32 as f64 / 65536.0)
+}
+
+// Find a tag's data by its four character signature
+pub fn find_tag<'a>(data: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let tag_count = read_u32(data, ICC_HEADER_SIZE)? as usize;
+
+    for index in 0..tag_count {
+        let entry = ICC_HEADER_SIZE + 4 + index * 12;
+        if data.get(entry..entry + 4)? != signature {
+            continue;
+        }
+
+        let offset = read_u32(data, entry + 4)? as usize;
+        let size = read_u32(data, entry + 8)? as usize;
+        return data.get(offset..offset + size);
+    }
+
+    None
+}
+
+//==============================================================================
+// HDR Calibration
+//==============================================================================
+
+// The Windows HDR Calibration app writes its calibration as an MHC2 tag, which plain ICC
+// profiles don't have
+pub fn is_hdr_calibration(data: &[u8]) -> bool {
+    find_tag(data, TAG_MHC2).is_some()
+}
+
+// False for a file that can't be read as well
+pub fn is_hdr_calibration_file(path: &Path) -> bool {
+    std::fs::read(path).is_ok_and(|data| is_hdr_calibration(&data))
+}
+
+//==============================================================================
+// Profile info
+//==============================================================================
+
+// What `icc info` shows: enough of the header and tags to tell one profile from another
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileInfo {
+    pub size: u32,
+    pub version: String,
+    pub device_class: String,
+    pub color_space: String,
+    pub connection_space: String,
+    pub created: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub description: Option<String>,
+    pub device_model: Option<String>,
+    pub copyright: Option<String>,
+    pub white_point: Option<[f64; 3]>,
+    pub luminance: Option<f64>,
+    pub has_vcgt: bool,
+    pub has_mhc2: bool,
+    pub tags: Vec<String>,
+}
+
+// Header signatures are four ASCII characters padded with spaces, all zero when unset
+fn signature(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..offset + 4)?;
+    if bytes == [0; 4] {
+        return None;
+    }
+    Some(String::from_utf8_lossy(bytes).trim_end().to_string())
+}
+
+fn device_class_name(class: &str) -> &str {
+    match class {
+        "scnr" => "input",
+        "mntr" => "display",
+        "prtr" => "output",
+        "link" => "device link",
+        "spac" => "color space",
+        "abst" => "abstract",
+        "nmcl" => "named color",
+        other => other,
+    }
+}
+
+// The date and time fields are six u16s, UTC
+fn creation_date(data: &[u8]) -> Option<String> {
+    let field = |index: usize| read_u16(data, 24 + index * 2);
+    let (year, month, day) = (field(0)?, field(1)?, field(2)?);
+    if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, field(3)?, field(4)?, field(5)?))
+}
+
+// Text tags are `desc` in v2 profiles and `mluc` in v4 ones, where the first record is used
+fn text_tag(tag: &[u8]) -> Option<String> {
+    let text = match tag.get(0..4)? {
+        b"desc" => {
+            let length = read_u32(tag, 8)? as usize;
+            String::from_utf8_lossy(tag.get(12..12 + length)?).to_string()
+        }
+        b"mluc" => {
+            if read_u32(tag, 8)? == 0 {
+                return None;
+            }
+            let length = read_u32(tag, 20)? as usize;
+            let offset = read_u32(tag, 24)? as usize;
+            let units: Vec<u16> = tag.get(offset..offset + length)?
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        b"text" => String::from_utf8_lossy(tag.get(8..)?).to_string(),
+        _ => return None,
+    };
+    let text = text.trim_end_matches('\0').trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn xyz_tag(tag: &[u8]) -> Option<[f64; 3]> {
+    if tag.get(0..4)? != b"XYZ " {
+        return None;
+    }
+    Some([read_s15_fixed16(tag, 8)?, read_s15_fixed16(tag, 12)?, read_s15_fixed16(tag, 16)?])
+}
+
+// The checks a profile has to pass before it's associated with a display: a header and tag
+// count, the acsp signature, and a declared size the file actually has
+pub fn check_profile(data: &[u8]) -> std::result::Result<(), String> {
+    if data.len() < ICC_HEADER_SIZE + 4 {
+        return Err(format!("{} bytes is too short for an ICC profile", data.len()));
+    }
+    if data.get(36..40) != Some(b"acsp") {
+        return Err("not an ICC profile, the acsp signature is missing".to_string());
+    }
+    let size = read_u32(data, 0).unwrap_or(0) as usize;
+    if size < ICC_HEADER_SIZE + 4 || size > data.len() {
+        return Err(format!("the header says the profile is {} bytes but the file has {}, it may be truncated", size, data.len()));
+    }
+    Ok(())
+}
+
+pub fn check_profile_file(path: &Path) -> std::result::Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    check_profile(&data).map_err(|e| format!("{} isn't a usable ICC profile: {}", path.display(), e))
+}
+
+pub fn parse_profile_info(data: &[u8]) -> std::result::Result<ProfileInfo, String> {
+    check_profile(data)?;
+
+    let tag_count = read_u32(data, ICC_HEADER_SIZE).unwrap_or(0) as usize;
+    let tags = (0..tag_count)
+        .map_while(|index| signature(data, ICC_HEADER_SIZE + 4 + index * 12))
+        .collect();
+    let text = |sig: &[u8; 4]| find_tag(data, sig).and_then(text_tag);
+
+    Ok(ProfileInfo {
+        size: read_u32(data, 0).unwrap_or(0),
+        version: format!("{}.{}.{}", data[8], data[9] >> 4, data[9] & 0x0F),
+        device_class: signature(data, 12).map(|class| device_class_name(&class).to_string()).unwrap_or_default(),
+        color_space: signature(data, 16).unwrap_or_default(),
+        connection_space: signature(data, 20).unwrap_or_default(),
+        created: creation_date(data),
+        manufacturer: signature(data, 48),
+        model: signature(data, 52),
+        description: text(b"desc"),
+        device_model: text(b"dmdd"),
+        copyright: text(b"cprt"),
+        white_point: find_tag(data, b"wtpt").and_then(xyz_tag),
+        // lumi holds the luminance in Y
+        luminance: find_tag(data, b"lumi").and_then(xyz_tag).map(|xyz| xyz[1]),
+        has_vcgt: find_tag(data, TAG_VCGT).is_some(),
+        has_mhc2: find_tag(data, TAG_MHC2).is_some(),
+        tags,
+    })
+}
+
+pub fn read_profile_info(name_or_path: &str) -> std::result::Result<(PathBuf, ProfileInfo), String> {
+    let path = resolve_profile_path(name_or_path);
+    let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let info = parse_profile_info(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok((path, info))
+}
+
+//==============================================================================
+// Writing display profiles
+//==============================================================================
+
+// The PCS illuminant, D50
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+// Bradford cone response
+const BRADFORD: [[f64; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+type Matrix = [[f64; 3]; 3];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 3]; 3];
+    for (row, product_row) in product.iter_mut().enumerate() {
+        for (column, value) in product_row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[row][k] * b[k][column]).sum();
+        }
+    }
+    product
+}
+
+fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|row| m[row][0] * v[0] + m[row][1] * v[1] + m[row][2] * v[2])
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2) + m[0][2] * cofactor(1, 2, 0, 1);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    Some([
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ].map(|row| row.map(|value| value / determinant)))
+}
+
+fn xy_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// Adapts XYZ under `white` to D50
+fn bradford_to_d50(white: [f64; 3]) -> Option<Matrix> {
+    let source = apply(&BRADFORD, white);
+    let destination = apply(&BRADFORD, D50);
+    let scale = [0, 1, 2].map(|i| {
+        let mut row = [0.0; 3];
+        row[i] = destination[i] / source[i];
+        row
+    });
+    Some(multiply(&invert(&BRADFORD)?, &multiply(&scale, &BRADFORD)))
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag_data(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    xyz.iter().for_each(|&value| tag.extend_from_slice(&s15_fixed16(value)));
+    tag
+}
+
+fn mluc_tag_data(text: &str) -> Vec<u8> {
+    let units: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    let mut tag = b"mluc\0\0\0\0".to_vec();
+    for value in [1, 12] {
+        tag.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    tag.extend_from_slice(b"enUS");
+    tag.extend_from_slice(&(units.len() as u32).to_be_bytes());
+    tag.extend_from_slice(&28u32.to_be_bytes());
+    tag.extend_from_slice(&units);
+    tag
+}
+
+// A v4 display profile for RGB primaries and a white point given as xy chromaticities, with the
+// sRGB tone curve. Colorants are adapted to D50 with Bradford, as the spec asks.
+pub fn display_profile(description: &str, primaries: [[f64; 2]; 3], white: [f64; 2]) -> std::result::Result<Vec<u8>, String> {
+    let columns = primaries.map(xy_to_xyz);
+    let primaries_matrix: Matrix = [0, 1, 2].map(|row| columns.map(|column| column[row]));
+    let scale = apply(&invert(&primaries_matrix).ok_or("the primaries don't span a color space")?, xy_to_xyz(white));
+    let rgb_to_xyz: Matrix = primaries_matrix.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]]);
+    let chad = bradford_to_d50(xy_to_xyz(white)).ok_or("the white point can't be adapted to D50")?;
+    let colorants = multiply(&chad, &rgb_to_xyz);
+
+    // IEC 61966-2-1, as a parametric curve of type 3
+    let mut trc = b"para\0\0\0\0\0\x03\0\0".to_vec();
+    for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+        trc.extend_from_slice(&s15_fixed16(value));
+    }
+    let mut chad_tag = b"sf32\0\0\0\0".to_vec();
+    chad.iter().flatten().for_each(|&value| chad_tag.extend_from_slice(&s15_fixed16(value)));
+
+    let mut tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", mluc_tag_data(description)),
+        (b"cprt", mluc_tag_data("No copyright, use freely")),
+        (b"wtpt", xyz_tag_data(D50)),
+        (b"chad", chad_tag),
+    ];
+    for (signature, column) in [(b"rXYZ", 0), (b"gXYZ", 1), (b"bXYZ", 2)] {
+        tags.push((signature, xyz_tag_data([0, 1, 2].map(|row| colorants[row][column]))));
+    }
+    for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+        tags.push((signature, trc.clone()));
+    }
+
+    let mut data = vec![0u8; ICC_HEADER_SIZE];
+    data[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+    data[12..24].copy_from_slice(b"mntrRGB XYZ ");
+    data[36..44].copy_from_slice(b"acspMSFT");
+    data[68..80].copy_from_slice(&xyz_tag_data(D50)[8..]);
+    data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+
+    // Tag data follows the table, each starting on a 4 byte boundary
+    let mut offset = data.len() + tags.len() * 12;
+    let mut tag_data = Vec::new();
+    for (signature, tag) in &tags {
+        data.extend_from_slice(*signature);
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        let padded = tag.len().next_multiple_of(4);
+        tag_data.extend_from_slice(tag);
+        tag_data.resize(tag_data.len() + padded - tag.len(), 0);
+        offset += padded;
+    }
+    data.extend_from_slice(&tag_data);
+    let size = data.len() as u32;
+    data[0..4].copy_from_slice(&size.to_be_bytes());
+    Ok(data)
+}
+
+//==============================================================================
+// VCGT (video card gamma table)
+//==============================================================================
+
+// Per-channel 256 entry ramps, ready for SetDeviceGammaRamp
+pub type GammaRamp = [[u16; 256]; 3];
+
+// The vcgt tag holds either a sampled table or a gamma/min/max formula per channel
+pub fn parse_vcgt(tag: &[u8]) -> Option<GammaRamp> {
+    if tag.get(0..4)? != TAG_VCGT {
+        return None;
+    }
+
+    let mut ramp = [[0u16; 256]; 3];
+
+    match read_u32(tag, 8)? {
+        0 => {
+            let channels = read_u16(tag, 12)? as usize;
+            let entry_count = read_u16(tag, 14)? as usize;
+            let entry_size = read_u16(tag, 16)? as usize;
+            if entry_count < 2 || !(entry_size == 1 || entry_size == 2) {
+                error!("Unsupported vcgt table: {} entries of {} bytes", entry_count, entry_size);
+                return None;
+            }
+
+            let read_entry = |channel: usize, index: usize| -> Option<f64> {
+                let offset = 18 + (channel * entry_count + index) * entry_size;
+                if entry_size == 1 {
+                    tag.get(offset).map(|&v| v as f64 / 255.0)
+                } else {
+                    read_u16(tag, offset).map(|v| v as f64 / 65535.0)
+                }
+            };
+
+            for (channel, channel_ramp) in ramp.iter_mut().enumerate() {
+                // A single channel table applies to all three
+                let source_channel = if channels == 1 { 0 } else { channel };
+
+                for (i, value) in channel_ramp.iter_mut().enumerate() {
+                    // Resample with linear interpolation to 256 entries
+                    let position = i as f64 * (entry_count - 1) as f64 / 255.0;
+                    let lower = position.floor() as usize;
+                    let upper = (lower + 1).min(entry_count - 1);
+                    let fraction = position - lower as f64;
+                    let sample = read_entry(source_channel, lower)? * (1.0 - fraction)
+                        + read_entry(source_channel, upper)? * fraction;
+                    *value = (sample.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                }
+            }
+        }
+        1 => {
+            for (channel, channel_ramp) in ramp.iter_mut().enumerate() {
+                let offset = 12 + channel * 12;
+                let gamma = read_s15_fixed16(tag, offset)?;
+                let min = read_s15_fixed16(tag, offset + 4)?;
+                let max = read_s15_fixed16(tag, offset + 8)?;
+
+                for (i, value) in channel_ramp.iter_mut().enumerate() {
+                    let sample = min + (max - min) * (i as f64 / 255.0).powf(gamma);
+                    *value = (sample.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                }
+            }
+        }
+        other => {
+            error!("Unknown vcgt type {}", other);
+            return None;
+        }
+    }
+
+    Some(ramp)
+}
+
+pub fn load_vcgt_from_profile(name_or_path: &str) -> Option<GammaRamp> {
+    let path = resolve_profile_path(name_or_path);
+    info!("Loading vcgt from {}", path.display());
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to read ICC profile {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let Some(tag) = find_tag(&data, TAG_VCGT) else {
+        error!("ICC profile {} has no vcgt tag", path.display());
+        return None;
+    };
+
+    parse_vcgt(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A header, the tag table and 4 bytes of data per tag
+    fn profile_with_tags(tags: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = vec![0u8; ICC_HEADER_SIZE];
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let data_start = ICC_HEADER_SIZE + 4 + tags.len() * 12;
+        for (index, tag) in tags.iter().enumerate() {
+            data.extend_from_slice(*tag);
+            data.extend_from_slice(&((data_start + index * 4) as u32).to_be_bytes());
+            data.extend_from_slice(&4u32.to_be_bytes());
+        }
+        for tag in tags {
+            data.extend_from_slice(*tag);
+        }
+        data
+    }
+
+    #[test]
+    fn mhc2_marks_hdr_calibration() {
+        assert!(is_hdr_calibration(&profile_with_tags(&[b"desc", b"MHC2", b"lumi"])));
+        assert!(!is_hdr_calibration(&profile_with_tags(&[b"desc", b"rXYZ", TAG_VCGT])));
+        assert_eq!(find_tag(&profile_with_tags(&[b"desc", b"MHC2"]), TAG_MHC2), Some(&b"MHC2"[..]));
+
+        let mut truncated = profile_with_tags(&[b"desc", b"MHC2"]);
+        truncated.truncate(ICC_HEADER_SIZE + 10);
+        assert!(!is_hdr_calibration(&truncated));
+    }
+
+    #[test]
+    fn profile_info_reads_the_header_and_tags() {
+        let mut data = profile_with_tags(&[b"desc", b"wtpt", b"dmdd", TAG_VCGT]);
+        data[8..10].copy_from_slice(&[4, 0x30]);
+        data[12..24].copy_from_slice(b"mntrRGB XYZ ");
+        for (index, field) in [2024u16, 3, 9, 14, 5, 0].iter().enumerate() {
+            data[24 + index * 2..26 + index * 2].copy_from_slice(&field.to_be_bytes());
+        }
+        data[36..40].copy_from_slice(b"acsp");
+        data[48..52].copy_from_slice(b"GSM ");
+
+        // Point desc, wtpt and dmdd at real tag data appended after the placeholders
+        for (index, tag) in [mluc_tag_data("LG TV SSCR2 HDR"), xyz_tag_data(D50), mluc_tag_data("LG TV SSCR2")].into_iter().enumerate() {
+            let entry = ICC_HEADER_SIZE + 4 + index * 12;
+            let offset = data.len() as u32;
+            data[entry + 4..entry + 8].copy_from_slice(&offset.to_be_bytes());
+            data[entry + 8..entry + 12].copy_from_slice(&(tag.len() as u32).to_be_bytes());
+            data.extend_from_slice(&tag);
+        }
+        let size = data.len() as u32;
+        data[0..4].copy_from_slice(&size.to_be_bytes());
+
+        let info = parse_profile_info(&data).unwrap();
+        assert_eq!(info.size, size);
+        assert_eq!(info.version, "4.3.0");
+        assert_eq!(info.device_class, "display");
+        assert_eq!((info.color_space.as_str(), info.connection_space.as_str()), ("RGB", "XYZ"));
+        assert_eq!(info.created.as_deref(), Some("2024-03-09 14:05:00 UTC"));
+        assert_eq!(info.manufacturer.as_deref(), Some("GSM"));
+        assert_eq!(info.model, None);
+        assert_eq!(info.description.as_deref(), Some("LG TV SSCR2 HDR"));
+        assert_eq!(info.device_model.as_deref(), Some("LG TV SSCR2"));
+        let white = info.white_point.unwrap();
+        assert!((white[0] - 0.9642).abs() < 1e-4 && (white[2] - 0.8249).abs() < 1e-4);
+        assert!(info.has_vcgt && !info.has_mhc2);
+        assert_eq!(info.tags, ["desc", "wtpt", "dmdd", "vcgt"]);
+
+        assert_eq!(check_profile(&data), Ok(()));
+        assert!(check_profile(&data[..data.len() - 1]).unwrap_err().contains("truncated"));
+        assert!(check_profile(&data[..64]).unwrap_err().contains("too short"));
+        data[36] = b'x';
+        assert!(parse_profile_info(&data).unwrap_err().contains("acsp"));
+    }
+
+    #[test]
+    fn srgb_primaries_give_the_srgb_colorants() {
+        let data = display_profile("sRGB", [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]], [0.3127, 0.3290]).unwrap();
+        let info = parse_profile_info(&data).unwrap();
+        assert_eq!(info.description.as_deref(), Some("sRGB"));
+        assert_eq!((info.device_class.as_str(), info.version.as_str()), ("display", "4.3.0"));
+        assert_eq!(info.size as usize, data.len());
+
+        // The published D50 colorants of sRGB, to the precision of s15Fixed16 and rounding
+        let colorant = |sig| xyz_tag(find_tag(&data, sig).unwrap()).unwrap();
+        for (sig, expected) in [(b"rXYZ", [0.4361, 0.2225, 0.0139]), (b"gXYZ", [0.3851, 0.7169, 0.0971]), (b"bXYZ", [0.1431, 0.0606, 0.7141])] {
+            let actual = colorant(sig);
+            assert!((0..3).all(|i| (actual[i] - expected[i]).abs() < 5e-4), "{:?} {:?}", actual, expected);
+        }
+        assert!(display_profile("flat", [[0.3, 0.3], [0.3, 0.3], [0.3, 0.3]], [0.3127, 0.3290]).is_err());
+    }
+}
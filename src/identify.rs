@@ -0,0 +1,385 @@
+use windows::Win32::Foundation::{COLORREF, ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint,
+    CreateFontW,
+    CreateSolidBrush,
+    DeleteObject,
+    DrawTextW,
+    EndPaint,
+    FillRect,
+    SelectObject,
+    SetBkMode,
+    SetTextColor,
+    DT_CENTER,
+    DT_LEFT,
+    DT_SINGLELINE,
+    DT_VCENTER,
+    FW_BOLD,
+    FW_NORMAL,
+    PAINTSTRUCT,
+    TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::HiDpi::{SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW,
+    DefWindowProcW,
+    DestroyWindow,
+    DispatchMessageW,
+    GetClientRect,
+    GetMessageW,
+    GetWindowTextW,
+    PostQuitMessage,
+    RegisterClassW,
+    SetLayeredWindowAttributes,
+    SetTimer,
+    ShowWindow,
+    TranslateMessage,
+    HMENU,
+    LWA_ALPHA,
+    MSG,
+    SW_SHOWNOACTIVATE,
+    WM_PAINT,
+    WM_TIMER,
+    WNDCLASSW,
+    WS_EX_LAYERED,
+    WS_EX_NOACTIVATE,
+    WS_EX_TOOLWINDOW,
+    WS_EX_TOPMOST,
+    WS_POPUP,
+};
+use windows::core::{w, PCWSTR};
+use tracing::{info, error};
+
+use crate::change_icc_profile;
+use crate::displays_info::{enumerate_displays, DisplayDevice};
+use crate::hdr;
+use crate::set_sdr_level;
+
+const OVERLAY_WIDTH: i32 = 480;
+const OVERLAY_HEIGHT: i32 = 300;
+const OVERLAY_MARGIN: i32 = 48;
+
+const READOUT_WIDTH: i32 = 560;
+const READOUT_HEIGHT: i32 = 200;
+const READOUT_PADDING: i32 = 20;
+
+//==============================================================================
+// Overlay window
+//==============================================================================
+
+// The window title holds "<index>\n<name>" so the paint handler doesn't need any shared state
+extern "system" fn overlay_window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_PAINT => {
+                paint_overlay(hwnd);
+                LRESULT(0)
+            }
+            WM_TIMER => {
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+unsafe fn paint_overlay(hwnd: HWND) {
+    let mut title = [0u16; 256];
+    let len = GetWindowTextW(hwnd, &mut title) as usize;
+    let title = String::from_utf16_lossy(&title[..len]);
+    let (number, name) = title.split_once('\n').unwrap_or((&title, ""));
+
+    let mut paint = PAINTSTRUCT::default();
+    let hdc = BeginPaint(hwnd, &mut paint);
+
+    let mut client = RECT::default();
+    let _ = GetClientRect(hwnd, &mut client);
+
+    let background = CreateSolidBrush(COLORREF(0x00302010));
+    FillRect(hdc, &client, background);
+    let _ = DeleteObject(background);
+
+    SetBkMode(hdc, TRANSPARENT);
+    SetTextColor(hdc, COLORREF(0x00FFFFFF));
+
+    let number_font = CreateFontW(180, 0, 0, 0, FW_BOLD.0 as i32, 0, 0, 0, 0, 0, 0, 0, 0, w!("Segoe UI"));
+    let name_font = CreateFontW(28, 0, 0, 0, FW_NORMAL.0 as i32, 0, 0, 0, 0, 0, 0, 0, 0, w!("Segoe UI"));
+
+    let mut number_rect = RECT { bottom: client.bottom - 64, ..client };
+    let mut name_rect = RECT { top: client.bottom - 72, bottom: client.bottom - 16, ..client };
+
+    let previous_font = SelectObject(hdc, number_font);
+    let mut number_text: Vec<u16> = number.encode_utf16().collect();
+    DrawTextW(hdc, &mut number_text, &mut number_rect, DT_CENTER | DT_VCENTER | DT_SINGLELINE);
+
+    SelectObject(hdc, name_font);
+    let mut name_text: Vec<u16> = name.encode_utf16().collect();
+    DrawTextW(hdc, &mut name_text, &mut name_rect, DT_CENTER | DT_VCENTER | DT_SINGLELINE);
+
+    SelectObject(hdc, previous_font);
+    let _ = DeleteObject(number_font);
+    let _ = DeleteObject(name_font);
+    let _ = EndPaint(hwnd, &paint);
+}
+
+//==============================================================================
+// Settings readout
+//==============================================================================
+
+// The readout's lines are its window title too
+extern "system" fn readout_window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_PAINT => {
+                paint_readout(hwnd);
+                LRESULT(0)
+            }
+            WM_TIMER => {
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+unsafe fn paint_readout(hwnd: HWND) {
+    let mut title = [0u16; 512];
+    let len = GetWindowTextW(hwnd, &mut title) as usize;
+
+    let mut paint = PAINTSTRUCT::default();
+    let hdc = BeginPaint(hwnd, &mut paint);
+
+    let mut client = RECT::default();
+    let _ = GetClientRect(hwnd, &mut client);
+
+    let background = CreateSolidBrush(COLORREF(0x00302010));
+    FillRect(hdc, &client, background);
+    let _ = DeleteObject(background);
+
+    SetBkMode(hdc, TRANSPARENT);
+    SetTextColor(hdc, COLORREF(0x00FFFFFF));
+
+    let font = CreateFontW(30, 0, 0, 0, FW_NORMAL.0 as i32, 0, 0, 0, 0, 0, 0, 0, 0, w!("Segoe UI"));
+    let previous_font = SelectObject(hdc, font);
+    let mut text_rect = RECT {
+        left: client.left + READOUT_PADDING,
+        top: client.top + READOUT_PADDING,
+        right: client.right - READOUT_PADDING,
+        bottom: client.bottom - READOUT_PADDING,
+    };
+    DrawTextW(hdc, &mut title[..len], &mut text_rect, DT_LEFT);
+
+    SelectObject(hdc, previous_font);
+    let _ = DeleteObject(font);
+    let _ = EndPaint(hwnd, &paint);
+}
+
+// What the readout shows, read back from the display rather than what was asked for
+pub fn readout_text(display: &DisplayDevice) -> String {
+    let name = display.get_target_device_name()
+        .map(|target| target.friendly_name)
+        .filter(|name| !name.is_empty())
+        .map(|name| format!("{} - {}", display.device_name.trim_start_matches("\\\\.\\"), name))
+        .unwrap_or_else(|| display.device_name.trim_start_matches("\\\\.\\").to_string());
+    let mode = format!("{}x{} @ {} Hz", display.current_resolution.0, display.current_resolution.1, display.current_refresh_rate);
+    let hdr = match hdr::get_advanced_color_info(display) {
+        Ok(info) if info.enabled => match set_sdr_level::get_display_sdr_white_raw(display) {
+            Ok(raw) => format!("HDR on, SDR white {} nits", set_sdr_level::raw_to_nits(raw)),
+            Err(_) => "HDR on".to_string(),
+        },
+        Ok(info) if info.supported => "HDR off".to_string(),
+        _ => "No HDR".to_string(),
+    };
+    let profile = change_icc_profile::get_display_default_icc_profile(display).unwrap_or_else(|| "none".to_string());
+    format!("{}\n{}\n{}\nICC profile: {}", name, mode, hdr, profile)
+}
+
+// A readout of the display's mode, HDR state, SDR white level and ICC profile in its top-right
+// corner, to check a change through the stream
+pub fn show_settings_readout(device: &DisplayDevice, duration_secs: u32) -> windows::core::Result<()> {
+    let text = readout_text(device);
+    let text_wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+
+    unsafe {
+        if let Err(e) = SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) {
+            info!("Could not set DPI awareness, the readout may be misplaced: {}", e);
+        }
+
+        let instance = GetModuleHandleW(PCWSTR::null())?;
+        let class_name = w!("SunshineHelperReadout");
+        let window_class = WNDCLASSW {
+            lpfnWndProc: Some(readout_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        // A `run` script can show the readout more than once
+        if RegisterClassW(&window_class) == 0 {
+            let error = windows::core::Error::from_win32();
+            if error.code() != ERROR_CLASS_ALREADY_EXISTS.to_hresult() {
+                error!("Failed to register readout window class: {}", error);
+                return Err(error);
+            }
+        }
+
+        let x = device.position.0 + device.current_resolution.0 as i32 - READOUT_WIDTH - OVERLAY_MARGIN;
+        info!("Showing the settings readout on {}", device.device_name);
+        let hwnd = CreateWindowExW(
+            WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_LAYERED | WS_EX_NOACTIVATE,
+            class_name,
+            PCWSTR::from_raw(text_wide.as_ptr()),
+            WS_POPUP,
+            x,
+            device.position.1 + OVERLAY_MARGIN,
+            READOUT_WIDTH,
+            READOUT_HEIGHT,
+            HWND::default(),
+            HMENU::default(),
+            instance,
+            None,
+        )?;
+
+        let _ = SetLayeredWindowAttributes(hwnd, COLORREF(0), 220, LWA_ALPHA);
+        let _ = ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+        SetTimer(hwnd, 1, timer_millis(duration_secs), None);
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+        let _ = DestroyWindow(hwnd);
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Helper functions for CLI commands
+//==============================================================================
+
+// At least a second, and a --duration too long for SetTimer's milliseconds runs as long as it can
+fn timer_millis(duration_secs: u32) -> u32 {
+    duration_secs.max(1).saturating_mul(1000)
+}
+
+// Show each display's index and name in the top-left corner of that display for a few seconds
+pub fn identify_displays(duration_secs: u32) -> windows::core::Result<()> {
+    let displays = enumerate_displays();
+    if displays.is_empty() {
+        error!("No displays found to identify");
+        return Err(windows::core::Error::from_win32());
+    }
+
+    unsafe {
+        // Display positions are physical pixels, so opt out of DPI virtualization
+        if let Err(e) = SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) {
+            info!("Could not set DPI awareness, overlays may be misplaced: {}", e);
+        }
+
+        let instance = GetModuleHandleW(PCWSTR::null())?;
+        let class_name = w!("SunshineHelperIdentify");
+
+        let window_class = WNDCLASSW {
+            lpfnWndProc: Some(overlay_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+
+        if RegisterClassW(&window_class) == 0 {
+            let error = windows::core::Error::from_win32();
+            error!("Failed to register overlay window class: {}", error);
+            return Err(error);
+        }
+
+        let mut windows = Vec::new();
+        for device in &displays {
+            let label = match device.get_target_device_name() {
+                Some(target) if !target.friendly_name.is_empty() => {
+                    format!("{}\n{} - {}", device.device_index, device.device_name, target.friendly_name)
+                }
+                _ => format!("{}\n{}", device.device_index, device.device_name),
+            };
+            let label_wide: Vec<u16> = label.encode_utf16().chain(std::iter::once(0)).collect();
+
+            info!("Showing identify overlay on {} at ({}, {})", device.device_name, device.position.0, device.position.1);
+
+            let hwnd = match CreateWindowExW(
+                WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_LAYERED | WS_EX_NOACTIVATE,
+                class_name,
+                PCWSTR::from_raw(label_wide.as_ptr()),
+                WS_POPUP,
+                device.position.0 + OVERLAY_MARGIN,
+                device.position.1 + OVERLAY_MARGIN,
+                OVERLAY_WIDTH,
+                OVERLAY_HEIGHT,
+                HWND::default(),
+                HMENU::default(),
+                instance,
+                None,
+            ) {
+                Ok(hwnd) => hwnd,
+                Err(e) => {
+                    error!("Failed to create overlay window for {}: {}", device.device_name, e);
+                    continue;
+                }
+            };
+
+            let _ = SetLayeredWindowAttributes(hwnd, COLORREF(0), 220, LWA_ALPHA);
+            let _ = ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+            windows.push(hwnd);
+        }
+
+        let Some(&first_window) = windows.first() else {
+            return Err(windows::core::Error::from_win32());
+        };
+
+        // One timer is enough, it ends the message loop for every overlay
+        SetTimer(first_window, 1, timer_millis(duration_secs), None);
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+
+        for hwnd in windows {
+            let _ = DestroyWindow(hwnd);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::displays_info::find_display;
+
+    #[test]
+    fn readout_reads_the_display_back() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.icc_profile = Some("HDR TV.icc".to_string());
+        tv.icc_profiles = vec!["HDR TV.icc".to_string()];
+        MockDisplayApi::install(vec![tv]);
+
+        let text = readout_text(&find_display("primary").unwrap());
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "DISPLAY1 - LG TV SSCR2");
+        assert_eq!(lines[1], "3840x2160 @ 120 Hz");
+        assert!(lines[2].starts_with("HDR on, SDR white "), "{}", lines[2]);
+        assert_eq!(lines[3], "ICC profile: HDR TV.icc");
+    }
+
+    #[test]
+    fn long_durations_dont_overflow_the_timer() {
+        assert_eq!(timer_millis(0), 1000);
+        assert_eq!(timer_millis(5), 5000);
+        assert_eq!(timer_millis(u32::MAX), u32::MAX);
+    }
+}
@@ -0,0 +1,425 @@
+use std::ffi::{c_void, CString};
+use std::mem::size_of;
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::Foundation::{E_FAIL, HMODULE, LUID};
+use windows::core::{w, Error, Result, PCSTR};
+use tracing::{info, error};
+
+use crate::displays_info::DisplayDevice;
+use crate::gpu_vendor::{ColorFormat, PixelFormat, ScalingMode};
+
+// Intel Graphics Control Library, exported by name from ControlLib.dll which ships with the
+// Arc and Xe drivers. Constants and struct layouts are from igcl_api.h.
+const CTL_RESULT_SUCCESS: u32 = 0;
+const CTL_VERSION: u32 = 1 << 16 | 1; // CTL_MAKE_VERSION(1, 1)
+
+const DISPLAY_CONFIG_FLAG_ACTIVE: u32 = 0x1;
+
+const WIRE_FORMAT_GET: u32 = 0;
+const WIRE_FORMAT_SET: u32 = 1;
+
+const COLOR_MODEL_RGB: u32 = 0;
+const COLOR_MODEL_YCBCR_420: u32 = 1;
+const COLOR_MODEL_YCBCR_422: u32 = 2;
+const COLOR_MODEL_YCBCR_444: u32 = 3;
+
+// ctl_output_bpc_flags_t bit n is BITS_PER_COLOR[n]
+const BITS_PER_COLOR: [u32; 4] = [6, 8, 10, 12];
+
+const SCALING_IDENTITY: u32 = 0x1;
+const SCALING_CENTERED: u32 = 0x2;
+const SCALING_STRETCHED: u32 = 0x4;
+const SCALING_ASPECT_RATIO_CENTERED_MAX: u32 = 0x8;
+
+type Handle = *mut c_void;
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+#[repr(C)]
+struct CtlInitArgs {
+    size: u32,
+    version: u8,
+    app_version: u32,
+    flags: u32,
+    supported_version: u32,
+    application_uid: [u32; 4],
+}
+
+#[repr(C)]
+struct CtlDeviceAdapterProperties {
+    size: u32,
+    version: u8,
+    device_id: *mut c_void, // caller provided LUID
+    device_id_size: u32,
+    device_type: u32,
+    supported_subfunction_flags: u32,
+    driver_version: u64,
+    firmware_version: [u64; 3],
+    pci_vendor_id: u32,
+    pci_device_id: u32,
+    rev_id: u32,
+    num_eus_per_sub_slice: u32,
+    num_sub_slices_per_slice: u32,
+    num_slices: u32,
+    name: [u8; 100],
+    graphics_adapter_properties: u32,
+    frequency: u32,
+    pci_subsys_id: u16,
+    pci_subsys_vendor_id: u16,
+    adapter_bdf: [u8; 3],
+    reserved: [u8; 112],
+}
+
+// ctl_os_display_encoder_identifier_t, a union of the Windows target ID and a generic pointer + size
+#[repr(C)]
+struct CtlOsDisplayEncoderId {
+    windows_display_encoder_id: u32,
+    _padding: u32,
+    _generic_size: u64,
+}
+
+#[repr(C)]
+struct CtlDisplayTiming {
+    size: u32,
+    version: u8,
+    pixel_clock: u64,
+    h_active: u32,
+    v_active: u32,
+    h_total: u32,
+    v_total: u32,
+    h_blank: u32,
+    v_blank: u32,
+    h_sync: u32,
+    v_sync: u32,
+    refresh_rate: f32,
+    signal_standard: u32,
+    vic_id: u8,
+}
+
+#[repr(C)]
+struct CtlDisplayProperties {
+    size: u32,
+    version: u8,
+    os_display_encoder: CtlOsDisplayEncoderId,
+    output_type: u32,
+    attached_display_mux_type: u32,
+    mux_attached_type: u32,
+    display_config_flags: u32,
+    feature_enabled_flags: u32,
+    feature_supported_flags: u32,
+    advanced_feature_enabled_flags: u32,
+    advanced_feature_supported_flags: u32,
+    timing: CtlDisplayTiming,
+    reserved: [u32; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CtlWireFormat {
+    color_model: u32,
+    color_depth: u32, // ctl_output_bpc_flags_t, a single bit when it describes the current format
+}
+
+#[repr(C)]
+struct CtlWireFormatConfig {
+    size: u32,
+    version: u8,
+    operation: u32,
+    supported: [CtlWireFormat; 4],
+    wire_format: CtlWireFormat,
+    reserved: [u32; 16],
+}
+
+#[repr(C)]
+struct CtlScalingSettings {
+    size: u32,
+    version: u8,
+    enable: bool,
+    scaling_type: u32,
+    custom_scaling_x: u32,
+    custom_scaling_y: u32,
+    hardware_mode_set: bool,
+    preferred_scaling_type: u32,
+}
+
+fn sized<T>() -> T {
+    // All IGCL structs are plain data that starts with a u32 Size
+    let mut value: T = unsafe { std::mem::zeroed() };
+    unsafe { *(&mut value as *mut T as *mut u32) = size_of::<T>() as u32 };
+    value
+}
+
+fn bits_from_flag(flag: u32) -> Option<u32> {
+    BITS_PER_COLOR.iter().enumerate().find(|(bit, _)| flag == 1 << bit).map(|(_, &bits)| bits)
+}
+
+fn flag_from_bits(bits_per_color: u32) -> Option<u32> {
+    BITS_PER_COLOR.iter().position(|&bits| bits == bits_per_color).map(|bit| 1 << bit)
+}
+
+//==============================================================================
+// Session
+//==============================================================================
+
+// An IGCL API handle, closed on drop
+struct IgclSession {
+    module: HMODULE,
+    api: Handle,
+}
+
+impl IgclSession {
+    fn open() -> Result<Self> {
+        let module = unsafe { LoadLibraryW(w!("ControlLib.dll")) }
+            .map_err(|_| Error::new(E_FAIL, "IGCL is not available (no Intel graphics driver installed?)"))?;
+
+        let mut session = IgclSession { module, api: std::ptr::null_mut() };
+        let mut args: CtlInitArgs = sized();
+        args.app_version = CTL_VERSION;
+
+        let mut api = std::ptr::null_mut();
+        unsafe {
+            let init: unsafe extern "C" fn(*mut CtlInitArgs, *mut Handle) -> u32 = session.function("ctlInit")?;
+            session.check(init(&mut args, &mut api), "ctlInit")?;
+        }
+        session.api = api;
+
+        info!("Loaded IGCL {}.{}", args.supported_version >> 16, args.supported_version & 0xFFFF);
+        Ok(session)
+    }
+
+    // F must be the unsafe extern "C" fn type matching the function's IGCL signature
+    unsafe fn function<F: Copy>(&self, name: &str) -> Result<F> {
+        let name_c = CString::new(name).map_err(|_| Error::new(E_FAIL, "Invalid IGCL function name"))?;
+        match GetProcAddress(self.module, PCSTR::from_raw(name_c.as_ptr() as *const u8)) {
+            Some(pointer) => Ok(std::mem::transmute_copy(&pointer)),
+            None => Err(Error::new(E_FAIL, format!("{} is not available in this Intel driver", name))),
+        }
+    }
+
+    fn check(&self, status: u32, what: &str) -> Result<()> {
+        if status == CTL_RESULT_SUCCESS {
+            return Ok(());
+        }
+        error!("{} failed: {:#x}", what, status);
+        Err(Error::new(E_FAIL, format!("{} failed with IGCL error {:#x}", what, status)))
+    }
+
+    // Calls an IGCL enumerator twice, once for the count and once for the handles
+    fn enumerate(&self, name: &str, parent: Handle) -> Result<Vec<Handle>> {
+        unsafe {
+            let enumerate: unsafe extern "C" fn(Handle, *mut u32, *mut Handle) -> u32 = self.function(name)?;
+            let mut count = 0;
+            self.check(enumerate(parent, &mut count, std::ptr::null_mut()), name)?;
+
+            let mut handles = vec![std::ptr::null_mut(); count as usize];
+            self.check(enumerate(parent, &mut count, handles.as_mut_ptr()), name)?;
+            handles.truncate(count as usize);
+            Ok(handles)
+        }
+    }
+
+    fn find_adapter(&self, adapter_id: LUID) -> Result<Handle> {
+        for device in self.enumerate("ctlEnumerateDevices", self.api)? {
+            let mut luid = LUID::default();
+            let mut properties: CtlDeviceAdapterProperties = sized();
+            properties.device_id = &mut luid as *mut LUID as *mut c_void;
+            properties.device_id_size = size_of::<LUID>() as u32;
+
+            unsafe {
+                let get_properties: unsafe extern "C" fn(Handle, *mut CtlDeviceAdapterProperties) -> u32 =
+                    self.function("ctlGetDeviceProperties")?;
+                self.check(get_properties(device, &mut properties), "ctlGetDeviceProperties")?;
+            }
+
+            if luid.LowPart == adapter_id.LowPart && luid.HighPart == adapter_id.HighPart {
+                return Ok(device);
+            }
+        }
+
+        Err(Error::new(E_FAIL, "IGCL does not know the display's adapter"))
+    }
+
+    // IGCL's Windows encoder ID is the display config target ID. Fall back to the only active
+    // output of the adapter when the driver doesn't fill it in.
+    fn find_output(&self, display: &DisplayDevice) -> Result<Handle> {
+        let adapter = self.find_adapter(display.adapter_id)?;
+        let mut active_outputs = Vec::new();
+
+        for output in self.enumerate("ctlEnumerateDisplayOutputs", adapter)? {
+            let mut properties: CtlDisplayProperties = sized();
+            unsafe {
+                let get_properties: unsafe extern "C" fn(Handle, *mut CtlDisplayProperties) -> u32 =
+                    self.function("ctlGetDisplayProperties")?;
+                self.check(get_properties(output, &mut properties), "ctlGetDisplayProperties")?;
+            }
+
+            if properties.display_config_flags & DISPLAY_CONFIG_FLAG_ACTIVE == 0 {
+                continue;
+            }
+            if properties.os_display_encoder.windows_display_encoder_id == display.target_id {
+                return Ok(output);
+            }
+            active_outputs.push(output);
+        }
+
+        match active_outputs.as_slice() {
+            [output] => Ok(*output),
+            _ => Err(Error::new(E_FAIL, format!("Could not match {} to an IGCL display output", display.device_name))),
+        }
+    }
+
+    fn wire_format(&self, output: Handle, config: &mut CtlWireFormatConfig) -> Result<()> {
+        unsafe {
+            let get_set: unsafe extern "C" fn(Handle, *mut CtlWireFormatConfig) -> u32 = self.function("ctlGetSetWireFormat")?;
+            self.check(get_set(output, config), "ctlGetSetWireFormat")
+        }
+    }
+
+    fn get_wire_format(&self, output: Handle) -> Result<CtlWireFormatConfig> {
+        let mut config: CtlWireFormatConfig = sized();
+        config.operation = WIRE_FORMAT_GET;
+        self.wire_format(output, &mut config)?;
+        Ok(config)
+    }
+}
+
+impl Drop for IgclSession {
+    fn drop(&mut self) {
+        if self.api.is_null() {
+            return;
+        }
+        unsafe {
+            if let Ok(close) = self.function::<unsafe extern "C" fn(Handle) -> u32>("ctlClose") {
+                close(self.api);
+            }
+        }
+    }
+}
+
+//==============================================================================
+// Output color format
+//==============================================================================
+
+pub fn get_color_format(display: &DisplayDevice) -> Result<ColorFormat> {
+    let session = IgclSession::open()?;
+    let output = session.find_output(display)?;
+    let current = session.get_wire_format(output)?.wire_format;
+
+    let pixel_format = match current.color_model {
+        COLOR_MODEL_RGB => PixelFormat::Rgb,
+        COLOR_MODEL_YCBCR_444 => PixelFormat::Ycc444,
+        COLOR_MODEL_YCBCR_422 => PixelFormat::Ycc422,
+        COLOR_MODEL_YCBCR_420 => PixelFormat::Ycc420,
+        other => return Err(Error::new(E_FAIL, format!("Unknown Intel color model {}", other))),
+    };
+
+    Ok(ColorFormat { pixel_format, bits_per_color: bits_from_flag(current.color_depth).unwrap_or(0) })
+}
+
+// The driver only takes a complete wire format, so fill in whichever part isn't changing
+pub fn set_color_format(device: &DisplayDevice, pixel_format: Option<PixelFormat>, bits_per_color: Option<u32>) -> Result<()> {
+    let session = IgclSession::open()?;
+    let output = session.find_output(device)?;
+    let mut config = session.get_wire_format(output)?;
+
+    if let Some(pixel_format) = pixel_format {
+        config.wire_format.color_model = match pixel_format {
+            PixelFormat::Rgb => COLOR_MODEL_RGB,
+            PixelFormat::Ycc444 => COLOR_MODEL_YCBCR_444,
+            PixelFormat::Ycc422 => COLOR_MODEL_YCBCR_422,
+            PixelFormat::Ycc420 => COLOR_MODEL_YCBCR_420,
+        };
+    }
+    if let Some(bits_per_color) = bits_per_color {
+        let Some(flag) = flag_from_bits(bits_per_color) else {
+            return Err(Error::new(E_FAIL, format!("{} bits per color is not one of {:?}", bits_per_color, BITS_PER_COLOR)));
+        };
+        config.wire_format.color_depth = flag;
+    }
+
+    let supported = config.supported.iter()
+        .any(|format| format.color_model == config.wire_format.color_model && format.color_depth & config.wire_format.color_depth != 0);
+    if !supported {
+        return Err(Error::new(E_FAIL, format!("{} does not support this color format at the current mode", device.device_name)));
+    }
+
+    info!("Setting wire format of {} to color model {}, {} bpc",
+        device.device_name, config.wire_format.color_model, bits_from_flag(config.wire_format.color_depth).unwrap_or(0));
+
+    config.operation = WIRE_FORMAT_SET;
+    session.wire_format(output, &mut config)
+}
+
+//==============================================================================
+// HDR
+//==============================================================================
+
+// Intel drivers can keep an 8 bpc wire format when Windows turns HDR on, which leaves the
+// display in HDR with visible banding. Raise it to 10 bpc first when the link allows it.
+pub fn prepare_hdr(device: &DisplayDevice) -> Result<()> {
+    let session = IgclSession::open()?;
+    let output = session.find_output(device)?;
+    let mut config = session.get_wire_format(output)?;
+
+    let current_bits = bits_from_flag(config.wire_format.color_depth).unwrap_or(0);
+    let ten_bit = flag_from_bits(10).unwrap_or(0);
+    let supports_ten_bit = config.supported.iter()
+        .any(|format| format.color_model == config.wire_format.color_model && format.color_depth & ten_bit != 0);
+
+    if current_bits >= 10 || !supports_ten_bit {
+        return Ok(());
+    }
+
+    info!("Raising wire format of {} from {} to 10 bpc before turning HDR on", device.device_name, current_bits);
+    config.wire_format.color_depth = ten_bit;
+    config.operation = WIRE_FORMAT_SET;
+    session.wire_format(output, &mut config)
+}
+
+//==============================================================================
+// Scaling
+//==============================================================================
+
+pub fn get_scaling(display: &DisplayDevice) -> Result<ScalingMode> {
+    let session = IgclSession::open()?;
+    let output = session.find_output(display)?;
+
+    let mut settings: CtlScalingSettings = sized();
+    unsafe {
+        let get_scaling: unsafe extern "C" fn(Handle, *mut CtlScalingSettings) -> u32 = session.function("ctlGetCurrentScaling")?;
+        session.check(get_scaling(output, &mut settings), "ctlGetCurrentScaling")?;
+    }
+
+    if !settings.enable {
+        return Ok(ScalingMode::Identity);
+    }
+    match settings.scaling_type {
+        SCALING_IDENTITY => Ok(ScalingMode::Identity),
+        SCALING_CENTERED => Ok(ScalingMode::Centered),
+        SCALING_STRETCHED => Ok(ScalingMode::Stretched),
+        SCALING_ASPECT_RATIO_CENTERED_MAX => Ok(ScalingMode::AspectRatio),
+        other => Err(Error::new(E_FAIL, format!("Unknown Intel scaling type {:#x}", other))),
+    }
+}
+
+pub fn set_scaling(device: &DisplayDevice, mode: ScalingMode) -> Result<()> {
+    let session = IgclSession::open()?;
+    let output = session.find_output(device)?;
+
+    let mut settings: CtlScalingSettings = sized();
+    settings.enable = true;
+    settings.scaling_type = match mode {
+        ScalingMode::Identity => SCALING_IDENTITY,
+        ScalingMode::Centered => SCALING_CENTERED,
+        ScalingMode::Stretched => SCALING_STRETCHED,
+        ScalingMode::AspectRatio => SCALING_ASPECT_RATIO_CENTERED_MAX,
+    };
+
+    info!("Setting scaling of {} to {:?}", device.device_name, mode);
+    unsafe {
+        let set_scaling: unsafe extern "C" fn(Handle, *mut CtlScalingSettings) -> u32 = session.function("ctlSetCurrentScaling")?;
+        session.check(set_scaling(output, &mut settings), "ctlSetCurrentScaling")
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use windows::Win32::Foundation::{BOOL, ERROR_CANCELLED, FALSE, TRUE};
+use windows::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+use windows::core::Error;
+use tracing::{warn, error};
+
+// Ctrl+C in the middle of `apply` or `run` would otherwise leave the displays half changed. While
+// one of those is in progress the first Ctrl+C only sets a flag: the batch stops before its next
+// step, rolls back what it changed and exits with the interrupted code. A second Ctrl+C, or one
+// while nothing is in progress, ends the process as usual.
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static IN_PROGRESS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "system" fn handle_ctrl(ctrl_type: u32) -> BOOL {
+    if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT {
+        return FALSE;
+    }
+    if IN_PROGRESS.load(Ordering::SeqCst) == 0 || INTERRUPTED.swap(true, Ordering::SeqCst) {
+        return FALSE;
+    }
+    warn!("Interrupted, stopping after the current step and undoing the changes made so far (press Ctrl+C again to quit now)");
+    TRUE
+}
+
+pub fn install() {
+    if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(handle_ctrl), true) } {
+        error!("Failed to install the Ctrl+C handler: {}", e.message());
+    }
+}
+
+// Marks a batch as in progress until dropped
+pub struct InProgress(());
+
+pub fn in_progress() -> InProgress {
+    IN_PROGRESS.fetch_add(1, Ordering::SeqCst);
+    InProgress(())
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        IN_PROGRESS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn interrupted(message: impl AsRef<str>) -> Error {
+    Error::new(ERROR_CANCELLED.to_hresult(), message)
+}
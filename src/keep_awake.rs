@@ -0,0 +1,135 @@
+use windows::Win32::System::Power::{
+    SetThreadExecutionState,
+    ES_CONTINUOUS,
+    ES_DISPLAY_REQUIRED,
+    ES_SYSTEM_REQUIRED,
+};
+use windows::Win32::System::Threading::{
+    CreateEventW,
+    OpenEventW,
+    SetEvent,
+    WaitForSingleObject,
+    CREATE_NO_WINDOW,
+    DETACHED_PROCESS,
+    EVENT_MODIFY_STATE,
+    INFINITE,
+};
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, E_FAIL};
+use windows::core::{w, Error, Result};
+use tracing::info;
+use std::os::windows::process::CommandExt;
+
+use crate::portable;
+
+// A named event doubles as the "already running" check and the stop signal for `keep-awake --stop`
+const STOP_EVENT_NAME: windows::core::PCWSTR = w!("Local\\SunshineHelperKeepAwake");
+
+//==============================================================================
+// Keep awake
+//==============================================================================
+
+// Blocks until the duration elapses or `keep-awake --stop` is run. The execution state
+// belongs to this thread, so Windows drops the request by itself if the process dies.
+pub fn keep_awake(duration_secs: Option<u32>) -> Result<()> {
+    let stop_event = unsafe { CreateEventW(None, true, false, STOP_EVENT_NAME)? };
+    if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+        let _ = unsafe { CloseHandle(stop_event) };
+        return Err(Error::new(E_FAIL, "keep-awake is already running"));
+    }
+
+    match duration_secs {
+        Some(secs) => info!("Keeping the system and displays awake for {} seconds", secs),
+        None => info!("Keeping the system and displays awake until stopped"),
+    }
+
+    hold();
+    unsafe {
+        WaitForSingleObject(stop_event, wait_millis(duration_secs));
+        let _ = CloseHandle(stop_event);
+    }
+    release();
+
+    info!("Released keep-awake request");
+    Ok(())
+}
+
+// Asks Windows to keep the system and displays on until release() is called on the same thread
+pub fn hold() {
+    unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED) };
+}
+
+pub fn release() {
+    unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+}
+
+// INFINITE is u32::MAX, so a very long duration stops just short of it rather than never expiring
+fn wait_millis(duration_secs: Option<u32>) -> u32 {
+    match duration_secs {
+        Some(secs) => secs.saturating_mul(1000).min(INFINITE - 1),
+        None => INFINITE,
+    }
+}
+
+// --duration in seconds, or with an s, m or h suffix like 90m
+pub fn parse_duration(text: &str) -> std::result::Result<u32, String> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last() {
+        Some((index, 's')) => (&text[..index], 1),
+        Some((index, 'm')) => (&text[..index], 60),
+        Some((index, 'h')) => (&text[..index], 3600),
+        _ => (text, 1),
+    };
+    let value: u32 = number.trim().parse().map_err(|_| format!("'{}' isn't a duration, give seconds or a number with s, m or h like 90m", text))?;
+    value.checked_mul(unit).ok_or_else(|| format!("{} is too long a duration", text))
+}
+
+// Returns false when no keep-awake instance was running
+pub fn stop_keep_awake() -> Result<bool> {
+    let Ok(stop_event) = (unsafe { OpenEventW(EVENT_MODIFY_STATE, false, STOP_EVENT_NAME) }) else {
+        return Ok(false);
+    };
+
+    let result = unsafe { SetEvent(stop_event) };
+    let _ = unsafe { CloseHandle(stop_event) };
+    result.map(|()| true)
+}
+
+pub fn spawn_keep_awake(duration_secs: Option<u32>) -> std::io::Result<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    if portable::requested() {
+        command.arg("--portable");
+    }
+    command.arg("keep-awake");
+    if let Some(secs) = duration_secs {
+        command.args(["--duration", &secs.to_string()]);
+    }
+
+    // No console, so Sunshine's prep command returns immediately
+    command.creation_flags((DETACHED_PROCESS | CREATE_NO_WINDOW).0).spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("45m"), Ok(2700));
+        assert_eq!(parse_duration("2h"), Ok(7200));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("-5").is_err());
+        assert!(parse_duration("1d").unwrap_err().contains("isn't a duration"));
+        assert!(parse_duration("2000000h").unwrap_err().contains("too long"));
+    }
+
+    #[test]
+    fn every_duration_expires() {
+        assert_eq!(wait_millis(None), INFINITE);
+        assert_eq!(wait_millis(Some(0)), 0);
+        assert_eq!(wait_millis(Some(90)), 90_000);
+        assert!(wait_millis(Some(u32::MAX)) < INFINITE);
+    }
+}
@@ -0,0 +1,63 @@
+// The helper's modules, shared by the sunshine_helper binary and shh.dll, the C API in ffi.rs.
+// The DLL exports nothing but the shh_* functions.
+
+pub mod displays_info;
+pub mod display_api;
+pub mod change_display_mode;
+pub mod set_sdr_level;
+pub mod change_icc_profile;
+pub mod edid;
+pub mod dxgi_info;
+pub mod identify;
+pub mod state;
+pub mod display_config;
+pub mod dpi_scaling;
+pub mod icc_file;
+pub mod gamma_ramp;
+pub mod registry;
+pub mod registry_mode;
+pub mod night_light;
+pub mod auto_hdr;
+pub mod hdr;
+pub mod ddc_ci;
+pub mod wmi_brightness;
+pub mod brightness;
+pub mod keep_awake;
+pub mod exit_code;
+pub mod logging;
+pub mod portable;
+pub mod event_log;
+pub mod retry;
+pub mod timings;
+pub mod apply;
+pub mod bench;
+pub mod schema;
+pub mod script;
+pub mod self_test;
+pub mod report;
+pub mod srgb_clamp;
+pub mod test_pattern;
+pub mod table;
+pub mod config;
+pub mod sunshine;
+pub mod processes;
+pub mod daemon;
+pub mod autostart;
+pub mod events;
+pub mod setup;
+pub mod completions;
+pub mod vdd;
+pub mod gpu_vendor;
+pub mod windows_version;
+pub mod elevation;
+pub mod session;
+pub mod interrupt;
+#[cfg(feature = "nvapi")]
+pub mod nvapi;
+#[cfg(feature = "adl")]
+pub mod adl;
+#[cfg(feature = "igcl")]
+pub mod igcl;
+#[cfg(feature = "gui")]
+pub mod gui;
+mod ffi;
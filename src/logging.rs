@@ -0,0 +1,243 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::filter::{self, FilterFn};
+use tracing_subscriber::fmt::{self, format::FmtSpan, time::ChronoLocal, MakeWriter};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+use crate::event_log::EventLog;
+use crate::portable;
+
+// Log records go to stderr and, with --log or --log-file, to a file. Both follow one level: error with -q,
+// info by default, debug with -v and trace with -vv, or whatever --log-level says. Stderr
+// only shows warnings and errors unless -v or --log-level asks for more, so a plain
+// invocation prints little besides its result.
+//
+// The file is plain text by default. With --log-format json each line is one object for a log
+// collector, e.g.
+//   {"timestamp":"2024-11-02T20:14:03.120+01:00","level":"ERROR","message":"SetDisplayConfig failed with code: 87",
+//    "win32_code":87,"target":"shh::display_config","spans":[{"modes":4,"name":"apply_display_config","paths":2}]}
+// win32_code is only there for records logged with one, as in error!(win32_code = result, "...").
+//
+// With --event-log warnings and errors are also reported to the Windows event log, whatever the level.
+//
+// Each Win32 operation is a tracing span (#[instrument]) with the display and the values asked
+// for, and records logged inside one carry it, e.g.
+//   2024-11-02 20:14:03  INFO script_line{line=2}:set_hdr{display=\\.\DISPLAY1 enabled=true}: Turning HDR on for \\.\DISPLAY1
+// so the steps of a prep sequence can be told apart. JSON lines list them under "spans". At
+// debug each span also logs when it opens and closes, with how long it took.
+
+pub const LOG_FILE: &str = "sunshine-helper.log";
+pub const DEFAULT_MAX_SIZE_MB: u64 = 10;
+pub const DEFAULT_KEEP: u32 = 5;
+
+// %LOCALAPPDATA%\sunshine-helper\logs\sunshine-helper.log, or logs\sunshine-helper.log beside the
+// executable in portable mode. Sunshine runs prep commands in its own working directory, so a
+// relative default ended up somewhere nobody looked.
+pub fn default_log_file() -> PathBuf {
+    if let Some(dir) = portable::dir() {
+        return dir.join("logs").join(LOG_FILE);
+    }
+    let base = std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("sunshine-helper").join("logs").join(LOG_FILE)
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub fn verbosity_level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    }
+}
+
+//==============================================================================
+// Log file with rotation
+//==============================================================================
+
+// Once the file reaches max_bytes it becomes sunshine-helper.log.1, the previous .1 becomes .2
+// and so on, keeping `keep` old files
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub keep: u32,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Rotation,
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+impl LogFile {
+    fn open(path: PathBuf, rotation: Rotation) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { path, file, size, rotation })
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        // Renames replace an existing target, so the oldest file just gets overwritten
+        for index in (1..self.rotation.keep).rev() {
+            let _ = fs::rename(rotated_path(&self.path, index), rotated_path(&self.path, index + 1));
+        }
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.size >= self.rotation.max_bytes {
+            // Keep appending to the full file rather than losing the record
+            let _ = self.rotate();
+        }
+        self.file.write_all(record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+}
+
+// The fmt layer formats a whole record before writing it in one go, so files only ever rotate
+// between records
+struct SharedLogFile(Mutex<LogFile>);
+
+struct LogFileWriter<'a>(&'a Mutex<LogFile>);
+
+impl Write for LogFileWriter<'_> {
+    fn write(&mut self, record: &[u8]) -> io::Result<usize> {
+        let mut file = self.0.lock().map_err(|_| io::Error::other("The log file lock is poisoned"))?;
+        file.write_record(record)?;
+        Ok(record.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut file = self.0.lock().map_err(|_| io::Error::other("The log file lock is poisoned"))?;
+        file.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for SharedLogFile {
+    type Writer = LogFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter(&self.0)
+    }
+}
+
+//==============================================================================
+// Logger
+//==============================================================================
+
+// Spans get through whatever the level, so the records that do still say which operation they
+// belong to
+fn records_up_to(level: LevelFilter) -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+    filter::filter_fn(move |metadata| metadata.is_span() || level >= *metadata.level())
+}
+
+// At debug and trace each span logs when it opens and when it closes, with how long it took
+fn span_events(level: LevelFilter) -> FmtSpan {
+    if level >= LevelFilter::DEBUG {
+        FmtSpan::NEW | FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    }
+}
+
+// Reports warnings and errors to the Windows event log, just the message as Event Viewer has
+// its own columns for the rest
+struct EventLogLayer(EventLog);
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLogLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        self.0.report(*event.metadata().level(), &message.0);
+    }
+}
+
+// `explicit` is whether the level came from -v or --log-level rather than the default
+pub fn setup_logger(log_file: Option<(PathBuf, Rotation)>, format: LogFormat, use_event_log: bool, level: LevelFilter, explicit: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let file = match log_file {
+        Some((path, rotation)) => Some(SharedLogFile(Mutex::new(LogFile::open(path, rotation)?))),
+        None => None,
+    };
+    let file: Option<Box<dyn Layer<Registry> + Send + Sync>> = file.map(|file| {
+        let layer = fmt::layer().with_writer(file).with_ansi(false).with_span_events(span_events(level));
+        match format {
+            LogFormat::Text => layer
+                .with_timer(ChronoLocal::new("%Y-%m-%d %H:%M:%S".to_string()))
+                .with_target(false)
+                .with_filter(records_up_to(level))
+                .boxed(),
+            LogFormat::Json => layer
+                .json()
+                .with_timer(ChronoLocal::new("%Y-%m-%dT%H:%M:%S%.3f%:z".to_string()))
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_span_list(true)
+                .with_filter(records_up_to(level))
+                .boxed(),
+        }
+    });
+
+    let stderr_level = if explicit { level } else { level.min(LevelFilter::WARN) };
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .with_span_events(span_events(stderr_level))
+        .with_filter(records_up_to(stderr_level));
+
+    let event_log = if use_event_log { Some(EventLogLayer(EventLog::open()?).with_filter(LevelFilter::WARN)) } else { None };
+
+    tracing_subscriber::registry().with(file).with(stderr).with(event_log).try_init()?;
+    Ok(())
+}
@@ -1,18 +1,28 @@
-mod displays_info;
-mod change_display_mode;
-mod set_sdr_level;
-mod change_icc_profile;
-
-
-use clap::{Parser, Subcommand, value_parser};
-use log::{info, LevelFilter};
-use std::fs::OpenOptions;
-use env_logger::{Builder, Target};
-use std::io::{Write};
-use chrono::Local;
+use shh::{
+    displays_info, display_api, change_display_mode, set_sdr_level, change_icc_profile, edid,
+    dxgi_info, identify, state, display_config, dpi_scaling, icc_file, gamma_ramp, registry_mode,
+    night_light, auto_hdr, hdr, ddc_ci, brightness, keep_awake, exit_code, logging, portable,
+    event_log, retry, timings, apply, bench, schema, script, self_test, report, srgb_clamp,
+    test_pattern, table, config, sunshine, daemon, autostart, events, setup, completions, vdd,
+    gpu_vendor, windows_version, elevation, session, interrupt,
+};
+#[cfg(feature = "gui")]
+use shh::gui;
+
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, value_parser};
+use clap::builder::{FalseyValueParser, Resettable};
+use clap::error::ErrorKind;
+use tracing::{info, info_span, warn, error};
+use tracing::level_filters::LevelFilter;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::IsTerminal;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 use displays_info::{enumerate_displays};
+use exit_code::ExitCode;
+use table::{Cell, Color, DocumentFormat, OutputFormat, Table};
+use windows_version::Feature;
 
 //==============================================================================
 // CLI setup
@@ -20,39 +30,79 @@ use displays_info::{enumerate_displays};
 #[derive(Parser)]
 #[command(name = "sunshine-helper")]
 #[command(about = "Windows display API utility", long_about = None)]
+#[command(arg_required_else_help = true)]
 struct Cli {
-    #[arg(short, long, help = "Enable logging to file")]
+    #[arg(short, long, help = "Also write the log to a file, %LOCALAPPDATA%\\sunshine-helper\\logs\\sunshine-helper.log unless --log-file says otherwise")]
     log: bool,
 
-    #[command(subcommand)]
-    command: Commands,
-}
+    #[arg(long, value_name = "PATH", help = "Write the log to this file (implies --log)")]
+    log_file: Option<std::path::PathBuf>,
 
-// Hard coding some ICC profile strings. Don't use these! I mean, you can if you really want.
-#[derive(Debug)]
-enum PresetString {
-    LgOled,
-    SteamDeck,
-    TestProfile,
-}
+    #[arg(long, value_name = "MB", default_value_t = logging::DEFAULT_MAX_SIZE_MB, value_parser = value_parser!(u64).range(1..),
+          help = "Start a new log file once the current one reaches this size")]
+    log_max_size: u64,
 
-impl PresetString {
-    fn as_str(&self) -> &'static str {
-        match self {
-            PresetString::LgOled => "HDR LG OLED.icc", // Update these to your own ICC profile names if you want to save some typing.
-            PresetString::SteamDeck => "HDR Steam Deck.icc",
-            PresetString::TestProfile => "HDR bad test.icc",
-        }
-    }
+    #[arg(long, value_name = "COUNT", default_value_t = logging::DEFAULT_KEEP, help = "How many old log files to keep")]
+    log_keep: u32,
 
-    fn from_index(index: u8) -> Option<Self> {
-        match index {
-            0 => Some(Self::LgOled),
-            1 => Some(Self::SteamDeck),
-            2 => Some(Self::TestProfile),
-            _ => None,
-        }
-    }
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet", help = "Log more detail, to stderr and the log file: -v for debug, -vv for trace")]
+    verbose: u8,
+
+    #[arg(short, long, global = true, help = "Only log errors")]
+    quiet: bool,
+
+    #[arg(long, global = true, value_name = "LEVEL", help = "Log level to use instead of -v/-q: off, error, warn, info, debug or trace")]
+    log_level: Option<LevelFilter>,
+
+    #[arg(long, value_enum, default_value = "text", help = "Format of the log file: text, or json for one object per line")]
+    log_format: logging::LogFormat,
+
+    #[arg(long, help = "Also report warnings and errors to the Windows Application event log")]
+    event_log: bool,
+
+    #[arg(long, global = true, help = "How often to retry a failed mode, SDR, ICC or HDR change (default 3)")]
+    retries: Option<u32>,
+
+    #[arg(long, global = true, value_name = "MS", help = "Delay before the first retry, doubled for each one after (default 250)")]
+    retry_delay: Option<u64>,
+
+    #[arg(long, global = true, value_enum, default_value = "auto", help = "Color tables: auto (only on a terminal, and not with NO_COLOR set), always or never")]
+    color: table::ColorChoice,
+
+    #[arg(long, global = true, help = "Keep the config, state files and logs next to the executable instead of under %APPDATA%, like a portable.flag file beside it does")]
+    portable: bool,
+
+    #[arg(long, global = true, value_name = "ADAPTER", help = "Only use the displays of one GPU, picked by its index or LUID as `test ed --wide` shows them or by part of its name like nvidia. For hybrid graphics, where a monitor can show up on both GPUs.")]
+    adapter: Option<displays_info::AdapterSelector>,
+
+    #[arg(long, value_name = "FIXTURE", help = "Run against the displays described in a JSON fixture instead of the real ones, printing each change")]
+    mock: Option<std::path::PathBuf>,
+
+    #[arg(long, global = true, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "500",
+          help = "After changing displays, wait until Windows has stopped re-enumerating them and the topology has stayed the same for this long (500 ms without a value), up to 10 s")]
+    settle: Option<u64>,
+
+    #[arg(long, global = true, help = "Do all the lookups and checks, then print each change that would be made, with the old and new values and the Windows call, without making it")]
+    dry_run: bool,
+
+    #[arg(long, help = "Run the command elevated, through a UAC prompt, if it isn't already. Its output goes to a new console window.")]
+    elevate: bool,
+
+    #[arg(long, help = "Change displays even from a Remote Desktop session, where the GPU's displays are detached")]
+    allow_remote: bool,
+
+    #[arg(long, global = true, help = "After a command changes displays, show the primary display's mode, HDR state, SDR white level and ICC profile on it for a few seconds")]
+    overlay: bool,
+
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text",
+          help = "Report how long each step took (enumeration, QueryDisplayConfig, mode switch, settle wait, verification) to stderr when the command ends, as text or with --timings=json as JSON")]
+    timings: Option<timings::TimingsFormat>,
+
+    #[arg(long, exclusive = true, help = "List the exit codes commands use and what each means")]
+    print_exit_codes: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
 }
 
 #[derive(Debug, Clone)]
@@ -64,7 +114,7 @@ impl FromStr for StringOrPreset {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // Try to parse as number first
         if let Ok(num) = s.parse::<u8>() {
-            let preset = PresetString::from_index(num)
+            let preset = change_icc_profile::PresetString::from_index(num)
                 .ok_or_else(|| "Invalid preset number".to_string())?;
             Ok(StringOrPreset(preset.as_str().to_string()))
         } else {
@@ -74,6 +124,26 @@ impl FromStr for StringOrPreset {
     }
 }
 
+// Shared by the commands that flip a Windows setting
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Toggle {
+    On,
+    Off,
+    Status,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum VddAction {
+    Enable,
+    Disable,
+    Status,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum EventLogAction {
+    Register,
+    Unregister,
+}
 
 #[derive(Subcommand)]
 enum Commands {
@@ -82,33 +152,475 @@ enum Commands {
         #[command(subcommand)]
         subcommand: TestCommands,
     },
-    #[command(
-        alias = "cpdm",
-        about = "Change the primary display mode (must be a mode reported by the display unless --unsafe is used)"
-    )]
-    ChangePrimaryDisplayMode {
-        #[arg(help = "Width of the display resolution")]
+    #[command(about = "Show the current state of each display")]
+    Status {
+        #[arg(long, help = "Include DXGI color information (active color space, bit depth, luminance and primaries)")]
+        detailed: bool,
+        #[arg(long, value_enum, default_value = "text", conflicts_with = "detailed",
+              help = "text, json/yaml to print the current state as a spec for apply, or psobject for one JSON line of displays for ConvertFrom-Json")]
+        format: DocumentFormat,
+    },
+    #[command(about = "Write everything known about a display (state, EDID, HDR and luminance, ICC profiles, modes) as markdown or JSON, e.g. for a bug report")]
+    Report {
+        #[arg(default_value = "primary", help = "Display to report on: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_enum, default_value = "markdown", help = "markdown to read or paste into an issue, json to compare or process")]
+        format: report::ReportFormat,
+        #[arg(short, long, value_name = "PATH", help = "File to write the report to instead of printing it")]
+        output: Option<std::path::PathBuf>,
+    },
+    #[command(about = "List the modes a display offers, optionally only those that pass the filters")]
+    Modes {
+        #[arg(default_value = "primary", help = "Display to list: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_enum, default_value = "table", help = "Output format: table, json, yaml, csv with one line per mode, or psobject for one JSON line of modes for ConvertFrom-Json")]
+        format: table::OutputFormat,
+        #[command(flatten)]
+        filter: displays_info::ModeFilter,
+    },
+    #[command(about = "Show the Windows build and which display features it supports")]
+    Capabilities,
+    #[command(about = "Briefly show each display's index and name on that display")]
+    Identify {
+        #[arg(short, long, default_value_t = 3, help = "How long to show the overlay for, in seconds")]
+        duration: u32,
+    },
+    #[command(about = "Cover a display with HDR reference patches (white levels, a gradient, clipping bars) until Esc or a click")]
+    TestPattern {
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_enum, default_value = "scrgb", help = "Swap chain format: scrgb (FP16, linear BT.709) or hdr10 (10 bit PQ, BT.2020)")]
+        format: test_pattern::PatternFormat,
+        #[arg(short, long, default_value_t = 0, help = "Close after this many seconds, 0 to wait for Esc or a click")]
+        duration: u32,
+    },
+    #[command(about = "Show a display's mode, HDR state, SDR white level and ICC profile on it for a few seconds")]
+    Overlay {
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(short, long, default_value_t = OVERLAY_SECS, help = "How long to show the readout for, in seconds")]
+        duration: u32,
+    },
+    #[command(about = "Remove a display from the desktop (it can be re-enabled with enable-display)")]
+    DisableDisplay {
+        #[arg(help = "Display to disable: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Re-enable a display previously disabled with disable-display")]
+    EnableDisplay {
+        #[arg(help = "Display to enable: a device index, a device name like DISPLAY2, the monitor name or its display ID, as it was when disabled")]
+        selector: String,
+    },
+    #[command(about = "Make another display the primary display, keeping every display's resolution")]
+    SetPrimary {
+        #[arg(help = "Display to make primary: a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Move a display to a position on the virtual desktop, relative to the primary display")]
+    SetPosition {
+        #[arg(help = "Display to move: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(allow_negative_numbers = true, help = "Desktop X coordinate of the display's top-left corner")]
+        x: i32,
+        #[arg(allow_negative_numbers = true, help = "Desktop Y coordinate of the display's top-left corner")]
+        y: i32,
+    },
+    #[command(about = "Apply a whole display arrangement from a JSON file")]
+    SetLayout {
+        #[arg(help = "Path to a JSON file like [{\"display\": \"DISPLAY2\", \"x\": 3840, \"y\": 0}]")]
+        file: std::path::PathBuf,
+    },
+    #[command(about = "Write every display path and mode Windows knows, inactive ones included, to a file for import-config")]
+    ExportConfig {
+        #[arg(help = "JSON file to write")]
+        file: std::path::PathBuf,
+    },
+    #[command(about = "Put back the whole display configuration from an export-config file, in a single SetDisplayConfig call")]
+    ImportConfig {
+        #[arg(help = "File written by export-config")]
+        file: std::path::PathBuf,
+    },
+    #[command(about = "Bring the displays in line with a spec file, only changing what differs")]
+    Apply {
+        #[arg(help = "Path to a JSON spec like {\"displays\": [{\"display\": \"primary\", \"hdr\": true, \"sdr_nits\": 240}]}, or the same as YAML in a .yaml/.yml file or TOML in a .toml file")]
+        file: std::path::PathBuf,
+        #[arg(long, help = "Leave earlier changes in place when a later one fails")]
+        no_rollback: bool,
+    },
+    #[command(about = "Run commands from a script file, one per line, in a single process. Use - to read them from stdin.")]
+    Run {
+        script: std::path::PathBuf,
+    },
+    #[command(about = "Register the event source --event-log writes as, so Event Viewer shows the messages cleanly (needs an elevated prompt)")]
+    EventLog {
+        #[arg(value_enum)]
+        action: EventLogAction,
+    },
+    #[command(about = "Print the JSON Schema of a command's --json output, or of all of them")]
+    Schema {
+        #[arg(value_enum)]
+        command: Option<schema::SchemaCommand>,
+    },
+    #[command(about = "Print a shell completion script, with completion of display selectors and ICC profile names")]
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+    // Called by the completion scripts with the words typed so far
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        words: Vec<String>,
+    },
+    #[command(about = "Show which settings differ from a spec file, without changing anything")]
+    Diff {
+        #[arg(help = "Path to a JSON, YAML or TOML spec, as for apply")]
+        file: std::path::PathBuf,
+        #[arg(long, conflicts_with = "format", help = "Print the differences as JSON, same as --format json")]
+        json: bool,
+        #[arg(long, value_enum, default_value = "text", help = "Output format: text, json, yaml, or psobject for one JSON line of differences for ConvertFrom-Json")]
+        format: DocumentFormat,
+    },
+    #[command(about = "Check that a display is in the given state, exiting with the mismatch code and listing what differs if it isn't")]
+    Verify {
+        #[arg(default_value = "primary", help = "Display to check: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_name = "WxH@HZ", help = "Expected mode, like 3840x2160@120")]
+        mode: Option<apply::ModeSpec>,
+        #[arg(long, value_name = "on|off", value_parser = clap::builder::BoolishValueParser::new(), help = "Expected HDR state")]
+        hdr: Option<bool>,
+        #[arg(long, value_name = "LEVEL", value_parser = value_parser!(u32).range(0..=100), help = "Expected SDR white level (0-100, as the Windows slider shows it)")]
+        sdr: Option<u32>,
+        #[arg(long, value_name = "PROFILE", help = "Expected default ICC profile, by file name")]
+        icc: Option<String>,
+    },
+    #[command(about = "Print a JSON line for each display that's attached or removed and each mode, HDR or primary change, until cancelled")]
+    Events {
+        #[arg(long, value_name = "MS", default_value_t = 500, value_parser = value_parser!(u64).range(50..), help = "How often to look at the displays, in milliseconds")]
+        interval: u64,
+    },
+    #[command(about = "Set up the displays for a Sunshine stream from the config file, picking the connecting client's profile")]
+    SunshinePrep {
+        #[arg(long, value_name = "PATH", help = "Config file to use instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "NAME", help = "Client name to use instead of SUNSHINE_CLIENT_NAME, to try a profile outside Sunshine")]
+        client: Option<String>,
+        #[arg(long, value_name = "NAME", help = "App name to use instead of SUNSHINE_APP_NAME, to try a preset outside Sunshine")]
+        app: Option<String>,
+        #[arg(long, help = "Keep the changes already made if a later one fails, instead of rolling them back")]
+        no_rollback: bool,
+    },
+    #[command(about = "Walk through a first setup: pick the streaming display, measure an SDR level, pick an ICC profile and write the config")]
+    Setup {
+        #[arg(long, value_name = "PATH", help = "Config file to write instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "DIR", help = "Sunshine's config directory, %ProgramFiles%\\Sunshine\\config unless given")]
+        sunshine_dir: Option<std::path::PathBuf>,
+    },
+    #[command(about = "Open a small window with the display, HDR, SDR level, ICC profile and mode controls (builds with --features gui)")]
+    Gui,
+    #[command(about = "Work with Sunshine's own configuration (sunshine.conf and apps.json)")]
+    Sunshine {
+        #[command(subcommand)]
+        subcommand: SunshineCommands,
+    },
+    #[command(about = "Work with the helper's config file")]
+    Config {
+        #[command(subcommand)]
+        subcommand: ConfigCommands,
+    },
+    #[command(about = "Save the current state of every display under a name, to bring back later with restore-state")]
+    SaveState {
+        #[arg(value_parser = parse_state_name, help = "Name to save it as, e.g. tv-hdr (letters, digits, - _ and .)")]
+        name: String,
+    },
+    #[command(about = "Bring the displays back to a state saved with save-state, only changing what differs")]
+    RestoreState {
+        #[arg(value_parser = parse_state_name, help = "Name the state was saved as")]
+        name: String,
+        #[arg(long, help = "Keep the changes already made if a later one fails, instead of rolling them back")]
+        no_rollback: bool,
+        #[arg(long, help = "Delete the saved state once it's restored")]
+        delete: bool,
+    },
+    #[command(about = "List the states saved with save-state")]
+    ListStates,
+    #[command(about = "Delete a state saved with save-state")]
+    DeleteState {
+        #[arg(value_parser = parse_state_name, help = "Name the state was saved as")]
+        name: String,
+    },
+    #[command(about = "Set a display's scaling (the Settings > Display > Scale percentage)")]
+    SetScaling {
+        #[arg(help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(help = "Scale percentage: 100, 125, 150, 175, 200, 225, 250, 300, 350, 400, 450 or 500")]
+        percent: u32,
+    },
+    #[command(about = "Load a gamma ramp into a display's video card LUT, from a gamma value or an ICC profile's vcgt tag")]
+    SetGamma {
+        #[arg(help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(required_unless_present = "icc", conflicts_with = "icc", value_parser = parse_gamma, help = "Gamma adjustment from 0.1 to 10, 1.0 is unchanged, higher is brighter")]
+        gamma: Option<f64>,
+        #[arg(long, help = "Load the vcgt calibration curves from this ICC profile (file name in the color directory, or a path)")]
+        icc: Option<String>,
+    },
+    #[command(about = "Reset a display's gamma ramp to linear")]
+    ResetGamma {
+        #[arg(help = "Display to reset: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Switch display topology like Win+P does, or restore the topology from before the first switch")]
+    Topology {
+        #[arg(value_enum, help = "extend, clone, internal (first display only), external (second display only) or restore")]
+        topology: display_config::Topology,
+    },
+    #[command(about = "Set a monitor's backlight brightness (DDC/CI, or WMI for laptop panels)")]
+    SetBrightness {
+        #[arg(help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(value_parser = value_parser!(u32).range(0..=100), help = "Brightness (0-100)")]
+        percent: u32,
+    },
+    #[command(about = "Show a monitor's backlight brightness (DDC/CI, or WMI for laptop panels)")]
+    GetBrightness {
+        #[arg(default_value = "primary", help = "Display to query: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Read or write raw DDC/CI VCP features (input source, picture mode, ...)")]
+    Ddc {
+        #[command(subcommand)]
+        subcommand: DdcCommands,
+    },
+    #[command(about = "Turn HDR on or off for a display, or show its HDR state")]
+    Hdr {
+        #[arg(value_enum)]
+        action: Toggle,
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Turn HDR on only if the display supports it and it's off, with a distinct exit code for each outcome")]
+    EnsureHdr {
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Turn the \"Play streaming HDR video\" setting on or off, or show its state")]
+    HdrVideo {
+        #[arg(value_enum)]
+        action: Toggle,
+    },
+    #[command(about = "Keep the system and displays from going to sleep while streaming")]
+    KeepAwake {
+        #[arg(short, long, value_parser = keep_awake::parse_duration, help = "Release after this long instead of waiting for --stop: seconds, or with s, m or h like 90m")]
+        duration: Option<u32>,
+        #[arg(long, help = "Run in the background and return immediately, for use in a prep command")]
+        detach: bool,
+        #[arg(long, conflicts_with_all = ["duration", "detach"], help = "Stop a running keep-awake instance")]
+        stop: bool,
+    },
+    #[command(about = "Watch in the background for Sunshine streams that end without running their undo commands, and run the config's process [rules]")]
+    Daemon {
+        #[arg(long, help = "Run in the background and return immediately")]
+        detach: bool,
+        #[arg(long, conflicts_with_all = ["detach", "disconnect_grace", "sunshine_log", "config", "keep_awake"], help = "Stop a running daemon")]
+        stop: bool,
+        #[arg(long, value_name = "SECS", help = "Also restore once Sunshine's log says the client disconnected and it hasn't reconnected within this many seconds")]
+        disconnect_grace: Option<u64>,
+        #[arg(long, value_name = "PATH", help = "Sunshine's log file, %ProgramFiles%\\Sunshine\\config\\sunshine.log unless given")]
+        sunshine_log: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "PATH", help = "Config file with the [rules] to run, instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+        #[arg(long, help = "Keep the host and its displays from sleeping while a stream is in progress")]
+        keep_awake: bool,
+    },
+    #[command(about = "Apply a spec or start the daemon at every logon, from a scheduled task or the Run key")]
+    Autostart {
+        #[command(subcommand)]
+        subcommand: AutostartCommands,
+    },
+    #[command(about = "Enable or disable the virtual display driver's device (Parsec VDD, SudoVDA, IddSampleDriver), or show its state")]
+    Vdd {
+        #[arg(value_enum)]
+        action: VddAction,
+        #[arg(long, value_enum, help = "Driver to act on, needed when more than one is installed")]
+        driver: Option<vdd::VddDriver>,
+    },
+    #[command(about = "Turn variable refresh rate (G-SYNC / FreeSync) on or off, or show a display's VRR state")]
+    Vrr {
+        #[arg(value_enum)]
+        action: Toggle,
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Register a custom resolution with the GPU driver, for client resolutions the display doesn't offer")]
+    CreateCustomMode {
+        width: u32,
+        height: u32,
+        refresh_rate: u32,
+        #[arg(short, long, default_value = "primary", help = "Display to add the mode to: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        display: String,
+    },
+    #[command(about = "Dangerous: add a mode to a display's EDID through a registry override, for displays and dummy plugs that don't offer it and drivers without custom resolutions. Needs an elevated prompt.")]
+    AddRegistryMode {
         width: u32,
-        #[arg(help = "Height of the display resolution")]
         height: u32,
-        #[arg(help = "Refresh rate of the display resolution")]
         refresh_rate: u32,
+        #[arg(short, long, default_value = "primary", help = "Display to add the mode to: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        display: String,
         #[arg(
             long = "unsafe",
-            help = "Don't use this, it is not safe. This bypasses the check for your display's reported supported modes. Only consider this for known working custom resolutions."
+            required = true,
+            help = "Required. The override replaces the EDID the display reports, and a timing it can't show leaves it blank until the mode is removed with remove-registry-mode (from Remote Desktop, or in Safe Mode)."
         )]
         unsafe_mode: bool,
     },
+    #[command(about = "Take a mode added with add-registry-mode out of the display's EDID override again. Needs an elevated prompt.")]
+    RemoveRegistryMode {
+        #[arg(required_unless_present = "all")]
+        width: Option<u32>,
+        #[arg(required_unless_present = "all")]
+        height: Option<u32>,
+        #[arg(required_unless_present = "all")]
+        refresh_rate: Option<u32>,
+        #[arg(short, long, default_value = "primary", help = "Display to remove the mode from: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        display: String,
+        #[arg(long, conflicts_with_all = ["width", "height", "refresh_rate"], help = "Delete the whole override, going back to the display's own EDID")]
+        all: bool,
+    },
+    #[command(about = "Show or pin a display's output pixel format (RGB / YCbCr) and bit depth")]
+    ColorFormat {
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_enum, help = "Pixel format to set")]
+        format: Option<gpu_vendor::PixelFormat>,
+        #[arg(long, help = "Bits per color to set (6, 8, 10, 12 or 16)")]
+        bpc: Option<u32>,
+    },
+    #[command(about = "Show or set how the GPU scales modes below the display's native resolution")]
+    Scaling {
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, value_enum, help = "Scaling mode to set")]
+        mode: Option<gpu_vendor::ScalingMode>,
+    },
+    #[command(about = "Turn the GPU's output dithering on or off, or show whether it is on")]
+    Dithering {
+        #[arg(value_enum)]
+        action: Toggle,
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Map SDR content into a wide-gamut panel's real gamut instead of stretching it, or show whether that's on")]
+    ClampSrgb {
+        #[arg(value_enum)]
+        action: Toggle,
+        #[arg(default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(about = "Turn Windows night light on or off, or show whether it is on")]
+    NightLight {
+        #[arg(value_enum)]
+        action: Toggle,
+    },
+    #[command(about = "Turn Windows Auto HDR on or off, globally or for one game, or show its state")]
+    AutoHdr {
+        #[arg(value_enum)]
+        action: Toggle,
+        #[arg(long, help = "Full path of a game executable to set a per-game override for instead of the global setting")]
+        exe: Option<String>,
+    },
+    #[command(
+        alias = "cpdm",
+        about = "Change the primary display mode (must be a mode reported by the display unless --force is used)"
+    )]
+    ChangePrimaryDisplayMode {
+        #[arg(required_unless_present = "fit", help = "Width of the display resolution")]
+        width: Option<u32>,
+        #[arg(required_unless_present = "fit", help = "Height of the display resolution")]
+        height: Option<u32>,
+        #[arg(required_unless_present = "fit", help = "Refresh rate of the display resolution")]
+        refresh_rate: Option<u32>,
+        #[arg(
+            long,
+            value_name = "WxH[@HZ]",
+            conflicts_with_all = ["width", "height", "refresh_rate", "rotate", "force"],
+            help = "Pick the supported mode that best fits a client of this resolution: the same resolution, else the highest with its aspect ratio, else the one with the least letterboxing. The refresh rate is the given one or the closest to it, the highest without one."
+        )]
+        fit: Option<change_display_mode::FitTarget>,
+        #[arg(
+            long,
+            help = "Rotate the desktop clockwise by 0, 90, 180 or 270 degrees. Width and height are the size after rotation, e.g. 1600 2560 for a portrait tablet."
+        )]
+        rotate: Option<change_display_mode::Rotation>,
+        #[arg(
+            long,
+            alias = "unsafe",
+            help = "Skip the check against the modes the display reports, for driver-level custom modes that work but aren't listed. A mode the display can't show leaves it blank, so from a console it's undone after --revert-after seconds unless confirmed."
+        )]
+        force: bool,
+        #[arg(long, value_name = "SECS",
+              help = "With --force, go back to the previous mode after this many seconds unless Enter is pressed on the console. 15 by default when run from a console, 0 (keep the mode without asking) otherwise, as in prep commands.")]
+        revert_after: Option<u32>,
+        #[arg(long, help = "Refuse the mode if the display only offers it interlaced (progressive is always preferred when both exist)")]
+        progressive: bool,
+    },
+    #[command(
+        alias = "srr",
+        about = "Change only the refresh rate of a display, keeping its resolution"
+    )]
+    SetRefresh {
+        #[arg(help = "Refresh rate in Hz, which the display has to offer at its current resolution")]
+        refresh_rate: u32,
+        #[arg(default_value = "primary", help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+    #[command(
+        alias = "sres",
+        about = "Change only the resolution of a display, keeping its refresh rate"
+    )]
+    SetResolution {
+        #[arg(help = "Width of the display resolution")]
+        width: u32,
+        #[arg(help = "Height of the display resolution, which the display has to offer at its current refresh rate")]
+        height: u32,
+        #[arg(default_value = "primary", help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
     #[command(
         alias = "ssdrl",
-        about = "Set the SDR white level of the primary display"
+        about = "Set the SDR white level of the primary display, or of every HDR display with --all"
     )]
     SetSdrLevel {
         #[arg(
             value_parser = value_parser!(u32).range(0..=100),
+            required_unless_present = "raw",
+            conflicts_with = "raw",
             help = "SDR white level (0-100, matches Windows SDR content brightness slider)"
         )]
-        level: u32,
+        level: Option<u32>,
+        #[arg(long, help = "Pass this value to the API as is instead of a slider level, in 1000ths of 80 nits (1000-6000 is the slider's range)")]
+        raw: Option<u32>,
+        #[arg(long, default_value_t = 1000, help = "Raw value for slider level 0")]
+        raw_min: u32,
+        #[arg(long, default_value_t = 6000, help = "Highest raw value a slider level maps to, higher levels are clamped to it")]
+        raw_max: u32,
+        #[arg(long, default_value_t = 50, help = "Raw value per slider level")]
+        raw_step: u32,
+        #[arg(long, help = "Set every HDR capable display at once instead of only the primary one")]
+        all: bool,
+    },
+    #[command(
+        alias = "gsdrl",
+        about = "Show the SDR white level of a display, or of every display with --all"
+    )]
+    GetSdrLevel {
+        #[arg(default_value = "primary", help = "Display to query: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, help = "Report every active display in one table or document, the selector is ignored")]
+        all: bool,
+        #[arg(long, value_enum, default_value = "table", help = "Output format: table, json, yaml, csv with one line per display, or psobject for one JSON line of displays for ConvertFrom-Json")]
+        format: table::OutputFormat,
     },
     #[command(
         alias = "sicc",
@@ -118,6 +630,126 @@ enum Commands {
         #[arg(help = "Name of the ICC profile to set. Remember to include the *.icc extension! You can also enter a preset number here, but the names are hardcoded, so that's only if you built this yourself and changed the enum.")]
         profile_name: StringOrPreset,
     },
+    #[command(
+        alias = "shdrc",
+        about = "Make a Windows HDR Calibration profile the display's HDR (advanced color) profile"
+    )]
+    SetHdrCalibration {
+        #[arg(help = "Name of the calibration profile, the display's newest one when left out")]
+        profile_name: Option<String>,
+        #[arg(short, long, default_value = "primary", help = "Display to use: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        display: String,
+    },
+    #[command(about = "Inspect ICC profile files")]
+    Icc {
+        #[command(subcommand)]
+        subcommand: IccCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum IccCommands {
+    #[command(about = "Show a profile's header and key tags: description, device model, white point, creation date and whether it has VCGT or MHC2 data")]
+    Info {
+        #[arg(help = "Profile file name in the color directory, or a path to it")]
+        profile: String,
+    },
+    #[command(about = "Add a profile to a display's list of associated profiles, like the Color Management dialog, without making it the default")]
+    Associate {
+        #[arg(help = "Profile file name in the color directory, or a path to it")]
+        profile: String,
+        #[arg(default_value = "primary", help = "Display to add it to: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long = "default", help = "Also make it the display's default profile")]
+        make_default: bool,
+    },
+    #[command(about = "Put back the default profile set-icc-profile replaced")]
+    Restore {
+        #[arg(default_value = "primary", help = "Display to restore: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DdcCommands {
+    #[command(about = "Read a VCP feature's current and maximum value")]
+    Get {
+        #[arg(help = "Display to query: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(value_parser = ddc_ci::parse_vcp_code, help = "VCP code in hex, e.g. 60 for input source")]
+        code: u8,
+    },
+    #[command(about = "Write a VCP feature")]
+    Set {
+        #[arg(help = "Display to change: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(value_parser = ddc_ci::parse_vcp_code, help = "VCP code in hex, e.g. 60 for input source")]
+        code: u8,
+        #[arg(value_parser = ddc_ci::parse_vcp_value, help = "New value, decimal or 0x-prefixed hex")]
+        value: u32,
+    },
+    #[command(about = "Show the VCP features a monitor reports in its capabilities string")]
+    Capabilities {
+        #[arg(default_value = "primary", help = "Display to query: \"primary\", a device index, a device name like DISPLAY2, the monitor name or its display ID")]
+        selector: String,
+        #[arg(long, help = "Also print the raw capabilities string")]
+        raw: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SunshineCommands {
+    #[command(about = "Print the prep-cmd entries that run sunshine-prep for each stream, or write them into sunshine.conf or apps.json")]
+    GenerateConfig {
+        #[arg(long, value_name = "PATH", help = "Helper config file for sunshine-prep to use instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "NAME", num_args = 0.., help = "Add the entries to these apps in apps.json instead of to global_prep_cmd, or to the apps in the config's [apps] sections if none are named")]
+        apps: Option<Vec<String>>,
+        #[arg(long, help = "Write the entries into Sunshine's files (keeping a .bak copy) instead of printing them")]
+        write: bool,
+        #[arg(long, value_name = "DIR", help = "Sunshine's config directory, %ProgramFiles%\\Sunshine\\config unless given")]
+        sunshine_dir: Option<std::path::PathBuf>,
+    },
+    #[command(about = "Check sunshine.conf, apps.json and the helper's profiles against the modes and HDR support of the connected displays")]
+    Validate {
+        #[arg(long, value_name = "PATH", help = "Helper config file to check instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+        #[arg(long, value_name = "DIR", help = "Sunshine's config directory, %ProgramFiles%\\Sunshine\\config unless given")]
+        sunshine_dir: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AutostartCommands {
+    #[command(about = "Run apply with a spec, or the daemon, at every logon. Replaces what an earlier install set up.")]
+    Install {
+        #[arg(long, value_name = "PATH", help = "Spec to apply at logon, as for apply")]
+        spec: Option<std::path::PathBuf>,
+        #[arg(long, help = "Start the daemon at logon instead")]
+        daemon: bool,
+        #[arg(long, help = "Use the current user's Run key instead of a scheduled task. Needs no elevated prompt, but starts straight away and without admin rights.")]
+        run_key: bool,
+        #[arg(long, value_name = "SECS", default_value_t = 15, help = "How long the scheduled task waits after logon, for the displays to come up")]
+        delay: u32,
+    },
+    #[command(about = "Remove the scheduled task and the Run key entry")]
+    Remove,
+    #[command(about = "Show what runs at logon")]
+    Status,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    #[command(about = "Check the config file: its format, the profiles against the connected displays, the [rules] against the saved states and the [defaults] against the options")]
+    Validate {
+        #[arg(long, value_name = "PATH", help = "Config file to check instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+    },
+    #[command(about = "Rewrite a config file from an older version of the helper in the current format, keeping the old one as config.toml.v<version>.bak")]
+    Upgrade {
+        #[arg(long, value_name = "PATH", help = "Config file to upgrade instead of %APPDATA%\\sunshine-helper\\config.toml")]
+        config: Option<std::path::PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -128,44 +760,209 @@ enum TestCommands {
         message: String,
     },
     #[command(alias = "ed")]
-    EnumerateDisplays,
+    EnumerateDisplays {
+        #[arg(long, conflicts_with = "format", help = "Print the displays and installed virtual display drivers as JSON, same as --format json")]
+        json: bool,
+        #[arg(long, value_enum, default_value = "table", help = "Output format: table, json, yaml, csv with one line per display, or psobject for one JSON line of displays for ConvertFrom-Json")]
+        format: table::OutputFormat,
+        #[arg(long, help = "Add adapter, connection, HDR, display ID, position and state flag columns")]
+        wide: bool,
+        #[arg(long, help = "Also list monitors that are connected but not part of the desktop, like a switched off virtual display, and GPU outputs with nothing on them")]
+        all: bool,
+    },
     #[command(alias = "pdm")]
-    PrimaryDisplayModes,
+    PrimaryDisplayModes {
+        #[arg(long, value_enum, default_value = "table", help = "Output format: table, json, yaml, csv with one line per mode, or psobject for one JSON line of modes for ConvertFrom-Json")]
+        format: table::OutputFormat,
+        #[command(flatten)]
+        filter: displays_info::ModeFilter,
+    },
     #[command(alias = "licc")]
-    ListICCProfiles,
+    ListICCProfiles {
+        #[arg(long, help = "List every profile in the system color directory and the displays each is associated with, not just the primary display's")]
+        all: bool,
+    },
     #[command(alias = "qdc")]
     QueryDisplayConfig, //TODO: Remove this test command
+    #[command(alias = "edid", about = "Parse each display's EDID and summarise its identity and HDR capabilities")]
+    EdidInfo {
+        #[arg(long, help = "Also print a hex dump of the raw EDID bytes")]
+        raw: bool,
+    },
+    #[command(about = "Re-apply the primary display's current mode, SDR white level and ICC profile and check each reads back")]
+    SelfTest,
+    #[command(about = "Time display enumeration, mode switches, HDR toggles and SDR white level changes on the primary display")]
+    Bench {
+        #[arg(long, default_value_t = 5, value_parser = value_parser!(u32).range(1..), help = "How often to run each operation")]
+        runs: u32,
+    },
 }
 
-//==============================================================================
-// Logger setup
-//==============================================================================
+fn parse_state_name(name: &str) -> Result<String, String> {
+    if state::is_valid_state_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err("State names use letters, digits, - _ and . (up to 64 characters, not starting with .)".to_string())
+    }
+}
+
+// 0 and below make no ramp at all, and far outside this range every entry ends up black or white
+fn parse_gamma(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(gamma) if (gamma_ramp::MIN_GAMMA..=gamma_ramp::MAX_GAMMA).contains(&gamma) => Ok(gamma),
+        Ok(_) => Err(format!("Gamma must be between {} and {}", gamma_ramp::MIN_GAMMA, gamma_ramp::MAX_GAMMA)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Every option can also come from a SUNSHINE_HELPER_<OPTION> environment variable, e.g.
+// SUNSHINE_HELPER_LOG_LEVEL=debug or SUNSHINE_HELPER_CONFIG, which is easier to template in a
+// prep command than a long argument list, or from the [defaults] section of the config file
+// under the same name without the prefix (log_level = "debug"). The display selector of every
+// command is SUNSHINE_HELPER_DISPLAY. Flags take 1/0, true/false, yes/no or on/off. The command
+// line wins over the environment, which wins over the config file.
+const ENV_PREFIX: &str = "SUNSHINE_HELPER_";
+
+// How long `overlay` and --overlay show the settings readout
+const OVERLAY_SECS: u32 = 5;
+
+// Built once, script lines and completions parse with it again
+fn cli_command() -> clap::Command {
+    static COMMAND: OnceLock<clap::Command> = OnceLock::new();
+    COMMAND.get_or_init(build_cli_command).clone()
+}
+
+fn build_cli_command() -> clap::Command {
+    let defaults = config::load_defaults();
+    let command = with_settings(Cli::command(), &defaults);
+
+    let mut names = BTreeSet::new();
+    setting_names(&command, &mut names);
+    for key in defaults.keys().filter(|key| !names.contains(key.as_str())) {
+        eprintln!("warning: [defaults] in {} sets `{}`, which isn't an option", config::config_file_path().display(), key);
+    }
+    command
+}
+
+fn with_settings(command: clap::Command, defaults: &BTreeMap<String, serde_json::Value>) -> clap::Command {
+    let subcommands: Vec<String> = command.get_subcommands().map(|subcommand| subcommand.get_name().to_string()).collect();
+    let name = command.get_name().to_string();
+    subcommands.iter().fold(command.mut_args(|arg| setting_for(&name, arg, defaults)), |command, name| {
+        command.mut_subcommand(name, |subcommand| with_settings(subcommand, defaults))
+    })
+}
+
+fn setting_for(command: &str, arg: clap::Arg, defaults: &BTreeMap<String, serde_json::Value>) -> clap::Arg {
+    let arg = with_env_var(command, arg);
+    let Some(name) = setting_name(&arg) else {
+        return arg;
+    };
+    // A default stands in for an argument only needed without another one, like set-sdr-level's
+    // level without --raw. Arguments every use needs, like disable-display's selector, stay required.
+    match defaults.get(&name) {
+        Some(serde_json::Value::Array(values)) => arg.default_values(values.iter().map(default_text)).required_unless_present(Resettable::<clap::Id>::Reset),
+        Some(value) => arg.default_value(default_text(value)).required_unless_present(Resettable::<clap::Id>::Reset),
+        None => arg,
+    }
+}
+
+// What the option would get on the command line
+fn default_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn with_env_var(command: &str, arg: clap::Arg) -> clap::Arg {
+    let id = arg.get_id().as_str();
+    let name = match (command, id) {
+        _ if arg.get_env().is_some() || arg.is_exclusive_set() => return arg,
+        (_, "selector" | "display") => "DISPLAY".to_string(),
+        (_, "level") => "SDR_LEVEL".to_string(),
+        // SUNSHINE_HELPER_FORMAT is the output format, these --format and --raw take other values
+        ("report", "format") => "REPORT_FORMAT".to_string(),
+        ("test-pattern", "format") => "PATTERN_FORMAT".to_string(),
+        ("color-format", "format") => "PIXEL_FORMAT".to_string(),
+        ("set-sdr-level", "raw") => "SDR_RAW".to_string(),
+        // A count, SUNSHINE_HELPER_LOG_LEVEL does the same. SUNSHINE_HELPER_FORMAT=json does what
+        // --json does without clashing with a --format given on the command line.
+        (_, "verbose" | "json") => return arg,
+        // Other positionals are what the command is about, not settings
+        _ if arg.is_positional() => return arg,
+        _ => id.to_ascii_uppercase(),
+    };
+    let arg = arg.env(format!("{}{}", ENV_PREFIX, name));
+    match arg.get_action() {
+        ArgAction::SetTrue => arg.value_parser(FalseyValueParser::new()),
+        _ => arg,
+    }
+}
+
+// log_level for SUNSHINE_HELPER_LOG_LEVEL. Not portable, which decides where the config file
+// with the [defaults] is.
+fn setting_name(arg: &clap::Arg) -> Option<String> {
+    let env = arg.get_env()?.to_str()?;
+    env.strip_prefix(ENV_PREFIX).map(str::to_ascii_lowercase).filter(|name| name != "portable")
+}
+
+fn setting_names(command: &clap::Command, names: &mut BTreeSet<String>) {
+    names.extend(command.get_arguments().filter_map(setting_name));
+    for subcommand in command.get_subcommands() {
+        setting_names(subcommand, names);
+    }
+}
 
-fn setup_logger(logging_enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
-    if !logging_enabled {
-        return Ok(());
+// [defaults] entries that aren't an option or hold a value the option doesn't take, for config
+// validate. Each value is tried by parsing the commands that have the option with only that default.
+fn check_defaults(defaults: &BTreeMap<String, serde_json::Value>) -> Vec<sunshine::Issue> {
+    fn commands_with(command: &clap::Command, name: &str, path: &mut Vec<String>, found: &mut Vec<Vec<String>>) {
+        if command.get_arguments().any(|arg| setting_name(arg).as_deref() == Some(name)) {
+            found.push(path.clone());
+        }
+        for subcommand in command.get_subcommands() {
+            path.push(subcommand.get_name().to_string());
+            commands_with(subcommand, name, path, found);
+            path.pop();
+        }
     }
 
-    let log_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open("sunshine-helper.log")?;
+    let command = with_settings(Cli::command(), &BTreeMap::new());
+    let mut issues = Vec::new();
+    for (name, value) in defaults {
+        let mut found = Vec::new();
+        commands_with(&command, name, &mut Vec::new(), &mut found);
+        if found.is_empty() {
+            issues.push(sunshine::Issue::warning(format!("[defaults] sets `{}`, which isn't an option", name)));
+            continue;
+        }
+        let with_default = with_settings(Cli::command(), &BTreeMap::from([(name.clone(), value.clone())]));
+        let rejected = found.iter().find_map(|path| {
+            let args = std::iter::once("sunshine_helper").chain(path.iter().map(String::as_str));
+            with_default.clone().try_get_matches_from(args).err()
+                .filter(|e| matches!(e.kind(), ErrorKind::InvalidValue | ErrorKind::ValueValidation))
+        });
+        if let Some(e) = rejected {
+            let message = e.to_string();
+            let reason = message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            issues.push(sunshine::Issue::error(format!("[defaults] {} = {}: {}", name, value, reason)));
+        }
+    }
+    issues
+}
 
-    Builder::new()
-        .format(|buf, record| {
-            writeln!(buf,
-                     "{} [{}] - {}",
-                     Local::now().format("%Y-%m-%d %H:%M:%S"),
-                     record.level(),
-                     record.args()
-            )
-        })
-        .filter_level(LevelFilter::Info)
-        .target(Target::Pipe(Box::new(log_file)))
-        .init();
+// --portable or SUNSHINE_HELPER_PORTABLE, looked at before clap gets to them
+fn portable_requested() -> bool {
+    // The values FalseyValueParser takes as false
+    let enabled = |value: std::ffi::OsString| {
+        !matches!(value.to_string_lossy().to_ascii_lowercase().as_str(), "" | "0" | "n" | "no" | "f" | "false" | "off")
+    };
+    std::env::args_os().skip(1).any(|arg| arg == "--portable")
+        || std::env::var_os(format!("{}PORTABLE", ENV_PREFIX)).is_some_and(enabled)
+}
 
-    Ok(())
+fn parse_cli<I: Into<std::ffi::OsString> + Clone>(args: impl IntoIterator<Item = I>) -> Result<Cli, clap::Error> {
+    cli_command().try_get_matches_from(args).and_then(|matches| Cli::from_arg_matches(&matches))
 }
 
 //==============================================================================
@@ -173,124 +970,2200 @@ fn setup_logger(logging_enabled: bool) -> Result<(), Box<dyn std::error::Error>>
 //==============================================================================
 
 fn main() {
-    // Parse CLI arguments
-    let cli = Cli::parse();
+    // [defaults] come from the config file before the command line is parsed, so portable mode
+    // has to be known first
+    if portable_requested() {
+        portable::enable();
+    }
+
+    // Parse CLI arguments. Usage errors get their own exit code instead of clap's 2, which means display-not-found here.
+    let cli = match parse_cli(std::env::args_os()) {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            if e.use_stderr() { ExitCode::InvalidInput.exit() } else { ExitCode::Success.exit() }
+        }
+    };
+
+    if let Some(format) = cli.timings {
+        timings::start(format);
+    }
+
+    if cli.print_exit_codes {
+        exit_code::print_exit_codes();
+        return;
+    }
+    let Some(command) = cli.command else {
+        let _ = cli_command().print_help();
+        ExitCode::InvalidInput.exit();
+    };
 
     // Setup logger
-    if let Err(e) = setup_logger(cli.log) {
+    let explicit_level = cli.verbose > 0 || cli.log_level.is_some();
+    let log_level = cli.log_level.unwrap_or_else(|| logging::verbosity_level(cli.quiet, cli.verbose));
+    let log_file = (cli.log || cli.log_file.is_some()).then(|| {
+        let rotation = logging::Rotation { max_bytes: cli.log_max_size * 1024 * 1024, keep: cli.log_keep };
+        (cli.log_file.clone().unwrap_or_else(logging::default_log_file), rotation)
+    });
+    if let Err(e) = logging::setup_logger(log_file, cli.log_format, cli.event_log, log_level, explicit_level) {
         eprintln!("Failed to initialize logger: {}", e);
-        std::process::exit(1);
+        ExitCode::Failure.exit();
+    }
+
+    table::init_color(cli.color);
+
+    // The panic message would otherwise only reach stderr, which prep commands don't keep. Any
+    // undo snapshot is applied after this, while unwinding.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        error!("Crashed: {}", panic_info);
+        default_hook(panic_info);
+    }));
+    // Ctrl+C during apply or run rolls back instead of leaving the displays half changed
+    interrupt::install();
+
+    // Nothing reaches the real displays with --mock, and state files go to %TEMP% instead of %APPDATA%
+    if let Some(fixture) = &cli.mock {
+        match display_api::mock::MockDisplayApi::load(fixture) {
+            Ok(mock) => display_api::use_mock(Box::leak(Box::new(mock))),
+            Err(e) => {
+                eprintln!("Failed to load mock fixture {}: {}", fixture.display(), e);
+                ExitCode::InvalidInput.exit();
+            }
+        }
+        state::use_state_dir(std::env::temp_dir().join("sunshine-helper-mock"));
+        info!("Running against mock displays from {}", fixture.display());
+    }
+    if let Some(settle) = cli.settle {
+        displays_info::use_settle(std::time::Duration::from_millis(settle));
+    }
+    if cli.dry_run {
+        display_api::use_dry_run();
+        info!("Dry run, changes are printed instead of made");
+    }
+    if let Some(adapter) = cli.adapter.clone() {
+        info!("Only using the displays of adapter {}", adapter);
+        displays_info::use_adapter(adapter);
+    }
+
+    if cli.elevate && !display_api::is_mock() && !elevation::is_elevated() {
+        match elevation::relaunch_elevated() {
+            Ok(code) => std::process::exit(code as i32),
+            Err(e) => {
+                eprintln!("Failed to relaunch elevated: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+    }
+
+    // Over Remote Desktop only reading is safe, changes would go to the remote session's display
+    if let Some(remote) = session::remote_session() {
+        if !command.changes_displays() {
+            warn!("Running in {}, the displays shown are the remote session's rather than the GPU's", remote.describe());
+        } else if cli.allow_remote {
+            warn!("Running in {}, changing displays anyway because of --allow-remote", remote.describe());
+        } else {
+            eprintln!("This is {}, where Windows detaches the GPU's displays. {}.", remote.describe(), remote.advice());
+            ExitCode::Unsupported.exit();
+        }
+    }
+
+    let prep_retry = retry::RetryPolicy::from_args(cli.retries, cli.retry_delay, retry::PREP_RETRIES);
+    let show_overlay = cli.overlay && command.changes_displays() && !display_api::is_mock();
+    run_command(command, prep_retry);
+
+    // Failed commands have exited already. The readout is only a convenience, so failing to show it isn't an error.
+    if show_overlay {
+        if let Some(primary) = displays_info::find_display("primary") {
+            if let Err(e) = identify::show_settings_readout(&primary, OVERLAY_SECS) {
+                warn!("Failed to show the overlay: {}", e);
+            }
+        }
     }
+    timings::report();
+}
+
+impl Commands {
+    // Commands that only use the display API, which --mock replaces. The rest talk to drivers,
+    // monitors or the registry directly.
+    fn runs_on_mock(&self) -> bool {
+        !matches!(self,
+            Commands::Identify { .. } | Commands::EventLog { .. } | Commands::SetGamma { .. } | Commands::ResetGamma { .. }
+            | Commands::SetBrightness { .. } | Commands::GetBrightness { .. } | Commands::Ddc { .. } | Commands::HdrVideo { .. }
+            | Commands::KeepAwake { .. } | Commands::Vdd { .. } | Commands::Vrr { .. } | Commands::CreateCustomMode { .. }
+            | Commands::AddRegistryMode { .. } | Commands::RemoveRegistryMode { .. }
+            | Commands::ColorFormat { .. } | Commands::Scaling { .. } | Commands::Dithering { .. } | Commands::NightLight { .. }
+            | Commands::AutoHdr { .. } | Commands::Daemon { .. } | Commands::Autostart { .. } | Commands::ClampSrgb { .. } | Commands::TestPattern { .. } | Commands::Overlay { .. })
+    }
+
+    // Whether the command changes display state, which over Remote Desktop would hit the remote
+    // session's display. Per-user settings like night light don't count.
+    fn changes_displays(&self) -> bool {
+        match self {
+            Commands::Test { subcommand } => matches!(subcommand, TestCommands::SelfTest | TestCommands::Bench { .. }),
+            Commands::Status { .. } | Commands::Report { .. } | Commands::Modes { .. } | Commands::Identify { .. } | Commands::Diff { .. } | Commands::Verify { .. } | Commands::Events { .. } | Commands::ExportConfig { .. } | Commands::Schema { .. }
+            | Commands::SaveState { .. } | Commands::ListStates | Commands::DeleteState { .. }
+            | Commands::Completions { .. } | Commands::Complete { .. } | Commands::GetBrightness { .. } | Commands::GetSdrLevel { .. } | Commands::Capabilities
+            | Commands::EventLog { .. } | Commands::KeepAwake { .. } | Commands::HdrVideo { .. } | Commands::NightLight { .. }
+            | Commands::AutoHdr { .. } | Commands::Sunshine { .. } | Commands::Config { .. } | Commands::Autostart { .. } | Commands::TestPattern { .. } | Commands::Overlay { .. } => false,
+            Commands::Ddc { subcommand } => matches!(subcommand, DdcCommands::Set { .. }),
+            Commands::Icc { subcommand } => !matches!(subcommand, IccCommands::Info { .. }),
+            Commands::Hdr { action, .. } | Commands::Vrr { action, .. } | Commands::Dithering { action, .. } | Commands::ClampSrgb { action, .. } => !matches!(action, Toggle::Status),
+            Commands::Vdd { action, .. } => !matches!(action, VddAction::Status),
+            Commands::ColorFormat { format, bpc, .. } => format.is_some() || bpc.is_some(),
+            Commands::Scaling { mode, .. } => mode.is_some(),
+            Commands::Daemon { stop, .. } => !stop,
+            _ => true,
+        }
+    }
+
+    // Changes --dry-run can't print instead of making. Unlike changes_displays this counts per-user
+    // settings and the system's power requests too, state files are printed by state.rs.
+    fn writes_outside_display_api(&self) -> bool {
+        match self {
+            Commands::NightLight { action } | Commands::AutoHdr { action, .. } | Commands::HdrVideo { action } => !matches!(action, Toggle::Status),
+            Commands::KeepAwake { .. } | Commands::Setup { .. } | Commands::Gui | Commands::Sunshine { .. } | Commands::Config { .. } | Commands::Autostart { .. } => true,
+            _ => self.changes_displays() && !self.runs_on_mock(),
+        }
+    }
+}
 
-    match cli.command {
+fn run_command(command: Commands, prep_retry: retry::RetryPolicy) {
+    if display_api::is_mock() && !command.runs_on_mock() {
+        eprintln!("This command doesn't go through the display API and can't run with --mock");
+        ExitCode::Unsupported.exit();
+    }
+    if display_api::is_dry_run() && command.writes_outside_display_api() {
+        eprintln!("This command changes settings or files outside the display API and can't run with --dry-run");
+        ExitCode::Unsupported.exit();
+    }
+    let changes_displays = command.changes_displays();
+
+    match command {
         Commands::Test { subcommand } => match subcommand {
             TestCommands::Echo { message } => {
                 info!("Echo test command received with message: {}", message);
                 println!("Echo: {}", message);
             }
-            TestCommands::EnumerateDisplays => {
+            TestCommands::EnumerateDisplays { json, format, wide, all } => {
+                let format = if json { OutputFormat::Json } else { format };
                 info!("Display enumeration test initiated");
                 let displays = enumerate_displays();
+                let inactive_outputs = if all { displays_info::enumerate_inactive_outputs() } else { Vec::new() };
+                // The virtual display drivers are real devices, not part of a mock fixture
+                let virtual_devices = if display_api::is_mock() { Vec::new() } else {
+                    vdd::list_virtual_display_devices().unwrap_or_else(|e| {
+                        error!("Failed to list virtual display drivers: {}", e);
+                        Vec::new()
+                    })
+                };
+
+                let active_displays = |driver| displays.iter().filter(|d| d.virtual_driver() == Some(driver)).count();
 
-                match displays.len() {
-                    0 => {
-                        println!("Error: No displays found!");
+                if matches!(format, OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Psobject) {
+                    let document = schema::displays_document(&displays, all.then_some(&inactive_outputs[..]), &virtual_devices);
+                    // Like CSV, displays only, the inactive outputs with --all after them
+                    if format == OutputFormat::Psobject {
+                        let records = document["displays"].as_array().into_iter().flatten()
+                            .chain(document["inactive_outputs"].as_array().into_iter().flatten())
+                            .cloned().collect::<Vec<_>>();
+                        table::print_records(&records.into());
+                        return;
                     }
-                    _ => {
-                        println!("\nDisplay Information:");
-                        println!("-------------------");
-
-                        for display in displays {
-                            println!("\nDevice Index: {}", display.device_index);
-                            println!("Name: {}", display.device_name);
-                            println!("Description: {}", display.device_string);
-                            println!("Primary Display: {}", if display.is_primary { "Yes" } else { "No" });
-                            println!("Current Resolution: {}x{}",
-                                     display.current_resolution.0,
-                                     display.current_resolution.1);
-                            println!("Refresh Rate: {}Hz", display.current_refresh_rate);
-                            println!("State Flags: {:#010x}", display.state_flags);
+                    table::print_value(&schema::versioned(document), format == OutputFormat::Yaml);
+                    return;
+                }
+
+                // One table only, so no virtual display drivers. The virtual_driver column still shows which displays are virtual.
+                if format == OutputFormat::Csv {
+                    let mut rows: Vec<Vec<String>> = displays.iter().map(|display| vec![
+                        display.device_index.to_string(),
+                        display.device_name.clone(),
+                        display.device_string.clone(),
+                        display.adapter_index.to_string(),
+                        displays_info::luid_text(display.adapter_id),
+                        display.get_target_device_name().map(|name| name.friendly_name).unwrap_or_default(),
+                        display.is_primary.to_string(),
+                        display.current_resolution.0.to_string(),
+                        display.current_resolution.1.to_string(),
+                        display.current_refresh_rate.to_string(),
+                        display.virtual_driver().map(|driver| format!("{:?}", driver)).unwrap_or_default(),
+                        display.connection_name().to_string(),
+                        display.position.0.to_string(),
+                        display.position.1.to_string(),
+                        display.stable_id().unwrap_or_default(),
+                    ]).collect();
+                    let mut headers = vec!["device_index", "device_name", "description", "adapter_index", "adapter_luid", "monitor", "primary", "width", "height",
+                        "refresh_rate", "virtual_driver", "connection", "position_x", "position_y", "display_id"];
+                    // With --all a status column, and the inactive outputs with only what they have
+                    if all {
+                        headers.push("status");
+                        rows.iter_mut().for_each(|row| row.push("active".to_string()));
+                        let empty = String::new;
+                        rows.extend(inactive_outputs.iter().map(|output| vec![
+                            empty(),
+                            output.device_name.clone(),
+                            output.adapter.clone(),
+                            empty(),
+                            displays_info::luid_text(output.adapter_id),
+                            output.monitor.clone(),
+                            empty(), empty(), empty(), empty(),
+                            output.virtual_driver().map(|driver| format!("{:?}", driver)).unwrap_or_default(),
+                            empty(), empty(), empty(), empty(),
+                            output.status.name().to_string(),
+                        ]));
+                    }
+                    table::print_csv(&headers, &rows);
+                    return;
+                }
+
+                if displays.is_empty() {
+                    eprintln!("Error: No displays found!");
+                } else {
+                    let mut headers = vec!["Index", "Name", "Monitor", "Primary", "Resolution", "Refresh", "Virtual"];
+                    if wide {
+                        headers.extend(["Adapter", "Adapter LUID", "Connection", "HDR", "Display ID", "Position", "State Flags"]);
+                    }
+                    let mut displays_table = Table::new(headers);
+
+                    for display in &displays {
+                        let monitor = display.get_target_device_name().map(|name| name.friendly_name).unwrap_or_default();
+                        let mut row = vec![
+                            Cell::new(display.device_index),
+                            Cell::colored(&display.device_name, Color::Cyan),
+                            Cell::new(monitor),
+                            if display.is_primary { Cell::colored("Yes", Color::Green) } else { Cell::new("No") },
+                            Cell::new(format!("{}x{}", display.current_resolution.0, display.current_resolution.1)),
+                            Cell::new(format!("{}Hz", display.current_refresh_rate)),
+                            match display.virtual_driver() {
+                                Some(driver) => Cell::colored(format!("{:?}", driver), Color::Yellow),
+                                None => Cell::new("No"),
+                            },
+                        ];
+                        if wide {
+                            let hdr_cell = match hdr::get_advanced_color_info(display) {
+                                Ok(info) if info.enabled => Cell::colored("On", Color::Green),
+                                Ok(info) if info.force_disabled => Cell::colored("Blocked", Color::Red),
+                                Ok(info) if info.supported => Cell::new("Off"),
+                                Ok(_) => Cell::colored("Unsupported", Color::Dim),
+                                Err(_) => Cell::colored("Unknown", Color::Dim),
+                            };
+                            row.extend([
+                                Cell::new(format!("{}: {}", display.adapter_index, display.device_string)),
+                                Cell::new(displays_info::luid_text(display.adapter_id)),
+                                Cell::new(display.connection_name()),
+                                hdr_cell,
+                                Cell::new(display.stable_id().unwrap_or_default()),
+                                Cell::new(format!("{},{}", display.position.0, display.position.1)),
+                                Cell::new(format!("{:#010x}", display.state_flags)),
+                            ]);
                         }
+                        displays_table.add_row(row);
                     }
+                    displays_table.print();
                 }
-            }
-            TestCommands::PrimaryDisplayModes => {
-                match displays_info::get_primary_display_info() {
-                    Some((primary, modes)) => {
-                        println!("\nPrimary Display Information:");
-                        println!("-------------------------");
-                        println!("Name: {} ({})", primary.device_name, primary.device_string);
-                        println!("Current: {}x{} @{}Hz",
-                                 primary.current_resolution.0,
-                                 primary.current_resolution.1,
-                                 primary.current_refresh_rate
-                        );
-                        println!("\nSupported Modes:");
-                        for mode in &modes {
-                            println!("  {}x{} @{}Hz", mode.width, mode.height, mode.refresh_rate);
+
+                if all {
+                    println!("\nInactive outputs:");
+                    if inactive_outputs.is_empty() {
+                        println!("None, every connected monitor is in use");
+                    } else {
+                        let mut outputs_table = Table::new(vec!["Status", "Name", "Monitor", "Adapter", "Virtual"]);
+                        for output in &inactive_outputs {
+                            outputs_table.add_row(vec![
+                                match output.status {
+                                    displays_info::OutputStatus::Inactive => Cell::colored(output.status.name(), Color::Yellow),
+                                    displays_info::OutputStatus::Disconnected => Cell::colored(output.status.name(), Color::Dim),
+                                },
+                                Cell::new(&output.device_name),
+                                Cell::new(&output.monitor),
+                                Cell::new(&output.adapter),
+                                match output.virtual_driver() {
+                                    Some(driver) => Cell::colored(format!("{:?}", driver), Color::Yellow),
+                                    None => Cell::new("No"),
+                                },
+                            ]);
                         }
+                        outputs_table.print();
                     }
-                    None => {
-                        println!("Error: Failed to get primary display information");
+                }
+
+                if !virtual_devices.is_empty() {
+                    println!("\nVirtual Display Drivers:");
+                    let mut drivers_table = Table::new(vec!["Driver", "Description", "Status", "Active Displays"]);
+                    for device in &virtual_devices {
+                        drivers_table.add_row(vec![
+                            Cell::colored(format!("{:?}", device.driver), Color::Yellow),
+                            Cell::new(&device.description),
+                            Cell::new(format!("{:?}", device.status)),
+                            Cell::new(active_displays(device.driver)),
+                        ]);
                     }
+                    drivers_table.print();
                 }
             }
-            TestCommands::ListICCProfiles => {
-                info!("ICC profile enumeration test initiated");
-                if let Some((primary, _)) = displays_info::get_primary_display_info() {
+            TestCommands::PrimaryDisplayModes { format, filter } => {
+                let Some((primary, modes)) = displays_info::get_primary_display_info() else {
+                    eprintln!("Error: Failed to get primary display information");
+                    ExitCode::DisplayNotFound.exit();
+                };
+                print_modes("Primary display", &primary, &filter.apply(&primary, modes), format);
+            }
+            TestCommands::ListICCProfiles { all: true } => {
+                info!("System-wide ICC profile enumeration test initiated");
+                let profiles = change_icc_profile::list_all_icc_profiles();
+                if profiles.is_empty() {
+                    eprintln!("No ICC profiles found in {}", icc_file::color_directory().display());
+                    return;
+                }
+                let short = |device_name: &String| device_name.trim_start_matches("\\\\.\\").to_string();
+                let mut profiles_table = Table::new(vec!["Name", "Associated With", "Default For", "HDR Calibration"]);
+                for profile in profiles {
+                    let color = if profile.default_for.is_empty() { Color::Plain } else { Color::Green };
+                    profiles_table.add_row(vec![
+                        Cell::colored(&profile.name, color),
+                        Cell::new(profile.displays.iter().map(short).collect::<Vec<_>>().join(", ")),
+                        Cell::colored(profile.default_for.iter().map(short).collect::<Vec<_>>().join(", "), color),
+                        Cell::new(if icc_file::is_hdr_calibration_file(&profile.path) { "Yes" } else { "" }),
+                    ]);
+                }
+                profiles_table.print();
+            }
+            TestCommands::ListICCProfiles { all: false } => {
+                info!("ICC profile enumeration test initiated");
+                if let Some((primary, _)) = displays_info::get_primary_display_info() {
                     let profiles = change_icc_profile::list_icc_profiles();
 
                     match profiles.len() {
-                        0 => println!("No ICC profiles found for primary display"),
+                        0 => eprintln!("No ICC profiles found for primary display"),
                         _ => {
-                            println!("\nICC Profiles for Primary Display:");
-                            println!("--------------------------------");
-                            println!("Display: {} ({})", primary.device_name, primary.device_string);
-
+                            println!("Primary display: {} ({})\n", primary.device_name, primary.device_string);
+                            let default_profile = change_icc_profile::get_display_default_icc_profile(&primary);
+                            let hdr_profile = change_icc_profile::get_display_default_hdr_icc_profile(&primary);
+                            let mut profiles_table = Table::new(vec!["Name", "Path", "Default", "HDR Calibration"]);
                             for (profile_name, profile_path) in profiles {
-                                println!("\nProfile Name: {}", profile_name);
-                                println!("Profile Path: {}", profile_path.display());
+                                let is_default = default_profile.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(&profile_name));
+                                let color = if is_default { Color::Green } else { Color::Plain };
+                                // Calibration profiles only apply as the HDR profile, see set-hdr-calibration
+                                let calibration = match icc_file::is_hdr_calibration_file(&profile_path) {
+                                    false => Cell::new(""),
+                                    true if hdr_profile.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(&profile_name)) => Cell::colored("Yes, active", Color::Green),
+                                    true => Cell::colored("Yes", Color::Yellow),
+                                };
+                                profiles_table.add_row(vec![
+                                    Cell::colored(&profile_name, color),
+                                    Cell::new(profile_path.display()),
+                                    Cell::colored(if is_default { "*" } else { "" }, color),
+                                    calibration,
+                                ]);
                             }
+                            profiles_table.print();
                         }
                     }
                 } else {
-                    println!("Error: Failed to get primary display information");
+                    eprintln!("Error: Failed to get primary display information");
                 }
             }
             TestCommands::QueryDisplayConfig => { //TODO: Remove this test command
                 info!("QueryDisplayConfig test initiated");
                 if let Err(e) = displays_info::test_query_display_config() {
-                    println!("Error querying display config: {}", e);
+                    eprintln!("Error querying display config: {}", e);
+                }
+            }
+            TestCommands::EdidInfo { raw } => {
+                info!("EDID info test initiated");
+                let displays = enumerate_displays();
+
+                if displays.is_empty() {
+                    eprintln!("Error: No displays found!");
+                }
+
+                for display in displays {
+                    println!("\nDisplay: {} ({})", display.device_name, display.device_string);
+                    println!("-------------------");
+                    if let Some(target_name) = display.get_target_device_name() {
+                        println!("Monitor: {}", target_name.friendly_name);
+                    }
+
+                    let Some(edid_bytes) = edid::read_display_edid(&display) else {
+                        eprintln!("Error: Failed to read EDID");
+                        continue;
+                    };
+
+                    if raw {
+                        for (line, chunk) in edid_bytes.chunks(16).enumerate() {
+                            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                            println!("  {:04X}: {}", line * 16, hex.join(" "));
+                        }
+                    }
+
+                    let Some(edid) = edid::parse_edid(&edid_bytes) else {
+                        eprintln!("Error: Failed to parse EDID");
+                        continue;
+                    };
+
+                    println!("Display ID: {}", edid.stable_id());
+                    println!("Monitor Name: {}", edid.monitor_name.as_deref().unwrap_or("Unknown"));
+                    println!("Manufacturer: {}", edid.manufacturer);
+                    println!("Product Code: {:04X}", edid.product_code);
+                    println!("Serial: {}", edid.serial_string.clone().unwrap_or_else(|| edid.serial_number.to_string()));
+                    println!("Manufactured: week {} of {}", edid.manufacture_week, edid.manufacture_year);
+                    match &edid.native_mode {
+                        Some(mode) => println!("Native Mode: {}x{}{} @{}Hz", mode.width, mode.height, if mode.interlaced { "i" } else { "" }, mode.refresh_rate),
+                        None => println!("Native Mode: Unknown"),
+                    }
+
+                    match &edid.hdr {
+                        Some(hdr) => {
+                            let mut formats = Vec::new();
+                            if hdr.hdr10 { formats.push("HDR10"); }
+                            if hdr.hlg { formats.push("HLG"); }
+                            println!("HDR Formats: {}", if formats.is_empty() { "None".to_string() } else { formats.join(", ") });
+                            println!("BT.2020: {}", if hdr.bt2020 { "Yes" } else { "No" });
+                            println!("SDR EOTF: {}", if hdr.sdr { "Yes" } else { "No" });
+                            if let Some(max) = hdr.max_luminance {
+                                println!("Max Luminance: {:.0} nits", max);
+                            }
+                            if let Some(avg) = hdr.max_frame_avg_luminance {
+                                println!("Max Frame-Average Luminance: {:.0} nits", avg);
+                            }
+                            if let Some(min) = hdr.min_luminance {
+                                println!("Min Luminance: {:.4} nits", min);
+                            }
+                        }
+                        None => println!("HDR Formats: None (no HDR static metadata block)"),
+                    }
+                }
+            }
+            TestCommands::SelfTest => {
+                info!("Self-test initiated");
+                let steps = self_test::run(prep_retry);
+                let mut steps_table = Table::new(vec!["Step", "Result", "Detail"]);
+                for step in &steps {
+                    let (result, detail) = match &step.outcome {
+                        self_test::Outcome::Passed(detail) => (Cell::colored("PASS", Color::Green), detail),
+                        self_test::Outcome::Skipped(detail) => (Cell::colored("SKIP", Color::Dim), detail),
+                        self_test::Outcome::Failed(detail) => (Cell::colored("FAIL", Color::Red), detail),
+                    };
+                    steps_table.add_row(vec![Cell::new(step.name), result, Cell::new(detail)]);
+                }
+                steps_table.print();
+
+                let failures = steps.iter().filter(|step| matches!(step.outcome, self_test::Outcome::Failed(_))).count();
+                if failures > 0 {
+                    eprintln!("\nSelf-test failed: {} step(s) failed", failures);
+                    ExitCode::Failure.exit();
+                }
+                eprintln!("\nSelf-test passed");
+            }
+            TestCommands::Bench { runs } => {
+                info!("Benchmark initiated ({} runs)", runs);
+                let measurements = match bench::run(runs) {
+                    Ok(measurements) => measurements,
+                    Err(e) => {
+                        eprintln!("Benchmark failed: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                };
+
+                let millis = |duration: Option<std::time::Duration>| duration.map(|d| format!("{} ms", d.as_millis())).unwrap_or_default();
+                let mut bench_table = Table::new(vec!["Operation", "Runs", "p50", "p95", "Max"]);
+                for measurement in &measurements {
+                    let color = if measurement.skipped.is_some() { Color::Dim } else { Color::Plain };
+                    bench_table.add_row(vec![
+                        Cell::colored(measurement.operation, color),
+                        Cell::colored(measurement.samples.len(), color),
+                        Cell::new(millis(measurement.percentile(50))),
+                        Cell::new(millis(measurement.percentile(95))),
+                        Cell::new(millis(measurement.max())),
+                    ]);
+                }
+                bench_table.print();
+                eprintln!();
+                for measurement in &measurements {
+                    if let Some(reason) = &measurement.skipped {
+                        eprintln!("{} skipped: {}", measurement.operation, reason);
+                    }
+                }
+                eprintln!("Mode switch times include the settle wait. Budget prep-cmd timeouts from p95 or max, not p50.");
+            }
+        }
+        Commands::Capabilities => {
+            let build = windows_version::build();
+            match build {
+                Some(build) => println!("Windows: {}", windows_version::describe(build)),
+                None => println!("Windows: unknown build, assuming a current one"),
+            }
+            let mut table = Table::new(vec!["Feature", "Available", "Requires"]);
+            for feature in Feature::ALL {
+                let available = match feature.check(build) {
+                    Ok(()) => Cell::colored("Yes", Color::Green),
+                    Err(_) if feature == Feature::SdrWhiteLevelSet => Cell::colored("Registry fallback", Color::Yellow),
+                    Err(_) => Cell::colored("No", Color::Red),
+                };
+                table.add_row(vec![
+                    Cell::new(feature.name()),
+                    available,
+                    Cell::new(format!("{}+", windows_version::release_name(feature.min_build()))),
+                ]);
+            }
+            table.print();
+        }
+        Commands::Status { detailed, format } => {
+            info!("Status command received (detailed: {})", detailed);
+            if format != DocumentFormat::Text {
+                match apply::current_spec().map(|spec| serde_json::to_value(&spec).unwrap_or_default()) {
+                    Ok(spec) if format == DocumentFormat::Psobject => table::print_records(&spec["displays"]),
+                    Ok(spec) => table::print_value(&schema::versioned(spec), format == DocumentFormat::Yaml),
+                    Err(e) => {
+                        eprintln!("Failed to read the current display state: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+                return;
+            }
+
+            let displays = enumerate_displays();
+            if displays.is_empty() {
+                eprintln!("Error: No displays found!");
+                ExitCode::DisplayNotFound.exit();
+            }
+
+            let color_info = if detailed && !display_api::is_mock() {
+                match dxgi_info::query_output_color_info() {
+                    Ok(outputs) => outputs,
+                    Err(e) => {
+                        eprintln!("Failed to query DXGI outputs: {}", e);
+                        Vec::new()
+                    }
+                }
+            } else {
+                Vec::new()
+            };
+
+            for display in displays {
+                println!("\n{} ({}){}", display.device_name, display.device_string,
+                         if display.is_primary { " [Primary]" } else { "" });
+                println!("  Mode: {}x{} @{}Hz",
+                         display.current_resolution.0,
+                         display.current_resolution.1,
+                         display.current_refresh_rate);
+                if let Ok(scaling) = dpi_scaling::get_display_scaling(&display) {
+                    println!("  Scaling: {}% (recommended {}%)", scaling.current, scaling.recommended);
+                }
+
+                if !detailed {
+                    continue;
+                }
+
+                match color_info.iter().find(|o| o.device_name == display.device_name) {
+                    Some(output) => {
+                        println!("  Adapter: {}", output.adapter_name);
+                        println!("  HDR Active: {}", if output.is_hdr_active() { "Yes" } else { "No" });
+                        println!("  Color Space: {} ({})", output.color_space_name(), output.color_space.0);
+                        println!("  Bits Per Color: {}", output.bits_per_color);
+                        println!("  Luminance: min {:.4} / max {:.0} / max full-frame {:.0} nits",
+                                 output.min_luminance, output.max_luminance, output.max_full_frame_luminance);
+                        println!("  Primaries: R({:.3}, {:.3}) G({:.3}, {:.3}) B({:.3}, {:.3}) W({:.4}, {:.4})",
+                                 output.red_primary[0], output.red_primary[1],
+                                 output.green_primary[0], output.green_primary[1],
+                                 output.blue_primary[0], output.blue_primary[1],
+                                 output.white_point[0], output.white_point[1]);
+                    }
+                    None => println!("  No DXGI output information available"),
+                }
+            }
+        }
+        Commands::Report { selector, format, output } => {
+            info!("Report command received for selector '{}'", selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Error: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let report = report::build(&display);
+            let contents = match format {
+                report::ReportFormat::Markdown => report::markdown(&report),
+                report::ReportFormat::Json => format!("{}\n", serde_json::to_string_pretty(&schema::versioned(report)).unwrap_or_default()),
+            };
+            match output {
+                Some(path) => match std::fs::write(&path, contents) {
+                    Ok(()) => eprintln!("Wrote the report on {} to {}", display.device_name, path.display()),
+                    Err(e) => {
+                        eprintln!("Failed to write {}: {}", path.display(), e);
+                        ExitCode::Failure.exit();
+                    }
+                },
+                None => print!("{}", contents),
+            }
+        }
+        Commands::TestPattern { selector, format, duration } => {
+            info!("Test pattern command received for selector '{}' ({:?})", selector, format);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to show the test pattern: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let hdr_enabled = hdr::get_advanced_color_info(&display).is_ok_and(|info| info.enabled);
+            let sdr_white = set_sdr_level::get_display_sdr_white_raw(&display).map(set_sdr_level::raw_to_nits).unwrap_or(80);
+            let levels = |levels: &[f64]| levels.iter().map(|nits| format!("{}", nits)).collect::<Vec<_>>().join(", ");
+            eprintln!("Test pattern on {}, left to right (press Esc or click to close):", display.device_name);
+            eprintln!("  Top row: white at {} nits", levels(&test_pattern::WHITE_LEVELS));
+            eprintln!("  Middle row: a gradient from black to the SDR white level ({} nits), every step should be visible", sdr_white);
+            eprintln!("  Bottom row: {} nits, bars that look the same have clipped", levels(&test_pattern::CLIPPING_LEVELS));
+            if !hdr_enabled {
+                eprintln!("HDR is off on {}, the patches are only accurate with HDR on and the brighter ones clip to white", display.device_name);
+            }
+            if let Err(e) = test_pattern::show_test_pattern(&display, format, sdr_white as f64, duration) {
+                eprintln!("Failed to show the test pattern: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Overlay { selector, duration } => {
+            info!("Overlay command received for selector '{}' ({}s)", selector, duration);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to show the overlay: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            if let Err(e) = identify::show_settings_readout(&display, duration) {
+                eprintln!("Failed to show the overlay: {}", e);
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Identify { duration } => {
+            info!("Identify command received (duration: {}s)", duration);
+            if let Err(e) = identify::identify_displays(duration) {
+                eprintln!("Failed to identify displays: {}", e);
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::DisableDisplay { selector } => {
+            info!("Disable display command received for selector '{}'", selector);
+            match display_config::disable_display(&selector) {
+                Ok(display) => eprintln!("Successfully disabled {} ({})", display.device_name, display.friendly_name),
+                Err(e) => {
+                    eprintln!("Failed to disable display: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::EnableDisplay { selector } => {
+            info!("Enable display command received for selector '{}'", selector);
+            match display_config::enable_display(&selector) {
+                Ok(display) => eprintln!("Successfully enabled {} ({})", display.device_name, display.friendly_name),
+                Err(e) => {
+                    eprintln!("Failed to enable display: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::SetPrimary { selector } => {
+            info!("Set primary command received for selector '{}'", selector);
+            match display_config::set_primary_display(&selector) {
+                Ok(display) => eprintln!("Successfully set {} as the primary display", display.device_name),
+                Err(e) => {
+                    eprintln!("Failed to set primary display: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::SetPosition { selector, x, y } => {
+            info!("Set position command received for selector '{}': ({}, {})", selector, x, y);
+            let position = display_config::DisplayPosition { display: selector.clone(), x, y };
+            match display_config::set_display_positions(&[position]) {
+                Ok(()) => eprintln!("Successfully moved '{}' to ({}, {})", selector, x, y),
+                Err(e) => {
+                    eprintln!("Failed to set display position: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Apply { file, no_rollback } => {
+            info!("Apply command received with file: {}", file.display());
+            let result = apply::load_spec_file(&file).and_then(|spec| apply::differences(&spec)).and_then(|differences| {
+                for difference in &differences {
+                    eprintln!("{}: {} (was {})", difference.display, difference.desired, difference.current);
+                }
+                apply::apply_with_undo(&differences, prep_retry, !no_rollback).map(|()| differences.len())
+            });
+            match result {
+                Ok(0) => eprintln!("Displays already match {}", file.display()),
+                Ok(count) => eprintln!("Successfully applied {} change(s) from {}", count, file.display()),
+                Err(e) => {
+                    eprintln!("Failed to apply {}: {}", file.display(), e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Run { script: source } => {
+            info!("Run command received with script: {}", source.display());
+            let lines = match script::read_script(&source) {
+                Ok(lines) => lines,
+                Err(e) => {
+                    eprintln!("Failed to read script {}: {}", source.display(), e);
+                    ExitCode::InvalidInput.exit();
+                }
+            };
+            // A failing line exits and leaves the undo file, a crashing one restores it while unwinding
+            let snapshot = apply::UndoSnapshot::save(&[], prep_retry);
+            let _in_progress = interrupt::in_progress();
+            // Each line runs like a separate invocation, so a failing command stops the script
+            for (line_number, line) in lines {
+                if interrupt::is_interrupted() {
+                    eprintln!("Interrupted before line {} of {}", line_number, source.display());
+                    if let Some(Err(e)) = snapshot.map(apply::UndoSnapshot::restore) {
+                        eprintln!("Failed to undo the earlier lines: {}", e.message());
+                    }
+                    ExitCode::Interrupted.exit();
+                }
+                let parsed = script::split_command_line(&line).and_then(|args| {
+                    parse_cli(std::iter::once("sunshine-helper".to_string()).chain(args)).map_err(|e| e.to_string())
+                });
+                let line_cli = match parsed {
+                    Ok(line_cli) => line_cli,
+                    Err(e) => {
+                        eprintln!("Failed to parse line {} of {}: {}", line_number, source.display(), e.trim_end());
+                        ExitCode::InvalidInput.exit();
+                    }
+                };
+                let line_command = match line_cli.command {
+                    Some(Commands::Run { .. }) => {
+                        eprintln!("Failed to run line {} of {}: scripts can't run other scripts", line_number, source.display());
+                        ExitCode::InvalidInput.exit();
+                    }
+                    Some(line_command) => line_command,
+                    None => {
+                        eprintln!("Failed to run line {} of {}: no command given", line_number, source.display());
+                        ExitCode::InvalidInput.exit();
+                    }
+                };
+
+                let line_retry = match (line_cli.retries, line_cli.retry_delay) {
+                    (None, None) => prep_retry,
+                    (retries, retry_delay) => retry::RetryPolicy::from_args(retries, retry_delay, retry::PREP_RETRIES),
+                };
+                let _span = info_span!("script_line", line = line_number).entered();
+                info!("Running line {}: {}", line_number, line);
+                eprintln!("> {}", line);
+                run_command(line_command, line_retry);
+            }
+            if let Some(snapshot) = snapshot {
+                snapshot.finish();
+            }
+        }
+        Commands::EventLog { action } => {
+            info!("Event log command received: {:?}", action);
+            let result = match action {
+                EventLogAction::Register => event_log::register_event_source()
+                    .map(|()| eprintln!("Successfully registered event source '{}'", event_log::EVENT_SOURCE)),
+                EventLogAction::Unregister => event_log::unregister_event_source()
+                    .map(|()| eprintln!("Successfully unregistered event source '{}'", event_log::EVENT_SOURCE)),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update event source: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Completions { shell } => {
+            print!("{}", completions::completion_script(shell));
+        }
+        Commands::Complete { words } => {
+            let mut command = cli_command();
+            command.build();
+            for candidate in completions::complete(&command, &words) {
+                println!("{}", candidate);
+            }
+        }
+        Commands::Schema { command } => {
+            let document = match command {
+                Some(command) => schema::schema(command),
+                None => serde_json::Value::Object(schema::SchemaCommand::ALL.iter()
+                    .map(|command| (command.name().to_string(), schema::schema(*command)))
+                    .collect()),
+            };
+            println!("{}", serde_json::to_string_pretty(&document).unwrap_or_default());
+        }
+        Commands::Setup { config, sunshine_dir } => {
+            info!("Setup command received");
+            let options = setup::SetupOptions {
+                config_path: config.unwrap_or_else(config::config_file_path),
+                sunshine_dir: sunshine_dir.unwrap_or_else(sunshine::default_config_dir),
+            };
+            if let Err(e) = setup::run(&options, prep_retry) {
+                eprintln!("Setup failed: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Gui => {
+            info!("Gui command received");
+            #[cfg(feature = "gui")]
+            if let Err(e) = gui::run(prep_retry) {
+                eprintln!("The window failed: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+            #[cfg(not(feature = "gui"))]
+            {
+                eprintln!("This build doesn't include the window, rebuild with `--features gui`");
+                ExitCode::Unsupported.exit();
+            }
+        }
+        Commands::Sunshine { subcommand } => match subcommand {
+            SunshineCommands::GenerateConfig { config, apps, write, sunshine_dir } => {
+                info!("Sunshine generate-config command received");
+                let config_path = config.clone().unwrap_or_else(config::config_file_path);
+                let helper_config = match config::load(&config_path) {
+                    Ok(helper_config) => Some(helper_config),
+                    Err(e) => {
+                        eprintln!("Note: {} (sunshine-prep has nothing to do until it exists)", e.message());
+                        None
+                    }
+                };
+                // --apps on its own means the apps the config has presets for
+                let apps = match apps {
+                    Some(names) if names.is_empty() => {
+                        let names: Vec<String> = helper_config.iter().flat_map(|helper_config| helper_config.apps.keys().cloned()).collect();
+                        if names.is_empty() {
+                            eprintln!("{} has no [apps] sections, name the apps after --apps", config_path.display());
+                            ExitCode::InvalidInput.exit();
+                        }
+                        Some(names)
+                    }
+                    apps => apps,
+                };
+
+                // Sunshine runs prep commands in its own working directory
+                let config = config.map(|config| std::env::current_dir().map_or(config.clone(), |dir| dir.join(&config)));
+                let exe = std::env::current_exe().unwrap_or_else(|_| "sunshine_helper.exe".into());
+                let commands = sunshine::prep_commands(&exe, config.as_deref());
+                let dir = sunshine_dir.unwrap_or_else(sunshine::default_config_dir);
+                let result = match (apps, write) {
+                    (None, false) => {
+                        eprintln!("Add this line to {}, replacing any global_prep_cmd line already there:", dir.join(sunshine::SUNSHINE_CONF).display());
+                        println!("{}", sunshine::global_prep_cmd_line(&commands));
+                        Ok(())
+                    }
+                    (Some(names), false) => {
+                        eprintln!("Add this to {} in {}:", names.join(", "), dir.join(sunshine::APPS_JSON).display());
+                        println!("{}", sunshine::prep_cmd_json(&commands));
+                        Ok(())
+                    }
+                    (None, true) => {
+                        let path = dir.join(sunshine::SUNSHINE_CONF);
+                        sunshine::read_config_file(&path)
+                            .and_then(|contents| sunshine::patch_sunshine_conf(&contents, &commands)
+                                .map_err(|e| exit_code::invalid_input(format!("Failed to update {}: {}", path.display(), e))))
+                            .and_then(|contents| sunshine::write_with_backup(&path, &contents))
+                            .map(|()| eprintln!("Updated global_prep_cmd in {}, restart Sunshine to pick it up", path.display()))
+                    }
+                    (Some(names), true) => {
+                        let path = dir.join(sunshine::APPS_JSON);
+                        sunshine::read_config_file(&path)
+                            .and_then(|contents| sunshine::patch_apps_json(&contents, &commands, &names)
+                                .map_err(|e| exit_code::invalid_input(format!("Failed to update {}: {}", path.display(), e))))
+                            .and_then(|(contents, patched)| sunshine::write_with_backup(&path, &contents).map(|()| patched))
+                            .map(|patched| eprintln!("Updated prep-cmd of {} in {}, restart Sunshine to pick it up", patched.join(", "), path.display()))
+                    }
+                };
+                if let Err(e) = result {
+                    eprintln!("{}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+            SunshineCommands::Validate { config, sunshine_dir } => {
+                let dir = sunshine_dir.unwrap_or_else(sunshine::default_config_dir);
+                let conf_path = dir.join(sunshine::SUNSHINE_CONF);
+                info!("Sunshine validate command received for {}", conf_path.display());
+                let settings = match sunshine::read_config_file(&conf_path) {
+                    Ok(contents) => sunshine::parse_conf(&contents),
+                    Err(e) => {
+                        eprintln!("{}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                };
+                let apps_path = dir.join(sunshine::APPS_JSON);
+                let apps = std::fs::read_to_string(&apps_path).ok().and_then(|contents| match serde_json::from_str(&contents) {
+                    Ok(apps) => Some(apps),
+                    Err(e) => {
+                        eprintln!("warning: {} isn't valid JSON, skipping it: {}", apps_path.display(), e);
+                        None
+                    }
+                });
+                // A config named on the command line has to load, the default one is optional
+                let config_path = config.clone().unwrap_or_else(config::config_file_path);
+                let helper_config = match config::load(&config_path) {
+                    Ok(helper_config) => Some(helper_config),
+                    Err(e) if config.is_some() || config_path.exists() => {
+                        eprintln!("{}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                    Err(_) => None,
+                };
+
+                let issues = sunshine::validate(&settings, apps.as_ref(), helper_config.as_ref());
+                for issue in &issues {
+                    match issue.severity {
+                        sunshine::Severity::Error => eprintln!("error: {}", issue.message),
+                        sunshine::Severity::Warning => eprintln!("warning: {}", issue.message),
+                    }
+                }
+                let errors = issues.iter().filter(|issue| issue.severity == sunshine::Severity::Error).count();
+                match (errors, issues.len()) {
+                    (_, 0) => eprintln!("Sunshine's configuration matches the connected displays"),
+                    (0, warnings) => eprintln!("{} warning(s), streams may still start but some clients can get a black screen", warnings),
+                    (errors, total) => {
+                        eprintln!("{} error(s) and {} warning(s)", errors, total - errors);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            }
+        },
+        Commands::Config { subcommand } => match subcommand {
+            ConfigCommands::Validate { config } => {
+                let path = config.unwrap_or_else(config::config_file_path);
+                info!("Config validate command received for {}", path.display());
+                let helper_config = match config::load(&path) {
+                    Ok(helper_config) => helper_config,
+                    Err(e) => {
+                        eprintln!("error: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                };
+
+                let mut issues = helper_config.check_profiles(&enumerate_displays());
+                if config::stored_version(&path).is_ok_and(|version| version < config::CONFIG_VERSION) {
+                    issues.push(sunshine::Issue::warning(format!("{} is from an older version of the helper, `config upgrade` rewrites it as config version {}", path.display(), config::CONFIG_VERSION)));
+                }
+                issues.extend(helper_config.check_rules());
+                issues.extend(check_defaults(&helper_config.defaults));
+                for issue in &issues {
+                    match issue.severity {
+                        sunshine::Severity::Error => eprintln!("error: {}", issue.message),
+                        sunshine::Severity::Warning => eprintln!("warning: {}", issue.message),
+                    }
+                }
+                let errors = issues.iter().filter(|issue| issue.severity == sunshine::Severity::Error).count();
+                match (errors, issues.len()) {
+                    (_, 0) => eprintln!("{} is valid (config version {})", path.display(), config::CONFIG_VERSION),
+                    (0, warnings) => eprintln!("{} warning(s)", warnings),
+                    (errors, total) => {
+                        eprintln!("{} error(s) and {} warning(s)", errors, total - errors);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            }
+            ConfigCommands::Upgrade { config } => {
+                let path = config.unwrap_or_else(config::config_file_path);
+                info!("Config upgrade command received for {}", path.display());
+                match config::upgrade(&path) {
+                    Ok(Some(backup)) => eprintln!("Successfully upgraded {} to config version {}, the old file is {}", path.display(), config::CONFIG_VERSION, backup.display()),
+                    Ok(None) => eprintln!("{} is already config version {} (unchanged)", path.display(), config::CONFIG_VERSION),
+                    Err(e) => {
+                        eprintln!("Failed to upgrade {}: {}", path.display(), e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+        },
+        Commands::SunshinePrep { config, client, app, no_rollback } => {
+            let path = config.unwrap_or_else(config::config_file_path);
+            let mut session = config::Session::from_env();
+            if client.is_some() {
+                session.client_name = client;
+            }
+            if app.is_some() {
+                session.app_name = app;
+            }
+            info!("Sunshine prep command received for client {:?} ({:?}) streaming {:?} with config {}",
+                session.client_name, session.client_uuid, session.app_name, path.display());
+            let config = match config::load(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            };
+            let Some(spec) = config.prep_spec(&session) else {
+                eprintln!("Nothing to set up for {}", session.client_name.as_deref().unwrap_or("this client"));
+                return;
+            };
+            let result = apply::differences(&spec).and_then(|differences| {
+                for difference in &differences {
+                    eprintln!("{}: {} (was {})", difference.display, difference.desired, difference.current);
+                }
+                apply::apply_with_undo(&differences, prep_retry, !no_rollback).map(|()| differences.len())
+            });
+            // Saved even when nothing changed, it's what the stream should look like
+            if result.is_ok() {
+                if let Err(e) = state::save_named_state(sunshine::STREAM_PREP_STATE, &spec) {
+                    warn!("Failed to save the stream's spec, the daemon can't apply it again after a driver reset: {}", e);
+                }
+            }
+            match result {
+                Ok(0) => eprintln!("Displays are already set up"),
+                Ok(count) => eprintln!("Successfully made {} change(s)", count),
+                Err(e) => {
+                    eprintln!("Failed to set up the displays: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::SaveState { name } => {
+            info!("Save state command received with name: {}", name);
+            let spec = match apply::current_spec() {
+                Ok(spec) => spec,
+                Err(e) => {
+                    eprintln!("Failed to read the current display state: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            };
+            match state::save_named_state(&name, &spec) {
+                Ok(()) => eprintln!("Saved the state of {} display(s) as '{}'", spec.displays.len(), name),
+                Err(e) => {
+                    eprintln!("Failed to save state '{}': {}", name, e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+        Commands::RestoreState { name, no_rollback, delete } => {
+            info!("Restore state command received with name: {}", name);
+            let Some(spec) = state::load_named_state::<apply::Spec>(&name) else {
+                eprintln!("No saved state named '{}' (see list-states)", name);
+                ExitCode::InvalidInput.exit();
+            };
+            let result = apply::differences(&spec).and_then(|differences| {
+                for difference in &differences {
+                    eprintln!("{}: {} (was {})", difference.display, difference.desired, difference.current);
+                }
+                apply::apply_with_undo(&differences, prep_retry, !no_rollback).map(|()| differences.len())
+            });
+            match result {
+                Ok(0) => eprintln!("Displays already match '{}'", name),
+                Ok(count) => eprintln!("Successfully restored '{}' ({} change(s))", name, count),
+                Err(e) => {
+                    eprintln!("Failed to restore '{}': {}", name, e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+            if delete {
+                if let Err(e) = state::delete_named_state(&name) {
+                    eprintln!("Failed to delete state '{}': {}", name, e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+        Commands::ListStates => {
+            let states = state::list_named_states();
+            if states.is_empty() {
+                eprintln!("No saved states, save one with save-state <name>");
+                return;
+            }
+            let mut states_table = Table::new(vec!["Name", "Displays", "Saved"]);
+            for (name, saved) in &states {
+                let displays = state::load_named_state::<apply::Spec>(name)
+                    .map_or_else(|| Cell::colored("unreadable", Color::Red), |spec| Cell::new(spec.displays.len()));
+                let saved = saved.map(|saved| chrono::DateTime::<chrono::Local>::from(saved).format("%Y-%m-%d %H:%M").to_string());
+                states_table.add_row(vec![Cell::colored(name, Color::Cyan), displays, Cell::new(saved.unwrap_or_default())]);
+            }
+            states_table.print();
+        }
+        Commands::DeleteState { name } => {
+            match state::delete_named_state(&name) {
+                Ok(true) => eprintln!("Deleted saved state '{}'", name),
+                Ok(false) => {
+                    eprintln!("No saved state named '{}'", name);
+                    ExitCode::InvalidInput.exit();
+                }
+                Err(e) => {
+                    eprintln!("Failed to delete state '{}': {}", name, e);
+                    ExitCode::Failure.exit();
+                }
+            }
+        }
+        Commands::Diff { file, json, format } => {
+            let format = if json { DocumentFormat::Json } else { format };
+            info!("Diff command received with file: {}", file.display());
+            let differences = match apply::load_spec_file(&file).and_then(|spec| apply::differences(&spec)) {
+                Ok(differences) => differences,
+                Err(e) => {
+                    eprintln!("Failed to compare with {}: {}", file.display(), e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            };
+            if format == DocumentFormat::Psobject {
+                table::print_records(&serde_json::to_value(&differences).unwrap_or_default());
+            } else if format != DocumentFormat::Text {
+                let document = schema::versioned(serde_json::json!({ "differences": differences }));
+                table::print_value(&document, format == DocumentFormat::Yaml);
+            } else if differences.is_empty() {
+                eprintln!("Displays match {}", file.display());
+            } else {
+                for difference in &differences {
+                    println!("{}: {} (currently {})", difference.display, difference.desired, difference.current);
+                }
+            }
+        }
+        Commands::Verify { selector, mode, hdr, sdr, icc } => {
+            info!("Verify command received for selector '{}'", selector);
+            if mode.is_none() && hdr.is_none() && sdr.is_none() && icc.is_none() {
+                eprintln!("Nothing to verify, give at least one of --mode, --hdr, --sdr and --icc");
+                ExitCode::InvalidInput.exit();
+            }
+            let expected = apply::DisplaySpec {
+                display: selector.clone(),
+                enabled: None,
+                primary: None,
+                mode,
+                hdr,
+                sdr_nits: sdr.map(set_sdr_level::level_to_nits),
+                icc_profile: icc,
+            };
+            let mismatches = match apply::differences(&apply::Spec { displays: vec![expected] }) {
+                Ok(mismatches) => mismatches,
+                Err(e) => {
+                    eprintln!("Failed to verify '{}': {}", selector, e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            };
+            if mismatches.is_empty() {
+                eprintln!("'{}' is as expected", selector);
+            } else {
+                eprintln!("'{}' isn't as expected:", selector);
+                for mismatch in &mismatches {
+                    eprintln!("  expected {}, currently {}", mismatch.desired, mismatch.current);
+                }
+                ExitCode::Mismatch.exit();
+            }
+        }
+        Commands::Events { interval } => {
+            info!("Events command received (interval: {} ms)", interval);
+            events::run(std::time::Duration::from_millis(interval));
+        }
+        Commands::SetLayout { file } => {
+            info!("Set layout command received with file: {}", file.display());
+            let result = display_config::load_layout_file(&file)
+                .and_then(|layout| display_config::set_display_positions(&layout));
+            match result {
+                Ok(()) => eprintln!("Successfully applied layout from {}", file.display()),
+                Err(e) => {
+                    eprintln!("Failed to apply layout: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::ExportConfig { file } => {
+            info!("Export config command received with file: {}", file.display());
+            match display_config::export_config(&file) {
+                Ok(config) => eprintln!("Exported the display config ({} monitor(s)) to {}", config.targets.len(), file.display()),
+                Err(e) => {
+                    eprintln!("Failed to export the display config: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::ImportConfig { file } => {
+            info!("Import config command received with file: {}", file.display());
+            let result = display_config::load_exported_config(&file)
+                .and_then(|config| display_config::import_config(&config));
+            match result {
+                Ok(()) => eprintln!("Successfully imported the display config from {}", file.display()),
+                Err(e) => {
+                    eprintln!("Failed to import the display config: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Modes { selector, format, filter } => {
+            info!("Modes command received for selector '{}'", selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Error: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let modes = filter.apply(&display, display.get_supported_modes());
+            print_modes("Display", &display, &modes, format);
+        }
+        Commands::SetScaling { selector, percent } => {
+            info!("Set scaling command received for selector '{}': {}%", selector, percent);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to set scaling: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match dpi_scaling::set_display_scaling(&display, percent) {
+                Ok(()) => eprintln!("Successfully set scaling of {} to {}%", display.device_name, percent),
+                Err(e) => {
+                    eprintln!("Failed to set scaling: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::SetGamma { selector, gamma, icc } => {
+            info!("Set gamma command received for selector '{}' (gamma: {:?}, icc: {:?})", selector, gamma, icc);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to set gamma: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+
+            let ramp = match (&icc, gamma) {
+                (Some(profile), _) => match icc_file::load_vcgt_from_profile(profile) {
+                    Some(ramp) => ramp,
+                    None => {
+                        eprintln!("Failed to set gamma: could not load a vcgt table from '{}'", profile);
+                        ExitCode::InvalidInput.exit();
+                    }
+                },
+                (None, Some(gamma)) => gamma_ramp::gamma_ramp(gamma),
+                (None, None) => unreachable!("clap requires a gamma or --icc"),
+            };
+
+            match gamma_ramp::set_display_gamma_ramp(&display, &ramp) {
+                Ok(()) => eprintln!("Successfully set gamma ramp for {}", display.device_name),
+                Err(e) => {
+                    eprintln!("Failed to set gamma: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::ResetGamma { selector } => {
+            info!("Reset gamma command received for selector '{}'", selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to reset gamma: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match gamma_ramp::set_display_gamma_ramp(&display, &gamma_ramp::linear_ramp()) {
+                Ok(()) => eprintln!("Successfully reset gamma ramp for {}", display.device_name),
+                Err(e) => {
+                    eprintln!("Failed to reset gamma: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Topology { topology } => {
+            info!("Topology command received: {:?}", topology);
+            match display_config::set_topology(topology) {
+                Ok(()) => eprintln!("Successfully applied topology {:?}", topology),
+                Err(e) => {
+                    eprintln!("Failed to apply topology: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::SetBrightness { selector, percent } => {
+            info!("Set brightness command received for selector '{}': {}", selector, percent);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to set brightness: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match brightness::set_display_brightness(&display, percent) {
+                Ok(()) => eprintln!("Successfully set brightness of {} to {}", display.device_name, percent),
+                Err(e) => {
+                    eprintln!("Failed to set brightness: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::GetBrightness { selector } => {
+            info!("Get brightness command received for selector '{}'", selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to get brightness: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match brightness::get_display_brightness(&display) {
+                Ok(brightness) => println!("Brightness of {}: {} (raw {}, range {}-{})", display.device_name,
+                    brightness.percent(), brightness.current, brightness.minimum, brightness.maximum),
+                Err(e) => {
+                    eprintln!("Failed to get brightness: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Ddc { subcommand } => match subcommand {
+            DdcCommands::Get { selector, code } => {
+                info!("DDC get command received for selector '{}': code {:02X}", selector, code);
+                let Some(display) = displays_info::find_display(&selector) else {
+                    eprintln!("Failed to read VCP code: no active display matches '{}'", selector);
+                    ExitCode::DisplayNotFound.exit();
+                };
+                match ddc_ci::get_vcp(&display, code) {
+                    Ok(value) => println!("VCP {:02X} ({}) of {}: {} (max {})", code,
+                        ddc_ci::vcp_code_name(code).unwrap_or("unknown"), display.device_name, value.current, value.maximum),
+                    Err(e) => {
+                        eprintln!("Failed to read VCP code: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+            DdcCommands::Set { selector, code, value } => {
+                info!("DDC set command received for selector '{}': code {:02X} = {}", selector, code, value);
+                let Some(display) = displays_info::find_display(&selector) else {
+                    eprintln!("Failed to write VCP code: no active display matches '{}'", selector);
+                    ExitCode::DisplayNotFound.exit();
+                };
+                match ddc_ci::set_vcp(&display, code, value) {
+                    Ok(()) => eprintln!("Successfully set VCP {:02X} of {} to {}", code, display.device_name, value),
+                    Err(e) => {
+                        eprintln!("Failed to write VCP code: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+            DdcCommands::Capabilities { selector, raw } => {
+                info!("DDC capabilities command received for selector '{}'", selector);
+                let Some(display) = displays_info::find_display(&selector) else {
+                    eprintln!("Failed to read capabilities: no active display matches '{}'", selector);
+                    ExitCode::DisplayNotFound.exit();
+                };
+                match ddc_ci::get_capabilities(&display) {
+                    Ok(capabilities) => {
+                        println!("Capabilities of {}:", display.device_name);
+                        println!("  Model: {}", capabilities.model.as_deref().unwrap_or("unknown"));
+                        println!("  MCCS version: {}", capabilities.mccs_version.as_deref().unwrap_or("unknown"));
+                        println!("  VCP features:");
+                        for feature in &capabilities.vcp {
+                            let name = ddc_ci::vcp_code_name(feature.code).unwrap_or("");
+                            if feature.values.is_empty() {
+                                println!("    {:02X} {}", feature.code, name);
+                            } else {
+                                let values: Vec<String> = feature.values.iter().map(|v| format!("{:02X}", v)).collect();
+                                println!("    {:02X} {} [{}]", feature.code, name, values.join(" "));
+                            }
+                        }
+                        if raw {
+                            println!("  Raw: {}", capabilities.raw);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read capabilities: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+        },
+        Commands::EnsureHdr { selector } => {
+            info!("Ensure HDR command received for selector '{}'", selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to ensure HDR: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let color_info = hdr::get_advanced_color_info(&display).unwrap_or_else(|e| {
+                eprintln!("Failed to read the HDR state of {}: {}", display.device_name, e.message());
+                ExitCode::from_error(&e).exit();
+            });
+            if !color_info.supported {
+                eprintln!("{} doesn't support HDR", display.device_name);
+                ExitCode::Unsupported.exit();
+            }
+            if color_info.enabled {
+                eprintln!("HDR is already on for {} (unchanged)", display.device_name);
+                ExitCode::Unchanged.exit();
+            }
+            match retry::with_retries(prep_retry, "HDR change", || hdr::set_hdr(&display, true)) {
+                Ok(()) => eprintln!("Successfully turned HDR on for {}", display.device_name),
+                Err(e) => {
+                    eprintln!("Failed to turn HDR on: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::Hdr { action, selector } => {
+            info!("HDR command received: {:?} for selector '{}'", action, selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update HDR: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let hdr_enabled = hdr::get_advanced_color_info(&display).ok().map(|color_info| color_info.enabled);
+            let result = match action {
+                Toggle::On | Toggle::Off if hdr_enabled == Some(matches!(action, Toggle::On)) => {
+                    eprintln!("HDR is already {} for {} (unchanged)", if matches!(action, Toggle::On) { "on" } else { "off" }, display.device_name);
+                    Ok(())
                 }
+                Toggle::On => retry::with_retries(prep_retry, "HDR change", || hdr::set_hdr(&display, true))
+                    .map(|()| eprintln!("Successfully turned HDR on for {}", display.device_name)),
+                Toggle::Off => retry::with_retries(prep_retry, "HDR change", || hdr::set_hdr(&display, false))
+                    .map(|()| eprintln!("Successfully turned HDR off for {}", display.device_name)),
+                Toggle::Status => hdr::get_advanced_color_info(&display).map(|color_info| {
+                    let state = match (color_info.supported, color_info.enabled) {
+                        (false, _) => "unsupported",
+                        (true, true) => "on",
+                        (true, false) => "off",
+                    };
+                    println!("HDR for {}: {} ({} bits per color)", display.device_name, state, color_info.bits_per_color);
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update HDR: {}", e.message());
+                ExitCode::from_error(&e).exit();
             }
         }
-        Commands::ChangePrimaryDisplayMode { width, height, refresh_rate, unsafe_mode } => {
-            info!("Change primary display mode command received with parameters: {}x{} @{}Hz (unsafe: {})",
-            width, height, refresh_rate, unsafe_mode);
-            if change_display_mode::change_primary_display_mode(width, height, refresh_rate, unsafe_mode) {
-                println!("Successfully changed primary display mode to {}x{} @{}Hz", width, height, refresh_rate);
+        Commands::HdrVideo { action } => {
+            info!("HDR video streaming command received: {:?}", action);
+            let result = match action {
+                Toggle::On => hdr::set_hdr_video_streaming(true)
+                    .map(|()| eprintln!("Successfully turned HDR video streaming on")),
+                Toggle::Off => hdr::set_hdr_video_streaming(false)
+                    .map(|()| eprintln!("Successfully turned HDR video streaming off")),
+                Toggle::Status => hdr::is_hdr_video_streaming_enabled()
+                    .map(|enabled| println!("HDR video streaming: {}", if enabled { "on" } else { "off" })),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update HDR video streaming: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::KeepAwake { duration, detach, stop } => {
+            info!("Keep awake command received (duration: {:?}, detach: {}, stop: {})", duration, detach, stop);
+            if stop {
+                match keep_awake::stop_keep_awake() {
+                    Ok(true) => eprintln!("Successfully stopped keep-awake"),
+                    Ok(false) => eprintln!("keep-awake was not running"),
+                    Err(e) => {
+                        eprintln!("Failed to stop keep-awake: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            } else if detach {
+                match keep_awake::spawn_keep_awake(duration) {
+                    Ok(()) => eprintln!("Started keep-awake in the background"),
+                    Err(e) => {
+                        eprintln!("Failed to start keep-awake: {}", e);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            } else if let Err(e) = keep_awake::keep_awake(duration) {
+                eprintln!("Failed to keep the system awake: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Daemon { detach, stop, disconnect_grace, sunshine_log, config, keep_awake } => {
+            info!("Daemon command received (detach: {}, stop: {})", detach, stop);
+            // A config named on the command line has to load, the default one is optional
+            let config = config.map(|config| std::env::current_dir().map_or(config.clone(), |dir| dir.join(&config)));
+            let config_path = config.clone().unwrap_or_else(config::config_file_path);
+            let rules = match config::load(&config_path) {
+                Ok(helper_config) => helper_config.rules,
+                Err(e) if !stop && (config.is_some() || config_path.exists()) => {
+                    eprintln!("{}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+                Err(_) => Default::default(),
+            };
+            let options = daemon::DaemonOptions {
+                disconnect_grace: disconnect_grace.map(std::time::Duration::from_secs),
+                sunshine_log,
+                config,
+                rules,
+                keep_awake,
+            };
+            if stop {
+                match daemon::stop_daemon() {
+                    Ok(true) => eprintln!("Successfully stopped the daemon"),
+                    Ok(false) => eprintln!("The daemon was not running"),
+                    Err(e) => {
+                        eprintln!("Failed to stop the daemon: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            } else if detach {
+                match daemon::spawn_daemon(&options) {
+                    Ok(()) => eprintln!("Started the daemon in the background"),
+                    Err(e) => {
+                        eprintln!("Failed to start the daemon: {}", e);
+                        ExitCode::Failure.exit();
+                    }
+                }
+            } else if let Err(e) = daemon::run(options, prep_retry) {
+                eprintln!("Daemon failed: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Autostart { subcommand } => match subcommand {
+            AutostartCommands::Install { spec, daemon, run_key, delay } => {
+                info!("Autostart install command received (spec: {:?}, daemon: {}, run key: {})", spec, daemon, run_key);
+                let target = match (spec, daemon) {
+                    (Some(spec), false) => autostart::Target::Apply(spec),
+                    (None, true) => autostart::Target::Daemon,
+                    _ => {
+                        eprintln!("Give either --spec <path> or --daemon");
+                        ExitCode::InvalidInput.exit();
+                    }
+                };
+                let result = autostart::command_line(&target).and_then(|command| {
+                    if run_key { autostart::install_run_key(&command) } else { autostart::install_task(&command, delay) }.map(|()| command)
+                });
+                match result {
+                    Ok(command) if run_key => eprintln!("Successfully added to the Run key: {}", command),
+                    Ok(command) => eprintln!("Successfully created scheduled task '{}': {}", autostart::TASK_NAME, command),
+                    Err(e) => {
+                        eprintln!("Failed to set up autostart: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+            AutostartCommands::Remove => {
+                info!("Autostart remove command received");
+                match autostart::remove() {
+                    Ok(true) => eprintln!("Successfully removed autostart"),
+                    Ok(false) => eprintln!("Autostart wasn't installed (unchanged)"),
+                    Err(e) => {
+                        eprintln!("Failed to remove autostart: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+            AutostartCommands::Status => {
+                info!("Autostart status command received");
+                match autostart::installed() {
+                    Ok(installed) if installed.is_empty() => eprintln!("Autostart isn't installed"),
+                    Ok(installed) => {
+                        for entry in installed {
+                            match entry.detail {
+                                Some(detail) => println!("{} ({}): {}", entry.method, detail, entry.command),
+                                None => println!("{}: {}", entry.method, entry.command),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read the autostart entries: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+        },
+        Commands::Vdd { action, driver } => {
+            info!("VDD command received: {:?} (driver: {:?})", action, driver);
+            let result = match action {
+                VddAction::Enable | VddAction::Disable => {
+                    let enabled = matches!(action, VddAction::Enable);
+                    vdd::set_vdd_enabled(driver, enabled).map(|device| {
+                        eprintln!("Successfully {} {} ({:?})", if enabled { "enabled" } else { "disabled" }, device.description, device.status);
+                    })
+                }
+                VddAction::Status => vdd::list_virtual_display_devices().map(|devices| {
+                    let devices: Vec<_> = devices.into_iter().filter(|device| device.is_driver(driver)).collect();
+                    if devices.is_empty() {
+                        eprintln!("No virtual display driver installed");
+                    }
+                    for device in devices {
+                        println!("{:?}: {} [{}] {:?}", device.driver, device.description, device.instance_id, device.status);
+                    }
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update virtual display driver: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Vrr { action, selector } => {
+            info!("VRR command received: {:?} for selector '{}'", action, selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update VRR: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let result = match action {
+                Toggle::On => gpu_vendor::set_vrr(&display, true)
+                    .map(|()| eprintln!("Successfully turned VRR on for {}", display.device_name)),
+                Toggle::Off => gpu_vendor::set_vrr(&display, false)
+                    .map(|()| eprintln!("Successfully turned VRR off for {}", display.device_name)),
+                Toggle::Status => gpu_vendor::get_vrr(&display).map(|vrr| {
+                    let state = match (vrr.supported, vrr.enabled) {
+                        (false, _) => "unsupported",
+                        (true, true) => "on",
+                        (true, false) => "off",
+                    };
+                    println!("VRR for {}: {}{}", display.device_name, state, if vrr.active { " (active)" } else { "" });
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update VRR: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::CreateCustomMode { width, height, refresh_rate, display: selector } => {
+            info!("Create custom mode command received for selector '{}': {}x{} @{}Hz", selector, width, height, refresh_rate);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to create custom mode: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match gpu_vendor::create_custom_mode(&display, width, height, refresh_rate) {
+                Ok(()) => eprintln!("Successfully created custom mode {}x{} @{}Hz on {}", width, height, refresh_rate, display.device_name),
+                Err(e) => {
+                    eprintln!("Failed to create custom mode: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::AddRegistryMode { width, height, refresh_rate, display: selector, unsafe_mode: _ } => {
+            info!("Add registry mode command received for selector '{}': {}x{} @{}Hz", selector, width, height, refresh_rate);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to add registry mode: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match registry_mode::add_registry_mode(&display, width, height, refresh_rate) {
+                Ok(true) => eprintln!("Added {}x{} @{}Hz to the EDID override of {}. If the display stays blank, run remove-registry-mode {} {} {}",
+                    width, height, refresh_rate, display.device_name, width, height, refresh_rate),
+                Ok(false) => eprintln!("The EDID override of {} already has {}x{} @{}Hz (unchanged)", display.device_name, width, height, refresh_rate),
+                Err(e) => {
+                    eprintln!("Failed to add registry mode: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::RemoveRegistryMode { width, height, refresh_rate, display: selector, all } => {
+            info!("Remove registry mode command received for selector '{}': {:?}x{:?} @{:?}Hz (all: {})", selector, width, height, refresh_rate, all);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to remove registry mode: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let mode = match (width, height, refresh_rate) {
+                (Some(width), Some(height), Some(refresh_rate)) if !all => Some((width, height, refresh_rate)),
+                _ => None,
+            };
+            match (registry_mode::remove_registry_mode(&display, mode), mode) {
+                (Ok(true), Some((width, height, refresh_rate))) => eprintln!("Removed {}x{} @{}Hz from the EDID override of {}", width, height, refresh_rate, display.device_name),
+                (Ok(true), None) => eprintln!("Removed the EDID override of {}", display.device_name),
+                (Ok(false), Some((width, height, refresh_rate))) => eprintln!("{} has no {}x{} @{}Hz in an EDID override (unchanged)", display.device_name, width, height, refresh_rate),
+                (Ok(false), None) => eprintln!("{} has no EDID override (unchanged)", display.device_name),
+                (Err(e), _) => {
+                    eprintln!("Failed to remove registry mode: {}", e.message());
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::ColorFormat { selector, format, bpc } => {
+            info!("Color format command received for selector '{}' (format: {:?}, bpc: {:?})", selector, format, bpc);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update color format: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let result = if format.is_none() && bpc.is_none() {
+                gpu_vendor::get_color_format(&display).map(|color_format| {
+                    println!("Color format of {}: {:?}, {} bpc", display.device_name, color_format.pixel_format, color_format.bits_per_color);
+                })
             } else {
-                println!("Failed to change primary display mode to {}x{} @{}Hz", width, height, refresh_rate);
+                gpu_vendor::set_color_format(&display, format, bpc)
+                    .map(|()| eprintln!("Successfully updated color format of {}", display.device_name))
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update color format: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Scaling { selector, mode } => {
+            info!("Scaling command received for selector '{}' (mode: {:?})", selector, mode);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update scaling: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let result = match mode {
+                None => gpu_vendor::get_scaling(&display)
+                    .map(|mode| println!("Scaling of {}: {:?}", display.device_name, mode)),
+                Some(mode) => gpu_vendor::set_scaling(&display, mode)
+                    .map(|()| eprintln!("Successfully set scaling of {} to {:?}", display.device_name, mode)),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update scaling: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::Dithering { action, selector } => {
+            info!("Dithering command received: {:?} for selector '{}'", action, selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update dithering: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let result = match action {
+                Toggle::On => gpu_vendor::set_dithering(&display, true)
+                    .map(|()| eprintln!("Successfully turned dithering on for {}", display.device_name)),
+                Toggle::Off => gpu_vendor::set_dithering(&display, false)
+                    .map(|()| eprintln!("Successfully turned dithering off for {}", display.device_name)),
+                Toggle::Status => gpu_vendor::get_dithering(&display).map(|enabled| {
+                    println!("Dithering for {}: {}", display.device_name, if enabled { "on" } else { "off" });
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update dithering: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::ClampSrgb { action, selector } => {
+            info!("sRGB clamp command received: {:?} for selector '{}'", action, selector);
+            let Some(display) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to update the sRGB clamp: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            let result = match action {
+                Toggle::On => srgb_clamp::set_srgb_clamp(&display, true).map(|changed| match changed {
+                    true => eprintln!("Successfully turned the sRGB clamp on for {}. It needs \"Automatically manage color for apps\" on in Settings > Display > Color profile.", display.device_name),
+                    false => eprintln!("The sRGB clamp is already on for {} (unchanged)", display.device_name),
+                }),
+                Toggle::Off => srgb_clamp::set_srgb_clamp(&display, false).map(|changed| match changed {
+                    true => eprintln!("Successfully turned the sRGB clamp off for {}", display.device_name),
+                    false => eprintln!("The sRGB clamp is already off for {} (unchanged)", display.device_name),
+                }),
+                Toggle::Status => {
+                    println!("sRGB clamp for {}: {}", display.device_name, if srgb_clamp::get_srgb_clamp(&display) { "on" } else { "off" });
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update the sRGB clamp: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::NightLight { action } => {
+            info!("Night light command received: {:?}", action);
+            let result = match action {
+                Toggle::On => night_light::set_night_light(true)
+                    .map(|()| eprintln!("Successfully turned night light on")),
+                Toggle::Off => night_light::set_night_light(false)
+                    .map(|()| eprintln!("Successfully turned night light off")),
+                Toggle::Status => night_light::is_night_light_enabled()
+                    .map(|enabled| println!("Night light: {}", if enabled { "on" } else { "off" })),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update night light: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::AutoHdr { action, exe } => {
+            info!("Auto HDR command received: {:?} (exe: {:?})", action, exe);
+            let target = exe.as_deref().unwrap_or("all games");
+            let result = match action {
+                Toggle::On => auto_hdr::set_auto_hdr(exe.as_deref(), true)
+                    .map(|()| eprintln!("Successfully turned Auto HDR on for {}", target)),
+                Toggle::Off => auto_hdr::set_auto_hdr(exe.as_deref(), false)
+                    .map(|()| eprintln!("Successfully turned Auto HDR off for {}", target)),
+                Toggle::Status => auto_hdr::get_auto_hdr(exe.as_deref()).map(|state| match state {
+                    Some(enabled) => println!("Auto HDR for {}: {}", target, if enabled { "on" } else { "off" }),
+                    None if exe.is_some() => println!("Auto HDR for {}: follows the global setting", target),
+                    None => println!("Auto HDR for {}: off (never set)", target),
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to update Auto HDR: {}", e.message());
+                ExitCode::from_error(&e).exit();
+            }
+        }
+        Commands::ChangePrimaryDisplayMode { width, height, refresh_rate, fit, rotate, force, revert_after, progressive } => {
+            let Some(primary) = displays_info::find_display("primary") else {
+                eprintln!("Failed to change primary display mode: no primary display found");
+                ExitCode::DisplayNotFound.exit();
+            };
+            let (width, height, refresh_rate) = match (fit, width, height, refresh_rate) {
+                (Some(target), ..) => fit_primary_mode(&primary, target),
+                (None, Some(width), Some(height), Some(refresh_rate)) => (width, height, refresh_rate),
+                _ => unreachable!("clap requires a mode or --fit"),
+            };
+            info!("Change primary display mode command received with parameters: {}x{} @{}Hz (rotation: {:?}, force: {})",
+            width, height, refresh_rate, rotate, force);
+            // Nobody can confirm the mode without a console, so unattended runs like Sunshine's keep it unless asked
+            let revert_after = revert_after.unwrap_or(if std::io::stdin().is_terminal() { 15 } else { 0 });
+            if change_display_mode::is_current_mode(&primary, width, height, refresh_rate, rotate) {
+                eprintln!("Primary display mode is already {}x{} @{}Hz (unchanged)", width, height, refresh_rate);
+                return;
+            }
+            // Retrying can't make the display offer a mode it doesn't have
+            let listed = change_display_mode::is_supported_mode(&primary, width, height, refresh_rate, rotate);
+            if !force && !listed {
+                eprintln!("Failed to change primary display mode: {}x{} @{}Hz is not supported by {}", width, height, refresh_rate, primary.device_name);
+                ExitCode::ModeUnsupported.exit();
+            }
+            if progressive && !force && !change_display_mode::is_progressive_mode(&primary, width, height, refresh_rate, rotate) {
+                eprintln!("Failed to change primary display mode: {} only offers {}x{} @{}Hz interlaced", primary.device_name, width, height, refresh_rate);
+                ExitCode::ModeUnsupported.exit();
+            }
+            if force && !listed {
+                warn!("FORCING {}x{} @{}Hz on {}, which the display doesn't report as supported. If it can't show it, the screen stays blank{}.",
+                    width, height, refresh_rate, primary.device_name,
+                    if revert_after > 0 { format!(" until the previous mode comes back in {} seconds", revert_after) } else { " and nothing puts it back".to_string() });
+            }
+            let result = retry::with_retries(prep_retry, "Display mode change", || {
+                if change_display_mode::change_primary_display_mode(width, height, refresh_rate, rotate, force) {
+                    Ok(())
+                } else {
+                    Err("mode change failed")
+                }
+            });
+            if result.is_err() {
+                eprintln!("Failed to change primary display mode to {}x{} @{}Hz", width, height, refresh_rate);
+                ExitCode::Failure.exit();
+            }
+            if force && !listed && revert_after > 0 && !display_api::is_dry_run() && !change_display_mode::keep_or_revert(&primary, revert_after) {
+                eprintln!("The forced mode {}x{} @{}Hz wasn't confirmed, {} is back to {}x{} @{}Hz", width, height, refresh_rate,
+                    primary.device_name, primary.current_resolution.0, primary.current_resolution.1, primary.current_refresh_rate);
+                ExitCode::Failure.exit();
             }
+            eprintln!("Successfully changed primary display mode to {}x{} @{}Hz", width, height, refresh_rate);
         }
-        Commands::SetSdrLevel { level } => {
-            match set_sdr_level::set_primary_display_sdr_white(level) {
-                Ok(()) => println!("Successfully set SDR white level to {}", level),
+        Commands::SetRefresh { refresh_rate, selector } => {
+            info!("Set refresh rate command received for selector '{}': {}Hz", selector, refresh_rate);
+            change_mode_keeping_rest(&selector, None, Some(refresh_rate), prep_retry);
+        }
+        Commands::SetResolution { width, height, selector } => {
+            info!("Set resolution command received for selector '{}': {}x{}", selector, width, height);
+            change_mode_keeping_rest(&selector, Some((width, height)), None, prep_retry);
+        }
+        Commands::SetSdrLevel { level, raw, raw_min, raw_max, raw_step, all } => {
+            let mapping = set_sdr_level::SdrMapping::new(raw_min, raw_max, raw_step).unwrap_or_else(|e| {
+                eprintln!("{}", e.message());
+                ExitCode::InvalidInput.exit();
+            });
+            // Compared as raw values, a custom mapping can put a level anywhere
+            let (target, description) = match (raw, level) {
+                (Some(raw), _) => (raw, format!("raw {} ({} nits)", raw, set_sdr_level::raw_to_nits(raw))),
+                (None, Some(level)) => (mapping.to_raw(level), level.to_string()),
+                (None, None) => unreachable!("clap requires a level or --raw"),
+            };
+            if all {
+                set_all_sdr_levels(target, &description, prep_retry);
+                return;
+            }
+            let current = displays_info::find_display("primary").and_then(|primary| set_sdr_level::get_display_sdr_white_raw(&primary).ok());
+            if current == Some(target) {
+                eprintln!("SDR white level is already {} (unchanged)", description);
+                return;
+            }
+            let result = retry::with_retries(prep_retry, "SDR white level change", || match (raw, level) {
+                (None, Some(level)) => set_sdr_level::set_primary_display_sdr_white(level, mapping),
+                _ => set_sdr_level::set_primary_display_sdr_white_raw(target),
+            });
+            match result {
+                Ok(()) => eprintln!("Successfully set SDR white level to {}", description),
+                Err(e) => {
+                    eprintln!("Failed to set SDR white level: {}", e);
+                    ExitCode::from_error(&e).exit();
+                }
+            }
+        }
+        Commands::GetSdrLevel { selector, all, format } => {
+            info!("Get SDR level command received for {}", if all { "every display".to_string() } else { format!("selector '{}'", selector) });
+            let displays = if all {
+                enumerate_displays()
+            } else {
+                match displays_info::find_display(&selector) {
+                    Some(display) => vec![display],
+                    None => {
+                        eprintln!("Error: no active display matches '{}'", selector);
+                        ExitCode::DisplayNotFound.exit();
+                    }
+                }
+            };
+            print_sdr_levels(&displays, format);
+        }
+        Commands::SetHdrCalibration { profile_name, display: selector } => {
+            info!("Set HDR calibration command received for selector '{}': {:?}", selector, profile_name);
+            let Some(target) = displays_info::find_display(&selector) else {
+                eprintln!("Failed to set the HDR calibration profile: no active display matches '{}'", selector);
+                ExitCode::DisplayNotFound.exit();
+            };
+            match change_icc_profile::set_display_hdr_calibration_profile(&target, profile_name.as_deref()) {
+                Ok((name, false)) => eprintln!("'{}' is already the HDR calibration profile of {} (unchanged)", name, target.device_name),
+                Ok((name, true)) => {
+                    eprintln!("Successfully made '{}' the HDR calibration profile of {}", name, target.device_name);
+                    if !hdr::get_advanced_color_info(&target).is_ok_and(|info| info.enabled) {
+                        eprintln!("It applies once HDR is on");
+                    }
+                }
                 Err(e) => {
-                    println!("Failed to set SDR white level: {}", e);
-                    std::process::exit(1);
+                    eprintln!("Failed to set the HDR calibration profile: {}", e.message());
+                    ExitCode::from_error(&e).exit();
                 }
             }
         }
         Commands::SetICCProfile { profile_name } => {
             info!("Set ICC profile command received with profile name: {}", profile_name.0);
-            match change_icc_profile::change_primary_display_icc_profile(&profile_name.0) {
-                Ok(()) => println!("Successfully set ICC profile to '{}'", profile_name.0),
+            let current = displays_info::find_display("primary").and_then(|primary| change_icc_profile::get_display_default_icc_profile(&primary));
+            if current.is_some_and(|current| current.eq_ignore_ascii_case(&profile_name.0)) {
+                eprintln!("ICC profile is already '{}' (unchanged)", profile_name.0);
+                return;
+            }
+            match retry::with_retries(prep_retry, "ICC profile change", || change_icc_profile::change_primary_display_icc_profile(&profile_name.0)) {
+                Ok(()) => eprintln!("Successfully set ICC profile to '{}'", profile_name.0),
                 Err(e) => {
-                    println!("Failed to set ICC profile: {}", e);
-                    std::process::exit(1);
+                    eprintln!("Failed to set ICC profile: {}", e);
+                    ExitCode::from_error(&e).exit();
                 }
             }
         }
+        Commands::Icc { subcommand } => match subcommand {
+            IccCommands::Info { profile } => {
+                info!("ICC info command received for '{}'", profile);
+                let (path, profile) = match icc_file::read_profile_info(&profile) {
+                    Ok(read) => read,
+                    Err(e) => {
+                        eprintln!("Failed to read the ICC profile: {}", e);
+                        ExitCode::InvalidInput.exit();
+                    }
+                };
+                let or_unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "Unknown".to_string());
+                println!("Profile: {}", path.display());
+                println!("Description: {}", or_unknown(&profile.description));
+                println!("Device Model: {}", or_unknown(&profile.device_model));
+                println!("Manufacturer / Model: {} / {}", or_unknown(&profile.manufacturer), or_unknown(&profile.model));
+                println!("Created: {}", or_unknown(&profile.created));
+                println!("Version: {}", profile.version);
+                println!("Class: {}, {} to {}", profile.device_class, profile.color_space, profile.connection_space);
+                println!("Size: {} bytes", profile.size);
+                match profile.white_point {
+                    Some([x, y, z]) if y > 0.0 => println!("White Point: X {:.4} Y {:.4} Z {:.4} (x {:.4} y {:.4})", x, y, z, x / (x + y + z), y / (x + y + z)),
+                    _ => println!("White Point: Unknown"),
+                }
+                if let Some(luminance) = profile.luminance {
+                    println!("Luminance: {:.0} nits", luminance);
+                }
+                println!("VCGT (calibration curves): {}", if profile.has_vcgt { "Yes" } else { "No" });
+                println!("MHC2 (HDR Calibration): {}", if profile.has_mhc2 { "Yes" } else { "No" });
+                println!("Copyright: {}", or_unknown(&profile.copyright));
+                println!("Tags: {}", profile.tags.join(" "));
+            }
+            IccCommands::Associate { profile, selector, make_default } => {
+                info!("ICC associate command received for '{}' on selector '{}'", profile, selector);
+                let Some(display) = displays_info::find_display(&selector) else {
+                    eprintln!("Failed to associate the ICC profile: no active display matches '{}'", selector);
+                    ExitCode::DisplayNotFound.exit();
+                };
+                match change_icc_profile::associate_display_icc_profile(&display, &profile, make_default) {
+                    Ok(associated) => {
+                        let default = if make_default { ", as its default profile" } else { "" };
+                        match associated {
+                            true => eprintln!("Successfully associated '{}' with {}{}", profile, display.device_name, default),
+                            false => eprintln!("'{}' was already associated with {}{}", profile, display.device_name, default),
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to associate the ICC profile: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+            IccCommands::Restore { selector } => {
+                info!("ICC restore command received for selector '{}'", selector);
+                let Some(display) = displays_info::find_display(&selector) else {
+                    eprintln!("Failed to restore the ICC profile: no active display matches '{}'", selector);
+                    ExitCode::DisplayNotFound.exit();
+                };
+                match change_icc_profile::restore_display_icc_profile(&display) {
+                    Ok(Some(profile)) => eprintln!("Successfully restored ICC profile '{}' on {}", profile, display.device_name),
+                    Ok(None) => eprintln!("No saved ICC profile for {} (unchanged)", display.device_name),
+                    Err(e) => {
+                        eprintln!("Failed to restore the ICC profile: {}", e.message());
+                        ExitCode::from_error(&e).exit();
+                    }
+                }
+            }
+        },
+    }
+
+    // The next command, or the next line of a script, gets a topology that has stopped changing
+    if changes_displays {
+        displays_info::settle();
+    }
+}
+
+// The mode list of `modes` and `test pdm`, filtered already
+fn print_modes(label: &str, display: &displays_info::DisplayDevice, modes: &[displays_info::DisplayMode], format: OutputFormat) {
+    match format {
+        OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Psobject => {
+            let document = schema::modes_document(display, modes);
+            if format == OutputFormat::Psobject {
+                table::print_records(&document["modes"]);
+                return;
+            }
+            table::print_value(&schema::versioned(document), format == OutputFormat::Yaml);
+        }
+        OutputFormat::Csv => {
+            let rows: Vec<Vec<String>> = modes.iter().map(|mode| vec![
+                mode.width.to_string(),
+                mode.height.to_string(),
+                mode.refresh_rate.to_string(),
+                mode.interlaced.to_string(),
+                mode.fixed_output.name().to_string(),
+                display.is_current_mode(mode).to_string(),
+            ]).collect();
+            table::print_csv(&["width", "height", "refresh_rate", "interlaced", "scaling", "current"], &rows);
+        }
+        OutputFormat::Table => {
+            println!("{}: {} ({})\n", label, display.device_name, display.device_string);
+            let mut modes_table = Table::new(vec!["Resolution", "Refresh", "Scaling", "Current"]);
+            for mode in modes {
+                let current = display.is_current_mode(mode);
+                let color = if current { Color::Green } else { Color::Plain };
+                modes_table.add_row(vec![
+                    Cell::colored(format!("{}x{}{}", mode.width, mode.height, if mode.interlaced { "i" } else { "" }), color),
+                    Cell::colored(format!("{}Hz", mode.refresh_rate), color),
+                    Cell::colored(mode.fixed_output.name(), color),
+                    Cell::colored(if current { "*" } else { "" }, color),
+                ]);
+            }
+            modes_table.print();
+        }
+    }
+}
+
+// `set-sdr-level --all` reports each display, and exits with the first failure's code once all
+// of them have been tried
+fn set_all_sdr_levels(raw: u32, description: &str, retry_policy: retry::RetryPolicy) {
+    let results = set_sdr_level::set_all_displays_sdr_white_raw(raw, retry_policy).unwrap_or_else(|e| {
+        eprintln!("Failed to set SDR white levels: {}", e.message());
+        ExitCode::from_error(&e).exit();
+    });
+    let mut failure = None;
+    for (display, result) in results {
+        match result {
+            Ok(false) => eprintln!("SDR white level of {} is already {} (unchanged)", display.device_name, description),
+            Ok(true) => eprintln!("Successfully set SDR white level of {} to {}", display.device_name, description),
+            Err(e) => {
+                eprintln!("Failed to set SDR white level of {}: {}", display.device_name, e.message());
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(e) = failure {
+        ExitCode::from_error(&e).exit();
+    }
+}
+
+// The SDR white level of each display for `get-sdr-level`. Windows keeps a level for displays
+// with HDR off too, it only takes effect once HDR is on.
+fn print_sdr_levels(displays: &[displays_info::DisplayDevice], format: OutputFormat) {
+    let readings = set_sdr_level::get_all_sdr_white_raw(displays);
+
+    match format {
+        OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Psobject => {
+            let document = schema::sdr_levels_document(displays, &readings);
+            if format == OutputFormat::Psobject {
+                table::print_records(&document["displays"]);
+                return;
+            }
+            table::print_value(&schema::versioned(document), format == OutputFormat::Yaml);
+        }
+        OutputFormat::Csv => {
+            let optional = |value: Option<u32>| value.map(|value| value.to_string()).unwrap_or_default();
+            let rows: Vec<Vec<String>> = displays.iter().zip(&readings).map(|(display, (hdr, raw))| vec![
+                display.device_name.clone(),
+                display.stable_id().unwrap_or_default(),
+                display.is_primary.to_string(),
+                hdr.map(|hdr| hdr.to_string()).unwrap_or_default(),
+                optional(raw.map(set_sdr_level::raw_to_level)),
+                optional(raw.map(set_sdr_level::raw_to_nits)),
+                optional(*raw),
+            ]).collect();
+            table::print_csv(&["device_name", "display_id", "primary", "hdr", "level", "nits", "raw"], &rows);
+        }
+        OutputFormat::Table => {
+            let mut levels_table = Table::new(vec!["Display", "Name", "Primary", "HDR", "Level", "Nits", "Raw"]);
+            for (display, (hdr, raw)) in displays.iter().zip(&readings) {
+                let name = display.get_target_device_name().map(|target| target.friendly_name).unwrap_or_default();
+                let (level_cell, nits_cell, raw_cell) = match *raw {
+                    Some(raw) => (Cell::new(set_sdr_level::raw_to_level(raw).to_string()), Cell::new(set_sdr_level::raw_to_nits(raw).to_string()), Cell::new(raw.to_string())),
+                    None => (Cell::colored("Unknown", Color::Dim), Cell::new(""), Cell::new("")),
+                };
+                levels_table.add_row(vec![
+                    Cell::colored(&display.device_name, Color::Cyan),
+                    Cell::new(name),
+                    if display.is_primary { Cell::colored("Yes", Color::Green) } else { Cell::new("No") },
+                    match hdr {
+                        Some(true) => Cell::colored("On", Color::Green),
+                        Some(false) => Cell::new("Off"),
+                        None => Cell::colored("Unsupported", Color::Dim),
+                    },
+                    level_cell,
+                    nits_cell,
+                    raw_cell,
+                ]);
+            }
+            levels_table.print();
+        }
+    }
+}
+
+// set-refresh and set-resolution: the display's current mode with one part replaced. The
+// combination has to be one the display offers, the error lists the rates it has at that size.
+fn change_mode_keeping_rest(selector: &str, resolution: Option<(u32, u32)>, refresh_rate: Option<u32>, retry_policy: retry::RetryPolicy) {
+    let Some(display) = displays_info::find_display(selector) else {
+        eprintln!("Failed to change the display mode: no active display matches '{}'", selector);
+        ExitCode::DisplayNotFound.exit();
+    };
+    let (width, height) = resolution.unwrap_or(display.current_resolution);
+    let refresh_rate = refresh_rate.unwrap_or(display.current_refresh_rate);
+    if change_display_mode::is_current_mode(&display, width, height, refresh_rate, None) {
+        eprintln!("{} is already {}x{} @{}Hz (unchanged)", display.device_name, width, height, refresh_rate);
+        return;
+    }
+    if !change_display_mode::is_supported_mode(&display, width, height, refresh_rate, None) {
+        let offered = match change_display_mode::refresh_rates_at(&display, width, height).as_slice() {
+            [] => format!("it has no {}x{} mode at all", width, height),
+            rates => format!("at {}x{} it offers {} Hz", width, height, rates.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")),
+        };
+        eprintln!("Failed to change the display mode: {} doesn't offer {}x{} @{}Hz, {}", display.device_name, width, height, refresh_rate, offered);
+        ExitCode::ModeUnsupported.exit();
+    }
+
+    let result = retry::with_retries(retry_policy, "Display mode change", || {
+        if change_display_mode::change_display_mode(&display, width, height, refresh_rate, None, false) {
+            Ok(())
+        } else {
+            Err("mode change failed")
+        }
+    });
+    if result.is_ok() {
+        eprintln!("Successfully changed {} to {}x{} @{}Hz", display.device_name, width, height, refresh_rate);
+    } else {
+        eprintln!("Failed to change {} to {}x{} @{}Hz", display.device_name, width, height, refresh_rate);
+        ExitCode::Failure.exit();
+    }
+}
+
+// Picks the mode for cpdm --fit and says why, exiting when the display offers nothing
+fn fit_primary_mode(primary: &displays_info::DisplayDevice, target: change_display_mode::FitTarget) -> (u32, u32, u32) {
+    let client = format!("{}x{}", target.width, target.height);
+    let Some(((width, height, refresh_rate), reason)) = change_display_mode::fit_mode(&primary.get_supported_modes(), target) else {
+        eprintln!("Failed to change primary display mode: {} reports no modes to fit {} to", primary.device_name, client);
+        ExitCode::ModeUnsupported.exit();
+    };
+    let decision = match reason {
+        change_display_mode::FitReason::Exact => format!("{} offers {} itself", primary.device_name, client),
+        change_display_mode::FitReason::SameAspect => format!("{}x{} is the highest resolution with the same aspect ratio", width, height),
+        change_display_mode::FitReason::Letterbox(coverage) => format!("no mode has the same aspect ratio, {}x{} letterboxes the least ({:.0}% of the picture is used)", width, height, coverage * 100.0),
+    };
+    let rate = match target.refresh_rate {
+        Some(wanted) if wanted != refresh_rate => format!(" at {} Hz, the closest to {} Hz", refresh_rate, wanted),
+        _ => format!(" at {} Hz", refresh_rate),
+    };
+    eprintln!("Fitting {}: {}{}", client, decision, rate);
+    (width, height, refresh_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The variable an option of a top-level command reads, None without one
+    fn env_var(command: &clap::Command, subcommand: &str, id: &str) -> Option<String> {
+        let arg = command.find_subcommand(subcommand)?.get_arguments().find(|arg| arg.get_id() == id)?;
+        arg.get_env().map(|name| name.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn output_format_variable_leaves_other_formats_alone() {
+        let command = with_settings(Cli::command(), &BTreeMap::new());
+        for subcommand in ["status", "modes", "get-sdr-level"] {
+            assert_eq!(env_var(&command, subcommand, "format").as_deref(), Some("SUNSHINE_HELPER_FORMAT"), "{} --format", subcommand);
+        }
+        assert_eq!(env_var(&command, "report", "format").as_deref(), Some("SUNSHINE_HELPER_REPORT_FORMAT"));
+        assert_eq!(env_var(&command, "test-pattern", "format").as_deref(), Some("SUNSHINE_HELPER_PATTERN_FORMAT"));
+        assert_eq!(env_var(&command, "color-format", "format").as_deref(), Some("SUNSHINE_HELPER_PIXEL_FORMAT"));
+        assert_eq!(env_var(&command, "set-sdr-level", "raw").as_deref(), Some("SUNSHINE_HELPER_SDR_RAW"));
+        assert_eq!(env_var(&command, "diff", "json"), None);
+    }
+
+    #[test]
+    fn defaults_are_checked_against_the_options() {
+        let defaults = BTreeMap::from([
+            ("adaptor".to_string(), serde_json::json!("nvidia")),
+            ("display".to_string(), serde_json::json!("LG TV")),
+            ("retries".to_string(), serde_json::json!("many")),
+        ]);
+        let issues: Vec<(sunshine::Severity, String)> = check_defaults(&defaults).into_iter().map(|issue| (issue.severity, issue.message)).collect();
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert_eq!(issues[0], (sunshine::Severity::Warning, "[defaults] sets `adaptor`, which isn't an option".to_string()));
+        assert_eq!(issues[1].0, sunshine::Severity::Error);
+        assert!(issues[1].1.starts_with("[defaults] retries = \"many\": "), "{}", issues[1].1);
     }
 }
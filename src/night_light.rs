@@ -0,0 +1,62 @@
+use windows::Win32::System::Registry::HKEY_CURRENT_USER;
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, instrument};
+
+
+// Night light has no public API. Its state lives in a CloudStore blob that the Settings app
+// and the shell both watch, so rewriting the blob toggles it immediately.
+const STATE_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\CloudStore\\Store\\DefaultAccount\\Current\\\
+default$windows.data.bluelightreduction.bluelightreductionstate\\windows.data.bluelightreduction.bluelightreductionstate";
+const STATE_VALUE: &str = "Data";
+
+// Byte 18 holds the blob size marker, which grows by two when the "on" field is present
+const SIZE_OFFSET: usize = 18;
+const SIZE_ON: u8 = 0x15;
+const SIZE_OFF: u8 = 0x13;
+const ON_FIELD_OFFSET: usize = 23;
+const ON_FIELD: [u8; 2] = [0x02, 0x01];
+
+// Bytes 10-14 are a timestamp. Windows ignores the write unless it moves forward.
+const TIMESTAMP_RANGE: std::ops::Range<usize> = 10..15;
+
+fn read_state() -> Result<Vec<u8>> {
+    let data = crate::registry::read_binary(HKEY_CURRENT_USER, STATE_KEY, STATE_VALUE)?;
+    if data.len() <= ON_FIELD_OFFSET {
+        return Err(Error::new(E_FAIL, format!("Unexpected night light state blob ({} bytes)", data.len())));
+    }
+    Ok(data)
+}
+
+fn bump_timestamp(data: &mut [u8]) {
+    if let Some(byte) = data[TIMESTAMP_RANGE].iter_mut().find(|b| **b != 0xFF) {
+        *byte += 1;
+    }
+}
+
+pub fn is_night_light_enabled() -> Result<bool> {
+    Ok(read_state()?[SIZE_OFFSET] == SIZE_ON)
+}
+
+#[instrument]
+pub fn set_night_light(enabled: bool) -> Result<()> {
+    let mut data = read_state()?;
+    let currently_enabled = data[SIZE_OFFSET] == SIZE_ON;
+
+    if currently_enabled == enabled {
+        info!("Night light is already {}", if enabled { "on" } else { "off" });
+        return Ok(());
+    }
+
+    if enabled {
+        data.splice(ON_FIELD_OFFSET..ON_FIELD_OFFSET, ON_FIELD);
+        data[SIZE_OFFSET] = SIZE_ON;
+    } else {
+        data.drain(ON_FIELD_OFFSET..ON_FIELD_OFFSET + ON_FIELD.len());
+        data[SIZE_OFFSET] = SIZE_OFF;
+    }
+    bump_timestamp(&mut data);
+
+    info!("Turning night light {}", if enabled { "on" } else { "off" });
+    crate::registry::write_binary(HKEY_CURRENT_USER, STATE_KEY, STATE_VALUE, &data)
+}
@@ -0,0 +1,533 @@
+use std::ffi::{c_void, CString};
+use std::mem::size_of;
+use std::sync::OnceLock;
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{s, w, Error, Result};
+use tracing::{info, error};
+
+use crate::displays_info::DisplayDevice;
+use crate::gpu_vendor::{ColorFormat, PixelFormat, VrrStatus};
+
+// NVAPI only exports nvapi_QueryInterface, every other function is looked up by its ID.
+// IDs and struct layouts are from the public NVAPI SDK headers (nvapi_interface.h, nvapi.h).
+const NVAPI_INITIALIZE: u32 = 0x0150E828;
+const NVAPI_GET_ERROR_MESSAGE: u32 = 0x6C2D048C;
+const NVAPI_DISP_GET_DISPLAY_ID_BY_DISPLAY_NAME: u32 = 0xAE457190;
+const NVAPI_DISP_GET_VRR_INFO: u32 = 0xDF8FDA57;
+const NVAPI_DISP_COLOR_CONTROL: u32 = 0x92F9D80D;
+const NVAPI_DISP_GET_TIMING: u32 = 0x175167E9;
+const NVAPI_DISP_TRY_CUSTOM_DISPLAY: u32 = 0x1F7DB630;
+const NVAPI_DISP_SAVE_CUSTOM_DISPLAY: u32 = 0x49882876;
+const NVAPI_DISP_REVERT_CUSTOM_DISPLAY_TRIAL: u32 = 0xCBBD40F0;
+const NVAPI_DRS_CREATE_SESSION: u32 = 0x0694D52E;
+const NVAPI_DRS_DESTROY_SESSION: u32 = 0xDAD9CFF8;
+const NVAPI_DRS_LOAD_SETTINGS: u32 = 0x375DBD6B;
+const NVAPI_DRS_SAVE_SETTINGS: u32 = 0xFCBC7E14;
+const NVAPI_DRS_GET_BASE_PROFILE: u32 = 0xDA8466A0;
+const NVAPI_DRS_GET_SETTING: u32 = 0x73BF8338;
+const NVAPI_DRS_SET_SETTING: u32 = 0x577DD202;
+
+const NVAPI_OK: i32 = 0;
+const NVAPI_SETTING_NOT_FOUND: i32 = -160;
+
+// "G-SYNC - Global Feature" in the driver settings database, 0 off and 1 on
+const VRR_GLOBAL_FEATURE_ID: u32 = 0x1094F157;
+const NVDRS_DWORD_TYPE: u32 = 0;
+
+// NV_TIMING_OVERRIDE_CVT_RB: reduced blanking keeps high resolution modes within link bandwidth
+const TIMING_OVERRIDE_CVT_RB: u32 = 6;
+const FORMAT_A8R8G8B8: u32 = 21;
+
+const COLOR_CMD_GET: u8 = 1;
+const COLOR_CMD_SET: u8 = 2;
+const COLOR_COLORIMETRY_AUTO: u8 = 0xFF;
+const COLOR_DYNAMIC_RANGE_AUTO: u8 = 2;
+const COLOR_SELECTION_POLICY_USER: u32 = 0;
+
+type NvStatus = i32;
+type QueryInterfaceFn = unsafe extern "C" fn(u32) -> *const c_void;
+
+// NVAPI struct versions are the struct size with the version number in the high word
+const fn struct_version<T>(version: u32) -> u32 {
+    size_of::<T>() as u32 | (version << 16)
+}
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+#[repr(C)]
+struct NvGetVrrInfo {
+    version: u32,
+    flags: u32, // bIsVRREnabled, bIsVRRPossible, bIsVRRRequested, bIsVRRIndicatorEnabled, bIsDisplayInVRRMode
+    reserved: [u32; 4],
+}
+
+const VRR_ENABLED: u32 = 0x1;
+const VRR_POSSIBLE: u32 = 0x2;
+const VRR_ACTIVE: u32 = 0x10;
+
+#[repr(C)]
+#[derive(Default)]
+struct NvTimingInput {
+    version: u32,
+    width: u32,
+    height: u32,
+    refresh_rate: f32,
+    flag: [u32; 3], // NV_TIMING_FLAG: progressive, no scaling
+    timing_type: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct NvTiming {
+    h_visible: u16,
+    h_border: u16,
+    h_front_porch: u16,
+    h_sync_width: u16,
+    h_total: u16,
+    h_sync_polarity: u8,
+    v_visible: u16,
+    v_border: u16,
+    v_front_porch: u16,
+    v_sync_width: u16,
+    v_total: u16,
+    v_sync_polarity: u8,
+    interlaced: u16,
+    pixel_clock: u32, // in 10 kHz units
+    ext_flag: u32,
+    ext_refresh_rate: u16,
+    ext_refresh_rate_x1000: u32,
+    ext_aspect: u32,
+    ext_repeat: u16,
+    ext_status: u32,
+    ext_name: [u8; 40],
+}
+
+impl Default for NvTiming {
+    fn default() -> Self {
+        // Plain integers all the way down, so all-zero is valid
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NvCustomDisplay {
+    version: u32,
+    width: u32,
+    height: u32,
+    depth: u32,
+    color_format: u32,
+    source_partition: [f32; 4], // x, y, w, h of the desktop to scan out
+    x_ratio: f32,
+    y_ratio: f32,
+    timing: NvTiming,
+    flags: u32, // hwModeSetOnly
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NvColorData {
+    version: u32,
+    size: u16,
+    cmd: u8,
+    data: NvColorDataFields,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct NvColorDataFields {
+    color_format: u8,
+    colorimetry: u8,
+    dynamic_range: u8,
+    bpc: u32,
+    color_selection_policy: u32,
+    depth: u32,
+}
+
+#[repr(C)]
+struct NvdrsSetting {
+    version: u32,
+    setting_name: [u16; 2048],
+    setting_id: u32,
+    setting_type: u32,
+    setting_location: u32,
+    is_current_predefined: u32,
+    is_predefined_valid: u32,
+    predefined_value: [u8; 4100], // union of a DWORD, binary blob and wide string
+    current_value: [u8; 4100],
+}
+
+impl NvdrsSetting {
+    fn new() -> Self {
+        NvdrsSetting {
+            version: struct_version::<NvdrsSetting>(1),
+            setting_name: [0; 2048],
+            setting_id: 0,
+            setting_type: 0,
+            setting_location: 0,
+            is_current_predefined: 0,
+            is_predefined_valid: 0,
+            predefined_value: [0; 4100],
+            current_value: [0; 4100],
+        }
+    }
+
+    fn current_dword(&self) -> u32 {
+        u32::from_le_bytes([self.current_value[0], self.current_value[1], self.current_value[2], self.current_value[3]])
+    }
+}
+
+//==============================================================================
+// Loading
+//==============================================================================
+
+struct NvApi {
+    query_interface: QueryInterfaceFn,
+}
+
+static NVAPI: OnceLock<Option<NvApi>> = OnceLock::new();
+
+fn load() -> Option<NvApi> {
+    unsafe {
+        let module = LoadLibraryW(w!("nvapi64.dll")).ok()?;
+        let query_interface: QueryInterfaceFn = std::mem::transmute(GetProcAddress(module, s!("nvapi_QueryInterface"))?);
+        let nvapi = NvApi { query_interface };
+
+        let initialize: unsafe extern "C" fn() -> NvStatus = nvapi.function(NVAPI_INITIALIZE)?;
+        let status = initialize();
+        if status != NVAPI_OK {
+            error!("NvAPI_Initialize failed: {}", status);
+            return None;
+        }
+
+        info!("Loaded NVAPI");
+        Some(nvapi)
+    }
+}
+
+fn nvapi() -> Result<&'static NvApi> {
+    NVAPI.get_or_init(load).as_ref()
+        .ok_or_else(|| Error::new(E_FAIL, "NVAPI is not available (no NVIDIA driver installed?)"))
+}
+
+impl NvApi {
+    // F must be the unsafe extern "C" fn type matching the function's NVAPI signature
+    unsafe fn function<F: Copy>(&self, id: u32) -> Option<F> {
+        let pointer = (self.query_interface)(id);
+        if pointer.is_null() {
+            error!("NVAPI function {:08X} is not available in this driver", id);
+            return None;
+        }
+        Some(std::mem::transmute_copy(&pointer))
+    }
+
+    unsafe fn require<F: Copy>(&self, id: u32) -> Result<F> {
+        self.function(id).ok_or_else(|| Error::new(E_FAIL, format!("NVAPI function {:08X} is not available in this driver", id)))
+    }
+
+    fn error_message(&self, status: NvStatus) -> String {
+        let mut buffer = [0u8; 64];
+        let message = unsafe {
+            match self.function::<unsafe extern "C" fn(NvStatus, *mut u8) -> NvStatus>(NVAPI_GET_ERROR_MESSAGE) {
+                Some(get_error_message) if get_error_message(status, buffer.as_mut_ptr()) == NVAPI_OK => {
+                    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+                    String::from_utf8_lossy(&buffer[..len]).to_string()
+                }
+                _ => String::new(),
+            }
+        };
+        format!("{} ({})", message, status)
+    }
+
+    fn check(&self, status: NvStatus, what: &str) -> Result<()> {
+        if status == NVAPI_OK {
+            return Ok(());
+        }
+        let message = self.error_message(status);
+        error!("{} failed: {}", what, message);
+        Err(Error::new(E_FAIL, format!("{} failed: {}", what, message)))
+    }
+}
+
+fn display_id(nvapi: &NvApi, display: &DisplayDevice) -> Result<u32> {
+    let name = CString::new(display.device_name.as_str()).map_err(|_| Error::new(E_FAIL, "Invalid display name"))?;
+    let mut display_id = 0u32;
+
+    unsafe {
+        let get_display_id: unsafe extern "C" fn(*const u8, *mut u32) -> NvStatus =
+            nvapi.require(NVAPI_DISP_GET_DISPLAY_ID_BY_DISPLAY_NAME)?;
+        nvapi.check(get_display_id(name.as_ptr() as *const u8, &mut display_id), "NvAPI_DISP_GetDisplayIdByDisplayName")?;
+    }
+
+    Ok(display_id)
+}
+
+//==============================================================================
+// Driver settings (DRS)
+//==============================================================================
+
+// A DRS session with the settings database loaded, destroyed on drop
+struct DrsSession<'a> {
+    nvapi: &'a NvApi,
+    handle: *mut c_void,
+}
+
+impl<'a> DrsSession<'a> {
+    fn open(nvapi: &'a NvApi) -> Result<Self> {
+        let mut handle = std::ptr::null_mut();
+        unsafe {
+            let create_session: unsafe extern "C" fn(*mut *mut c_void) -> NvStatus = nvapi.require(NVAPI_DRS_CREATE_SESSION)?;
+            nvapi.check(create_session(&mut handle), "NvAPI_DRS_CreateSession")?;
+        }
+
+        let session = DrsSession { nvapi, handle };
+        unsafe {
+            let load_settings: unsafe extern "C" fn(*mut c_void) -> NvStatus = nvapi.require(NVAPI_DRS_LOAD_SETTINGS)?;
+            nvapi.check(load_settings(session.handle), "NvAPI_DRS_LoadSettings")?;
+        }
+        Ok(session)
+    }
+
+    fn base_profile(&self) -> Result<*mut c_void> {
+        let mut profile = std::ptr::null_mut();
+        unsafe {
+            let get_base_profile: unsafe extern "C" fn(*mut c_void, *mut *mut c_void) -> NvStatus =
+                self.nvapi.require(NVAPI_DRS_GET_BASE_PROFILE)?;
+            self.nvapi.check(get_base_profile(self.handle, &mut profile), "NvAPI_DRS_GetBaseProfile")?;
+        }
+        Ok(profile)
+    }
+
+    // None when the setting was never changed from the driver default
+    fn get_global_dword(&self, setting_id: u32) -> Result<Option<u32>> {
+        let profile = self.base_profile()?;
+        let mut setting = NvdrsSetting::new();
+
+        let status = unsafe {
+            let get_setting: unsafe extern "C" fn(*mut c_void, *mut c_void, u32, *mut NvdrsSetting) -> NvStatus =
+                self.nvapi.require(NVAPI_DRS_GET_SETTING)?;
+            get_setting(self.handle, profile, setting_id, &mut setting)
+        };
+
+        if status == NVAPI_SETTING_NOT_FOUND {
+            return Ok(None);
+        }
+        self.nvapi.check(status, "NvAPI_DRS_GetSetting")?;
+        Ok(Some(setting.current_dword()))
+    }
+
+    fn set_global_dword(&self, setting_id: u32, value: u32) -> Result<()> {
+        let profile = self.base_profile()?;
+        let mut setting = NvdrsSetting::new();
+        setting.setting_id = setting_id;
+        setting.setting_type = NVDRS_DWORD_TYPE;
+        setting.current_value[..4].copy_from_slice(&value.to_le_bytes());
+
+        unsafe {
+            let set_setting: unsafe extern "C" fn(*mut c_void, *mut c_void, *mut NvdrsSetting) -> NvStatus =
+                self.nvapi.require(NVAPI_DRS_SET_SETTING)?;
+            self.nvapi.check(set_setting(self.handle, profile, &mut setting), "NvAPI_DRS_SetSetting")?;
+
+            let save_settings: unsafe extern "C" fn(*mut c_void) -> NvStatus = self.nvapi.require(NVAPI_DRS_SAVE_SETTINGS)?;
+            self.nvapi.check(save_settings(self.handle), "NvAPI_DRS_SaveSettings")
+        }
+    }
+}
+
+impl Drop for DrsSession<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(destroy_session) = self.nvapi.function::<unsafe extern "C" fn(*mut c_void) -> NvStatus>(NVAPI_DRS_DESTROY_SESSION) {
+                destroy_session(self.handle);
+            }
+        }
+    }
+}
+
+//==============================================================================
+// VRR (G-SYNC)
+//==============================================================================
+
+pub fn get_vrr(display: &DisplayDevice) -> Result<VrrStatus> {
+    let nvapi = nvapi()?;
+    let display_id = display_id(nvapi, display)?;
+
+    let mut vrr_info = NvGetVrrInfo {
+        version: struct_version::<NvGetVrrInfo>(1),
+        flags: 0,
+        reserved: [0; 4],
+    };
+
+    unsafe {
+        let get_vrr_info: unsafe extern "C" fn(u32, *mut NvGetVrrInfo) -> NvStatus = nvapi.require(NVAPI_DISP_GET_VRR_INFO)?;
+        nvapi.check(get_vrr_info(display_id, &mut vrr_info), "NvAPI_Disp_GetVRRInfo")?;
+    }
+
+    Ok(VrrStatus {
+        supported: vrr_info.flags & VRR_POSSIBLE != 0,
+        enabled: vrr_info.flags & VRR_ENABLED != 0,
+        active: vrr_info.flags & VRR_ACTIVE != 0,
+    })
+}
+
+// G-SYNC is a global driver setting, so this affects every G-SYNC display on the system
+pub fn set_vrr(display: &DisplayDevice, enabled: bool) -> Result<()> {
+    let status = get_vrr(display)?;
+    if !status.supported {
+        return Err(Error::new(E_FAIL, format!("{} does not support G-SYNC", display.device_name)));
+    }
+
+    let session = DrsSession::open(nvapi()?)?;
+    let current = session.get_global_dword(VRR_GLOBAL_FEATURE_ID)?;
+    info!("Setting G-SYNC global feature from {:?} to {}", current, enabled as u32);
+    session.set_global_dword(VRR_GLOBAL_FEATURE_ID, enabled as u32)
+}
+
+//==============================================================================
+// Custom resolutions
+//==============================================================================
+
+// Same as adding a custom resolution in the NVIDIA Control Panel: the driver computes a CVT-RB
+// timing, tries it on the display, and saves it once the display accepted it
+pub fn create_custom_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32) -> Result<()> {
+    let nvapi = nvapi()?;
+    let mut display_id = display_id(nvapi, device)?;
+
+    let mut timing_input = NvTimingInput {
+        version: struct_version::<NvTimingInput>(1),
+        width,
+        height,
+        refresh_rate: refresh_rate as f32,
+        timing_type: TIMING_OVERRIDE_CVT_RB,
+        ..Default::default()
+    };
+    let mut timing = NvTiming::default();
+
+    unsafe {
+        let get_timing: unsafe extern "C" fn(u32, *mut NvTimingInput, *mut NvTiming) -> NvStatus =
+            nvapi.require(NVAPI_DISP_GET_TIMING)?;
+        nvapi.check(get_timing(display_id, &mut timing_input, &mut timing), "NvAPI_DISP_GetTiming")?;
+    }
+
+    info!("Trying custom mode {}x{} @{}Hz on {} (pixel clock {} kHz)",
+        width, height, refresh_rate, device.device_name, timing.pixel_clock * 10);
+
+    let mut custom_display = NvCustomDisplay {
+        version: struct_version::<NvCustomDisplay>(1),
+        width,
+        height,
+        depth: 32,
+        color_format: FORMAT_A8R8G8B8,
+        source_partition: [0.0, 0.0, 1.0, 1.0],
+        x_ratio: 1.0,
+        y_ratio: 1.0,
+        timing,
+        flags: 0,
+    };
+
+    unsafe {
+        let try_custom_display: unsafe extern "C" fn(*mut u32, u32, *mut NvCustomDisplay) -> NvStatus =
+            nvapi.require(NVAPI_DISP_TRY_CUSTOM_DISPLAY)?;
+        nvapi.check(try_custom_display(&mut display_id, 1, &mut custom_display), "NvAPI_DISP_TryCustomDisplay")?;
+
+        let save_custom_display: unsafe extern "C" fn(u32, u32, u32) -> NvStatus = nvapi.require(NVAPI_DISP_SAVE_CUSTOM_DISPLAY)?;
+        let status = save_custom_display(display_id, 1, 1);
+        if status != NVAPI_OK {
+            // Don't leave the display running a trial mode that will vanish on the next mode change
+            if let Some(revert) = nvapi.function::<unsafe extern "C" fn(*mut u32, u32) -> NvStatus>(NVAPI_DISP_REVERT_CUSTOM_DISPLAY_TRIAL) {
+                revert(&mut display_id, 1);
+            }
+            return nvapi.check(status, "NvAPI_DISP_SaveCustomDisplay");
+        }
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Output color format
+//==============================================================================
+
+fn pixel_format_to_nv(pixel_format: PixelFormat) -> u8 {
+    match pixel_format {
+        PixelFormat::Rgb => 0,
+        PixelFormat::Ycc422 => 1,
+        PixelFormat::Ycc444 => 2,
+        PixelFormat::Ycc420 => 3,
+    }
+}
+
+fn pixel_format_from_nv(value: u8) -> Option<PixelFormat> {
+    match value {
+        0 => Some(PixelFormat::Rgb),
+        1 => Some(PixelFormat::Ycc422),
+        2 => Some(PixelFormat::Ycc444),
+        3 => Some(PixelFormat::Ycc420),
+        _ => None,
+    }
+}
+
+// NV_BPC counts up from 6 bpc = 1, with 0 meaning the driver default
+const BITS_PER_COLOR: [u32; 5] = [6, 8, 10, 12, 16];
+
+fn color_control(nvapi: &NvApi, display_id: u32, color_data: &mut NvColorData) -> Result<()> {
+    color_data.version = struct_version::<NvColorData>(5);
+    color_data.size = size_of::<NvColorData>() as u16;
+
+    unsafe {
+        let color_control: unsafe extern "C" fn(u32, *mut NvColorData) -> NvStatus = nvapi.require(NVAPI_DISP_COLOR_CONTROL)?;
+        nvapi.check(color_control(display_id, color_data), "NvAPI_Disp_ColorControl")
+    }
+}
+
+pub fn get_color_format(display: &DisplayDevice) -> Result<ColorFormat> {
+    let nvapi = nvapi()?;
+    let display_id = display_id(nvapi, display)?;
+
+    let mut color_data = NvColorData { cmd: COLOR_CMD_GET, ..Default::default() };
+    color_control(nvapi, display_id, &mut color_data)?;
+
+    let pixel_format = pixel_format_from_nv(color_data.data.color_format)
+        .ok_or_else(|| Error::new(E_FAIL, format!("Unknown NVIDIA color format {}", color_data.data.color_format)))?;
+    let bits_per_color = (color_data.data.bpc as usize)
+        .checked_sub(1)
+        .and_then(|index| BITS_PER_COLOR.get(index))
+        .copied()
+        .unwrap_or(0);
+
+    Ok(ColorFormat { pixel_format, bits_per_color })
+}
+
+pub fn set_color_format(device: &DisplayDevice, pixel_format: Option<PixelFormat>, bits_per_color: Option<u32>) -> Result<()> {
+    let current = get_color_format(device)?;
+    let pixel_format = pixel_format.unwrap_or(current.pixel_format);
+    let bits_per_color = bits_per_color.unwrap_or(current.bits_per_color);
+
+    let bpc = match BITS_PER_COLOR.iter().position(|&bits| bits == bits_per_color) {
+        Some(index) => index as u32 + 1,
+        None if bits_per_color == 0 => 0,
+        None => return Err(Error::new(E_FAIL, format!("{} bits per color is not one of {:?}", bits_per_color, BITS_PER_COLOR))),
+    };
+
+    info!("Setting output color format of {} to {:?} at {} bpc", device.device_name, pixel_format, bits_per_color);
+
+    let nvapi = nvapi()?;
+    let display_id = display_id(nvapi, device)?;
+    let mut color_data = NvColorData {
+        cmd: COLOR_CMD_SET,
+        data: NvColorDataFields {
+            color_format: pixel_format_to_nv(pixel_format),
+            colorimetry: COLOR_COLORIMETRY_AUTO,
+            dynamic_range: COLOR_DYNAMIC_RANGE_AUTO,
+            bpc,
+            color_selection_policy: COLOR_SELECTION_POLICY_USER,
+            depth: 0,
+        },
+        ..Default::default()
+    };
+
+    color_control(nvapi, display_id, &mut color_data)
+}
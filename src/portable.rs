@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Portable mode keeps the config, state files and logs next to the executable instead of under
+// %APPDATA% and %LOCALAPPDATA%, for a copy carried on a USB stick or kept in a Sunshine tools
+// folder. It's on with --portable (or SUNSHINE_HELPER_PORTABLE) or when a portable.flag file
+// sits beside the executable. The layout is the same as the usual one: config.toml, states\
+// and the other state files in that directory, the log in its logs\ folder.
+
+pub const FLAG_FILE: &str = "portable.flag";
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// For --portable, the flag file needs nothing
+pub fn enable() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+// The executable's directory when running portable
+pub fn dir() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    (REQUESTED.load(Ordering::Relaxed) || exe_dir.join(FLAG_FILE).exists()).then_some(exe_dir)
+}
+
+// Only --portable needs passing on to a process the helper starts, the flag file is found again
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,27 @@
+use std::mem::size_of;
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+use windows::core::Result;
+
+use crate::displays_info::wide_to_string;
+
+// Executable names of the running processes, e.g. sunshine.exe, from a ToolHelp snapshot. Names
+// are enough to tell whether something runs and work without the rights to open other users'
+// processes.
+pub fn running_process_names() -> Result<Vec<String>> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)? };
+    let mut entry = PROCESSENTRY32W { dwSize: size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+    let mut names = Vec::new();
+
+    let mut result = unsafe { Process32FirstW(snapshot, &mut entry) };
+    while result.is_ok() {
+        names.push(wide_to_string(&entry.szExeFile));
+        result = unsafe { Process32NextW(snapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    Ok(names)
+}
+
+pub fn is_running(names: &[String], exe: &str) -> bool {
+    names.iter().any(|name| name.eq_ignore_ascii_case(exe))
+}
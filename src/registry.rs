@@ -0,0 +1,223 @@
+use windows::Win32::System::Registry::{
+    RegCloseKey,
+    RegDeleteKeyValueW,
+    RegDeleteTreeW,
+    RegEnumKeyExW,
+    RegGetValueW,
+    RegOpenKeyExW,
+    RegSetKeyValueW,
+    HKEY,
+    KEY_READ,
+    REG_BINARY,
+    REG_DWORD,
+    REG_EXPAND_SZ,
+    REG_ROUTINE_FLAGS,
+    REG_SZ,
+    RRF_RT_REG_BINARY,
+    RRF_RT_REG_DWORD,
+    RRF_RT_REG_SZ,
+};
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_NO_MORE_ITEMS, ERROR_SUCCESS};
+use windows::core::{PCWSTR, PWSTR, Result};
+use tracing::error;
+
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+//==============================================================================
+// Raw access
+//==============================================================================
+
+fn read_value(root: HKEY, key: &str, value: &str, flags: REG_ROUTINE_FLAGS) -> Result<Vec<u8>> {
+    let key_wide = to_wide(key);
+    let value_wide = to_wide(value);
+
+    // First call gets the size of the value
+    let mut size: u32 = 0;
+    let result = unsafe {
+        RegGetValueW(
+            root,
+            PCWSTR::from_raw(key_wide.as_ptr()),
+            PCWSTR::from_raw(value_wide.as_ptr()),
+            flags,
+            None,
+            None,
+            Some(&mut size),
+        )
+    };
+
+    if result != ERROR_SUCCESS {
+        if result != ERROR_FILE_NOT_FOUND {
+            error!(win32_code = result.0, "Failed to query size of registry value {}\\{}: {:?}", key, value, result);
+        }
+        return Err(result.into());
+    }
+
+    let mut data = vec![0u8; size as usize];
+    let result = unsafe {
+        RegGetValueW(
+            root,
+            PCWSTR::from_raw(key_wide.as_ptr()),
+            PCWSTR::from_raw(value_wide.as_ptr()),
+            flags,
+            None,
+            Some(data.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+    };
+
+    if result != ERROR_SUCCESS {
+        error!(win32_code = result.0, "Failed to read registry value {}\\{}: {:?}", key, value, result);
+        return Err(result.into());
+    }
+
+    data.truncate(size as usize);
+    Ok(data)
+}
+
+// RegSetKeyValueW creates the key if it doesn't exist yet
+fn write_value(root: HKEY, key: &str, value: &str, value_type: u32, data: &[u8]) -> Result<()> {
+    let key_wide = to_wide(key);
+    let value_wide = to_wide(value);
+
+    let result = unsafe {
+        RegSetKeyValueW(
+            root,
+            PCWSTR::from_raw(key_wide.as_ptr()),
+            PCWSTR::from_raw(value_wide.as_ptr()),
+            value_type,
+            Some(data.as_ptr() as *const _),
+            data.len() as u32,
+        )
+    };
+
+    if result != ERROR_SUCCESS {
+        error!(win32_code = result.0, "Failed to write registry value {}\\{}: {:?}", key, value, result);
+        return Err(result.into());
+    }
+
+    Ok(())
+}
+
+//==============================================================================
+// Binary values
+//==============================================================================
+
+pub fn read_binary(root: HKEY, key: &str, value: &str) -> Result<Vec<u8>> {
+    read_value(root, key, value, RRF_RT_REG_BINARY)
+}
+
+pub fn write_binary(root: HKEY, key: &str, value: &str, data: &[u8]) -> Result<()> {
+    write_value(root, key, value, REG_BINARY.0, data)
+}
+
+//==============================================================================
+// String values
+//==============================================================================
+
+// A missing value is Ok(None) since most settings only get written once changed from default
+pub fn read_string(root: HKEY, key: &str, value: &str) -> Result<Option<String>> {
+    let data = match read_value(root, key, value, RRF_RT_REG_SZ) {
+        Ok(data) => data,
+        Err(e) if e.code() == ERROR_FILE_NOT_FOUND.to_hresult() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let wide: Vec<u16> = data.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    Ok(Some(String::from_utf16_lossy(&wide[..len])))
+}
+
+pub fn write_string(root: HKEY, key: &str, value: &str, data: &str) -> Result<()> {
+    let bytes: Vec<u8> = to_wide(data).iter().flat_map(|c| c.to_le_bytes()).collect();
+    write_value(root, key, value, REG_SZ.0, &bytes)
+}
+
+// For paths with %SystemRoot% and the like, expanded by whoever reads them
+pub fn write_expand_string(root: HKEY, key: &str, value: &str, data: &str) -> Result<()> {
+    let bytes: Vec<u8> = to_wide(data).iter().flat_map(|c| c.to_le_bytes()).collect();
+    write_value(root, key, value, REG_EXPAND_SZ.0, &bytes)
+}
+
+//==============================================================================
+// DWORD values
+//==============================================================================
+
+pub fn read_dword(root: HKEY, key: &str, value: &str) -> Result<Option<u32>> {
+    match read_value(root, key, value, RRF_RT_REG_DWORD) {
+        Ok(data) if data.len() >= 4 => Ok(Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))),
+        Ok(_) => Ok(None),
+        Err(e) if e.code() == ERROR_FILE_NOT_FOUND.to_hresult() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn write_dword(root: HKEY, key: &str, value: &str, data: u32) -> Result<()> {
+    write_value(root, key, value, REG_DWORD.0, &data.to_le_bytes())
+}
+
+//==============================================================================
+// Keys
+//==============================================================================
+
+// Deletes the key with all its values and subkeys. A key that doesn't exist is fine.
+pub fn delete_key(root: HKEY, key: &str) -> Result<()> {
+    let key_wide = to_wide(key);
+    let result = unsafe { RegDeleteTreeW(root, PCWSTR::from_raw(key_wide.as_ptr())) };
+
+    if result != ERROR_SUCCESS && result != ERROR_FILE_NOT_FOUND {
+        error!(win32_code = result.0, "Failed to delete registry key {}: {:?}", key, result);
+        return Err(result.into());
+    }
+
+    Ok(())
+}
+
+// A value that doesn't exist is already deleted
+pub fn delete_value(root: HKEY, key: &str, value: &str) -> Result<()> {
+    let key_wide = to_wide(key);
+    let value_wide = to_wide(value);
+    let result = unsafe { RegDeleteKeyValueW(root, PCWSTR::from_raw(key_wide.as_ptr()), PCWSTR::from_raw(value_wide.as_ptr())) };
+
+    if result != ERROR_SUCCESS && result != ERROR_FILE_NOT_FOUND {
+        error!(win32_code = result.0, "Failed to delete registry value {}\\{}: {:?}", key, value, result);
+        return Err(result.into());
+    }
+
+    Ok(())
+}
+
+// Names of the key's direct subkeys. A key that doesn't exist has none.
+pub fn subkeys(root: HKEY, key: &str) -> Result<Vec<String>> {
+    let key_wide = to_wide(key);
+    let mut handle = HKEY::default();
+    let result = unsafe { RegOpenKeyExW(root, PCWSTR::from_raw(key_wide.as_ptr()), 0, KEY_READ, &mut handle) };
+    if result == ERROR_FILE_NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if result != ERROR_SUCCESS {
+        error!(win32_code = result.0, "Failed to open registry key {}: {:?}", key, result);
+        return Err(result.into());
+    }
+
+    let mut names = Vec::new();
+    let mut result = ERROR_SUCCESS;
+    for index in 0.. {
+        // Key names are at most 255 characters
+        let mut name = [0u16; 256];
+        let mut len = name.len() as u32;
+        result = unsafe { RegEnumKeyExW(handle, index, PWSTR::from_raw(name.as_mut_ptr()), &mut len, None, PWSTR::null(), None, None) };
+        if result != ERROR_SUCCESS {
+            break;
+        }
+        names.push(String::from_utf16_lossy(&name[..len as usize]));
+    }
+    unsafe { let _ = RegCloseKey(handle); }
+
+    if result != ERROR_NO_MORE_ITEMS {
+        error!(win32_code = result.0, "Failed to list subkeys of registry key {}: {:?}", key, result);
+        return Err(result.into());
+    }
+    Ok(names)
+}
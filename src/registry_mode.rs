@@ -0,0 +1,147 @@
+use std::mem::size_of;
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    SetupDiCallClassInstaller,
+    SetupDiCreateDeviceInfoList,
+    SetupDiDestroyDeviceInfoList,
+    SetupDiOpenDeviceInfoW,
+    SetupDiSetClassInstallParamsW,
+    DICS_FLAG_GLOBAL,
+    DICS_PROPCHANGE,
+    DIF_PROPERTYCHANGE,
+    SP_CLASSINSTALL_HEADER,
+    SP_DEVINFO_DATA,
+    SP_PROPCHANGE_PARAMS,
+};
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, HWND};
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use windows::core::{Result, PCWSTR};
+use tracing::{info, error, instrument};
+
+use crate::displays_info::{self, DisplayDevice};
+use crate::edid;
+use crate::elevation;
+use crate::exit_code;
+use crate::registry;
+
+// For displays and dummy plugs whose EDID lacks a mode and whose driver has no custom
+// resolutions, `add-registry-mode` writes an EDID override the way CRU does: the display's EDID
+// with an extra detailed timing, as the numbered 128-byte blocks under its Device
+// Parameters\EDID_OVERRIDE key. Windows reads the override instead of the EDID once the monitor
+// device restarts. A timing the display can't show leaves it blank, so removing the mode (or the
+// whole override) again has its own command.
+
+const OVERRIDE_KEY: &str = "EDID_OVERRIDE";
+const EDID_BLOCK_SIZE: usize = 128;
+
+// The monitor's device instance and its Device Parameters key under HKLM
+fn monitor_device(display: &DisplayDevice) -> Result<(String, String)> {
+    let instance_id = display.get_target_device_name()
+        .and_then(|target| target.device_instance_id())
+        .ok_or_else(|| exit_code::not_supported(format!("{} has no monitor device to override", display.device_name)))?;
+    let key = format!("SYSTEM\\CurrentControlSet\\Enum\\{}\\Device Parameters", instance_id);
+    Ok((instance_id, key))
+}
+
+fn read_override(key: &str) -> Result<Option<Vec<u8>>> {
+    let override_key = format!("{}\\{}", key, OVERRIDE_KEY);
+    let mut edid = Vec::new();
+    for index in 0.. {
+        match registry::read_binary(HKEY_LOCAL_MACHINE, &override_key, &index.to_string()) {
+            Ok(block) => edid.extend_from_slice(&block),
+            Err(e) if e.code() == ERROR_FILE_NOT_FOUND.to_hresult() => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((!edid.is_empty()).then_some(edid))
+}
+
+// Replaces the override, or deletes it when it's None
+fn write_override(key: &str, edid: Option<&[u8]>) -> Result<()> {
+    let override_key = format!("{}\\{}", key, OVERRIDE_KEY);
+    registry::delete_key(HKEY_LOCAL_MACHINE, &override_key)?;
+    for (index, block) in edid.unwrap_or_default().chunks(EDID_BLOCK_SIZE).enumerate() {
+        registry::write_binary(HKEY_LOCAL_MACHINE, &override_key, &index.to_string(), block)?;
+    }
+    Ok(())
+}
+
+// Restarting the monitor device makes Windows read its EDID, and so the override, again
+fn restart_monitor(instance_id: &str) -> Result<()> {
+    let instance_wide: Vec<u16> = instance_id.encode_utf16().chain(std::iter::once(0)).collect();
+    let set = unsafe { SetupDiCreateDeviceInfoList(None, HWND::default())? };
+
+    let mut device = SP_DEVINFO_DATA { cbSize: size_of::<SP_DEVINFO_DATA>() as u32, ..Default::default() };
+    let params = SP_PROPCHANGE_PARAMS {
+        ClassInstallHeader: SP_CLASSINSTALL_HEADER {
+            cbSize: size_of::<SP_CLASSINSTALL_HEADER>() as u32,
+            InstallFunction: DIF_PROPERTYCHANGE,
+        },
+        StateChange: DICS_PROPCHANGE,
+        Scope: DICS_FLAG_GLOBAL,
+        HwProfile: 0,
+    };
+    let result = unsafe {
+        SetupDiOpenDeviceInfoW(set, PCWSTR::from_raw(instance_wide.as_ptr()), HWND::default(), 0, Some(&mut device))
+            .and_then(|()| SetupDiSetClassInstallParamsW(set, Some(&device), Some(&params.ClassInstallHeader), size_of::<SP_PROPCHANGE_PARAMS>() as u32))
+            .and_then(|()| SetupDiCallClassInstaller(DIF_PROPERTYCHANGE, set, Some(&device)))
+    };
+    let _ = unsafe { SetupDiDestroyDeviceInfoList(set) };
+    displays_info::invalidate_topology();
+
+    if let Err(e) = &result {
+        error!("Failed to restart monitor device {}: {}", instance_id, e);
+    }
+    result
+}
+
+//==============================================================================
+// Commands
+//==============================================================================
+
+// False when the override already has the mode
+#[instrument(skip_all, fields(display = %device.device_name, width = width, height = height, refresh_rate = refresh_rate))]
+pub fn add_registry_mode(device: &DisplayDevice, width: u32, height: u32, refresh_rate: u32) -> Result<bool> {
+    elevation::require_elevation("Writing an EDID override")?;
+    let (instance_id, key) = monitor_device(device)?;
+
+    let descriptor = edid::detailed_timing(width, height, refresh_rate).map_err(exit_code::invalid_input)?;
+    // Modes add up in an existing override
+    let current = match read_override(&key)? {
+        Some(current) => current,
+        None => edid::read_display_edid(device)
+            .ok_or_else(|| exit_code::not_supported(format!("Failed to read the EDID of {}", device.device_name)))?,
+    };
+    let Some(updated) = edid::add_detailed_timing(&current, &descriptor).map_err(exit_code::not_supported)? else {
+        return Ok(false);
+    };
+
+    info!("Writing an EDID override with {}x{} @{}Hz to HKLM\\{}\\{}", width, height, refresh_rate, key, OVERRIDE_KEY);
+    write_override(&key, Some(&updated))?;
+    restart_monitor(&instance_id)?;
+    Ok(true)
+}
+
+// Takes the mode out of the override, or the whole override with `mode` None. The override goes
+// once only the display's own EDID is left. False when there was nothing to remove.
+#[instrument(skip_all, fields(display = %device.device_name, mode = ?mode))]
+pub fn remove_registry_mode(device: &DisplayDevice, mode: Option<(u32, u32, u32)>) -> Result<bool> {
+    elevation::require_elevation("Removing an EDID override")?;
+    let (instance_id, key) = monitor_device(device)?;
+
+    let Some(current) = read_override(&key)? else {
+        return Ok(false);
+    };
+    let updated = match mode {
+        Some((width, height, refresh_rate)) => match edid::remove_detailed_timing(&current, width, height, refresh_rate) {
+            Some(updated) => Some(updated),
+            None => return Ok(false),
+        },
+        None => None,
+    };
+    let updated = updated.filter(|updated| edid::read_display_edid(device).as_ref() != Some(updated));
+
+    info!("{} the EDID override in HKLM\\{}\\{}", if updated.is_some() { "Rewriting" } else { "Deleting" }, key, OVERRIDE_KEY);
+    write_override(&key, updated.as_deref())?;
+    restart_monitor(&instance_id)?;
+    Ok(true)
+}
@@ -0,0 +1,213 @@
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+use crate::change_icc_profile;
+use crate::display_api;
+use crate::displays_info::DisplayDevice;
+use crate::dpi_scaling;
+use crate::dxgi_info;
+use crate::edid;
+use crate::hdr;
+use crate::set_sdr_level;
+use crate::windows_version;
+
+// `report` gathers everything the helper can find out about a display into one document for a
+// bug report, or to compare two machines: the current state, what the EDID says, the HDR and
+// luminance capabilities Windows and DXGI report, the ICC profiles and every mode. The JSON is
+// the report, the markdown is rendered from it, so both always say the same. Values that can't
+// be read are null, and the markdown says "unknown".
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+//==============================================================================
+// Gathering
+//==============================================================================
+
+fn edid_summary(display: &DisplayDevice) -> Value {
+    let Some(edid) = edid::get_display_edid_info(display) else {
+        return Value::Null;
+    };
+    json!({
+        "display_id": edid.stable_id(),
+        "monitor_name": edid.monitor_name,
+        "manufacturer": edid.manufacturer,
+        "product_code": format!("{:04X}", edid.product_code),
+        "serial": edid.serial_string.clone().unwrap_or_else(|| edid.serial_number.to_string()),
+        "manufactured": format!("week {} of {}", edid.manufacture_week, edid.manufacture_year),
+        "native_mode": edid.native_mode.map(|mode| format!("{}x{}{} @{}Hz", mode.width, mode.height, if mode.interlaced { "i" } else { "" }, mode.refresh_rate)),
+        "hdr10": edid.hdr.as_ref().is_some_and(|hdr| hdr.hdr10),
+        "hlg": edid.hdr.as_ref().is_some_and(|hdr| hdr.hlg),
+        "bt2020": edid.hdr.as_ref().is_some_and(|hdr| hdr.bt2020),
+        "max_luminance": edid.hdr.as_ref().and_then(|hdr| hdr.max_luminance),
+        "max_frame_avg_luminance": edid.hdr.as_ref().and_then(|hdr| hdr.max_frame_avg_luminance),
+        "min_luminance": edid.hdr.as_ref().and_then(|hdr| hdr.min_luminance),
+    })
+}
+
+// DXGI only knows real outputs, a mock fixture has none
+fn luminance(display: &DisplayDevice) -> Value {
+    if display_api::is_mock() {
+        return Value::Null;
+    }
+    let outputs = dxgi_info::query_output_color_info().unwrap_or_default();
+    let Some(output) = outputs.iter().find(|output| output.device_name == display.device_name) else {
+        return Value::Null;
+    };
+    json!({
+        "color_space": output.color_space_name(),
+        "bits_per_color": output.bits_per_color,
+        "min_luminance": output.min_luminance,
+        "max_luminance": output.max_luminance,
+        "max_full_frame_luminance": output.max_full_frame_luminance,
+        "red_primary": output.red_primary,
+        "green_primary": output.green_primary,
+        "blue_primary": output.blue_primary,
+        "white_point": output.white_point,
+    })
+}
+
+pub fn build(display: &DisplayDevice) -> Value {
+    let color = hdr::get_advanced_color_info(display).ok();
+    let scaling = dpi_scaling::get_display_scaling(display).ok();
+    let sdr_raw = set_sdr_level::get_display_sdr_white_raw(display).ok();
+    let default_profile = change_icc_profile::get_display_default_icc_profile(display);
+    let profiles: Vec<_> = change_icc_profile::get_display_icc_profiles(display).into_iter().map(|profile| json!({
+        "name": profile.name,
+        "default": default_profile.as_ref().is_some_and(|default| default.eq_ignore_ascii_case(&profile.name)),
+    })).collect();
+    let modes: Vec<_> = display.get_supported_modes().iter().map(|mode| json!({
+        "width": mode.width,
+        "height": mode.height,
+        "refresh_rate": mode.refresh_rate,
+        "interlaced": mode.interlaced,
+        "scaling": mode.fixed_output.name(),
+    })).collect();
+
+    json!({
+        "generated_by": format!("sunshine_helper {}", env!("CARGO_PKG_VERSION")),
+        "windows": windows_version::build().map(windows_version::describe),
+        "display": {
+            "device_name": display.device_name,
+            "display_id": display.stable_id(),
+            "monitor": display.get_target_device_name().map(|target| target.friendly_name),
+            "adapter": display.device_string,
+            "adapter_device_id": display.adapter_device_id,
+            "connection": display.connection_name(),
+            "virtual_driver": display.virtual_driver().map(|driver| format!("{:?}", driver)),
+            "primary": display.is_primary,
+        },
+        "current": {
+            "resolution": [display.current_resolution.0, display.current_resolution.1],
+            "refresh_rate": display.current_refresh_rate,
+            "position": [display.position.0, display.position.1],
+            "hdr": color.as_ref().map(|color| color.enabled),
+            "sdr_level": sdr_raw.map(set_sdr_level::raw_to_level),
+            "sdr_nits": sdr_raw.map(set_sdr_level::raw_to_nits),
+            "scaling": scaling.as_ref().map(|scaling| scaling.current),
+            "recommended_scaling": scaling.as_ref().map(|scaling| scaling.recommended),
+            "icc_profile": default_profile,
+        },
+        "hdr": color.map(|color| json!({
+            "supported": color.supported,
+            "enabled": color.enabled,
+            "force_disabled": color.force_disabled,
+            "bits_per_color": color.bits_per_color,
+        })),
+        "luminance": luminance(display),
+        "edid": edid_summary(display),
+        "icc_profiles": profiles,
+        "modes": modes,
+    })
+}
+
+//==============================================================================
+// Markdown
+//==============================================================================
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "unknown".to_string(),
+        Value::String(text) => text.clone(),
+        Value::Bool(true) => "yes".to_string(),
+        Value::Bool(false) => "no".to_string(),
+        Value::Array(values) => values.iter().map(text).collect::<Vec<_>>().join(", "),
+        value => value.to_string(),
+    }
+}
+
+// A two column table of an object's fields, in the report's order
+fn field_table(out: &mut String, fields: &Value) {
+    let _ = writeln!(out, "| Field | Value |\n| --- | --- |");
+    if let Value::Object(fields) = fields {
+        for (name, value) in fields {
+            let _ = writeln!(out, "| {} | {} |", name, text(value).replace('|', "\\|"));
+        }
+    }
+    out.push('\n');
+}
+
+pub fn markdown(report: &Value) -> String {
+    let mut out = String::new();
+    let display = &report["display"];
+    let _ = writeln!(out, "# Display report: {} ({})\n", text(&display["monitor"]), text(&display["device_name"]));
+    let _ = writeln!(out, "Generated by {} on {}.\n", text(&report["generated_by"]), text(&report["windows"]));
+
+    for (title, section) in [("Display", "display"), ("Current state", "current"), ("HDR", "hdr"), ("Luminance (DXGI)", "luminance"), ("EDID", "edid")] {
+        let _ = writeln!(out, "## {}\n", title);
+        match &report[section] {
+            Value::Null => out.push_str("Not available.\n\n"),
+            fields => field_table(&mut out, fields),
+        }
+    }
+
+    out.push_str("## ICC profiles\n\n");
+    match report["icc_profiles"].as_array().map(Vec::as_slice).unwrap_or_default() {
+        [] => out.push_str("None associated.\n\n"),
+        profiles => {
+            for profile in profiles {
+                let _ = writeln!(out, "- {}{}", text(&profile["name"]), if profile["default"] == true { " (default)" } else { "" });
+            }
+            out.push('\n');
+        }
+    }
+
+    out.push_str("## Modes\n\n| Resolution | Refresh | Scaling |\n| --- | --- | --- |\n");
+    for mode in report["modes"].as_array().map(Vec::as_slice).unwrap_or_default() {
+        let _ = writeln!(out, "| {}x{}{} | {}Hz | {} |", mode["width"], mode["height"],
+            if mode["interlaced"] == true { "i" } else { "" }, mode["refresh_rate"], text(&mode["scaling"]));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::displays_info;
+
+    #[test]
+    fn markdown_follows_the_json() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.modes = vec![(3840, 2160, 120), (1920, 1080, 60)];
+        tv.icc_profiles = vec!["HDR TV.icc".to_string(), "sRGB | wide.icc".to_string()];
+        tv.icc_profile = Some("HDR TV.icc".to_string());
+        MockDisplayApi::install(vec![tv]);
+
+        let report = build(&displays_info::find_display("primary").unwrap());
+        assert_eq!(report["current"]["resolution"], json!([3840, 2160]));
+        assert_eq!(report["hdr"]["enabled"], true);
+        assert_eq!(report["modes"].as_array().unwrap().len(), 2);
+        assert!(report["luminance"].is_null());
+
+        let markdown = markdown(&report);
+        assert!(markdown.starts_with("# Display report: LG TV SSCR2 (\\\\.\\DISPLAY1)\n"));
+        assert!(markdown.contains("| refresh_rate | 120 |"));
+        assert!(markdown.contains("## Luminance (DXGI)\n\nNot available."));
+        assert!(markdown.contains("- HDR TV.icc (default)\n- sRGB | wide.icc\n"));
+        assert!(markdown.contains("| 1920x1080 | 60Hz | default |"));
+    }
+}
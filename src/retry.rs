@@ -0,0 +1,51 @@
+use std::fmt::Display;
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::{info, error};
+
+// Right after a hotplug or an HDR toggle the display stack is still settling, and SDR, ICC
+// and mode calls can fail once and succeed a moment later. Prep commands retry these with
+// exponential backoff instead of failing the whole chain.
+
+// Used by the prep-style commands (cpdm, ssdrl, sicc, hdr) unless --retries says otherwise
+pub const PREP_RETRIES: u32 = 3;
+pub const DEFAULT_RETRY_DELAY_MS: u64 = 250;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration, // before the first retry, doubled for each one after
+}
+
+impl RetryPolicy {
+    // Command line overrides on top of a command's default retry count
+    pub fn from_args(retries: Option<u32>, retry_delay_ms: Option<u64>, default_retries: u32) -> Self {
+        RetryPolicy {
+            retries: retries.unwrap_or(default_retries),
+            delay: Duration::from_millis(retry_delay_ms.unwrap_or(DEFAULT_RETRY_DELAY_MS)),
+        }
+    }
+}
+
+pub fn with_retries<T, E: Display>(policy: RetryPolicy, what: &str, mut operation: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    let mut delay = policy.delay;
+    let mut attempt = 0;
+
+    loop {
+        match operation() {
+            Ok(value) => {
+                if attempt > 0 {
+                    info!("{} succeeded after {} retr{}", what, attempt, if attempt == 1 { "y" } else { "ies" });
+                }
+                return Ok(value);
+            }
+            Err(e) if attempt < policy.retries => {
+                attempt += 1;
+                error!("{} failed ({}), retry {}/{} in {} ms", what, e, attempt, policy.retries, delay.as_millis());
+                sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
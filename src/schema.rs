@@ -0,0 +1,584 @@
+use serde_json::{json, Map, Value};
+
+use crate::displays_info::{self, DisplayDevice, DisplayMode, InactiveOutput};
+use crate::set_sdr_level;
+use crate::vdd::VirtualDisplayDevice;
+
+// Every JSON (and YAML) document the helper prints starts with a schema_version. Within a major
+// version fields are only ever added, so a script written against 1.0 keeps working on 1.x.
+// Renaming, removing or retyping a field means 2.0. `schema <command>` prints the JSON Schema
+// of each document. The documents main.rs doesn't get from another module are built below,
+// and the tests check every one against its schema.
+
+pub const SCHEMA_VERSION: &str = "1.1"; // 1.1: interlaced and scaling on modes
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SchemaCommand {
+    EnumerateDisplays,
+    PrimaryDisplayModes,
+    Status,
+    Diff,
+    GetSdrLevel,
+    Events,
+    Report,
+}
+
+impl SchemaCommand {
+    pub const ALL: [SchemaCommand; 7] = [
+        SchemaCommand::EnumerateDisplays,
+        SchemaCommand::PrimaryDisplayModes,
+        SchemaCommand::Status,
+        SchemaCommand::Diff,
+        SchemaCommand::GetSdrLevel,
+        SchemaCommand::Events,
+        SchemaCommand::Report,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchemaCommand::EnumerateDisplays => "enumerate-displays",
+            SchemaCommand::PrimaryDisplayModes => "primary-display-modes",
+            SchemaCommand::Status => "status",
+            SchemaCommand::Diff => "diff",
+            SchemaCommand::GetSdrLevel => "get-sdr-level",
+            SchemaCommand::Events => "events",
+            SchemaCommand::Report => "report",
+        }
+    }
+}
+
+// Puts schema_version in front of the document's own fields
+pub fn versioned(document: Value) -> Value {
+    let mut versioned = Map::new();
+    versioned.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    if let Value::Object(fields) = document {
+        versioned.extend(fields);
+    }
+    Value::Object(versioned)
+}
+
+fn document(title: &str, properties: Value, required: &[&str]) -> Value {
+    let mut all_properties = Map::new();
+    all_properties.insert("schema_version".to_string(), json!({
+        "type": "string",
+        "pattern": "^1\\.[0-9]+$",
+        "description": "Major.minor version of this document's shape",
+    }));
+    if let Value::Object(properties) = properties {
+        all_properties.extend(properties);
+    }
+
+    let mut all_required = vec!["schema_version"];
+    all_required.extend(required);
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": title,
+        "type": "object",
+        "properties": all_properties,
+        "required": all_required,
+    })
+}
+
+fn mode_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "width": { "type": "integer", "minimum": 0 },
+            "height": { "type": "integer", "minimum": 0 },
+            "refresh_rate": { "type": "integer", "minimum": 0 },
+        },
+        "required": ["width", "height", "refresh_rate"],
+    })
+}
+
+pub fn schema(command: SchemaCommand) -> Value {
+    match command {
+        SchemaCommand::EnumerateDisplays => document("test enumerate-displays --json", json!({
+            "displays": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "device_index": { "type": "integer" },
+                        "device_name": { "type": "string" },
+                        "description": { "type": "string" },
+                        "adapter_index": { "type": "integer", "description": "For --adapter, in the order the adapters' first displays enumerate" },
+                        "adapter_luid": { "type": "string", "description": "For --adapter, the LUID's high and low parts in hex" },
+                        "primary": { "type": "boolean" },
+                        "resolution": { "type": "array", "items": { "type": "integer" }, "minItems": 2, "maxItems": 2 },
+                        "refresh_rate": { "type": "integer" },
+                        "virtual": { "type": "boolean" },
+                        "virtual_driver": { "enum": ["Parsec", "SudoVda", "IddSample", null] },
+                        "display_id": { "type": ["string", "null"], "description": "ID that survives reboots, manufacturer-product-serial from the EDID or the monitor's device instance ID, usable as a selector" },
+                    },
+                    "required": ["device_index", "device_name", "description", "primary", "resolution", "refresh_rate", "virtual", "virtual_driver", "display_id"],
+                },
+            },
+            "virtual_display_drivers": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "driver": { "enum": ["Parsec", "SudoVda", "IddSample"] },
+                        "description": { "type": "string" },
+                        "instance_id": { "type": "string" },
+                        "status": { "type": "string", "description": "Running, Disabled, Stopped or Problem(<CM_PROB code>)" },
+                        "active_displays": { "type": "integer" },
+                    },
+                    "required": ["driver", "description", "instance_id", "status", "active_displays"],
+                },
+            },
+            "inactive_outputs": {
+                "type": "array",
+                "description": "Only with --all",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "status": { "enum": ["inactive", "disconnected"], "description": "inactive: a monitor is connected but not part of the desktop. disconnected: a GPU output with nothing on it" },
+                        "device_name": { "type": "string", "description": "Empty for an inactive monitor, which has no GDI name until it's enabled" },
+                        "monitor": { "type": "string", "description": "Empty for a disconnected output" },
+                        "description": { "type": "string" },
+                        "adapter_luid": { "type": "string" },
+                        "virtual_driver": { "enum": ["Parsec", "SudoVda", "IddSample", null] },
+                    },
+                    "required": ["status", "device_name", "monitor", "description", "adapter_luid", "virtual_driver"],
+                },
+            },
+        }), &["displays", "virtual_display_drivers"]),
+        SchemaCommand::PrimaryDisplayModes => {
+            let mut mode = mode_schema();
+            mode["properties"]["interlaced"] = json!({ "type": "boolean" });
+            mode["properties"]["scaling"] = json!({ "enum": ["default", "stretch", "center"] });
+            mode["properties"]["current"] = json!({ "type": "boolean" });
+            mode["required"] = json!(["width", "height", "refresh_rate", "interlaced", "scaling", "current"]);
+            document("modes --format json (and test primary-display-modes)", json!({
+                "device_name": { "type": "string" },
+                "description": { "type": "string" },
+                "modes": { "type": "array", "items": mode },
+            }), &["device_name", "description", "modes"])
+        }
+        SchemaCommand::Status => document("status --format json (a spec for apply)", json!({
+            "displays": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "display": { "type": "string" },
+                        "enabled": { "type": "boolean" },
+                        "primary": { "type": "boolean" },
+                        "mode": mode_schema(),
+                        "hdr": { "type": "boolean", "description": "Left out for displays without HDR" },
+                        "sdr_nits": { "type": "integer", "minimum": 80, "maximum": 480, "description": "Only while HDR is on" },
+                        "icc_profile": { "type": "string" },
+                    },
+                    "required": ["display"],
+                },
+            },
+        }), &["displays"]),
+        SchemaCommand::Diff => document("diff --json", json!({
+            "differences": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "display": { "type": "string", "description": "Selector from the spec" },
+                        "desired": {
+                            "oneOf": [
+                                { "const": "primary" },
+                                { "type": "object", "properties": { "enabled": { "type": "boolean" } }, "required": ["enabled"] },
+                                { "type": "object", "properties": { "mode": mode_schema() }, "required": ["mode"] },
+                                { "type": "object", "properties": { "hdr": { "type": "boolean" } }, "required": ["hdr"] },
+                                { "type": "object", "properties": { "sdr_nits": { "type": "integer" } }, "required": ["sdr_nits"] },
+                                { "type": "object", "properties": { "icc_profile": { "type": "string" } }, "required": ["icc_profile"] },
+                            ],
+                        },
+                        "current": { "type": "string", "description": "Human readable current value" },
+                    },
+                    "required": ["display", "desired", "current"],
+                },
+            },
+        }), &["differences"]),
+        SchemaCommand::GetSdrLevel => document("get-sdr-level --format json", json!({
+            "displays": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "device_name": { "type": "string" },
+                        "display_id": { "type": ["string", "null"] },
+                        "primary": { "type": "boolean" },
+                        "hdr": { "type": ["boolean", "null"], "description": "Null for displays without HDR, the level only takes effect while HDR is on" },
+                        "level": { "type": ["integer", "null"], "minimum": 0, "description": "Slider value in Settings, null when it can't be read" },
+                        "nits": { "type": ["integer", "null"] },
+                        "raw": { "type": ["integer", "null"], "description": "API value, in 1000ths of 80 nits" },
+                    },
+                    "required": ["device_name", "display_id", "primary", "hdr", "level", "nits", "raw"],
+                },
+            },
+        }), &["displays"]),
+        SchemaCommand::Events => document("events (one document per line)", json!({
+            "event": { "enum": ["attached", "removed", "mode_changed", "hdr_changed", "primary_changed"] },
+            "time": { "type": "string", "format": "date-time" },
+            "display": { "type": "string", "description": "Device name, for primary_changed the new primary" },
+            "display_id": { "type": ["string", "null"] },
+            "mode": mode_schema(),
+            "hdr": { "type": ["boolean", "null"], "description": "attached only, null for displays without HDR" },
+            "primary": { "type": "boolean", "description": "attached only" },
+            "old": { "description": "The mode, HDR state or previous primary's device name before the change" },
+            "new": { "description": "The mode or HDR state after the change" },
+        }), &["event", "time", "display", "display_id"]),
+        SchemaCommand::Report => {
+            let mut mode = mode_schema();
+            mode["properties"]["interlaced"] = json!({ "type": "boolean" });
+            mode["properties"]["scaling"] = json!({ "enum": ["default", "stretch", "center"] });
+            mode["required"] = json!(["width", "height", "refresh_rate", "interlaced", "scaling"]);
+            let nullable = |kind: &str| json!({ "type": [kind, "null"] });
+            let point = json!({ "type": "array", "items": { "type": "number" }, "minItems": 2, "maxItems": 2 });
+            document("report --format json", json!({
+                "generated_by": { "type": "string" },
+                "windows": { "type": ["string", "null"], "description": "Release and build, null when it can't be read" },
+                "display": {
+                    "type": "object",
+                    "properties": {
+                        "device_name": { "type": "string" },
+                        "display_id": nullable("string"),
+                        "monitor": nullable("string"),
+                        "adapter": { "type": "string" },
+                        "adapter_device_id": { "type": "string" },
+                        "connection": { "type": "string" },
+                        "virtual_driver": { "enum": ["Parsec", "SudoVda", "IddSample", null] },
+                        "primary": { "type": "boolean" },
+                    },
+                    "required": ["device_name", "display_id", "monitor", "adapter", "adapter_device_id", "connection", "virtual_driver", "primary"],
+                },
+                "current": {
+                    "type": "object",
+                    "properties": {
+                        "resolution": { "type": "array", "items": { "type": "integer" }, "minItems": 2, "maxItems": 2 },
+                        "refresh_rate": { "type": "integer" },
+                        "position": { "type": "array", "items": { "type": "integer" }, "minItems": 2, "maxItems": 2 },
+                        "hdr": nullable("boolean"),
+                        "sdr_level": nullable("integer"),
+                        "sdr_nits": nullable("integer"),
+                        "scaling": nullable("integer"),
+                        "recommended_scaling": nullable("integer"),
+                        "icc_profile": nullable("string"),
+                    },
+                    "required": ["resolution", "refresh_rate", "position", "hdr", "sdr_level", "sdr_nits", "scaling", "recommended_scaling", "icc_profile"],
+                },
+                "hdr": {
+                    "type": ["object", "null"],
+                    "properties": {
+                        "supported": { "type": "boolean" },
+                        "enabled": { "type": "boolean" },
+                        "force_disabled": { "type": "boolean" },
+                        "bits_per_color": { "type": "integer" },
+                    },
+                    "required": ["supported", "enabled", "force_disabled", "bits_per_color"],
+                },
+                "luminance": {
+                    "type": ["object", "null"],
+                    "description": "From DXGI, null for displays it doesn't know",
+                    "properties": {
+                        "color_space": { "type": "string" },
+                        "bits_per_color": { "type": "integer" },
+                        "min_luminance": { "type": "number" },
+                        "max_luminance": { "type": "number" },
+                        "max_full_frame_luminance": { "type": "number" },
+                        "red_primary": point,
+                        "green_primary": point,
+                        "blue_primary": point,
+                        "white_point": point,
+                    },
+                    "required": ["color_space", "bits_per_color", "min_luminance", "max_luminance", "max_full_frame_luminance", "red_primary", "green_primary", "blue_primary", "white_point"],
+                },
+                "edid": {
+                    "type": ["object", "null"],
+                    "properties": {
+                        "display_id": { "type": "string" },
+                        "monitor_name": nullable("string"),
+                        "manufacturer": { "type": "string" },
+                        "product_code": { "type": "string", "description": "Four hex digits" },
+                        "serial": { "type": "string" },
+                        "manufactured": { "type": "string" },
+                        "native_mode": { "type": ["string", "null"], "description": "Like 3840x2160 @120Hz" },
+                        "hdr10": { "type": "boolean" },
+                        "hlg": { "type": "boolean" },
+                        "bt2020": { "type": "boolean" },
+                        "max_luminance": nullable("number"),
+                        "max_frame_avg_luminance": nullable("number"),
+                        "min_luminance": nullable("number"),
+                    },
+                    "required": ["display_id", "monitor_name", "manufacturer", "product_code", "serial", "manufactured", "native_mode", "hdr10", "hlg", "bt2020", "max_luminance", "max_frame_avg_luminance", "min_luminance"],
+                },
+                "icc_profiles": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": { "type": "string" },
+                            "default": { "type": "boolean" },
+                        },
+                        "required": ["name", "default"],
+                    },
+                },
+                "modes": { "type": "array", "items": mode },
+            }), &["generated_by", "windows", "display", "current", "hdr", "luminance", "edid", "icc_profiles", "modes"])
+        }
+    }
+}
+
+//==============================================================================
+// Documents
+//==============================================================================
+
+// `test enumerate-displays`, the inactive outputs only with --all
+pub fn displays_document(displays: &[DisplayDevice], inactive_outputs: Option<&[InactiveOutput]>, virtual_devices: &[VirtualDisplayDevice]) -> Value {
+    let display_entries: Vec<_> = displays.iter().map(|display| json!({
+        "device_index": display.device_index,
+        "device_name": display.device_name,
+        "description": display.device_string,
+        "adapter_index": display.adapter_index,
+        "adapter_luid": displays_info::luid_text(display.adapter_id),
+        "primary": display.is_primary,
+        "resolution": [display.current_resolution.0, display.current_resolution.1],
+        "refresh_rate": display.current_refresh_rate,
+        "virtual": display.virtual_driver().is_some(),
+        "virtual_driver": display.virtual_driver().map(|driver| format!("{:?}", driver)),
+        "display_id": display.stable_id(),
+    })).collect();
+    let drivers: Vec<_> = virtual_devices.iter().map(|device| json!({
+        "driver": format!("{:?}", device.driver),
+        "description": device.description,
+        "instance_id": device.instance_id,
+        "status": format!("{:?}", device.status),
+        "active_displays": displays.iter().filter(|d| d.virtual_driver() == Some(device.driver)).count(),
+    })).collect();
+    let mut document = json!({
+        "displays": display_entries,
+        "virtual_display_drivers": drivers,
+    });
+    if let Some(inactive_outputs) = inactive_outputs {
+        document["inactive_outputs"] = inactive_outputs.iter().map(|output| json!({
+            "status": output.status.name(),
+            "device_name": output.device_name,
+            "monitor": output.monitor,
+            "description": output.adapter,
+            "adapter_luid": displays_info::luid_text(output.adapter_id),
+            "virtual_driver": output.virtual_driver().map(|driver| format!("{:?}", driver)),
+        })).collect::<Vec<_>>().into();
+    }
+    document
+}
+
+// `modes` and `test primary-display-modes`, filtered already
+pub fn modes_document(display: &DisplayDevice, modes: &[DisplayMode]) -> Value {
+    let mode_entries: Vec<_> = modes.iter().map(|mode| json!({
+        "width": mode.width,
+        "height": mode.height,
+        "refresh_rate": mode.refresh_rate,
+        "interlaced": mode.interlaced,
+        "scaling": mode.fixed_output.name(),
+        "current": display.is_current_mode(mode),
+    })).collect();
+    json!({
+        "device_name": display.device_name,
+        "description": display.device_string,
+        "modes": mode_entries,
+    })
+}
+
+// `get-sdr-level`, with the readings of set_sdr_level::get_all_sdr_white_raw
+pub fn sdr_levels_document(displays: &[DisplayDevice], readings: &[(Option<bool>, Option<u32>)]) -> Value {
+    let entries: Vec<_> = displays.iter().zip(readings).map(|(display, (hdr, raw))| json!({
+        "device_name": display.device_name,
+        "display_id": display.stable_id(),
+        "primary": display.is_primary,
+        "hdr": hdr,
+        "level": raw.map(set_sdr_level::raw_to_level),
+        "nits": raw.map(set_sdr_level::raw_to_nits),
+        "raw": raw,
+    })).collect();
+    json!({ "displays": entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::{self, Spec};
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+    use crate::events;
+    use crate::report;
+    use crate::vdd::{VddDriver, VddStatus};
+
+    // PowerShell's ConvertFrom-Json rejects empty names and names that differ only in case,
+    // and snake_case keeps them usable as $display.refresh_rate
+    fn check_property_names(schema: &Value, path: &str) {
+        match schema {
+            Value::Object(fields) => {
+                if let Some(Value::Object(properties)) = fields.get("properties") {
+                    let mut seen: Vec<String> = Vec::new();
+                    for name in properties.keys() {
+                        assert!(!name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+                            "{}: property `{}` isn't snake_case", path, name);
+                        assert!(!seen.contains(&name.to_ascii_lowercase()), "{}: property `{}` is there twice", path, name);
+                        seen.push(name.to_ascii_lowercase());
+                    }
+                }
+                for (key, value) in fields {
+                    check_property_names(value, &format!("{}.{}", path, key));
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|item| check_property_names(item, path)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn property_names_suit_powershell() {
+        for command in SchemaCommand::ALL {
+            check_property_names(&schema(command), command.name());
+        }
+    }
+
+    fn is_type(value: &Value, kind: &str) -> bool {
+        match kind {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            "string" => value.is_string(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        }
+    }
+
+    // Where a document strays from its schema: missing required fields, fields the schema doesn't
+    // have, and values of another type or outside an enum. Enough of JSON Schema for ours.
+    fn mismatches(value: &Value, schema: &Value, path: &str) -> Vec<String> {
+        if let Some(options) = schema["oneOf"].as_array() {
+            let matching = options.iter().filter(|option| mismatches(value, option, path).is_empty()).count();
+            return if matching == 1 { Vec::new() } else { vec![format!("{}: {} matches {} of the oneOf options", path, value, matching)] };
+        }
+
+        let mut found = Vec::new();
+        if schema.get("const").is_some_and(|constant| constant != value) {
+            found.push(format!("{}: {} isn't {}", path, value, schema["const"]));
+        }
+        if schema["enum"].as_array().is_some_and(|allowed| !allowed.contains(value)) {
+            found.push(format!("{}: {} isn't one of {}", path, value, schema["enum"]));
+        }
+        let kinds: Vec<&str> = match &schema["type"] {
+            Value::String(kind) => vec![kind.as_str()],
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !kinds.is_empty() && !kinds.iter().any(|kind| is_type(value, kind)) {
+            found.push(format!("{}: {} isn't {}", path, value, kinds.join(" or ")));
+        }
+
+        match value {
+            Value::Object(fields) => {
+                for name in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    if !fields.contains_key(name) {
+                        found.push(format!("{}: `{}` is missing", path, name));
+                    }
+                }
+                if let Some(properties) = schema["properties"].as_object() {
+                    for (name, field) in fields {
+                        match properties.get(name) {
+                            Some(property) => found.extend(mismatches(field, property, &format!("{}.{}", path, name))),
+                            None => found.push(format!("{}: `{}` isn't in the schema", path, name)),
+                        }
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item) = schema.get("items") {
+                    for (index, value) in items.iter().enumerate() {
+                        found.extend(mismatches(value, item, &format!("{}[{}]", path, index)));
+                    }
+                }
+            }
+            _ => {}
+        }
+        found
+    }
+
+    // The document as printed, with an entry in each of its lists so none passes for being empty
+    fn check_document(command: SchemaCommand, document: &Value) {
+        let found = mismatches(document, &schema(command), command.name());
+        assert!(found.is_empty(), "{}", found.join("\n"));
+        for (name, field) in document.as_object().into_iter().flatten() {
+            assert!(field.as_array().is_none_or(|items| !items.is_empty()), "{}: `{}` is empty", command.name(), name);
+        }
+    }
+
+    // An HDR TV with an interlaced mode, an SDR monitor, and a monitor switched off
+    fn displays() -> Vec<MockDisplay> {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.interlaced_modes = vec![(1920, 1080, 60)];
+        tv.icc_profiles = vec!["LG TV.icc".to_string()];
+        let mut switched_off = MockDisplay::new(3, "Virtual Display", (1920, 1080, 60));
+        switched_off.active = false;
+        vec![tv, MockDisplay::desk_monitor(), switched_off]
+    }
+
+    #[test]
+    fn display_documents_match_their_schemas() {
+        MockDisplayApi::install(displays());
+        let displays = displays_info::enumerate_displays();
+        let inactive_outputs = displays_info::enumerate_inactive_outputs();
+        let virtual_devices = [VirtualDisplayDevice {
+            driver: VddDriver::SudoVda,
+            description: "SudoMaker Virtual Display Adapter".to_string(),
+            instance_id: "ROOT\\DISPLAY\\0000".to_string(),
+            status: VddStatus::Problem(22),
+        }];
+        check_document(SchemaCommand::EnumerateDisplays, &versioned(displays_document(&displays, Some(&inactive_outputs), &virtual_devices)));
+        check_document(SchemaCommand::EnumerateDisplays, &versioned(displays_document(&displays, None, &virtual_devices)));
+
+        let tv = &displays[0];
+        check_document(SchemaCommand::PrimaryDisplayModes, &versioned(modes_document(tv, &tv.get_supported_modes())));
+        check_document(SchemaCommand::GetSdrLevel, &versioned(sdr_levels_document(&displays, &set_sdr_level::get_all_sdr_white_raw(&displays))));
+        check_document(SchemaCommand::Report, &versioned(report::build(tv)));
+    }
+
+    #[test]
+    fn spec_documents_match_their_schemas() {
+        MockDisplayApi::install(displays());
+        let spec = apply::current_spec().unwrap();
+        check_document(SchemaCommand::Status, &versioned(serde_json::to_value(&spec).unwrap()));
+
+        // One difference of each kind
+        let spec: Spec = serde_json::from_value(json!({ "displays": [
+            { "display": "\\\\.\\DISPLAY1", "mode": { "width": 1920, "height": 1080, "refresh_rate": 60 }, "hdr": false, "sdr_nits": 400, "icc_profile": "Calibrated.icc" },
+            { "display": "\\\\.\\DISPLAY2", "primary": true },
+            { "display": "Virtual Display", "enabled": true },
+        ] })).unwrap();
+        let differences = apply::differences(&spec).unwrap();
+        assert_eq!(differences.len(), 6);
+        check_document(SchemaCommand::Diff, &versioned(json!({ "differences": differences })));
+    }
+
+    #[test]
+    fn events_match_their_schema() {
+        MockDisplayApi::install(displays());
+        let before = events::snapshot();
+        let mut after = before.clone();
+        after[0].mode.2 = 60;
+        after[0].hdr = Some(false);
+        after[0].primary = false;
+        after[1].primary = true;
+
+        let mut changes = events::changes(&[], &before);
+        changes.extend(events::changes(&before, &after));
+        changes.extend(events::changes(&after, &after[..1]));
+        let kinds: Vec<&str> = changes.iter().filter_map(|event| event["event"].as_str()).collect();
+        assert_eq!(kinds, ["attached", "attached", "mode_changed", "hdr_changed", "primary_changed", "removed"]);
+        for event in &changes {
+            check_document(SchemaCommand::Events, event);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// Script files hold one command per line, written as on the command line without the
+// executable name, e.g.
+//   # Steam Deck session
+//   cpdm 1280 800 90
+//   ssdrl 0
+//   sicc "HDR Steam Deck.icc"
+// Blank lines and lines starting with # are skipped.
+
+// Split a line into arguments. Double quotes group words with spaces, as Windows does.
+pub fn split_command_line(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quote in '{}'", line));
+    }
+    if has_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+// Lines to run, with their line numbers for error messages. "-" reads from stdin.
+pub fn read_script(source: &Path) -> std::io::Result<Vec<(usize, String)>> {
+    let reader: Box<dyn BufRead> = if source.as_os_str() == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(std::fs::File::open(source)?))
+    };
+
+    let mut lines = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            lines.push((index + 1, trimmed.to_string()));
+        }
+    }
+    Ok(lines)
+}
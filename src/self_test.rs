@@ -0,0 +1,189 @@
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, error, instrument};
+
+use crate::apply::{self, Spec};
+use crate::change_display_mode;
+use crate::change_icc_profile;
+use crate::displays_info::{self, DisplayDevice};
+use crate::hdr;
+use crate::retry::RetryPolicy;
+use crate::set_sdr_level;
+
+// `test self-test` runs a new host through the same calls the prep commands make, without
+// changing anything for good. Each step sets a value the primary display already has, or nudges
+// one and puts it back, then reads it back. Whatever still differs from the snapshot taken at
+// the start is restored at the end.
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+pub enum Outcome {
+    Passed(String),
+    Skipped(String),
+    Failed(String),
+}
+
+pub struct Step {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+fn step(name: &'static str, result: Result<Outcome>) -> Step {
+    let outcome = result.unwrap_or_else(|e| Outcome::Failed(e.message()));
+    match &outcome {
+        Outcome::Passed(detail) => info!("Self-test step '{}' passed: {}", name, detail),
+        Outcome::Skipped(detail) => info!("Self-test step '{}' skipped: {}", name, detail),
+        Outcome::Failed(detail) => error!("Self-test step '{}' failed: {}", name, detail),
+    }
+    Step { name, outcome }
+}
+
+fn failed(message: String) -> Error {
+    Error::new(E_FAIL, message)
+}
+
+//==============================================================================
+// Steps
+//==============================================================================
+
+#[instrument(name = "self_test", skip_all)]
+pub fn run(retry_policy: RetryPolicy) -> Vec<Step> {
+
+    let snapshot = match apply::current_spec() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return vec![step("Snapshot", Err(e))],
+    };
+    let mut steps = vec![step("Snapshot", Ok(Outcome::Passed(format!("{} display(s)", snapshot.displays.len()))))];
+
+    let Some(primary) = displays_info::find_display("primary") else {
+        steps.push(step("Primary display", Err(failed("No primary display found".to_string()))));
+        return steps;
+    };
+
+    steps.push(step("Display mode", mode_round_trip(&primary)));
+    steps.push(step("SDR white level", sdr_round_trip(&primary)));
+    steps.push(step("ICC profile", icc_round_trip(&primary)));
+    steps.push(step("Restore", restore(&snapshot, retry_policy)));
+    steps
+}
+
+// The display as Windows reports it now
+fn read_back(display: &DisplayDevice) -> Result<DisplayDevice> {
+    displays_info::find_display(&display.device_name)
+        .ok_or_else(|| failed(format!("{} is gone", display.device_name)))
+}
+
+// Sets the mode the display already runs, which still goes through a full mode change. It skips
+// the supported mode check, a custom mode the driver doesn't list is still valid if it's current.
+fn mode_round_trip(primary: &DisplayDevice) -> Result<Outcome> {
+    let (width, height) = primary.current_resolution;
+    let refresh_rate = primary.current_refresh_rate;
+    if !change_display_mode::change_display_mode(primary, width, height, refresh_rate, None, true) {
+        return Err(failed(format!("Failed to set {}x{} @{}Hz", width, height, refresh_rate)));
+    }
+
+    let current = read_back(primary)?;
+    if !change_display_mode::is_current_mode(&current, width, height, refresh_rate, None) {
+        return Err(failed(format!("Set {}x{} @{}Hz but read back {}x{} @{}Hz", width, height, refresh_rate,
+            current.current_resolution.0, current.current_resolution.1, current.current_refresh_rate)));
+    }
+    Ok(Outcome::Passed(format!("{}x{} @{}Hz re-applied", width, height, refresh_rate)))
+}
+
+// One step up and back down, or down and back up at the top of the range
+fn sdr_round_trip(primary: &DisplayDevice) -> Result<Outcome> {
+    if !hdr::get_advanced_color_info(primary)?.enabled {
+        return Ok(Outcome::Skipped("HDR is off, the SDR white level only applies with HDR on".to_string()));
+    }
+
+    let level = set_sdr_level::get_display_sdr_white(primary)?;
+    let nudged = if level < 100 { level + 1 } else { level - 1 };
+    for target in [nudged, level] {
+        set_sdr_level::set_display_sdr_white(primary, target)?;
+        let current = set_sdr_level::get_display_sdr_white(primary)?;
+        if current != target {
+            return Err(failed(format!("Set level {} but read back {}", target, current)));
+        }
+    }
+    Ok(Outcome::Passed(format!("{} -> {} -> {} ({} nits)", level, nudged, level, set_sdr_level::level_to_nits(level))))
+}
+
+fn icc_round_trip(primary: &DisplayDevice) -> Result<Outcome> {
+    let Some(profile) = change_icc_profile::get_display_default_icc_profile(primary) else {
+        return Ok(Outcome::Skipped("No default ICC profile to re-associate".to_string()));
+    };
+
+    change_icc_profile::set_display_icc_profile(primary, &profile)?;
+    let current = change_icc_profile::get_display_default_icc_profile(primary);
+    if !current.as_deref().is_some_and(|current| current.eq_ignore_ascii_case(&profile)) {
+        return Err(failed(format!("Set '{}' but read back '{}'", profile, current.unwrap_or_default())));
+    }
+    Ok(Outcome::Passed(format!("'{}' re-associated", profile)))
+}
+
+// A difference here means a step left something changed, so it fails even when restoring works
+fn restore(snapshot: &Spec, retry_policy: RetryPolicy) -> Result<Outcome> {
+    let differences = apply::differences(snapshot)?;
+    if differences.is_empty() {
+        return Ok(Outcome::Passed("Displays match the snapshot".to_string()));
+    }
+
+    for difference in &differences {
+        error!("'{}' should be {} but is {}", difference.display, difference.desired, difference.current);
+    }
+    apply::apply_differences(&differences, retry_policy, false)?;
+    Err(failed(format!("Restored {} setting(s) that didn't match the snapshot", differences.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::from_args(Some(0), Some(0), 0)
+    }
+
+    fn outcomes(steps: &[Step]) -> Vec<(&str, &str)> {
+        steps.iter().map(|step| (step.name, match step.outcome {
+            Outcome::Passed(_) => "passed",
+            Outcome::Skipped(_) => "skipped",
+            Outcome::Failed(_) => "failed",
+        })).collect()
+    }
+
+    #[test]
+    fn round_trips_leave_the_display_as_it_was() {
+        let mut tv = MockDisplay::hdr_tv();
+        tv.icc_profiles = vec!["lg_oled.icm".to_string()];
+        tv.icc_profile = Some("lg_oled.icm".to_string());
+        let api = MockDisplayApi::install(vec![tv]);
+
+        let steps = run(policy());
+        assert_eq!(outcomes(&steps), [
+            ("Snapshot", "passed"),
+            ("Display mode", "passed"),
+            ("SDR white level", "passed"),
+            ("ICC profile", "passed"),
+            ("Restore", "passed"),
+        ]);
+        assert_eq!(api.calls(), [
+            "change_display_settings \\\\.\\DISPLAY1 3840x2160@120",
+            "set_sdr_white_level DISPLAY1 41 (244 nits)",
+            "set_sdr_white_level DISPLAY1 40 (240 nits)",
+            "set_icc_profile DISPLAY1 lg_oled.icm",
+        ]);
+    }
+
+    #[test]
+    fn sdr_and_icc_are_skipped_without_hdr_or_a_profile() {
+        let mut monitor = MockDisplay::new(1, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        MockDisplayApi::install(vec![monitor]);
+
+        let steps = run(policy());
+        assert_eq!(outcomes(&steps)[2..4], [("SDR white level", "skipped"), ("ICC profile", "skipped")]);
+    }
+}
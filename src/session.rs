@@ -0,0 +1,64 @@
+use windows::Win32::System::RemoteDesktop::{ProcessIdToSessionId, WTSGetActiveConsoleSessionId};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_REMOTESESSION};
+use tracing::info;
+
+use crate::display_api;
+
+// Over Remote Desktop the session gets its own virtual display and the GPU's outputs are detached
+// from it. Enumeration then lists the remote display, and mode, HDR and layout changes either
+// fail with odd errors or land on the remote display instead of the one Sunshine streams.
+
+// WTSGetActiveConsoleSessionId while no session is attached to the console
+const NO_CONSOLE_SESSION: u32 = 0xFFFF_FFFF;
+
+pub struct RemoteSession {
+    pub session_id: Option<u32>,
+    pub console_session_id: Option<u32>,
+}
+
+// None when running on the console (or against a mock, which has no session)
+pub fn remote_session() -> Option<RemoteSession> {
+    if display_api::is_mock() || unsafe { GetSystemMetrics(SM_REMOTESESSION) } == 0 {
+        return None;
+    }
+
+    let mut session_id = 0u32;
+    let session_id = unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id) }.ok().map(|()| session_id);
+    let console_session_id = Some(unsafe { WTSGetActiveConsoleSessionId() }).filter(|&id| id != NO_CONSOLE_SESSION);
+    info!("Running in Remote Desktop session {:?}, the console session is {:?}", session_id, console_session_id);
+    Some(RemoteSession { session_id, console_session_id })
+}
+
+impl RemoteSession {
+    pub fn describe(&self) -> String {
+        let session = self.session_id.map_or("a Remote Desktop session".to_string(), |id| format!("Remote Desktop session {}", id));
+        match self.console_session_id {
+            Some(console) => format!("{} (the console is session {})", session, console),
+            None => session,
+        }
+    }
+
+    // tscon moves this session to the console, which gives it the GPU's displays back and
+    // disconnects the Remote Desktop client
+    pub fn advice(&self) -> String {
+        let session = self.session_id.map_or("<session id>".to_string(), |id| id.to_string());
+        format!("Run `tscon {} /dest:console` from an elevated prompt to move this session to the console \
+            (this disconnects Remote Desktop), or add --allow-remote to try anyway", session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advice_names_the_session() {
+        let remote = RemoteSession { session_id: Some(2), console_session_id: Some(1) };
+        assert_eq!(remote.describe(), "Remote Desktop session 2 (the console is session 1)");
+        assert!(remote.advice().starts_with("Run `tscon 2 /dest:console`"));
+
+        let unknown = RemoteSession { session_id: None, console_session_id: None };
+        assert_eq!(unknown.describe(), "a Remote Desktop session");
+    }
+}
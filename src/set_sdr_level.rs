@@ -2,158 +2,388 @@ use std::mem::size_of;
 use windows::Win32::{
     Devices::Display::{
         DISPLAYCONFIG_DEVICE_INFO_HEADER,
-        DISPLAYCONFIG_PATH_INFO,
-        DisplayConfigSetDeviceInfo,
-        QueryDisplayConfig,
-        GetDisplayConfigBufferSizes,
-        DISPLAYCONFIG_MODE_INFO,
-        QDC_ONLY_ACTIVE_PATHS,
+        DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+        DISPLAYCONFIG_SDR_WHITE_LEVEL,
         DISPLAYCONFIG_DEVICE_INFO_TYPE,
     },
-    Foundation::{ERROR_SUCCESS, ERROR_INSUFFICIENT_BUFFER},
-    Graphics::Gdi::{EnumDisplayDevicesW, DISPLAY_DEVICEW, DISPLAY_DEVICE_PRIMARY_DEVICE},
+    Foundation::{ERROR_INVALID_PARAMETER, ERROR_NOT_SUPPORTED, ERROR_SUCCESS, E_FAIL, WIN32_ERROR},
+    System::Registry::HKEY_LOCAL_MACHINE,
 };
-use log::info;
+use windows::core::Error;
+use tracing::{debug, info, warn, error, instrument};
 
-const DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL: DISPLAYCONFIG_DEVICE_INFO_TYPE = DISPLAYCONFIG_DEVICE_INFO_TYPE(-18i32);
+use crate::display_api;
+use crate::displays_info::{self, DisplayDevice};
+use crate::elevation;
+use crate::exit_code;
+use crate::hdr;
+use crate::registry;
+use crate::retry::{self, RetryPolicy};
+use crate::windows_version::Feature;
 
-//==============================================================================
-// Structs
-//==============================================================================
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-struct DisplayconfigSetSdrWhiteLevel {
-    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
-    sdr_white_level: u32,
-    final_value: u8,
-}
+pub const DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL: DISPLAYCONFIG_DEVICE_INFO_TYPE = DISPLAYCONFIG_DEVICE_INFO_TYPE(-18i32);
 
-struct DisplayInfo {
-    path_info: DISPLAYCONFIG_PATH_INFO,
-    is_primary: bool,
-}
+// The setter is undocumented and older builds don't have it, even where the slider exists. Those
+// builds get the registry value the slider writes, which Windows only reads when HDR turns on.
+const MONITOR_DATA_STORE_KEY: &str = "SYSTEM\\CurrentControlSet\\Control\\GraphicsDrivers\\MonitorDataStore";
+const SDR_WHITE_LEVEL_VALUE: &str = "SDRWhiteLevel";
 
 //==============================================================================
-// Get displays information
+// Structs
 //==============================================================================
 
-impl DisplayInfo {
-    fn is_primary_display(&self) -> bool {
-        let mut display_device = DISPLAY_DEVICEW::default();
-        display_device.cb = size_of::<DISPLAY_DEVICEW>() as u32;
-
-        unsafe {
-            let mut device_index = 0;
-            while EnumDisplayDevicesW(None, device_index, &mut display_device, 0).as_bool() {
-                if (display_device.StateFlags & DISPLAY_DEVICE_PRIMARY_DEVICE) != 0 {
-                    return true;
-                }
-                device_index += 1;
-            }
-        }
-        false
-    }
+// How a 0-100 slider level becomes the value the API takes. The default is the Settings slider,
+// 1000-6000 in steps of 50. Some driver and monitor combinations accept values past 6000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdrMapping {
+    pub min: u32,
+    pub max: u32,
+    pub step: u32,
 }
 
-fn enumerate_displays() -> windows::core::Result<Vec<DisplayInfo>> {
-    let mut path_count: u32 = 0;
-    let mut mode_count: u32 = 0;
-    let flags = QDC_ONLY_ACTIVE_PATHS;
-
-    unsafe {
-        let result = GetDisplayConfigBufferSizes(flags, &mut path_count, &mut mode_count);
-        if result != ERROR_SUCCESS {
-            return Err(windows::core::Error::from_win32());
-        }
+impl Default for SdrMapping {
+    fn default() -> Self {
+        SdrMapping { min: 1000, max: 6000, step: 50 }
     }
+}
 
-    let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
-    let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
-
-    unsafe {
-        let mut result = QueryDisplayConfig(
-            flags,
-            &mut path_count,
-            paths.as_mut_ptr(),
-            &mut mode_count,
-            modes.as_mut_ptr(),
-            None,
-        );
-
-        if result == ERROR_INSUFFICIENT_BUFFER {
-            paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
-            modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
-
-            result = QueryDisplayConfig(
-                flags,
-                &mut path_count,
-                paths.as_mut_ptr(),
-                &mut mode_count,
-                modes.as_mut_ptr(),
-                None,
-            );
+impl SdrMapping {
+    pub fn new(min: u32, max: u32, step: u32) -> windows::core::Result<Self> {
+        if min > max {
+            return Err(exit_code::invalid_input(format!("Raw SDR white level range {}-{} is empty", min, max)));
         }
-
-        if result != ERROR_SUCCESS {
-            return Err(windows::core::Error::from_win32());
+        if step == 0 {
+            return Err(exit_code::invalid_input("Raw SDR white level step must be at least 1"));
         }
+        Ok(SdrMapping { min, max, step })
     }
 
-    let mut displays = Vec::new();
-    for path in paths.iter().take(path_count as usize) {
-        let mut display_info = DisplayInfo {
-            path_info: *path,
-            is_primary: false,
-        };
-        display_info.is_primary = display_info.is_primary_display();
-        displays.push(display_info);
+    pub fn to_raw(self, level: u32) -> u32 {
+        self.min.saturating_add(level.saturating_mul(self.step)).min(self.max)
     }
+}
 
-    Ok(displays)
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct DisplayconfigSetSdrWhiteLevel {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    sdr_white_level: u32,
+    final_value: u8,
 }
 
 //==============================================================================
 // Set SDR white level
 //==============================================================================
 
-fn set_sdr_white_level(path_info: &DISPLAYCONFIG_PATH_INFO, level: u32) -> windows::core::Result<()> {
-    // Map 0-100 directly to 1000-6000
-    let api_value = 1000 + (level * 50);
+fn set_sdr_white_level(device: &DisplayDevice, api_value: u32) -> windows::core::Result<()> {
+    write_sdr_white_level(device, api_value, Feature::SdrWhiteLevelSet.available())
+}
+
+fn write_sdr_white_level(device: &DisplayDevice, api_value: u32, setter_available: bool) -> windows::core::Result<()> {
+    // A mock has no registry to fall back to, unless it is dry run and the write is only reported
+    let fallback = !display_api::is_mock() || display_api::is_dry_run();
+    if fallback && !setter_available {
+        info!("This Windows build has no SDR white level setter, writing the registry instead");
+        return set_sdr_white_level_registry(device, api_value);
+    }
 
     let params = DisplayconfigSetSdrWhiteLevel {
         header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
             r#type: DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL,
             size: size_of::<DisplayconfigSetSdrWhiteLevel>() as u32,
-            adapterId: path_info.targetInfo.adapterId,
-            id: path_info.targetInfo.id,
+            adapterId: device.adapter_id,
+            id: device.target_id,
         },
         sdr_white_level: api_value,
         final_value: 1,
     };
 
-    let result = unsafe { DisplayConfigSetDeviceInfo(&params.header) };
+    let result = display_api::api().set_device_info(&params.header);
+    debug!("DisplayConfigSetDeviceInfo(SET_SDR_WHITE_LEVEL) for adapter {:08X}:{:08X} target {} with {} returned {}",
+        device.adapter_id.HighPart, device.adapter_id.LowPart, device.target_id, api_value, result);
     if result == ERROR_SUCCESS.0 as i32 {
         Ok(())
+    } else if fallback && (result == ERROR_NOT_SUPPORTED.0 as i32 || result == ERROR_INVALID_PARAMETER.0 as i32) {
+        warn!("DisplayConfigSetDeviceInfo refused the SDR white level ({}), writing the registry instead", result);
+        set_sdr_white_level_registry(device, api_value)
     } else {
-        Err(windows::core::Error::from_win32())
+        error!(win32_code = result, "Failed to set SDR white level: {}", result);
+        Err(WIN32_ERROR(result as u32).into())
+    }
+}
+
+// Writes the value the slider keeps per monitor. The running session doesn't pick it up, HDR has to
+// be turned off and on again, or the user has to sign out and back in. --dry-run only stands in for
+// the display API, so it is skipped and reported here.
+fn set_sdr_white_level_registry(display: &DisplayDevice, api_value: u32) -> windows::core::Result<()> {
+    if display_api::is_dry_run() {
+        eprintln!("[dry run] Would write {} = {} for {} to HKLM\\{}", SDR_WHITE_LEVEL_VALUE, api_value, display.device_name, MONITOR_DATA_STORE_KEY);
+        return Ok(());
+    }
+    elevation::require_elevation("Writing the SDR white level to the registry")?;
+    let hardware_id = display.get_target_device_name()
+        .and_then(|target| target.hardware_id().map(str::to_string))
+        .ok_or_else(|| Error::new(E_FAIL, format!("No monitor hardware ID for {}", display.device_name)))?;
+    let monitors = registry::subkeys(HKEY_LOCAL_MACHINE, MONITOR_DATA_STORE_KEY)?;
+    let monitor = monitor_data_store_key(&monitors, &hardware_id).map_err(|message| Error::new(E_FAIL, message))?;
+
+    let key = format!("{}\\{}", MONITOR_DATA_STORE_KEY, monitor);
+    info!("Writing {} = {} to HKLM\\{}", SDR_WHITE_LEVEL_VALUE, api_value, key);
+    registry::write_dword(HKEY_LOCAL_MACHINE, &key, SDR_WHITE_LEVEL_VALUE, api_value)?;
+    eprintln!("Note: this Windows build only takes the SDR white level from the registry. Toggle HDR or sign out and back in for {} to use it.", display.device_name);
+    Ok(())
+}
+
+// MonitorDataStore has a key per monitor Windows has seen, named after the hardware ID. Two
+// monitors of the same model can't be told apart, so that is an error rather than a guess.
+fn monitor_data_store_key<'a>(monitors: &'a [String], hardware_id: &str) -> Result<&'a str, String> {
+    let matches: Vec<&String> = monitors.iter()
+        .filter(|monitor| monitor.to_ascii_uppercase().starts_with(&hardware_id.to_ascii_uppercase()))
+        .collect();
+    match matches.as_slice() {
+        [monitor] => Ok(monitor),
+        [] => Err(format!("No MonitorDataStore entry for {}", hardware_id)),
+        _ => Err(format!("{} MonitorDataStore entries for {}, can't tell which one is connected", matches.len(), hardware_id)),
     }
 }
 
 //==============================================================================
 // Set primary display SDR white level helper for CLI command
 //==============================================================================
-pub fn set_primary_display_sdr_white(level: u32) -> windows::core::Result<()> {
+#[instrument(name = "set_sdr_white_level", skip_all, fields(display = tracing::field::Empty, level = level))]
+pub fn set_primary_display_sdr_white(level: u32, mapping: SdrMapping) -> windows::core::Result<()> {
     if level > 100 {
-        return Err(windows::core::Error::from_win32());
+        return Err(exit_code::invalid_input(format!("SDR white level {} is out of range (0-100)", level)));
     }
 
-    info!("Setting SDR white level to {}", level);
-    let displays = enumerate_displays()?;
+    info!("Setting SDR white level to {} (raw {})", level, mapping.to_raw(level));
+    set_primary_sdr_white_level(mapping.to_raw(level))
+}
 
-    if let Some(primary_display) = displays.iter().find(|d| d.is_primary) {
-        set_sdr_white_level(&primary_display.path_info, level)
-    } else {
-        Err(windows::core::Error::from_win32())
+// The value is passed to the API as is, for combinations the slider mapping doesn't cover
+#[instrument(name = "set_sdr_white_level", skip_all, fields(display = tracing::field::Empty, raw = raw))]
+pub fn set_primary_display_sdr_white_raw(raw: u32) -> windows::core::Result<()> {
+    info!("Setting raw SDR white level to {}", raw);
+    set_primary_sdr_white_level(raw)
+}
+
+// The span gets the primary display's device name once it's known
+fn set_primary_sdr_white_level(api_value: u32) -> windows::core::Result<()> {
+    match displays_info::find_display("primary") {
+        Some(primary_display) => {
+            tracing::Span::current().record("display", primary_display.device_name.as_str());
+            set_sdr_white_level(&primary_display, api_value)
+        }
+        None => Err(exit_code::display_not_found("No primary display found")),
+    }
+}
+
+//==============================================================================
+// Every HDR display at once, for `set-sdr-level --all`
+//==============================================================================
+
+// Each display's change runs on its own thread, so the driver round trips overlap. One result per
+// HDR capable display, in enumeration order, false where the level was already right.
+pub fn set_all_displays_sdr_white_raw(raw: u32, retry_policy: RetryPolicy) -> windows::core::Result<Vec<(DisplayDevice, windows::core::Result<bool>)>> {
+    let displays: Vec<DisplayDevice> = displays_info::enumerate_displays().into_iter()
+        .filter(|display| hdr::get_advanced_color_info(display).is_ok_and(|info| info.supported))
+        .collect();
+    if displays.is_empty() {
+        return Err(exit_code::display_not_found("No active display supports HDR"));
+    }
+
+    let results = displays_info::for_each_in_parallel(&displays, |display| {
+        if get_display_sdr_white_raw(display).ok() == Some(raw) {
+            return Ok(false);
+        }
+        set_sdr_white_level_with_retries(display, raw, retry_policy).map(|()| true)
+    });
+    Ok(displays.into_iter().zip(results).collect())
+}
+
+#[instrument(name = "set_sdr_white_level", skip_all, fields(display = %device.device_name, raw = raw), err(level = "info"))]
+fn set_sdr_white_level_with_retries(device: &DisplayDevice, raw: u32, retry_policy: RetryPolicy) -> windows::core::Result<()> {
+    info!("Setting raw SDR white level of {} to {}", device.device_name, raw);
+    let what = format!("SDR white level change on {}", device.device_name);
+    retry::with_retries(retry_policy, &what, || set_sdr_white_level(device, raw))
+}
+
+//==============================================================================
+// Per display SDR white level
+//==============================================================================
+
+// Levels are the 0-100 slider in Settings, i.e. 80 to 480 nits in steps of 4
+pub fn level_to_nits(level: u32) -> u32 {
+    80 + level * 4
+}
+
+pub fn nits_to_level(nits: u32) -> u32 {
+    (nits.clamp(80, 480) - 80 + 2) / 4
+}
+
+// The API value is in 1000ths of 80 nits
+pub fn raw_to_nits(raw: u32) -> u32 {
+    raw * 80 / 1000
+}
+
+// The nearest slider level, 1000 being level 0
+pub fn raw_to_level(raw: u32) -> u32 {
+    (raw.saturating_sub(1000) + 25) / 50
+}
+
+// The documented getter reports the white level in 1000ths of 80 nits, the same scale the
+// undocumented setter takes
+pub fn get_display_sdr_white(display: &DisplayDevice) -> windows::core::Result<u32> {
+    Ok(raw_to_level(get_display_sdr_white_raw(display)?))
+}
+
+pub fn get_display_sdr_white_raw(device: &DisplayDevice) -> windows::core::Result<u32> {
+    let mut request = DISPLAYCONFIG_SDR_WHITE_LEVEL {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+            size: size_of::<DISPLAYCONFIG_SDR_WHITE_LEVEL>() as u32,
+            adapterId: device.adapter_id,
+            id: device.target_id,
+        },
+        SDRWhiteLevel: 0,
+    };
+
+    let result = display_api::api().get_device_info(&mut request.header);
+    if result != ERROR_SUCCESS.0 as i32 {
+        error!(win32_code = result, "Failed to get SDR white level for {}: {}", device.device_name, result);
+        return Err(WIN32_ERROR(result as u32).into());
+    }
+
+    Ok(request.SDRWhiteLevel)
+}
+
+// Each display's HDR state, None on displays without it, and raw white level for `get-sdr-level`
+pub fn get_all_sdr_white_raw(displays: &[DisplayDevice]) -> Vec<(Option<bool>, Option<u32>)> {
+    displays.iter().map(|display| (
+        hdr::get_advanced_color_info(display).ok().filter(|info| info.supported).map(|info| info.enabled),
+        get_display_sdr_white_raw(display).ok(),
+    )).collect()
+}
+
+#[instrument(name = "set_sdr_white_level", skip_all, fields(display = %device.device_name, level = level), err(level = "info"))]
+pub fn set_display_sdr_white(device: &DisplayDevice, level: u32) -> windows::core::Result<()> {
+    if level > 100 {
+        return Err(exit_code::invalid_input(format!("SDR white level {} is out of range (0-100)", level)));
+    }
+
+    info!("Setting SDR white level of {} to {}", device.device_name, level);
+    set_sdr_white_level(device, SdrMapping::default().to_raw(level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    #[test]
+    fn primary_white_level_goes_to_the_primary_display() {
+        let mut monitor = MockDisplay::new(2, "DELL U2723QE", (2560, 1440, 60));
+        monitor.primary = true;
+        let api = MockDisplayApi::install(vec![MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120)), monitor]);
+
+        set_primary_display_sdr_white(50, SdrMapping::default()).unwrap();
+        assert_eq!(api.calls(), ["set_sdr_white_level DISPLAY2 50 (280 nits)"]);
+    }
+
+    #[test]
+    fn all_sets_every_hdr_display_that_differs() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.hdr_supported = true;
+        let mut monitor = MockDisplay::desk_monitor();
+        monitor.hdr_supported = true;
+        monitor.sdr_white_level = 4000;
+        let mut projector = MockDisplay::new(3, "Epson", (1920, 1080, 60));
+        projector.primary = true;
+        let api = MockDisplayApi::install(vec![tv, monitor, projector]);
+
+        let results = set_all_displays_sdr_white_raw(4000, RetryPolicy::from_args(Some(0), Some(0), 0)).unwrap();
+        let results: Vec<_> = results.into_iter().map(|(display, result)| (display.device_name, result.unwrap())).collect();
+        assert_eq!(results, [("\\\\.\\DISPLAY1".to_string(), true), ("\\\\.\\DISPLAY2".to_string(), false)]);
+        assert_eq!(api.calls(), ["set_sdr_white_level DISPLAY1 60 (320 nits)"]);
+    }
+
+    #[test]
+    fn a_dry_run_leaves_the_registry_alone_without_the_setter() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.primary = true;
+        tv.hdr_supported = true;
+        let api = MockDisplayApi::install(vec![tv]);
+        display_api::use_test_api(Box::leak(Box::new(display_api::dry_run::DryRunDisplayApi::new(api))));
+        let tv = displays_info::find_display("primary").unwrap();
+
+        // Writing the registry for real needs elevation and a MonitorDataStore entry, so this
+        // would fail if the dry run reached it
+        write_sdr_white_level(&tv, 4000, false).unwrap();
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn white_level_out_of_range() {
+        let api = MockDisplayApi::install(vec![MockDisplay::new(1, "TV", (1920, 1080, 60))]);
+        assert!(set_primary_display_sdr_white(101, SdrMapping::default()).is_err());
+        assert!(api.calls().is_empty());
+    }
+
+    #[test]
+    fn mapping_steps_and_clamps() {
+        let mapping = SdrMapping::default();
+        assert_eq!(mapping.to_raw(0), 1000);
+        assert_eq!(mapping.to_raw(100), 6000);
+
+        let wide = SdrMapping::new(1000, 7000, 60).unwrap();
+        assert_eq!(wide.to_raw(50), 4000);
+        assert_eq!(wide.to_raw(100), 7000);
+        assert_eq!(SdrMapping::new(1000, 5000, 50).unwrap().to_raw(100), 5000);
+
+        assert!(SdrMapping::new(6000, 1000, 50).is_err());
+        assert!(SdrMapping::new(1000, 6000, 0).is_err());
+    }
+
+    #[test]
+    fn raw_levels_round_to_the_slider() {
+        assert_eq!(raw_to_level(1000), 0);
+        assert_eq!(raw_to_level(3500), 50);
+        assert_eq!(raw_to_level(3524), 50);
+        assert_eq!(raw_to_level(3525), 51);
+        assert_eq!(raw_to_level(6000), 100);
+        assert_eq!(raw_to_level(900), 0);
+    }
+
+    #[test]
+    fn raw_white_level_is_passed_through() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.primary = true;
+        let api = MockDisplayApi::install(vec![tv]);
+
+        set_primary_display_sdr_white_raw(7000).unwrap();
+        assert_eq!(api.calls(), ["set_sdr_white_level DISPLAY1 raw 7000 (560 nits)"]);
+        assert_eq!(get_display_sdr_white_raw(&displays_info::find_display("primary").unwrap()).unwrap(), 7000);
+    }
+
+    #[test]
+    fn monitor_data_store_entry_by_hardware_id() {
+        let monitors = ["GSM5B08123456_0A_07E4_2C".to_string(), "DEL41A8A1B2C3_1F_07E6_4F".to_string()];
+        assert_eq!(monitor_data_store_key(&monitors, "gsm5b08"), Ok("GSM5B08123456_0A_07E4_2C"));
+        assert!(monitor_data_store_key(&monitors, "SAM7106").is_err());
+
+        let twins = ["DEL41A8A1_1F".to_string(), "DEL41A8B2_1F".to_string()];
+        assert!(monitor_data_store_key(&twins, "DEL41A8").is_err());
+    }
+
+    #[test]
+    fn levels_and_nits() {
+        assert_eq!(level_to_nits(0), 80);
+        assert_eq!(level_to_nits(100), 480);
+        assert_eq!(nits_to_level(240), 40);
+        assert_eq!(nits_to_level(50), 0);
+        assert_eq!(nits_to_level(1000), 100);
+        for level in 0..=100 {
+            assert_eq!(nits_to_level(level_to_nits(level)), level);
+        }
     }
 }
@@ -0,0 +1,268 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, error};
+
+use crate::apply::UndoSnapshot;
+use crate::change_icc_profile;
+use crate::config;
+use crate::displays_info::{self, DisplayDevice};
+use crate::exit_code;
+use crate::hdr;
+use crate::retry::RetryPolicy;
+use crate::set_sdr_level;
+use crate::sunshine;
+
+// `setup` asks its way through a first configuration: which display streams, whether it streams
+// in HDR and at what SDR white level, and which ICC profile to use. It writes that as the [prep]
+// section of the helper's config file and offers to add the prep commands to sunshine.conf.
+// Displays are changed while measuring the SDR level and put back at the end.
+
+const DEFAULT_SDR_NITS: u32 = 240;
+const SDR_NITS_STEP: u32 = 20;
+
+pub struct SetupOptions {
+    pub config_path: PathBuf,
+    pub sunshine_dir: PathBuf,
+}
+
+#[derive(Debug, Default)]
+struct Choices {
+    display: String,
+    hdr: Option<bool>,
+    sdr_nits: Option<u32>,
+    icc_profile: Option<String>,
+}
+
+//==============================================================================
+// Prompts
+//==============================================================================
+
+// None at the end of input, so a closed stdin takes the defaults instead of looping
+fn prompt(question: &str) -> Option<String> {
+    eprint!("{} ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    match std::io::stdin().lock().read_line(&mut answer) {
+        Ok(0) | Err(_) => {
+            eprintln!();
+            None
+        }
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+fn ask_yes_no(question: &str, default: bool) -> bool {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        let Some(answer) = prompt(&format!("{} {}", question, hint)) else {
+            return default;
+        };
+        match answer.to_ascii_lowercase().as_str() {
+            "" => return default,
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => eprintln!("Please answer y or n"),
+        }
+    }
+}
+
+// 1-based for the user, 0-based back
+fn ask_choice(question: &str, count: usize, default: usize) -> usize {
+    loop {
+        let Some(answer) = prompt(&format!("{} [{}]", question, default + 1)) else {
+            return default;
+        };
+        if answer.is_empty() {
+            return default;
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=count).contains(&number) => return number - 1,
+            _ => eprintln!("Please enter a number from 1 to {}", count),
+        }
+    }
+}
+
+//==============================================================================
+// Steps
+//==============================================================================
+
+// The monitor name, or the device name without one
+fn name_for(display: &DisplayDevice) -> String {
+    display.get_target_device_name()
+        .map(|target| target.friendly_name)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| display.device_name.trim_start_matches("\\\\.\\").to_string())
+}
+
+// The stable ID survives the display being renumbered, the name is the fallback
+fn selector_for(display: &DisplayDevice) -> String {
+    display.stable_id().unwrap_or_else(|| name_for(display))
+}
+
+fn pick_display(displays: &[DisplayDevice]) -> usize {
+    eprintln!("Connected displays:");
+    for (index, display) in displays.iter().enumerate() {
+        eprintln!("  {}. {} ({}, {}x{} @ {} Hz){}", index + 1, name_for(display), display.device_name,
+            display.current_resolution.0, display.current_resolution.1, display.current_refresh_rate,
+            if display.is_primary { ", primary" } else { "" });
+    }
+    let primary = displays.iter().position(|display| display.is_primary).unwrap_or(0);
+    ask_choice("Which display does Sunshine stream?", displays.len(), primary)
+}
+
+// Turns HDR on and steps the SDR white level until the desktop looks right
+fn measure_sdr_nits(display: &DisplayDevice) -> Result<u32> {
+    hdr::set_hdr(display, true)?;
+    eprintln!("HDR is on. Look at some regular desktop content (a browser, File Explorer) and adjust until it looks as bright as you like.");
+
+    let mut nits = DEFAULT_SDR_NITS;
+    loop {
+        set_sdr_level::set_display_sdr_white(display, set_sdr_level::nits_to_level(nits))?;
+        let question = format!("SDR content is at {} nits. Enter to keep it, + or - to change it by {}, or a value from 80 to 480:", nits, SDR_NITS_STEP);
+        let Some(answer) = prompt(&question) else {
+            return Ok(nits);
+        };
+        nits = match answer.as_str() {
+            "" => return Ok(nits),
+            "+" => (nits + SDR_NITS_STEP).min(480),
+            "-" => nits.saturating_sub(SDR_NITS_STEP).max(80),
+            value => match value.parse::<u32>() {
+                Ok(value) if (80..=480).contains(&value) => value,
+                _ => {
+                    eprintln!("Please enter +, - or a value from 80 to 480");
+                    nits
+                }
+            },
+        };
+    }
+}
+
+fn pick_icc_profile(display: &DisplayDevice) -> Option<String> {
+    let profiles = change_icc_profile::get_display_icc_profiles(display);
+    if profiles.is_empty() {
+        eprintln!("No ICC profiles are associated with this display, skipping the profile.");
+        return None;
+    }
+    eprintln!("ICC profiles associated with this display:");
+    eprintln!("  1. None, leave the profile alone");
+    for (index, profile) in profiles.iter().enumerate() {
+        eprintln!("  {}. {}", index + 2, profile.name);
+    }
+    match ask_choice("Which profile should streams use?", profiles.len() + 1, 0) {
+        0 => None,
+        choice => Some(profiles[choice - 1].name.clone()),
+    }
+}
+
+fn config_text(choices: &Choices) -> String {
+    let mut text = String::from("# Written by `sunshine_helper setup`. Add [clients.\"...\"] and [apps.\"...\"] sections to vary it per client or app.\n");
+    text.push_str(&format!("version = {}\n\n[prep]\n", config::CONFIG_VERSION));
+    text.push_str(&format!("display = {}\n", toml::Value::String(choices.display.clone())));
+    if let Some(hdr) = choices.hdr {
+        text.push_str(&format!("hdr = {}\n", hdr));
+    }
+    if let Some(nits) = choices.sdr_nits {
+        text.push_str(&format!("sdr_nits = {}\n", nits));
+    }
+    if let Some(profile) = &choices.icc_profile {
+        text.push_str(&format!("icc_profile = {}\n", toml::Value::String(profile.clone())));
+    }
+    text
+}
+
+// An existing config is kept as config.toml.bak
+fn write_config(path: &Path, text: &str) -> Result<()> {
+    let write = || {
+        if path.exists() {
+            std::fs::copy(path, path.with_extension("toml.bak"))?;
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, text)
+    };
+    write().map_err(|e| Error::new(E_FAIL, format!("Failed to write {}: {}", path.display(), e)))
+}
+
+//==============================================================================
+// Wizard
+//==============================================================================
+
+pub fn run(options: &SetupOptions, retry: RetryPolicy) -> Result<()> {
+    let displays = displays_info::enumerate_displays();
+    if displays.is_empty() {
+        return Err(exit_code::display_not_found("No active displays found"));
+    }
+
+    let device = &displays[pick_display(&displays)];
+    let mut choices = Choices { display: selector_for(device), ..Default::default() };
+    let name = name_for(device);
+    info!("Setting up {} as {}", device.device_name, choices.display);
+
+    // Everything measured below is put back before writing the config
+    let snapshot = UndoSnapshot::save(&[], retry);
+    let measured = (|| -> Result<()> {
+        let hdr_supported = hdr::get_advanced_color_info(device).is_ok_and(|info| info.supported);
+        if !hdr_supported {
+            eprintln!("{} isn't HDR capable, streams will be SDR.", name);
+        } else if ask_yes_no("Stream in HDR?", true) {
+            choices.hdr = Some(true);
+            choices.sdr_nits = Some(measure_sdr_nits(device)?);
+        } else {
+            choices.hdr = Some(false);
+        }
+        choices.icc_profile = pick_icc_profile(device);
+        Ok(())
+    })();
+    if let Some(Err(e)) = snapshot.map(UndoSnapshot::restore) {
+        error!("Failed to put the displays back after measuring: {}", e.message());
+    }
+    measured?;
+
+    let text = config_text(&choices);
+    println!("\n{}", text);
+    if options.config_path.exists() && !ask_yes_no(&format!("Replace {}?", options.config_path.display()), false) {
+        eprintln!("Left {} as it was", options.config_path.display());
+    } else {
+        write_config(&options.config_path, &text)?;
+        eprintln!("Wrote {}", options.config_path.display());
+    }
+
+    // Sunshine's side
+    let exe = std::env::current_exe().unwrap_or_else(|_| "sunshine_helper.exe".into());
+    let commands = sunshine::prep_commands(&exe, None);
+    let conf_path = options.sunshine_dir.join(sunshine::SUNSHINE_CONF);
+    if conf_path.exists() && ask_yes_no(&format!("Add the prep commands to {}?", conf_path.display()), true) {
+        let contents = sunshine::read_config_file(&conf_path)?;
+        let patched = sunshine::patch_sunshine_conf(&contents, &commands)
+            .map_err(|e| exit_code::invalid_input(format!("Failed to update {}: {}", conf_path.display(), e)))?;
+        sunshine::write_with_backup(&conf_path, &patched)?;
+        eprintln!("Updated {}, restart Sunshine to pick it up", conf_path.display());
+    } else {
+        eprintln!("Add this line to {} (or run `sunshine generate-config --write` later):", conf_path.display());
+        println!("{}", sunshine::global_prep_cmd_line(&commands));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_text_reads_back() {
+        let choices = Choices {
+            display: "LG TV \"SSCR2\"".to_string(),
+            hdr: Some(true),
+            sdr_nits: Some(260),
+            icc_profile: Some("HDR Steam Deck.icc".to_string()),
+        };
+        let value: serde_json::Value = toml::from_str(&config_text(&choices)).unwrap();
+        assert_eq!(value["prep"]["display"], "LG TV \"SSCR2\"");
+        assert_eq!(value["prep"]["hdr"], true);
+        assert_eq!(value["prep"]["sdr_nits"], 260);
+        assert_eq!(value["prep"]["icc_profile"], "HDR Steam Deck.icc");
+    }
+}
@@ -0,0 +1,115 @@
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::{info, warn, instrument};
+
+use crate::change_icc_profile;
+use crate::display_api;
+use crate::displays_info::DisplayDevice;
+use crate::dxgi_info;
+use crate::elevation;
+use crate::exit_code;
+use crate::hdr;
+use crate::icc_file;
+use crate::windows_version::{self, Feature};
+
+// Wide-gamut panels show SDR content, which is sRGB, stretched to their native primaries, and
+// captures of the desktop come out oversaturated. `clamp-srgb on` writes a profile describing
+// the panel's real primaries (as DXGI reports them from the EDID) and makes it the display's
+// advanced color profile. With "Automatically manage color for apps" on (Windows 11 22H2+),
+// Windows then maps sRGB into the panel's gamut instead of stretching it. `off` removes the
+// profile again, and Windows falls back to the display's other profiles.
+
+const SRGB_PRIMARIES: [[f64; 2]; 3] = [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]];
+
+// How far a primary can be from sRGB's and still count as sRGB
+const SRGB_TOLERANCE: f64 = 0.01;
+
+// One profile per display, as panels differ
+fn profile_name(display: &DisplayDevice) -> String {
+    let id = display.stable_id().unwrap_or_else(|| display.device_name.trim_start_matches("\\\\.\\").to_string());
+    let id: String = id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    format!("sunshine_helper sRGB clamp {}.icc", id)
+}
+
+// The panel's primaries and white point
+fn native_gamut(display: &DisplayDevice) -> Result<([[f64; 2]; 3], [f64; 2])> {
+    let outputs = dxgi_info::query_output_color_info()?;
+    let output = outputs.iter().find(|output| output.device_name == display.device_name)
+        .ok_or_else(|| exit_code::not_supported(format!("DXGI doesn't report the primaries of {}", display.device_name)))?;
+    let xy = |point: [f32; 2]| [point[0] as f64, point[1] as f64];
+    let primaries = [xy(output.red_primary), xy(output.green_primary), xy(output.blue_primary)];
+    let white = xy(output.white_point);
+    if primaries.iter().chain([&white]).any(|point| point[1] <= 0.0) {
+        return Err(exit_code::not_supported(format!("{} reports no usable primaries in its EDID", display.device_name)));
+    }
+    Ok((primaries, white))
+}
+
+fn is_srgb(primaries: &[[f64; 2]; 3]) -> bool {
+    primaries.iter().zip(SRGB_PRIMARIES).all(|(primary, srgb)| (primary[0] - srgb[0]).abs() < SRGB_TOLERANCE && (primary[1] - srgb[1]).abs() < SRGB_TOLERANCE)
+}
+
+pub fn get_srgb_clamp(display: &DisplayDevice) -> bool {
+    let name = profile_name(display);
+    change_icc_profile::get_display_default_hdr_icc_profile(display).is_some_and(|current| current.eq_ignore_ascii_case(&name))
+}
+
+// False when the clamp was already as asked
+#[instrument(skip_all, fields(display = %device.device_name, enabled = enabled))]
+pub fn set_srgb_clamp(device: &DisplayDevice, enabled: bool) -> Result<bool> {
+    let name = profile_name(device);
+    let path = icc_file::color_directory().join(&name);
+    let associated = change_icc_profile::get_display_icc_profiles(device).iter().any(|profile| profile.name.eq_ignore_ascii_case(&name));
+
+    if !enabled {
+        if !associated {
+            return Ok(false);
+        }
+        info!("Removing the sRGB clamp profile from {}", device.device_name);
+        display_api::api().remove_icc_association(device.adapter_id, device.source_id, &path)?;
+        return Ok(true);
+    }
+
+    windows_version::require(Feature::AutoColorManagement)?;
+    if hdr::get_advanced_color_info(device).is_ok_and(|info| info.enabled) {
+        return Err(exit_code::not_supported(format!("HDR is on for {}, which already shows SDR content as sRGB. The clamp is for SDR.", device.device_name)));
+    }
+    let (primaries, white) = native_gamut(device)?;
+    if is_srgb(&primaries) {
+        return Err(exit_code::not_supported(format!("{} reports sRGB primaries, there's nothing to clamp", device.device_name)));
+    }
+
+    let profile = icc_file::display_profile(&format!("sRGB clamp for {}", device.stable_id().unwrap_or_else(|| device.device_name.clone())), primaries, white)
+        .map_err(|e| exit_code::not_supported(format!("Failed to build a profile for {}: {}", device.device_name, e)))?;
+    // The color directory is under System32, only an elevated prompt can write there
+    if std::fs::read(&path).ok().as_deref() != Some(profile.as_slice()) {
+        elevation::require_elevation("Installing the sRGB clamp profile")?;
+        info!("Writing the sRGB clamp profile for {} to {}", device.device_name, path.display());
+        std::fs::write(&path, &profile).map_err(|e| Error::new(E_FAIL, format!("Failed to write {}: {}", path.display(), e)))?;
+    }
+
+    if get_srgb_clamp(device) {
+        return Ok(false);
+    }
+    if !associated {
+        display_api::api().associate_icc_profile(device.adapter_id, device.source_id, &path)?;
+    }
+    if let Some(calibration) = change_icc_profile::get_display_default_hdr_icc_profile(device) {
+        warn!("The sRGB clamp replaces '{}' as the advanced color profile of {}", calibration, device.device_name);
+    }
+    info!("Making the sRGB clamp profile the advanced color profile of {}", device.device_name);
+    display_api::api().set_default_hdr_icc_profile(device.adapter_id, device.source_id, &path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_wide_primaries_need_clamping() {
+        assert!(is_srgb(&[[0.639, 0.331], [0.301, 0.598], [0.151, 0.062]]));
+        // A typical DCI-P3 panel
+        assert!(!is_srgb(&[[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]]));
+    }
+}
@@ -0,0 +1,387 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{info, error};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::display_api;
+use crate::portable;
+
+const DISABLED_DISPLAYS_FILE: &str = "disabled_displays.json";
+const NAMED_STATES_DIR: &str = "states";
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static TEST_STATE_DIR: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+// Named states carry a sequence number above every other named state's, so which of two was
+// saved last doesn't depend on the file system's timestamps
+const SEQUENCE_FIELD: &str = "sequence";
+
+// State files carry a "version" next to their contents, so an upgrade can tell an older file
+// from a broken one. Each step takes a file from one version to the next, the version removed.
+const STATE_VERSION: u32 = 1;
+const MIGRATIONS: [fn(Value) -> Value; STATE_VERSION as usize] = [
+    // 1: disabled_displays.json, the one file that was a bare list, became {"displays": [...]}
+    |value| match value {
+        Value::Array(displays) => json!({ "displays": displays }),
+        value => value,
+    },
+];
+
+//==============================================================================
+// Structs
+//==============================================================================
+
+// Everything needed to find and re-enable a display after it has been removed from the desktop
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DisabledDisplay {
+    pub device_name: String,
+    pub device_index: u32,
+    pub friendly_name: String,
+    pub monitor_device_path: String,
+    pub adapter_id_low: u32,
+    pub adapter_id_high: i32,
+    pub target_id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+    pub position: (i32, i32),
+    pub was_primary: bool,
+    #[serde(default)]
+    pub display_id: Option<String>, // DisplayDevice::stable_id, missing from older records
+}
+
+#[derive(Serialize, Deserialize)]
+struct DisabledDisplays {
+    displays: Vec<DisabledDisplay>,
+}
+
+impl DisabledDisplay {
+    // Same selector rules as active displays, minus "primary"
+    pub fn matches_selector(&self, selector: &str) -> bool {
+        let selector = selector.trim();
+
+        if let Ok(index) = selector.parse::<u32>() {
+            return self.device_index == index;
+        }
+
+        let short_name = self.device_name.trim_start_matches("\\\\.\\");
+        self.device_name.eq_ignore_ascii_case(selector)
+            || short_name.eq_ignore_ascii_case(selector)
+            || self.friendly_name.eq_ignore_ascii_case(selector)
+            || self.display_id.as_ref().is_some_and(|id| id.eq_ignore_ascii_case(selector))
+    }
+}
+
+//==============================================================================
+// State files
+//==============================================================================
+
+// %APPDATA%\sunshine-helper, or the executable's directory in portable mode, falling back to the
+// working directory if APPDATA isn't set
+pub fn state_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(dir) = TEST_STATE_DIR.with(|dir| dir.borrow().clone()) {
+        return dir;
+    }
+    if let Some(dir) = STATE_DIR.get() {
+        return dir.clone();
+    }
+    if let Some(dir) = portable::dir() {
+        return dir;
+    }
+    let base = std::env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("sunshine-helper")
+}
+
+// Keeps state files somewhere else for the rest of the process, for --mock
+pub fn use_state_dir(dir: PathBuf) {
+    let _ = STATE_DIR.set(dir);
+}
+
+// Keeps the current test's state files apart from other tests', which run on their own threads
+#[cfg(test)]
+pub fn use_test_state_dir(dir: PathBuf) {
+    TEST_STATE_DIR.with(|current| *current.borrow_mut() = Some(dir));
+}
+
+pub fn load_state_file<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+    let path = state_dir().join(file_name);
+    let contents = fs::read_to_string(&path).ok()?;
+
+    // An older file is only migrated here, it's written in the current version the next time it's saved
+    let value = serde_json::from_str::<Value>(&contents).map_err(|e| e.to_string()).and_then(|mut value| {
+        let version = take_version(&mut value)?;
+        Ok(migrate(value, version))
+    });
+    match value.and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string())) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            error!("Failed to parse {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn save_state_file<T: Serialize>(file_name: &str, value: &T) -> std::io::Result<()> {
+    let dir = state_dir();
+    if display_api::is_dry_run() {
+        eprintln!("[dry run] Would write {}", dir.join(file_name).display());
+        return Ok(());
+    }
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(file_name);
+    back_up_older_version(&path);
+    let contents = serde_json::to_string_pretty(&versioned(serde_json::to_value(value)?))?;
+    fs::write(&path, contents)?;
+
+    info!("Saved state to {}", path.display());
+    Ok(())
+}
+
+pub fn remove_state_file(file_name: &str) {
+    let path = state_dir().join(file_name);
+    if path.exists() && display_api::is_dry_run() {
+        eprintln!("[dry run] Would remove {}", path.display());
+    } else if path.exists() {
+        if let Err(e) = fs::remove_file(&path) {
+            error!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+//==============================================================================
+// Versions
+//==============================================================================
+
+// Removes the version from a state file's object, 0 for files written before there was one
+fn take_version(value: &mut Value) -> std::result::Result<u32, String> {
+    let version = match value.as_object_mut().and_then(|map| map.remove("version")) {
+        None => 0,
+        Some(version) => version.as_u64().and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("version {} isn't a whole number", version))?,
+    };
+    if version > STATE_VERSION {
+        return Err(format!("written by a newer version of the helper (state version {}, this one reads up to {})", version, STATE_VERSION));
+    }
+    Ok(version)
+}
+
+fn migrate(value: Value, from: u32) -> Value {
+    MIGRATIONS[from as usize..].iter().fold(value, |value, step| step(value))
+}
+
+// The current version first, so it's the first thing seen when opening the file
+fn versioned(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut versioned = Map::new();
+            versioned.insert("version".to_string(), Value::from(STATE_VERSION));
+            versioned.extend(map);
+            Value::Object(versioned)
+        }
+        value => value,
+    }
+}
+
+// Before a file from an older version is saved over, it's kept as <file>.v<version>.bak
+fn back_up_older_version(path: &Path) {
+    let Some(version) = fs::read_to_string(path).ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+        .and_then(|mut value| take_version(&mut value).ok())
+        .filter(|version| *version < STATE_VERSION) else {
+        return;
+    };
+    let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+    match fs::copy(path, &backup) {
+        Ok(_) => info!("Upgrading {} from state version {} to {}, the old file is {}", path.display(), version, STATE_VERSION, backup.display()),
+        Err(e) => error!("Failed to keep a copy of {} before upgrading it: {}", path.display(), e),
+    }
+}
+
+//==============================================================================
+// Disabled displays
+//==============================================================================
+
+pub fn load_disabled_displays() -> Vec<DisabledDisplay> {
+    load_state_file::<DisabledDisplays>(DISABLED_DISPLAYS_FILE).map(|file| file.displays).unwrap_or_default()
+}
+
+pub fn save_disabled_displays(displays: &[DisabledDisplay]) -> std::io::Result<()> {
+    info!("Saving {} disabled display record(s)", displays.len());
+    save_state_file(DISABLED_DISPLAYS_FILE, &DisabledDisplays { displays: displays.to_vec() })
+}
+
+//==============================================================================
+// Named states
+//==============================================================================
+
+// Saved under states\<name>.json, so names are kept to what is safe in a file name everywhere
+pub fn is_valid_state_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !name.starts_with('.')
+}
+
+fn named_state_file(name: &str) -> String {
+    format!("{}\\{}.json", NAMED_STATES_DIR, name)
+}
+
+pub fn load_named_state<T: DeserializeOwned>(name: &str) -> Option<T> {
+    load_state_file(&named_state_file(name))
+}
+
+pub fn save_named_state<T: Serialize>(name: &str, value: &T) -> std::io::Result<()> {
+    if !display_api::is_dry_run() {
+        fs::create_dir_all(state_dir().join(NAMED_STATES_DIR))?;
+    }
+    let mut value = serde_json::to_value(value)?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert(SEQUENCE_FIELD.to_string(), Value::from(next_sequence()));
+    }
+    save_state_file(&named_state_file(name), &value)
+}
+
+// None for states saved before there was a sequence number
+pub fn named_state_sequence(name: &str) -> Option<u64> {
+    load_state_file::<Value>(&named_state_file(name))?.get(SEQUENCE_FIELD)?.as_u64()
+}
+
+fn next_sequence() -> u64 {
+    list_named_states().iter().filter_map(|(name, _)| named_state_sequence(name)).max().map_or(1, |sequence| sequence + 1)
+}
+
+// False if there was no state by that name
+pub fn delete_named_state(name: &str) -> std::io::Result<bool> {
+    let path = state_dir().join(named_state_file(name));
+    if display_api::is_dry_run() {
+        let exists = path.exists();
+        if exists {
+            eprintln!("[dry run] Would remove {}", path.display());
+        }
+        return Ok(exists);
+    }
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            info!("Removed {}", path.display());
+            Ok(true)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Names with when each was saved, sorted by name
+pub fn list_named_states() -> Vec<(String, Option<SystemTime>)> {
+    let Ok(entries) = fs::read_dir(state_dir().join(NAMED_STATES_DIR)) else {
+        return Vec::new();
+    };
+
+    let mut states: Vec<(String, Option<SystemTime>)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_stem()?.to_str()?.to_string();
+            let is_json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+            (is_json && is_valid_state_name(&name)).then(|| (name, entry.metadata().and_then(|metadata| metadata.modified()).ok()))
+        })
+        .collect();
+    states.sort_by_key(|(name, _)| name.to_lowercase());
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> DisabledDisplay {
+        DisabledDisplay {
+            device_name: "\\\\.\\DISPLAY3".to_string(),
+            device_index: 2,
+            friendly_name: "LG TV SSCR2".to_string(),
+            monitor_device_path: String::new(),
+            adapter_id_low: 1,
+            adapter_id_high: 0,
+            target_id: 103,
+            width: 3840,
+            height: 2160,
+            refresh_rate: 120,
+            position: (-3840, 0),
+            was_primary: false,
+            display_id: Some("GSM-C0A8-SSCR2".to_string()),
+        }
+    }
+
+    #[test]
+    fn disabled_display_selectors() {
+        let record = record();
+        assert!(record.matches_selector("2"));
+        assert!(record.matches_selector("display3"));
+        assert!(record.matches_selector("\\\\.\\DISPLAY3"));
+        assert!(record.matches_selector("lg tv sscr2"));
+        assert!(record.matches_selector("gsm-c0a8-sscr2"));
+        assert!(!record.matches_selector("primary"));
+        assert!(!record.matches_selector("3"));
+    }
+
+    #[test]
+    fn state_names() {
+        assert!(is_valid_state_name("tv-hdr"));
+        assert!(is_valid_state_name("Desk_SDR.2"));
+        assert!(!is_valid_state_name(""));
+        assert!(!is_valid_state_name("../desk"));
+        assert!(!is_valid_state_name("desk sdr"));
+        assert!(!is_valid_state_name(".hidden"));
+    }
+
+    #[test]
+    fn unversioned_disabled_displays_migrate() {
+        let mut value = serde_json::to_value(vec![record()]).unwrap();
+        let version = take_version(&mut value).unwrap();
+        assert_eq!(version, 0);
+
+        let file: DisabledDisplays = serde_json::from_value(migrate(value, version)).unwrap();
+        assert_eq!(file.displays.len(), 1);
+        assert_eq!(file.displays[0].target_id, 103);
+    }
+
+    #[test]
+    fn old_files_are_upgraded_when_saved_not_when_read() {
+        let dir = std::env::temp_dir().join("sunshine-helper-test-state-upgrade");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        use_test_state_dir(dir.clone());
+        let path = dir.join(DISABLED_DISPLAYS_FILE);
+        let old = serde_json::to_string(&vec![record()]).unwrap();
+        fs::write(&path, &old).unwrap();
+
+        assert_eq!(load_disabled_displays().len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), old);
+
+        save_disabled_displays(&load_disabled_displays()).unwrap();
+        assert_eq!(fs::read_to_string(dir.join(format!("{}.v0.bak", DISABLED_DISPLAYS_FILE))).unwrap(), old);
+        let mut saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(take_version(&mut saved), Ok(STATE_VERSION));
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let mut value = versioned(serde_json::to_value(DisabledDisplays { displays: vec![record()] }).unwrap());
+        assert_eq!(value.as_object().unwrap().keys().next().unwrap(), "version");
+        assert_eq!(take_version(&mut value), Ok(STATE_VERSION));
+        assert!(value.get("version").is_none());
+
+        let mut newer = json!({ "version": STATE_VERSION + 1, "displays": [] });
+        assert!(take_version(&mut newer).unwrap_err().contains("newer version"));
+    }
+}
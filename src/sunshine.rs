@@ -0,0 +1,422 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{Error, Result};
+use tracing::info;
+
+use crate::change_display_mode;
+use crate::config::Config;
+use crate::displays_info::{self, DisplayDevice};
+use crate::elevation::quote_argument;
+use crate::exit_code;
+use crate::hdr;
+use crate::portable;
+use crate::script;
+
+// Sunshine's own configuration. Prep commands go in sunshine.conf as
+//   global_prep_cmd = [{"do":"...","undo":"...","elevated":false}]
+// (a JSON array on one line of an otherwise key = value file), or per app in apps.json under
+// "prep-cmd". Sunshine runs the do commands in order when a stream starts and the undo commands
+// in reverse order when it ends. Escaping paths by hand inside that JSON is where setups go wrong,
+// so `sunshine generate-config` writes the entries for this helper.
+
+pub const SUNSHINE_CONF: &str = "sunshine.conf";
+pub const APPS_JSON: &str = "apps.json";
+const GLOBAL_PREP_CMD: &str = "global_prep_cmd";
+
+// Saved when a stream starts and restored when it ends
+pub const PRE_STREAM_STATE: &str = "sunshine-before-stream";
+// What sunshine-prep applied for the stream, for the daemon to apply again after a driver reset
+pub const STREAM_PREP_STATE: &str = "sunshine-stream";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PrepCommand {
+    #[serde(rename = "do")]
+    pub do_command: String,
+    #[serde(rename = "undo")]
+    pub undo_command: String,
+    pub elevated: bool,
+}
+
+// %ProgramFiles%\Sunshine\config, where the installer puts sunshine.conf and apps.json
+pub fn default_config_dir() -> PathBuf {
+    let base = std::env::var_os("ProgramFiles")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("C:\\Program Files"));
+    base.join("Sunshine").join("config")
+}
+
+//==============================================================================
+// Prep commands
+//==============================================================================
+
+// The current displays are saved first so the end of the stream puts them back, then
+// sunshine-prep applies the client's and app's profile. Both log to the helper's log file,
+// since Sunshine doesn't keep prep command output.
+pub fn prep_commands(exe: &Path, config: Option<&Path>) -> Vec<PrepCommand> {
+    let mut exe = quote_argument(&exe.to_string_lossy());
+    if portable::requested() {
+        exe.push_str(" --portable");
+    }
+    if let Some(adapter) = displays_info::adapter() {
+        exe.push_str(&format!(" --adapter {}", quote_argument(&adapter.to_string())));
+    }
+    let config = config.map_or(String::new(), |config| format!(" --config {}", quote_argument(&config.to_string_lossy())));
+    vec![
+        PrepCommand {
+            do_command: format!("{} --log save-state {}", exe, PRE_STREAM_STATE),
+            undo_command: format!("{} --log restore-state {} --delete", exe, PRE_STREAM_STATE),
+            elevated: false,
+        },
+        PrepCommand {
+            do_command: format!("{} --log sunshine-prep{}", exe, config),
+            undo_command: String::new(),
+            elevated: false,
+        },
+    ]
+}
+
+// Entries an earlier generate-config wrote, recognised by the executable's name
+fn is_helper_entry(entry: &Value) -> bool {
+    ["do", "undo"].iter().any(|key| {
+        entry.get(key).and_then(Value::as_str).is_some_and(|command| command.to_ascii_lowercase().contains("sunshine_helper"))
+    })
+}
+
+// The existing entries without the helper's, followed by the new ones
+fn merge_prep_commands(existing: Option<&Value>, commands: &[PrepCommand]) -> std::result::Result<Value, String> {
+    let mut entries = match existing {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(entries)) => entries.iter().filter(|entry| !is_helper_entry(entry)).cloned().collect(),
+        Some(other) => return Err(format!("expected a list of prep commands, found {}", other)),
+    };
+    entries.extend(commands.iter().map(|command| serde_json::to_value(command).expect("prep commands serialize")));
+    Ok(Value::Array(entries))
+}
+
+pub fn global_prep_cmd_line(commands: &[PrepCommand]) -> String {
+    format!("{} = {}", GLOBAL_PREP_CMD, serde_json::to_string(commands).expect("prep commands serialize"))
+}
+
+pub fn prep_cmd_json(commands: &[PrepCommand]) -> String {
+    format!("\"prep-cmd\": {}", serde_json::to_string_pretty(commands).expect("prep commands serialize"))
+}
+
+//==============================================================================
+// Patching
+//==============================================================================
+
+// Replaces the helper's entries in the global_prep_cmd line, adding the line if there is none
+pub fn patch_sunshine_conf(contents: &str, commands: &[PrepCommand]) -> std::result::Result<String, String> {
+    let newline = if contents.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+
+    let existing = lines.iter().position(|line| {
+        line.split_once('=').is_some_and(|(key, _)| key.trim() == GLOBAL_PREP_CMD)
+    });
+    match existing {
+        Some(index) => {
+            let (_, value) = lines[index].split_once('=').expect("found by its =");
+            let value: Value = serde_json::from_str(value.trim()).map_err(|e| format!("{} isn't valid JSON: {}", GLOBAL_PREP_CMD, e))?;
+            let merged = merge_prep_commands(Some(&value), commands)?;
+            lines[index] = format!("{} = {}", GLOBAL_PREP_CMD, merged);
+        }
+        None => lines.push(global_prep_cmd_line(commands)),
+    }
+
+    Ok(lines.join(newline) + newline)
+}
+
+// Replaces the helper's entries in the prep-cmd of the named apps (every app if none are named).
+// Returns the new file and the apps it changed.
+pub fn patch_apps_json(contents: &str, commands: &[PrepCommand], apps: &[String]) -> std::result::Result<(String, Vec<String>), String> {
+    let mut document: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    let entries = document.get_mut("apps").and_then(Value::as_array_mut).ok_or("no \"apps\" list")?;
+
+    let mut patched = Vec::new();
+    for app in entries.iter_mut() {
+        let name = app.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+        if !apps.is_empty() && !apps.iter().any(|wanted| wanted.eq_ignore_ascii_case(&name)) {
+            continue;
+        }
+        let merged = merge_prep_commands(app.get("prep-cmd"), commands).map_err(|e| format!("app {}: {}", name, e))?;
+        let Some(app) = app.as_object_mut() else {
+            continue;
+        };
+        app.insert("prep-cmd".to_string(), merged);
+        patched.push(name);
+    }
+
+    let missing: Vec<&String> = apps.iter().filter(|wanted| !patched.iter().any(|name| name.eq_ignore_ascii_case(wanted))).collect();
+    if !missing.is_empty() {
+        return Err(format!("no app named {}", missing.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", ")));
+    }
+    Ok((serde_json::to_string_pretty(&document).expect("JSON values serialize") + "\n", patched))
+}
+
+// Keeps the old file next to it as <file>.bak. Sunshine's config directory is under Program
+// Files, so writing there usually needs an elevated prompt.
+pub fn write_with_backup(path: &Path, contents: &str) -> Result<()> {
+    let backup = path.with_file_name(format!("{}.bak", path.file_name().unwrap_or_default().to_string_lossy()));
+    let write = || std::fs::copy(path, &backup).and_then(|_| std::fs::write(path, contents));
+    match write() {
+        Ok(()) => {
+            info!("Wrote {}, the old one is in {}", path.display(), backup.display());
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Err(exit_code::needs_elevation(format!(
+            "Writing {} needs an elevated prompt, run it as administrator or add --elevate", path.display()))),
+        Err(e) => Err(Error::new(E_FAIL, format!("Failed to write {}: {}", path.display(), e))),
+    }
+}
+
+pub fn read_config_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| exit_code::invalid_input(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+//==============================================================================
+// Validation
+//==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning, // may give a black screen for some clients
+    Error,   // will fail for every stream that gets there
+}
+
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn warning(message: String) -> Issue {
+        Issue { severity: Severity::Warning, message }
+    }
+
+    pub fn error(message: String) -> Issue {
+        Issue { severity: Severity::Error, message }
+    }
+}
+
+// key = value lines of sunshine.conf. Lists like `resolutions = [` may continue over several
+// lines until the closing ], as Sunshine writes them.
+pub fn parse_conf(contents: &str) -> Vec<(String, String)> {
+    let mut settings: Vec<(String, String)> = Vec::new();
+    let mut open_list: Option<(String, String)> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some((key, value)) = open_list.as_mut() {
+            value.push_str(line);
+            if line.ends_with(']') {
+                settings.push((key.clone(), value.clone()));
+                open_list = None;
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim().to_string(), value.trim().to_string());
+        if value.starts_with('[') && !value.ends_with(']') {
+            open_list = Some((key, value));
+        } else {
+            settings.push((key, value));
+        }
+    }
+    settings
+}
+
+fn setting<'a>(settings: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    settings.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+}
+
+// [352x240, 1920x1080] or [30, 60], items may be quoted
+fn parse_list(value: &str) -> Vec<String> {
+    value.trim().trim_start_matches('[').trim_end_matches(']')
+        .split(',')
+        .map(|item| item.trim().trim_matches('"').to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn parse_resolution(text: &str) -> Option<(u32, u32)> {
+    let (width, height) = text.split_once(['x', 'X'])?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+fn hdr_supported(display: &DisplayDevice) -> bool {
+    hdr::get_advanced_color_info(display).is_ok_and(|info| info.supported)
+}
+
+// Checks what Sunshine and the helper's profiles expect against the connected displays
+pub fn validate(settings: &[(String, String)], apps: Option<&Value>, helper_config: Option<&Config>) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let displays = displays_info::enumerate_displays();
+    let names = displays.iter().map(|device| device.device_name.trim_start_matches("\\\\.\\").to_string()).collect::<Vec<_>>().join(", ");
+
+    // Sunshine captures output_name, or the primary display if it isn't set
+    let output = match setting(settings, "output_name").filter(|name| !name.is_empty()) {
+        // Newer Sunshine versions store their own device id, which only Sunshine can resolve
+        Some(name) if name.starts_with('{') => {
+            issues.push(Issue::warning(format!("output_name {} is a Sunshine device id, checking the primary display instead", name)));
+            "primary"
+        }
+        Some(name) => name,
+        None => "primary",
+    };
+    let Some(device) = displays.iter().find(|device| displays_info::display_matches_selector(device, output)) else {
+        issues.push(Issue::error(format!("output_name {} matches no active display (active: {})", output, names)));
+        return issues;
+    };
+    info!("Validating against {}", device.device_name);
+    let modes = device.get_supported_modes();
+
+    // What clients can ask for
+    let fps: Vec<u32> = setting(settings, "fps").map(parse_list).unwrap_or_default().iter().filter_map(|fps| fps.parse().ok()).collect();
+    for resolution in setting(settings, "resolutions").map(parse_list).unwrap_or_default() {
+        let Some((width, height)) = parse_resolution(&resolution) else {
+            issues.push(Issue::warning(format!("resolutions: can't read {}", resolution)));
+            continue;
+        };
+        let supported: Vec<u32> = fps.iter().copied()
+            .filter(|&fps| modes.iter().any(|mode| (mode.width, mode.height, mode.refresh_rate) == (width, height, fps)))
+            .collect();
+        if !modes.iter().any(|mode| (mode.width, mode.height) == (width, height)) {
+            issues.push(Issue::warning(format!("{} doesn't offer {}x{}, which clients can pick from resolutions", device.device_name, width, height)));
+        } else if !fps.is_empty() && supported.is_empty() {
+            issues.push(Issue::warning(format!("{} offers {}x{} at none of the fps values {:?}", device.device_name, width, height, fps)));
+        }
+    }
+
+    // Sunshine's own display device settings
+    if setting(settings, "dd_resolution_option") == Some("manual") {
+        let resolution = setting(settings, "dd_manual_resolution").and_then(parse_resolution);
+        let refresh_rate = setting(settings, "dd_manual_refresh_rate").and_then(|rate| rate.parse::<f64>().ok());
+        if let (Some((width, height)), Some(refresh_rate)) = (resolution, refresh_rate) {
+            let refresh_rate = refresh_rate.round() as u32;
+            if !change_display_mode::is_supported_mode(device, width, height, refresh_rate, None) {
+                issues.push(Issue::error(format!("dd_manual_resolution {}x{} at {} Hz isn't a mode {} offers",
+                    width, height, refresh_rate, device.device_name)));
+            }
+        }
+    }
+    if !hdr_supported(device) && setting(settings, "dd_hdr_option").unwrap_or("auto") != "disabled" {
+        issues.push(Issue::warning(format!("{} isn't HDR capable, HDR clients will get an SDR stream", device.device_name)));
+    }
+
+    // The helper's profiles, as sunshine-prep would apply them
+    if let Some(helper_config) = helper_config {
+        issues.extend(helper_config.check_profiles(&displays));
+    }
+
+    // Helper entries pointing at a binary that has moved since
+    let mut entries: Vec<(String, Value)> = Vec::new();
+    if let Some(Ok(Value::Array(global))) = setting(settings, GLOBAL_PREP_CMD).map(serde_json::from_str::<Value>) {
+        entries.extend(global.into_iter().map(|entry| (GLOBAL_PREP_CMD.to_string(), entry)));
+    }
+    for app in apps.and_then(|apps| apps.get("apps")).and_then(Value::as_array).into_iter().flatten() {
+        let name = app.get("name").and_then(Value::as_str).unwrap_or_default();
+        for entry in app.get("prep-cmd").and_then(Value::as_array).into_iter().flatten() {
+            entries.push((format!("app {}", name), entry.clone()));
+        }
+    }
+    for (source, entry) in entries.iter().filter(|(_, entry)| is_helper_entry(entry)) {
+        for command in ["do", "undo"].iter().filter_map(|key| entry.get(key).and_then(Value::as_str)) {
+            let exe = script::split_command_line(command).ok().and_then(|args| args.into_iter().next());
+            if let Some(exe) = exe.filter(|exe| !Path::new(exe).exists()) {
+                issues.push(Issue::error(format!("{}: prep command runs {}, which doesn't exist", source, exe)));
+            }
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_api::mock::{MockDisplay, MockDisplayApi};
+
+    fn commands() -> Vec<PrepCommand> {
+        prep_commands(Path::new("C:\\Program Files\\sunshine_helper\\sunshine_helper.exe"), None)
+    }
+
+    #[test]
+    fn prep_commands_quote_the_path() {
+        let commands = commands();
+        assert_eq!(commands[0].do_command, "\"C:\\Program Files\\sunshine_helper\\sunshine_helper.exe\" --log save-state sunshine-before-stream");
+        assert_eq!(commands[1].undo_command, "");
+        assert!(global_prep_cmd_line(&commands).starts_with(
+            r#"global_prep_cmd = [{"do":"\"C:\\Program Files\\sunshine_helper\\sunshine_helper.exe\" --log save-state"#));
+    }
+
+    #[test]
+    fn sunshine_conf_keeps_other_entries() {
+        let conf = "output_name = \\\\.\\DISPLAY1\r\n\
+            global_prep_cmd = [{\"do\":\"C:\\\\old\\\\sunshine_helper.exe hdr on\",\"undo\":\"\",\"elevated\":false},{\"do\":\"obs.exe\",\"undo\":\"\",\"elevated\":false}]\r\n";
+        let patched = patch_sunshine_conf(conf, &commands()).unwrap();
+        let lines: Vec<&str> = patched.lines().collect();
+        assert_eq!(lines[0], "output_name = \\\\.\\DISPLAY1");
+        let value: Value = serde_json::from_str(lines[1].split_once('=').unwrap().1).unwrap();
+        let entries = value.as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["do"], "obs.exe");
+        assert!(patched.ends_with("\r\n"));
+
+        let added = patch_sunshine_conf("min_log_level = 2\n", &commands()).unwrap();
+        assert!(added.lines().nth(1).unwrap().starts_with("global_prep_cmd = [{"));
+    }
+
+    #[test]
+    fn conf_lists_can_span_lines() {
+        let settings = parse_conf("# comment\nresolutions = [\n    1280x800,\n    1920x1080\n]\nfps = [60, 90]\noutput_name = \\\\.\\DISPLAY2\n");
+        assert_eq!(parse_list(setting(&settings, "resolutions").unwrap()), ["1280x800", "1920x1080"]);
+        assert_eq!(parse_list(setting(&settings, "fps").unwrap()), ["60", "90"]);
+        assert_eq!(setting(&settings, "output_name"), Some("\\\\.\\DISPLAY2"));
+        assert_eq!(parse_resolution("1280x800"), Some((1280, 800)));
+    }
+
+    #[test]
+    fn validation_reports_what_the_display_lacks() {
+        let mut tv = MockDisplay::new(1, "LG TV SSCR2", (3840, 2160, 120));
+        tv.primary = true;
+        tv.modes = vec![(3840, 2160, 120), (1920, 1080, 60)];
+        MockDisplayApi::install(vec![tv]);
+
+        let settings = parse_conf("resolutions = [1920x1080, 1280x800]\nfps = [60, 120]\ndd_hdr_option = disabled\n");
+        let config = crate::config::Config {
+            prep: crate::config::Profile { hdr: Some(true), ..Default::default() },
+            ..Default::default()
+        };
+        let issues = validate(&settings, None, Some(&config));
+        let messages: Vec<&str> = issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(messages, [
+            "\\\\.\\DISPLAY1 doesn't offer 1280x800, which clients can pick from resolutions",
+            "[prep]: turns HDR on, but \\\\.\\DISPLAY1 isn't HDR capable",
+        ]);
+        assert_eq!(issues[1].severity, Severity::Error);
+
+        let missing = validate(&parse_conf("output_name = DISPLAY3\n"), None, None);
+        assert_eq!(missing[0].message, "output_name DISPLAY3 matches no active display (active: DISPLAY1)");
+    }
+
+    #[test]
+    fn apps_json_patches_named_apps() {
+        let apps = r#"{"env": {}, "apps": [{"name": "Desktop"}, {"name": "Cyberpunk 2077", "prep-cmd": [{"do": "a.exe", "undo": "", "elevated": false}]}]}"#;
+        let (patched, names) = patch_apps_json(apps, &commands(), &["cyberpunk 2077".to_string()]).unwrap();
+        assert_eq!(names, ["Cyberpunk 2077"]);
+        let value: Value = serde_json::from_str(&patched).unwrap();
+        assert!(value["apps"][0].get("prep-cmd").is_none());
+        assert_eq!(value["apps"][1]["prep-cmd"].as_array().unwrap().len(), 3);
+
+        let (_, names) = patch_apps_json(apps, &commands(), &[]).unwrap();
+        assert_eq!(names.len(), 2);
+        assert!(patch_apps_json(apps, &commands(), &["Steam".to_string()]).unwrap_err().contains("'Steam'"));
+    }
+}
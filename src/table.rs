@@ -0,0 +1,174 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use windows::Win32::System::Console::{
+    GetConsoleMode,
+    GetStdHandle,
+    SetConsoleMode,
+    CONSOLE_MODE,
+    ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    STD_OUTPUT_HANDLE,
+};
+
+
+// Aligned text tables for the listing commands, with ANSI colors when printing to a console
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+// Auto colors a console unless NO_COLOR is set. Older consoles need escape sequences turned on first.
+pub fn init_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Never => false,
+        ColorChoice::Always => true,
+        ColorChoice::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() && enable_escape_sequences(),
+    };
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+fn enable_escape_sequences() -> bool {
+    unsafe {
+        let Ok(handle) = GetStdHandle(STD_OUTPUT_HANDLE) else {
+            return false;
+        };
+        let mut mode = CONSOLE_MODE::default();
+        GetConsoleMode(handle, &mut mode).is_ok()
+            && SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Plain,
+    Green,
+    Yellow,
+    Red,
+    Cyan,
+    Dim,
+}
+
+impl Color {
+    fn code(&self) -> Option<&'static str> {
+        match self {
+            Color::Plain => None,
+            Color::Green => Some("32"),
+            Color::Yellow => Some("33"),
+            Color::Red => Some("31"),
+            Color::Cyan => Some("36"),
+            Color::Dim => Some("2"),
+        }
+    }
+}
+
+pub struct Cell {
+    text: String,
+    color: Color,
+}
+
+impl Cell {
+    pub fn new(text: impl ToString) -> Self {
+        Cell { text: text.to_string(), color: Color::Plain }
+    }
+
+    pub fn colored(text: impl ToString, color: Color) -> Self {
+        Cell { text: text.to_string(), color }
+    }
+}
+
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Table { headers, rows: Vec::new() }
+    }
+
+    pub fn add_row(&mut self, row: Vec<Cell>) {
+        self.rows.push(row);
+    }
+
+    pub fn print(&self) {
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| header.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.text.chars().count());
+            }
+        }
+
+        let color = COLOR.load(Ordering::Relaxed);
+        let paint = |text: &str, width: usize, code: Option<&str>| match code {
+            Some(code) if color => format!("\x1b[{}m{:<width$}\x1b[0m", code, text, width = width),
+            _ => format!("{:<width$}", text, width = width),
+        };
+
+        let header: Vec<String> = self.headers.iter().zip(&widths).map(|(header, &width)| paint(header, width, Some("1"))).collect();
+        println!("{}", header.join("  ").trim_end());
+        let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+        println!("{}", rule.join("  "));
+
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, &width)| paint(&cell.text, width, cell.color.code())).collect();
+            println!("{}", cells.join("  ").trim_end());
+        }
+    }
+}
+
+//==============================================================================
+// Machine-readable output
+//==============================================================================
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+    Csv,
+    Psobject,
+}
+
+// For output that is a nested document rather than rows, like status and diff
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum DocumentFormat {
+    Text,
+    Json,
+    Yaml,
+    Psobject,
+}
+
+pub fn print_value(value: &serde_json::Value, yaml: bool) {
+    if yaml {
+        print!("{}", serde_yaml::to_string(value).unwrap_or_default());
+    } else {
+        println!("{}", serde_json::to_string_pretty(value).unwrap_or_default());
+    }
+}
+
+// --format psobject: the records alone (a display, mode or difference each) as a JSON array on
+// one line, without the schema_version wrapper, so ConvertFrom-Json turns each into an object
+// with the property names of --format json
+pub fn print_records(records: &serde_json::Value) {
+    println!("{}", serde_json::to_string(records).unwrap_or_default());
+}
+
+// RFC 4180: fields with a comma, quote or line break are quoted, quotes doubled
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn print_csv(headers: &[&str], rows: &[Vec<String>]) {
+    println!("{}", headers.iter().map(|header| csv_field(header)).collect::<Vec<_>>().join(","));
+    for row in rows {
+        println!("{}", row.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
+    }
+}
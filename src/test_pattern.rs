@@ -0,0 +1,274 @@
+use windows::Win32::Foundation::{HMODULE, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice,
+    ID3D11Device,
+    ID3D11DeviceContext,
+    ID3D11DeviceContext1,
+    ID3D11RenderTargetView,
+    ID3D11Texture2D,
+    D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+    D3D11_SDK_VERSION,
+};
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1,
+    IDXGIFactory2,
+    IDXGISwapChain1,
+    IDXGISwapChain3,
+    DXGI_PRESENT,
+    DXGI_SCALING_STRETCH,
+    DXGI_SWAP_CHAIN_DESC1,
+    DXGI_SWAP_EFFECT_FLIP_DISCARD,
+    DXGI_USAGE_RENDER_TARGET_OUTPUT,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+    DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020,
+    DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT,
+    DXGI_SAMPLE_DESC,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::HiDpi::{SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW,
+    DefWindowProcW,
+    DestroyWindow,
+    DispatchMessageW,
+    PeekMessageW,
+    PostQuitMessage,
+    RegisterClassW,
+    SetTimer,
+    ShowWindow,
+    TranslateMessage,
+    HMENU,
+    MSG,
+    PM_REMOVE,
+    SW_SHOW,
+    WM_KEYDOWN,
+    WM_LBUTTONDOWN,
+    WM_QUIT,
+    WM_TIMER,
+    WNDCLASSW,
+    WS_EX_TOPMOST,
+    WS_POPUP,
+};
+use windows::core::{w, Interface, Result, PCWSTR};
+use tracing::{info, error};
+
+use crate::displays_info::DisplayDevice;
+
+// `test-pattern` covers a display with reference patches drawn straight into an HDR swap chain,
+// so what's on screen is exactly the luminance asked for: white levels around the SDR white
+// level, a gradient up to it for banding and black crush, and bars up to 10000 nits to find
+// where the display (or its tone mapping) clips. The swap chain is either scRGB (FP16, 1.0 is
+// 80 nits) or HDR10 (10 bit PQ in BT.2020). Patches are rectangles cleared with ClearView, so
+// no shaders are needed. There are no labels on screen, the console prints the layout.
+
+const VK_ESCAPE: usize = 0x1B;
+
+// Top row, the usual range of SDR white levels
+pub const WHITE_LEVELS: [f64; 9] = [80.0, 120.0, 160.0, 200.0, 240.0, 280.0, 320.0, 400.0, 480.0];
+
+// Bottom row, to find where highlights stop getting brighter
+pub const CLIPPING_LEVELS: [f64; 10] = [400.0, 600.0, 800.0, 1000.0, 1500.0, 2000.0, 3000.0, 4000.0, 6000.0, 10000.0];
+
+const GRADIENT_STEPS: usize = 64;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum PatternFormat {
+    Scrgb,
+    Hdr10,
+}
+
+//==============================================================================
+// Layout
+//==============================================================================
+
+// SMPTE ST 2084, nits to a 0-1 signal
+pub fn pq_encode(nits: f64) -> f64 {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+    let y = (nits / 10000.0).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+fn encode(nits: f64, format: PatternFormat) -> [f32; 4] {
+    let value = match format {
+        PatternFormat::Scrgb => nits / 80.0,
+        PatternFormat::Hdr10 => pq_encode(nits),
+    } as f32;
+    [value, value, value, 1.0]
+}
+
+// Rows split the height into thirds with a margin around each patch. The gradient goes from
+// black to the SDR white level in gamma 2.2 steps, like SDR content does.
+pub fn patches(width: i32, height: i32, sdr_white_nits: f64) -> Vec<(RECT, f64)> {
+    let margin = (height / 40).max(2);
+    let row_height = height / 3;
+    let mut patches = Vec::new();
+    let mut row = |row: i32, levels: &[f64], gap: i32| {
+        let cell = width / levels.len() as i32;
+        for (index, &nits) in levels.iter().enumerate() {
+            let left = index as i32 * cell;
+            patches.push((RECT {
+                left: left + gap,
+                top: row * row_height + margin,
+                right: left + cell - gap,
+                bottom: (row + 1) * row_height - margin,
+            }, nits));
+        }
+    };
+
+    row(0, &WHITE_LEVELS, margin);
+    let gradient: Vec<f64> = (0..GRADIENT_STEPS).map(|step| sdr_white_nits * (step as f64 / (GRADIENT_STEPS - 1) as f64).powf(2.2)).collect();
+    row(1, &gradient, 0);
+    row(2, &CLIPPING_LEVELS, margin);
+    patches
+}
+
+//==============================================================================
+// Window and swap chain
+//==============================================================================
+
+extern "system" fn pattern_window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_KEYDOWN if wparam.0 == VK_ESCAPE => {
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            WM_LBUTTONDOWN | WM_TIMER => {
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+unsafe fn create_swap_chain(hwnd: HWND, width: u32, height: u32, format: PatternFormat) -> Result<(ID3D11DeviceContext1, IDXGISwapChain1, ID3D11RenderTargetView)> {
+    let mut device: Option<ID3D11Device> = None;
+    let mut context: Option<ID3D11DeviceContext> = None;
+    D3D11CreateDevice(None, D3D_DRIVER_TYPE_HARDWARE, HMODULE::default(), D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+        None, D3D11_SDK_VERSION, Some(&mut device), None, Some(&mut context))?;
+    let device = device.ok_or_else(windows::core::Error::from_win32)?;
+    let context: ID3D11DeviceContext1 = context.ok_or_else(windows::core::Error::from_win32)?.cast()?;
+
+    let (buffer_format, color_space) = match format {
+        PatternFormat::Scrgb => (DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709),
+        PatternFormat::Hdr10 => (DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020),
+    };
+    let factory: IDXGIFactory2 = CreateDXGIFactory1()?;
+    let swap_chain = factory.CreateSwapChainForHwnd(&device, hwnd, &DXGI_SWAP_CHAIN_DESC1 {
+        Width: width,
+        Height: height,
+        Format: buffer_format,
+        SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+        BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+        BufferCount: 2,
+        Scaling: DXGI_SCALING_STRETCH,
+        SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+        ..Default::default()
+    }, None, None)?;
+    swap_chain.cast::<IDXGISwapChain3>()?.SetColorSpace1(color_space)?;
+
+    let back_buffer: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
+    let mut target: Option<ID3D11RenderTargetView> = None;
+    device.CreateRenderTargetView(&back_buffer, None, Some(&mut target))?;
+    let target = target.ok_or_else(windows::core::Error::from_win32)?;
+    Ok((context, swap_chain, target))
+}
+
+//==============================================================================
+// Helper functions for CLI commands
+//==============================================================================
+
+// Until Esc, a click or `duration_secs` (0 for no limit)
+pub fn show_test_pattern(device: &DisplayDevice, format: PatternFormat, sdr_white_nits: f64, duration_secs: u32) -> Result<()> {
+    let (width, height) = (device.current_resolution.0 as i32, device.current_resolution.1 as i32);
+    let patches = patches(width, height, sdr_white_nits);
+
+    unsafe {
+        // The window has to cover the display in physical pixels
+        if let Err(e) = SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) {
+            info!("Could not set DPI awareness, the pattern may not cover the display: {}", e);
+        }
+
+        let instance = GetModuleHandleW(PCWSTR::null())?;
+        let class_name = w!("SunshineHelperTestPattern");
+        let window_class = WNDCLASSW {
+            lpfnWndProc: Some(pattern_window_proc),
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        if RegisterClassW(&window_class) == 0 {
+            let error = windows::core::Error::from_win32();
+            error!("Failed to register test pattern window class: {}", error);
+            return Err(error);
+        }
+
+        info!("Showing a {:?} test pattern on {} at ({}, {})", format, device.device_name, device.position.0, device.position.1);
+        let hwnd = CreateWindowExW(WS_EX_TOPMOST, class_name, w!("sunshine_helper test pattern"), WS_POPUP,
+            device.position.0, device.position.1, width, height, HWND::default(), HMENU::default(), instance, None)?;
+
+        let result = (|| -> Result<()> {
+            let (context, swap_chain, target) = create_swap_chain(hwnd, width as u32, height as u32, format)?;
+            let _ = ShowWindow(hwnd, SW_SHOW);
+            if duration_secs > 0 {
+                SetTimer(hwnd, 1, duration_secs * 1000, None);
+            }
+
+            // Redrawn every frame, so the pattern survives anything covering it for a moment
+            let mut msg = MSG::default();
+            loop {
+                while PeekMessageW(&mut msg, HWND::default(), 0, 0, PM_REMOVE).as_bool() {
+                    if msg.message == WM_QUIT {
+                        return Ok(());
+                    }
+                    let _ = TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+                context.ClearView(&target, &encode(0.0, format), None);
+                for (rect, nits) in &patches {
+                    context.ClearView(&target, &encode(*nits, format), Some(&[*rect]));
+                }
+                swap_chain.Present(1, DXGI_PRESENT(0)).ok()?;
+            }
+        })();
+
+        let _ = DestroyWindow(hwnd);
+        if let Err(e) = &result {
+            error!("Test pattern failed on {}: {}", device.device_name, e);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pq_matches_reference_values() {
+        assert_eq!(pq_encode(0.0), pq_encode(-1.0));
+        assert!((pq_encode(100.0) - 0.5081).abs() < 1e-3);
+        assert!((pq_encode(1000.0) - 0.7518).abs() < 1e-3);
+        assert!((pq_encode(10000.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn patches_stay_on_the_display() {
+        let patches = patches(3840, 2160, 240.0);
+        assert_eq!(patches.len(), WHITE_LEVELS.len() + GRADIENT_STEPS + CLIPPING_LEVELS.len());
+        assert!(patches.iter().all(|(rect, _)| rect.left >= 0 && rect.top >= 0 && rect.right <= 3840 && rect.bottom <= 2160 && rect.left < rect.right && rect.top < rect.bottom));
+
+        let gradient = &patches[WHITE_LEVELS.len()..WHITE_LEVELS.len() + GRADIENT_STEPS];
+        assert_eq!((gradient[0].1, gradient[GRADIENT_STEPS - 1].1), (0.0, 240.0));
+        assert!(gradient.windows(2).all(|pair| pair[0].1 < pair[1].1 && pair[0].0.right <= pair[1].0.left));
+    }
+}
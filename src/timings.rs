@@ -0,0 +1,134 @@
+use std::fmt::{Display, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+// `--timings` reports how long each internal step of a command took: enumerating the displays,
+// QueryDisplayConfig, SetDisplayConfig and DisplayConfigSetDeviceInfo, the mode switch itself,
+// the wait for the display to settle after it and the check that the mode took. It's for
+// finding out why prep commands run into Sunshine's timeout on one machine and not another.
+// The report goes to stderr once the command ends, whether it succeeded or not, so the
+// command's own output stays parseable.
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TimingsFormat {
+    Text,
+    Json,
+}
+
+pub struct Timing {
+    pub step: &'static str,
+    pub detail: String,
+    pub started: Duration, // since the command started
+    pub duration: Duration,
+}
+
+// Set by start(), nothing is recorded without it
+static RECORDING: OnceLock<(TimingsFormat, Instant)> = OnceLock::new();
+static TIMINGS: Mutex<Vec<Timing>> = Mutex::new(Vec::new());
+
+pub fn start(format: TimingsFormat) {
+    let _ = RECORDING.set((format, Instant::now()));
+}
+
+// Times one step. The detail says what it was done to, like a display or QueryDisplayConfig's flags.
+pub fn time<T>(step: &'static str, detail: impl Display, operation: impl FnOnce() -> T) -> T {
+    let Some((_, start)) = RECORDING.get() else {
+        return operation();
+    };
+    let started = Instant::now();
+    let result = operation();
+    let timing = Timing { step, detail: detail.to_string(), started: started - *start, duration: started.elapsed() };
+    if let Ok(mut timings) = TIMINGS.lock() {
+        timings.push(timing);
+    }
+    result
+}
+
+//==============================================================================
+// Report
+//==============================================================================
+
+fn ms(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 10_000.0).round() / 10.0
+}
+
+// Total time per step, the slowest first
+fn totals(timings: &[Timing]) -> Vec<(&'static str, usize, Duration)> {
+    let mut totals: Vec<(&'static str, usize, Duration)> = Vec::new();
+    for timing in timings {
+        match totals.iter_mut().find(|(step, _, _)| *step == timing.step) {
+            Some((_, count, total)) => {
+                *count += 1;
+                *total += timing.duration;
+            }
+            None => totals.push((timing.step, 1, timing.duration)),
+        }
+    }
+    totals.sort_by_key(|&(_, _, total)| std::cmp::Reverse(total));
+    totals
+}
+
+pub fn text(timings: &[Timing], total: Duration) -> String {
+    let mut out = String::from("Timings:\n");
+    for timing in timings {
+        let _ = writeln!(out, "  {:>9.1} ms  +{:>9.1} ms  {:<20} {}", ms(timing.started), ms(timing.duration), timing.step, timing.detail);
+    }
+    out.push_str("By step:\n");
+    for (step, count, duration) in totals(timings) {
+        let _ = writeln!(out, "  {:<20} {:>9.1} ms in {} call(s)", step, ms(duration), count);
+    }
+    let _ = write!(out, "Total: {:.1} ms", ms(total));
+    out
+}
+
+pub fn json(timings: &[Timing], total: Duration) -> Value {
+    let steps: Vec<_> = timings.iter().map(|timing| json!({
+        "step": timing.step,
+        "detail": timing.detail,
+        "start_ms": ms(timing.started),
+        "ms": ms(timing.duration),
+    })).collect();
+    let totals: serde_json::Map<String, Value> = totals(timings).into_iter()
+        .map(|(step, count, duration)| (step.to_string(), json!({ "calls": count, "ms": ms(duration) })))
+        .collect();
+    json!({ "total_ms": ms(total), "steps": steps, "totals": totals })
+}
+
+// Prints what was recorded, once. Called at the end of main and by ExitCode::exit.
+pub fn report() {
+    let Some((format, start)) = RECORDING.get() else {
+        return;
+    };
+    let Ok(mut timings) = TIMINGS.lock() else {
+        return;
+    };
+    let timings = std::mem::take(&mut *timings);
+    match format {
+        TimingsFormat::Text => eprintln!("{}", text(&timings, start.elapsed())),
+        TimingsFormat::Json => eprintln!("{}", json(&timings, start.elapsed())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(step: &'static str, started: u64, duration: u64) -> Timing {
+        Timing { step, detail: String::new(), started: Duration::from_millis(started), duration: Duration::from_millis(duration) }
+    }
+
+    #[test]
+    fn steps_add_up_slowest_first() {
+        let timings = [timing("enumeration", 0, 20), timing("mode_switch", 20, 400), timing("settle_wait", 420, 3000), timing("enumeration", 3420, 15)];
+        let json = json(&timings, Duration::from_millis(3500));
+        assert_eq!(json["total_ms"], 3500.0);
+        assert_eq!(json["steps"][1], json!({ "step": "mode_switch", "detail": "", "start_ms": 20.0, "ms": 400.0 }));
+        assert_eq!(json["totals"]["enumeration"], json!({ "calls": 2, "ms": 35.0 }));
+        assert_eq!(json["totals"].as_object().unwrap().keys().next().unwrap(), "settle_wait");
+
+        let text = text(&timings, Duration::from_millis(3500));
+        assert!(text.contains("  settle_wait             3000.0 ms in 1 call(s)\n"));
+        assert!(text.ends_with("Total: 3500.0 ms"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::mem::size_of;
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    CM_Get_DevNode_Status,
+    SetupDiCallClassInstaller,
+    SetupDiDestroyDeviceInfoList,
+    SetupDiEnumDeviceInfo,
+    SetupDiGetClassDevsW,
+    SetupDiGetDeviceInstanceIdW,
+    SetupDiGetDeviceRegistryPropertyW,
+    SetupDiSetClassInstallParamsW,
+    CM_DEVNODE_STATUS_FLAGS,
+    CM_PROB,
+    CM_PROB_DISABLED,
+    CR_SUCCESS,
+    DICS_DISABLE,
+    DICS_ENABLE,
+    DICS_FLAG_GLOBAL,
+    DIF_PROPERTYCHANGE,
+    DIGCF_PRESENT,
+    DN_HAS_PROBLEM,
+    DN_STARTED,
+    GUID_DEVCLASS_DISPLAY,
+    HDEVINFO,
+    SETUP_DI_REGISTRY_PROPERTY,
+    SPDRP_DEVICEDESC,
+    SPDRP_HARDWAREID,
+    SP_CLASSINSTALL_HEADER,
+    SP_DEVINFO_DATA,
+    SP_PROPCHANGE_PARAMS,
+};
+use windows::Win32::Foundation::{E_FAIL, HWND};
+use windows::core::{Error, Result, PCWSTR};
+use tracing::{info, error, instrument};
+
+use crate::displays_info::{self, wide_to_string};
+use crate::elevation;
+use crate::exit_code;
+
+// Indirect display drivers used to give a headless streaming host a screen. Each installs
+// a root-enumerated device in the Display class, matched here by hardware ID.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum VddDriver {
+    Parsec,
+    SudoVda,
+    IddSample,
+}
+
+impl VddDriver {
+    pub fn from_hardware_id(hardware_id: &str) -> Option<Self> {
+        match hardware_id.to_ascii_lowercase().as_str() {
+            "root\\parsec\\vda" => Some(VddDriver::Parsec),
+            "root\\sudomaker\\sudovda" => Some(VddDriver::SudoVda),
+            // The original sample and its maintained fork (Virtual Display Driver by MikeTheTech)
+            "root\\iddsampledriver" | "root\\mttvdd" => Some(VddDriver::IddSample),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VddStatus {
+    Running,
+    Disabled,
+    Problem(u32), // CM_PROB_* code
+    Stopped,
+}
+
+pub struct VirtualDisplayDevice {
+    pub driver: VddDriver,
+    pub description: String,
+    pub instance_id: String,
+    pub status: VddStatus,
+}
+
+impl VirtualDisplayDevice {
+    // No filter matches every driver
+    pub fn is_driver(&self, driver: Option<VddDriver>) -> bool {
+        driver.is_none() || driver == Some(self.driver)
+    }
+}
+
+//==============================================================================
+// Device enumeration
+//==============================================================================
+
+// A SetupAPI device list, destroyed on drop
+struct DeviceInfoSet(HDEVINFO);
+
+impl Drop for DeviceInfoSet {
+    fn drop(&mut self) {
+        let _ = unsafe { SetupDiDestroyDeviceInfoList(self.0) };
+    }
+}
+
+fn open_display_class() -> Result<DeviceInfoSet> {
+    let set = unsafe { SetupDiGetClassDevsW(Some(&GUID_DEVCLASS_DISPLAY), PCWSTR::null(), HWND::default(), DIGCF_PRESENT)? };
+    Ok(DeviceInfoSet(set))
+}
+
+// REG_SZ and REG_MULTI_SZ properties, as a list of strings
+fn get_string_property(set: &DeviceInfoSet, device: &SP_DEVINFO_DATA, property: SETUP_DI_REGISTRY_PROPERTY) -> Vec<String> {
+    let mut buffer = [0u8; 1024];
+    if unsafe { SetupDiGetDeviceRegistryPropertyW(set.0, device, property, None, Some(&mut buffer), None) }.is_err() {
+        return Vec::new();
+    }
+
+    let wide: Vec<u16> = buffer.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    wide.split(|&c| c == 0).filter(|s| !s.is_empty()).map(String::from_utf16_lossy).collect()
+}
+
+fn get_status(device: &SP_DEVINFO_DATA) -> VddStatus {
+    let mut status = CM_DEVNODE_STATUS_FLAGS(0);
+    let mut problem = CM_PROB(0);
+    if unsafe { CM_Get_DevNode_Status(&mut status, &mut problem, device.DevInst, 0) } != CR_SUCCESS {
+        return VddStatus::Stopped;
+    }
+
+    if status.0 & DN_HAS_PROBLEM.0 != 0 {
+        if problem == CM_PROB_DISABLED {
+            VddStatus::Disabled
+        } else {
+            VddStatus::Problem(problem.0)
+        }
+    } else if status.0 & DN_STARTED.0 != 0 {
+        VddStatus::Running
+    } else {
+        VddStatus::Stopped
+    }
+}
+
+// Calls found for every installed virtual display device until it returns Some
+fn find_device<T>(mut found: impl FnMut(&DeviceInfoSet, &SP_DEVINFO_DATA, VirtualDisplayDevice) -> Option<T>) -> Result<Option<T>> {
+    let set = open_display_class()?;
+
+    for index in 0.. {
+        let mut device = SP_DEVINFO_DATA { cbSize: size_of::<SP_DEVINFO_DATA>() as u32, ..Default::default() };
+        if unsafe { SetupDiEnumDeviceInfo(set.0, index, &mut device) }.is_err() {
+            break; // ERROR_NO_MORE_ITEMS
+        }
+
+        let Some(driver) = get_string_property(&set, &device, SPDRP_HARDWAREID).iter().find_map(|id| VddDriver::from_hardware_id(id)) else {
+            continue;
+        };
+
+        let mut instance_id = [0u16; 512];
+        let _ = unsafe { SetupDiGetDeviceInstanceIdW(set.0, &device, Some(&mut instance_id), None) };
+
+        let virtual_device = VirtualDisplayDevice {
+            driver,
+            description: get_string_property(&set, &device, SPDRP_DEVICEDESC).into_iter().next().unwrap_or_default(),
+            instance_id: wide_to_string(&instance_id),
+            status: get_status(&device),
+        };
+
+        if let Some(result) = found(&set, &device, virtual_device) {
+            return Ok(Some(result));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn list_virtual_display_devices() -> Result<Vec<VirtualDisplayDevice>> {
+    let mut devices = Vec::new();
+    find_device::<()>(|_, _, device| {
+        devices.push(device);
+        None
+    })?;
+    Ok(devices)
+}
+
+//==============================================================================
+// Enable / disable
+//==============================================================================
+
+// Returns the device that was changed. Without a driver filter there must be exactly one
+// virtual display driver installed, so a script never toggles the wrong one.
+#[instrument(skip_all, fields(driver = ?driver, enabled = enabled))]
+pub fn set_vdd_enabled(driver: Option<VddDriver>, enabled: bool) -> Result<VirtualDisplayDevice> {
+    elevation::require_elevation(if enabled { "Enabling a virtual display driver" } else { "Disabling a virtual display driver" })?;
+    let candidates: Vec<_> = list_virtual_display_devices()?
+        .into_iter()
+        .filter(|device| device.is_driver(driver))
+        .collect();
+
+    let target = match candidates.as_slice() {
+        [] => return Err(Error::new(E_FAIL, "No matching virtual display driver is installed")),
+        [device] => device.instance_id.clone(),
+        _ => return Err(exit_code::invalid_input("Several virtual display drivers are installed, pick one with --driver")),
+    };
+
+    info!("{} virtual display device {}", if enabled { "Enabling" } else { "Disabling" }, target);
+
+    let changed = find_device(|set, device, virtual_device| {
+        if virtual_device.instance_id != target {
+            return None;
+        }
+
+        let params = SP_PROPCHANGE_PARAMS {
+            ClassInstallHeader: SP_CLASSINSTALL_HEADER {
+                cbSize: size_of::<SP_CLASSINSTALL_HEADER>() as u32,
+                InstallFunction: DIF_PROPERTYCHANGE,
+            },
+            StateChange: if enabled { DICS_ENABLE } else { DICS_DISABLE },
+            Scope: DICS_FLAG_GLOBAL,
+            HwProfile: 0,
+        };
+
+        let result = unsafe {
+            SetupDiSetClassInstallParamsW(set.0, Some(device), Some(&params.ClassInstallHeader), size_of::<SP_PROPCHANGE_PARAMS>() as u32)
+                .and_then(|()| SetupDiCallClassInstaller(DIF_PROPERTYCHANGE, set.0, Some(device)))
+        };
+
+        Some(result.map(|()| VirtualDisplayDevice { status: get_status(device), ..virtual_device }))
+    })?;
+    // The driver adds or removes its displays
+    displays_info::invalidate_topology();
+
+    match changed {
+        Some(Ok(device)) => Ok(device),
+        Some(Err(e)) => {
+            error!("Failed to change state of {}: {}", target, e);
+            Err(e)
+        }
+        None => Err(Error::new(E_FAIL, format!("Virtual display device {} disappeared", target))),
+    }
+}
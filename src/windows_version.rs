@@ -0,0 +1,153 @@
+use std::sync::OnceLock;
+use windows::Win32::System::Registry::HKEY_LOCAL_MACHINE;
+use windows::core::Result;
+use tracing::{info, error};
+
+use crate::display_api;
+use crate::exit_code;
+use crate::registry;
+
+// The build number is what decides which display APIs exist, e.g. 19045 is Windows 10 22H2 and
+// 22631 is Windows 11 23H2. Windows reports it in the registry next to the display version.
+const CURRENT_VERSION_KEY: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion";
+
+// First build of each release, oldest first
+const RELEASES: [(u32, &str); 16] = [
+    (14393, "Windows 10 1607"),
+    (15063, "Windows 10 1703"),
+    (16299, "Windows 10 1709"),
+    (17134, "Windows 10 1803"),
+    (17763, "Windows 10 1809"),
+    (18362, "Windows 10 1903"),
+    (18363, "Windows 10 1909"),
+    (19041, "Windows 10 2004"),
+    (19042, "Windows 10 20H2"),
+    (19043, "Windows 10 21H1"),
+    (19044, "Windows 10 21H2"),
+    (19045, "Windows 10 22H2"),
+    (22000, "Windows 11 21H2"),
+    (22621, "Windows 11 22H2"),
+    (22631, "Windows 11 23H2"),
+    (26100, "Windows 11 24H2"),
+];
+
+static BUILD: OnceLock<Option<u32>> = OnceLock::new();
+
+//==============================================================================
+// Features
+//==============================================================================
+
+// Display APIs that only exist from some build on. Commands check before calling them, so an old
+// build fails with what it needs instead of an invalid parameter error from the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    AdvancedColorSet,
+    PerDisplayScaling,
+    SdrWhiteLevelSet,
+    AutoColorManagement,
+    HdrStateApi,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::AdvancedColorSet,
+        Feature::PerDisplayScaling,
+        Feature::SdrWhiteLevelSet,
+        Feature::AutoColorManagement,
+        Feature::HdrStateApi,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::AdvancedColorSet => "Turning HDR on and off",
+            Feature::PerDisplayScaling => "Per-display scaling",
+            Feature::SdrWhiteLevelSet => "Setting the SDR white level",
+            Feature::AutoColorManagement => "Auto color management for SDR",
+            Feature::HdrStateApi => "HDR state API (24H2)",
+        }
+    }
+
+    pub fn min_build(&self) -> u32 {
+        match self {
+            Feature::AdvancedColorSet => 16299,
+            Feature::PerDisplayScaling => 14393,
+            Feature::SdrWhiteLevelSet => 19041,
+            Feature::AutoColorManagement => 22621,
+            Feature::HdrStateApi => 26100,
+        }
+    }
+
+    // An unknown build counts as current
+    pub fn check(&self, build: Option<u32>) -> Result<()> {
+        match build {
+            Some(build) if build < self.min_build() => Err(exit_code::not_supported(format!("{} requires {}+, this is {}",
+                self.name(), release_name(self.min_build()), describe(build)))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn available(&self) -> bool {
+        self.check(build()).is_ok()
+    }
+}
+
+pub fn require(feature: Feature) -> Result<()> {
+    feature.check(build())
+}
+
+//==============================================================================
+// Build detection
+//==============================================================================
+
+// None if it can't be read, callers then assume a current build. A mock stands for a current build.
+pub fn build() -> Option<u32> {
+    if display_api::is_mock() {
+        return None;
+    }
+
+    *BUILD.get_or_init(|| {
+        let build = match registry::read_string(HKEY_LOCAL_MACHINE, CURRENT_VERSION_KEY, "CurrentBuildNumber") {
+            Ok(value) => value.and_then(|value| value.trim().parse().ok()),
+            Err(e) => {
+                error!("Failed to read the Windows build number: {}", e.message());
+                None
+            }
+        };
+        info!("Windows build: {:?}", build);
+        build
+    })
+}
+
+pub fn release_name(build: u32) -> &'static str {
+    RELEASES.iter().rev()
+        .find(|(first_build, _)| *first_build <= build)
+        .map_or("an older Windows", |(_, name)| name)
+}
+
+pub fn describe(build: u32) -> String {
+    format!("{} (build {})", release_name(build), build)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exit_code::ExitCode;
+
+    #[test]
+    fn release_names() {
+        assert_eq!(release_name(19045), "Windows 10 22H2");
+        assert_eq!(release_name(22635), "Windows 11 23H2");
+        assert_eq!(release_name(27000), "Windows 11 24H2");
+        assert_eq!(release_name(10240), "an older Windows");
+    }
+
+    #[test]
+    fn features_fail_fast_on_older_builds() {
+        let error = Feature::HdrStateApi.check(Some(22631)).unwrap_err();
+        assert_eq!(error.message(), "HDR state API (24H2) requires Windows 11 24H2+, this is Windows 11 23H2 (build 22631)");
+        assert_eq!(ExitCode::from_error(&error), ExitCode::Unsupported);
+
+        assert!(Feature::SdrWhiteLevelSet.check(Some(19041)).is_ok());
+        assert!(Feature::AdvancedColorSet.check(None).is_ok());
+    }
+}
@@ -0,0 +1,172 @@
+use windows::Win32::System::Com::{
+    CoCreateInstance,
+    CoInitializeEx,
+    CoSetProxyBlanket,
+    CLSCTX_INPROC_SERVER,
+    COINIT_MULTITHREADED,
+    EOAC_NONE,
+    RPC_C_AUTHN_LEVEL_CALL,
+    RPC_C_IMP_LEVEL_IMPERSONATE,
+};
+use windows::Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE};
+use windows::Win32::System::Wmi::{
+    IWbemClassObject,
+    IWbemLocator,
+    IWbemServices,
+    WbemLocator,
+    WBEM_FLAG_FORWARD_ONLY,
+    WBEM_FLAG_RETURN_IMMEDIATELY,
+    WBEM_FLAG_RETURN_WBEM_COMPLETE,
+    WBEM_INFINITE,
+};
+use windows::Win32::Foundation::E_FAIL;
+use windows::core::{w, Error, Result, BSTR, PCWSTR, VARIANT};
+use tracing::{info, error};
+
+use crate::brightness::Brightness;
+use crate::displays_info::DisplayDevice;
+use crate::exit_code;
+
+// Laptop panels expose brightness through the ACPI backlight driver rather than DDC/CI.
+// WmiMonitorBrightness reads it, WmiMonitorBrightnessMethods.WmiSetBrightness changes it.
+
+//==============================================================================
+// WMI connection
+//==============================================================================
+
+fn connect() -> Result<IWbemServices> {
+    unsafe {
+        // Already initialized (possibly in another mode) is fine, COM stays usable either way
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+
+        let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)?;
+        let services = locator.ConnectServer(
+            &BSTR::from("root\\WMI"),
+            &BSTR::new(),
+            &BSTR::new(),
+            &BSTR::new(),
+            0,
+            &BSTR::new(),
+            None,
+        )?;
+
+        CoSetProxyBlanket(
+            &services,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            PCWSTR::null(),
+            RPC_C_AUTHN_LEVEL_CALL,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            None,
+            EOAC_NONE,
+        )?;
+
+        Ok(services)
+    }
+}
+
+fn query(services: &IWbemServices, wql: &str) -> Result<Vec<IWbemClassObject>> {
+    let mut objects = Vec::new();
+
+    unsafe {
+        let enumerator = services.ExecQuery(
+            &BSTR::from("WQL"),
+            &BSTR::from(wql),
+            WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY,
+            None,
+        )?;
+
+        loop {
+            let mut row = [None; 1];
+            let mut returned = 0;
+            enumerator.Next(WBEM_INFINITE, &mut row, &mut returned).ok()?;
+            match row[0].take() {
+                Some(object) if returned > 0 => objects.push(object),
+                _ => break,
+            }
+        }
+    }
+
+    Ok(objects)
+}
+
+fn get_property(object: &IWbemClassObject, name: PCWSTR) -> Result<VARIANT> {
+    let mut value = VARIANT::default();
+    unsafe { object.Get(name, 0, &mut value, None, None)? };
+    Ok(value)
+}
+
+// InstanceName is the monitor's PnP instance ID with a "_0" suffix
+fn instance_matches_display(instance_name: &str, display: &DisplayDevice) -> bool {
+    let Some(instance_id) = display.get_target_device_name().and_then(|t| t.device_instance_id()) else {
+        return false;
+    };
+    instance_name.to_lowercase().starts_with(&instance_id.to_lowercase())
+}
+
+// Find the WMI object for this display, or the only one there is when the paths don't line up
+fn find_instance(services: &IWbemServices, class: &str, display: &DisplayDevice) -> Result<IWbemClassObject> {
+    let mut objects = query(services, &format!("SELECT * FROM {} WHERE Active = TRUE", class))?;
+    if objects.is_empty() {
+        return Err(exit_code::not_supported(format!("{} has no WMI brightness control", display.device_name)));
+    }
+
+    for (index, object) in objects.iter().enumerate() {
+        let instance_name = BSTR::try_from(&get_property(object, w!("InstanceName"))?).unwrap_or_default();
+        if instance_matches_display(&instance_name.to_string(), display) {
+            return Ok(objects.swap_remove(index));
+        }
+    }
+
+    if objects.len() == 1 {
+        return Ok(objects.swap_remove(0));
+    }
+
+    Err(Error::new(E_FAIL, format!("Could not match {} to one of {} WMI brightness instances", display.device_name, objects.len())))
+}
+
+//==============================================================================
+// Brightness
+//==============================================================================
+
+pub fn get_brightness(display: &DisplayDevice) -> Result<Brightness> {
+    let services = connect()?;
+    let instance = find_instance(&services, "WmiMonitorBrightness", display)?;
+    let current = u32::try_from(&get_property(&instance, w!("CurrentBrightness"))?)?;
+
+    // WMI brightness is always a percentage
+    Ok(Brightness { minimum: 0, current, maximum: 100 })
+}
+
+pub fn set_brightness(device: &DisplayDevice, percent: u32) -> Result<()> {
+    let services = connect()?;
+    let instance = find_instance(&services, "WmiMonitorBrightnessMethods", device)?;
+    let path = BSTR::try_from(&get_property(&instance, w!("__PATH"))?)?;
+
+    info!("Setting brightness of {} to {}% through WMI", device.device_name, percent);
+
+    unsafe {
+        let mut class = None;
+        services.GetObject(&BSTR::from("WmiMonitorBrightnessMethods"), WBEM_FLAG_RETURN_WBEM_COMPLETE, None, Some(&mut class), None)?;
+        let Some(class) = class else {
+            return Err(Error::new(E_FAIL, "WmiMonitorBrightnessMethods class not found"));
+        };
+
+        let mut in_signature = None;
+        class.GetMethod(w!("WmiSetBrightness"), 0, &mut in_signature, std::ptr::null_mut())?;
+        let Some(in_signature) = in_signature else {
+            return Err(Error::new(E_FAIL, "WmiSetBrightness has no input parameters"));
+        };
+
+        let in_params = in_signature.SpawnInstance(0)?;
+        in_params.Put(w!("Timeout"), 0, &VARIANT::from(0u32), 0)?;
+        in_params.Put(w!("Brightness"), 0, &VARIANT::from(percent.min(100) as u8), 0)?;
+
+        if let Err(e) = services.ExecMethod(&path, &BSTR::from("WmiSetBrightness"), WBEM_FLAG_RETURN_WBEM_COMPLETE, None, &in_params, None, None) {
+            error!("WmiSetBrightness failed for {}: {}", device.device_name, e);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}